@@ -722,6 +722,7 @@ impl ProcState {
         code: code.clone(),
         module_url_specified: specifier.to_string(),
         module_url_found: found_specifier.to_string(),
+        redirects: vec![],
       }),
       _ => Err(anyhow!(
         "Loading unprepared module: {}",
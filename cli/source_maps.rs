@@ -88,6 +88,8 @@ pub fn apply_source_map<G: SourceMapGetter>(
     end_column,
     frames: js_error.frames.clone(),
     stack: None,
+    properties: js_error.properties.clone(),
+    promise_creation_frames: js_error.promise_creation_frames.clone(),
   }
 }
 
@@ -245,6 +247,8 @@ mod tests {
       end_column: None,
       frames: vec![],
       stack: None,
+      properties: None,
+      promise_creation_frames: vec![],
     };
     let getter = MockSourceMapGetter {};
     let actual = apply_source_map(&e, getter);
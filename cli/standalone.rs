@@ -166,6 +166,7 @@ impl ModuleLoader for EmbeddedModuleLoader {
         code,
         module_url_specified: module_specifier.to_string(),
         module_url_found: module_specifier.to_string(),
+        redirects: vec![],
       })
     }
     .boxed_local()
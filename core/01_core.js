@@ -13,21 +13,39 @@
     Array,
     ArrayPrototypeFill,
     ArrayPrototypeMap,
+    ArrayPrototypePop,
+    ArrayPrototypePush,
+    ArrayPrototypeSymbolIterator,
     ErrorCaptureStackTrace,
     Promise,
     ObjectEntries,
     ObjectFreeze,
+    ObjectDefineProperty,
     ObjectFromEntries,
+    ObjectGetOwnPropertyDescriptor,
+    ObjectGetPrototypeOf,
+    MapPrototypeEntries,
     MapPrototypeGet,
     MapPrototypeDelete,
     MapPrototypeSet,
     PromisePrototypeThen,
+    SafePromisePrototypeFinally,
     ObjectAssign,
+    ReflectOwnKeys,
+    SafeArrayIterator,
+    SafeFinalizationRegistry,
+    SafeWeakSet,
+    Set,
+    SetPrototypeValues,
+    String,
+    StringPrototypeMatchAll,
+    StringPrototypeSymbolIterator,
     SymbolFor,
+    Uint8Array,
   } = window.__bootstrap.primordials;
 
   // Available on start due to bindings.
-  const { opcallSync, opcallAsync } = window.Deno.core;
+  const { opcallSync, opcallAsync, cancelOp } = window.Deno.core;
 
   let opsCache = {};
   const errorMap = {};
@@ -115,18 +133,31 @@
     errorMap[className] = errorBuilder;
   }
 
+  function buildCustomError(className, message, code, properties) {
+    const errorBuilder = errorMap[className];
+    const err = errorBuilder ? errorBuilder(message) : new Error(
+      `Unregistered error class: "${className}"\n  ${message}\n  Classes of errors returned from ops should be registered via Deno.core.registerErrorClass().`,
+    );
+    // Set .code if error was a known OS error, see error_codes.rs
+    if (code) {
+      err.code = code;
+    }
+    // Extra fields attached to the error on the Rust side, eg. `errno`
+    if (properties) {
+      ObjectAssign(err, properties);
+    }
+    return err;
+  }
+
   function unwrapOpResult(res) {
     // .$err_class_name is a special key that should only exist on errors
     if (res?.$err_class_name) {
-      const className = res.$err_class_name;
-      const errorBuilder = errorMap[className];
-      const err = errorBuilder ? errorBuilder(res.message) : new Error(
-        `Unregistered error class: "${className}"\n  ${res.message}\n  Classes of errors returned from ops should be registered via Deno.core.registerErrorClass().`,
+      const err = buildCustomError(
+        res.$err_class_name,
+        res.message,
+        res.code,
+        res.properties,
       );
-      // Set .code if error was a known OS error, see error_codes.rs
-      if (res.code) {
-        err.code = res.code;
-      }
       // Strip unwrapOpResult() and errorBuilder() calls from stack trace
       ErrorCaptureStackTrace(err, unwrapOpResult);
       throw err;
@@ -134,12 +165,33 @@
     return res;
   }
 
-  function opAsync(opName, arg1 = null, arg2 = null) {
+  // `options.signal` cancels the op when it aborts: the op's future is
+  // dropped and the promise rejects with a `Canceled` error. It can be an
+  // `AbortSignal`, or any object with an `aborted` property and
+  // `addEventListener()`/`removeEventListener()` methods.
+  function opAsync(opName, arg1 = null, arg2 = null, options = undefined) {
+    const signal = options?.signal;
     const promiseId = nextPromiseId++;
-    const maybeError = opcallAsync(opsCache[opName], promiseId, arg1, arg2);
+    const maybeError = opcallAsync(
+      opsCache[opName],
+      promiseId,
+      arg1,
+      arg2,
+      signal !== undefined,
+    );
     // Handle sync error (e.g: error parsing args)
     if (maybeError) return unwrapOpResult(maybeError);
-    const p = PromisePrototypeThen(setPromise(promiseId), unwrapOpResult);
+    let p = PromisePrototypeThen(setPromise(promiseId), unwrapOpResult);
+    if (signal?.aborted) {
+      cancelOp(promiseId);
+    } else if (signal !== undefined) {
+      const onAbort = () => cancelOp(promiseId);
+      signal.addEventListener("abort", onAbort, { once: true });
+      p = SafePromisePrototypeFinally(
+        p,
+        () => signal.removeEventListener("abort", onAbort),
+      );
+    }
     // Save the id on the promise so it can later be ref'ed or unref'ed
     p[promiseIdSymbol] = promiseId;
     return p;
@@ -165,6 +217,20 @@
     return opAsync("op_shutdown", rid);
   }
 
+  // Subscribes to `topic` on the broadcast bus passed as
+  // `RuntimeOptions::broadcast_bus`. `recv()` resolves with the next message
+  // published to the topic by another subscription, in this or another
+  // runtime, or with null once the subscription is closed.
+  function broadcastSubscribe(topic) {
+    const rid = opSync("op_broadcast_bus_subscribe", topic);
+    return ObjectFreeze({
+      rid,
+      publish: (data) => opSync("op_broadcast_bus_publish", rid, data),
+      recv: () => opAsync("op_broadcast_bus_recv", rid),
+      close: () => tryClose(rid),
+    });
+  }
+
   function close(rid) {
     opSync("op_close", rid);
   }
@@ -173,10 +239,71 @@
     opSync("op_try_close", rid);
   }
 
+  // Calls `fn(rid)` and closes the resource if it throws, or if the promise it
+  // returns rejects, so that a failed operation doesn't leak the resource.
+  function closeOnError(rid, fn) {
+    let result;
+    try {
+      result = fn(rid);
+    } catch (err) {
+      tryClose(rid);
+      throw err;
+    }
+    if (result instanceof Promise) {
+      return PromisePrototypeThen(result, undefined, (err) => {
+        tryClose(rid);
+        throw err;
+      });
+    }
+    return result;
+  }
+
+  // Runs the finalizers of collected objects, see `registerFinalizer()`.
+  const finalizationRegistry = new SafeFinalizationRegistry((finalizerId) =>
+    opSync("op_run_finalizer", finalizerId)
+  );
+
+  // Ties a finalizer added with `OpState::add_finalizer()` to the lifetime of
+  // `object`, so it runs after `object` is garbage collected.
+  function registerFinalizer(object, finalizerId) {
+    finalizationRegistry.register(object, finalizerId);
+  }
+
+  function opMetadata() {
+    return opSync("op_metadata");
+  }
+
   function print(str, isErr = false) {
     opSync("op_print", str, isErr);
   }
 
+  // Milliseconds elapsed since the runtime was created, with sub-millisecond
+  // resolution unless clamped by the embedder.
+  function now() {
+    return opSync("op_now");
+  }
+
+  // Logs through the `log` crate of the embedder, see `op_log`. `level` is
+  // one of "error", "warn", "info", "debug" or "trace".
+  function log(level, target, message, fields = undefined) {
+    opSync("op_log", {
+      level,
+      target: target ?? null,
+      message: String(message),
+    }, fields ?? null);
+  }
+
+  // Returns an object with a method per log level, logging under `target`.
+  function createLogger(target) {
+    return ObjectFreeze({
+      error: (message, fields) => log("error", target, message, fields),
+      warn: (message, fields) => log("warn", target, message, fields),
+      info: (message, fields) => log("info", target, message, fields),
+      debug: (message, fields) => log("debug", target, message, fields),
+      trace: (message, fields) => log("trace", target, message, fields),
+    });
+  }
+
   function metrics() {
     const [aggregate, perOps] = opSync("op_metrics");
     aggregate.ops = ObjectFromEntries(ArrayPrototypeMap(
@@ -186,6 +313,196 @@
     return aggregate;
   }
 
+  // Global built-ins that are deep-frozen by `freezeIntrinsics()`.
+  const intrinsicNames = [
+    "AggregateError",
+    "Array",
+    "ArrayBuffer",
+    "Atomics",
+    "BigInt",
+    "BigInt64Array",
+    "BigUint64Array",
+    "Boolean",
+    "DataView",
+    "Date",
+    "decodeURI",
+    "decodeURIComponent",
+    "encodeURI",
+    "encodeURIComponent",
+    "Error",
+    "escape",
+    "eval",
+    "EvalError",
+    "FinalizationRegistry",
+    "Float32Array",
+    "Float64Array",
+    "Function",
+    "Int16Array",
+    "Int32Array",
+    "Int8Array",
+    "Intl",
+    "isFinite",
+    "isNaN",
+    "JSON",
+    "Map",
+    "Math",
+    "Number",
+    "Object",
+    "parseFloat",
+    "parseInt",
+    "Promise",
+    "Proxy",
+    "RangeError",
+    "ReferenceError",
+    "Reflect",
+    "RegExp",
+    "Set",
+    "SharedArrayBuffer",
+    "String",
+    "Symbol",
+    "SyntaxError",
+    "TypeError",
+    "Uint16Array",
+    "Uint32Array",
+    "Uint8Array",
+    "Uint8ClampedArray",
+    "unescape",
+    "URIError",
+    "WeakMap",
+    "WeakRef",
+    "WeakSet",
+    "WebAssembly",
+  ];
+
+  // Assigning to a property on an object whose prototype has a frozen data
+  // property of the same name fails (the "override mistake"). These commonly
+  // overridden properties are turned into accessors that define an own
+  // property on the receiver instead, eg. `this.name = "..."` in an error
+  // subclass keeps working.
+  const overridableProperties = [
+    ["Object", ["constructor", "toString", "toLocaleString", "valueOf"]],
+    ["Function", ["constructor", "toString"]],
+    ["Error", ["constructor", "name", "message", "toString"]],
+    ["AggregateError", ["constructor", "name", "message"]],
+    ["EvalError", ["constructor", "name", "message"]],
+    ["RangeError", ["constructor", "name", "message"]],
+    ["ReferenceError", ["constructor", "name", "message"]],
+    ["SyntaxError", ["constructor", "name", "message"]],
+    ["TypeError", ["constructor", "name", "message"]],
+    ["URIError", ["constructor", "name", "message"]],
+  ];
+
+  // Returns the original value, which is no longer reachable from `object`
+  // through a data property.
+  function enableOverride(object, key) {
+    const desc = ObjectGetOwnPropertyDescriptor(object, key);
+    if (!desc || !("value" in desc) || !desc.configurable) {
+      return undefined;
+    }
+    const { value } = desc;
+    ObjectDefineProperty(object, key, {
+      get() {
+        return value;
+      },
+      set(newValue) {
+        if (this === object) {
+          throw new TypeError(
+            `Cannot assign to read only property '${key}' of a frozen intrinsic`,
+          );
+        }
+        ObjectDefineProperty(this, key, {
+          value: newValue,
+          writable: true,
+          enumerable: true,
+          configurable: true,
+        });
+      },
+      enumerable: desc.enumerable,
+      configurable: false,
+    });
+    return value;
+  }
+
+  // Freezes `root` and every object reachable from it through own properties,
+  // accessors and prototypes.
+  function deepFreeze(root, seen) {
+    const queue = [root];
+    while (queue.length > 0) {
+      const value = ArrayPrototypePop(queue);
+      if (
+        (typeof value !== "object" && typeof value !== "function") ||
+        value === null || seen.has(value)
+      ) {
+        continue;
+      }
+      seen.add(value);
+      ObjectFreeze(value);
+      ArrayPrototypePush(queue, ObjectGetPrototypeOf(value));
+      for (const key of new SafeArrayIterator(ReflectOwnKeys(value))) {
+        const desc = ObjectGetOwnPropertyDescriptor(value, key);
+        if ("value" in desc) {
+          ArrayPrototypePush(queue, desc.value);
+        } else {
+          ArrayPrototypePush(queue, desc.get, desc.set);
+        }
+      }
+    }
+  }
+
+  // Deep-freezes the JS built-ins and their prototypes, including intrinsics
+  // that are not reachable from the global object (eg. %TypedArray% or the
+  // iterator prototypes). The global object itself is left untouched.
+  function freezeIntrinsics() {
+    const roots = ArrayPrototypeMap(intrinsicNames, (name) => globalThis[name]);
+    ArrayPrototypePush(
+      roots,
+      ObjectGetPrototypeOf(Uint8Array),
+      ObjectGetPrototypeOf(function* () {}),
+      ObjectGetPrototypeOf(async function () {}),
+      ObjectGetPrototypeOf(async function* () {}),
+      ArrayPrototypeSymbolIterator([]),
+      StringPrototypeSymbolIterator(""),
+      MapPrototypeEntries(new Map()),
+      SetPrototypeValues(new Set()),
+      StringPrototypeMatchAll("", /./g),
+    );
+    for (const [name, keys] of new SafeArrayIterator(overridableProperties)) {
+      const { prototype } = globalThis[name];
+      for (const key of new SafeArrayIterator(keys)) {
+        ArrayPrototypePush(roots, enableOverride(prototype, key));
+      }
+    }
+    const seen = new SafeWeakSet();
+    for (const root of new SafeArrayIterator(roots)) {
+      deepFreeze(root, seen);
+    }
+  }
+
+  // Restricts access to `Deno.core` once the embedder has finished
+  // bootstrapping, so user code can only reach ops through wrappers that
+  // captured `Deno.core` earlier. Either freezes it in place, or removes it
+  // from the global scope altogether.
+  function lock(remove) {
+    const { Deno, __bootstrap } = globalThis;
+    for (const namespace of new SafeArrayIterator([Deno, __bootstrap])) {
+      if (namespace === undefined || namespace.core !== core) {
+        continue;
+      }
+      if (remove) {
+        delete namespace.core;
+      } else {
+        ObjectDefineProperty(namespace, "core", {
+          value: core,
+          writable: false,
+          configurable: false,
+        });
+      }
+    }
+    if (!remove) {
+      ObjectFreeze(core);
+    }
+  }
+
   // Some "extensions" rely on "BadResource" and "Interrupted" errors in the
   // JS code (eg. "deno_net") so they are provided in "Deno.core" but later
   // reexported on "Deno.errors"
@@ -203,6 +520,16 @@
     }
   }
 
+  // Values of the `type` argument passed to the callback set with
+  // `Deno.core.setPromiseRejectCallback()`, mirroring V8's
+  // `PromiseRejectEvent`.
+  const promiseRejectEvents = ObjectFreeze({
+    rejectWithNoHandler: 0,
+    handlerAddedAfterReject: 1,
+    rejectAfterResolved: 2,
+    resolveAfterResolved: 3,
+  });
+
   // Extra Deno.core.* exports
   const core = ObjectAssign(globalThis.Deno.core, {
     opAsync,
@@ -210,20 +537,41 @@
     ops,
     close,
     tryClose,
+    closeOnError,
+    registerFinalizer,
     read,
     write,
     shutdown,
+    broadcastSubscribe,
     print,
     resources,
     metrics,
+    now,
+    log,
+    createLogger,
+    opMetadata,
     registerErrorBuilder,
     registerErrorClass,
+    buildCustomError,
     opresolve,
     syncOpsCache,
+    freezeIntrinsics,
+    lock,
     BadResource,
     Interrupted,
+    promiseRejectEvents,
   });
 
   ObjectAssign(globalThis.__bootstrap, { core });
   ObjectAssign(globalThis.Deno, { core });
+
+  // Embedders that provide their own `performance` can replace this one.
+  if (globalThis.performance === undefined) {
+    ObjectDefineProperty(globalThis, "performance", {
+      value: { now },
+      writable: true,
+      enumerable: false,
+      configurable: true,
+    });
+  }
 })(globalThis);
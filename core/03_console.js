@@ -0,0 +1,519 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+"use strict";
+
+((window) => {
+  const {
+    ArrayIsArray,
+    ArrayPrototypeIncludes,
+    ArrayPrototypeJoin,
+    ArrayPrototypeMap,
+    ArrayPrototypePop,
+    ArrayPrototypePush,
+    ArrayPrototypePushApply,
+    ArrayPrototypeReduce,
+    ArrayPrototypeShift,
+    ArrayPrototypeSlice,
+    ArrayBufferIsView,
+    DatePrototypeGetTime,
+    DatePrototypeToISOString,
+    ErrorCaptureStackTrace,
+    ErrorPrototypeToString,
+    FunctionPrototypeBind,
+    FunctionPrototypeToString,
+    JSONStringify,
+    MapPrototypeEntries,
+    MapPrototypeGet,
+    MapPrototypeGetSize,
+    MapPrototypeDelete,
+    MapPrototypeSet,
+    NumberIsNaN,
+    NumberParseFloat,
+    NumberParseInt,
+    ObjectDefineProperty,
+    ObjectGetOwnPropertySymbols,
+    ObjectGetPrototypeOf,
+    ObjectKeys,
+    ObjectPrototypeHasOwnProperty,
+    ObjectPrototypeIsPrototypeOf,
+    ObjectPrototypePropertyIsEnumerable,
+    RegExpPrototypeTest,
+    RegExpPrototypeToString,
+    SafeArrayIterator,
+    SafeMap,
+    SetPrototypeGetSize,
+    SetPrototypeValues,
+    StringPrototypeIncludes,
+    StringPrototypeRepeat,
+    StringPrototypeReplace,
+    StringPrototypeSlice,
+    StringPrototypeSplit,
+    String,
+    SymbolPrototypeToString,
+    TypedArrayPrototypeGetLength,
+    TypedArrayPrototypeGetSymbolToStringTag,
+    Error,
+    Date,
+    Map,
+    Set,
+    RegExp,
+  } = window.__bootstrap.primordials;
+  const core = window.Deno.core;
+
+  // Arrays and collections show at most this many entries.
+  const MAX_ENTRIES = 100;
+  // Values that format to more than this many characters are broken over
+  // multiple lines.
+  const LINE_WIDTH = 72;
+
+  const styles = {
+    special: [36, 39],
+    number: [33, 39],
+    bigint: [33, 39],
+    boolean: [33, 39],
+    undefined: [90, 39],
+    null: [1, 22],
+    string: [32, 39],
+    symbol: [32, 39],
+    date: [35, 39],
+    regexp: [31, 39],
+  };
+
+  function stylize(ctx, str, style) {
+    if (!ctx.colors) {
+      return str;
+    }
+    const [open, close] = styles[style];
+    return `\x1b[${open}m${str}\x1b[${close}m`;
+  }
+
+  function quote(str) {
+    return JSONStringify(str);
+  }
+
+  function formatKey(ctx, key) {
+    if (typeof key === "symbol") {
+      return `[${stylize(ctx, SymbolPrototypeToString(key), "symbol")}]`;
+    }
+    if (RegExpPrototypeTest(/^[a-zA-Z_$][a-zA-Z_$0-9]*$/, key)) {
+      return key;
+    }
+    return stylize(ctx, quote(key), "string");
+  }
+
+  function formatPrimitive(ctx, value) {
+    switch (typeof value) {
+      case "string":
+        return stylize(ctx, quote(value), "string");
+      case "number":
+        return stylize(
+          ctx,
+          value === 0 && 1 / value < 0 ? "-0" : String(value),
+          "number",
+        );
+      case "bigint":
+        return stylize(ctx, `${value}n`, "bigint");
+      case "boolean":
+        return stylize(ctx, String(value), "boolean");
+      case "undefined":
+        return stylize(ctx, "undefined", "undefined");
+      case "symbol":
+        return stylize(ctx, SymbolPrototypeToString(value), "symbol");
+    }
+    // null
+    return stylize(ctx, "null", "null");
+  }
+
+  function formatFunction(ctx, fn) {
+    const source = FunctionPrototypeToString(fn);
+    const isClass = StringPrototypeSlice(source, 0, 5) === "class";
+    let str;
+    if (isClass) {
+      str = `[class ${fn.name || "(anonymous)"}]`;
+    } else {
+      str = fn.name ? `[Function: ${fn.name}]` : "[Function (anonymous)]";
+    }
+    return stylize(ctx, str, "special");
+  }
+
+  function constructorName(obj) {
+    let proto = ObjectGetPrototypeOf(obj);
+    while (proto !== null) {
+      const constructor = ObjectPrototypeHasOwnProperty(proto, "constructor")
+        ? proto.constructor
+        : undefined;
+      if (typeof constructor === "function" && constructor.name !== "") {
+        return constructor.name;
+      }
+      proto = ObjectGetPrototypeOf(proto);
+    }
+    return null;
+  }
+
+  function ownEntries(ctx, obj, skipIndices) {
+    const entries = [];
+    for (const key of new SafeArrayIterator(ObjectKeys(obj))) {
+      if (skipIndices && RegExpPrototypeTest(/^\d+$/, key)) {
+        continue;
+      }
+      ArrayPrototypePush(
+        entries,
+        `${formatKey(ctx, key)}: ${formatValue(ctx, obj[key])}`,
+      );
+    }
+    const symbols = ObjectGetOwnPropertySymbols(obj);
+    for (const symbol of new SafeArrayIterator(symbols)) {
+      if (ObjectPrototypePropertyIsEnumerable(obj, symbol)) {
+        ArrayPrototypePush(
+          entries,
+          `${formatKey(ctx, symbol)}: ${formatValue(ctx, obj[symbol])}`,
+        );
+      }
+    }
+    return entries;
+  }
+
+  function formatList(ctx, prefix, entries, open, close) {
+    if (entries.length === 0) {
+      return `${prefix}${open}${close}`;
+    }
+    const joined = ArrayPrototypeJoin(entries, ", ");
+    const inline = `${prefix}${open} ${joined} ${close}`;
+    const length = ArrayPrototypeReduce(
+      entries,
+      (length, entry) => length + entry.length,
+      0,
+    );
+    if (
+      length + prefix.length <= LINE_WIDTH &&
+      !StringPrototypeIncludes(inline, "\n")
+    ) {
+      return inline;
+    }
+    // `obj` itself is the last of `ctx.seen`.
+    const indent = StringPrototypeRepeat("  ", ctx.seen.length - 1);
+    const lines = ArrayPrototypeMap(entries, (entry) => `${indent}  ${entry}`);
+    const body = ArrayPrototypeJoin(lines, ",\n");
+    return `${prefix}${open}\n${body}\n${indent}${close}`;
+  }
+
+  function pushLimited(entries, items, size, format) {
+    let count = 0;
+    for (const item of items) {
+      if (count === MAX_ENTRIES) {
+        ArrayPrototypePush(entries, `... ${size - MAX_ENTRIES} more items`);
+        break;
+      }
+      ArrayPrototypePush(entries, format(item));
+      count++;
+    }
+  }
+
+  function formatObject(ctx, obj) {
+    if (ObjectPrototypeIsPrototypeOf(Error.prototype, obj)) {
+      return typeof obj.stack === "string"
+        ? obj.stack
+        : ErrorPrototypeToString(obj);
+    }
+    if (ObjectPrototypeIsPrototypeOf(Date.prototype, obj)) {
+      const time = DatePrototypeGetTime(obj);
+      return stylize(
+        ctx,
+        NumberIsNaN(time) ? "Invalid Date" : DatePrototypeToISOString(obj),
+        "date",
+      );
+    }
+    if (ObjectPrototypeIsPrototypeOf(RegExp.prototype, obj)) {
+      return stylize(ctx, RegExpPrototypeToString(obj), "regexp");
+    }
+
+    const isArray = ArrayIsArray(obj);
+    if (ctx.seen.length > ctx.depth) {
+      const name = constructorName(obj);
+      return stylize(
+        ctx,
+        isArray ? "[Array]" : `[${name ?? "Object"}]`,
+        "special",
+      );
+    }
+
+    ArrayPrototypePush(ctx.seen, obj);
+    try {
+      const name = constructorName(obj);
+      const entries = [];
+      if (isArray) {
+        const length = obj.length;
+        for (let i = 0; i < length && i < MAX_ENTRIES; i++) {
+          ArrayPrototypePush(
+            entries,
+            i in obj ? formatValue(ctx, obj[i]) : "<empty item>",
+          );
+        }
+        if (length > MAX_ENTRIES) {
+          ArrayPrototypePush(
+            entries,
+            `... ${length - MAX_ENTRIES} more items`,
+          );
+        }
+        ArrayPrototypePushApply(entries, ownEntries(ctx, obj, true));
+        const prefix = name === "Array"
+          ? ""
+          : `${name ?? "Array"}(${length}) `;
+        return formatList(ctx, prefix, entries, "[", "]");
+      }
+      if (
+        ArrayBufferIsView(obj) &&
+        TypedArrayPrototypeGetSymbolToStringTag(obj) !== undefined
+      ) {
+        const length = TypedArrayPrototypeGetLength(obj);
+        for (let i = 0; i < length && i < MAX_ENTRIES; i++) {
+          ArrayPrototypePush(entries, formatValue(ctx, obj[i]));
+        }
+        if (length > MAX_ENTRIES) {
+          ArrayPrototypePush(
+            entries,
+            `... ${length - MAX_ENTRIES} more items`,
+          );
+        }
+        return formatList(ctx, `${name}(${length}) `, entries, "[", "]");
+      }
+      if (ObjectPrototypeIsPrototypeOf(Map.prototype, obj)) {
+        const size = MapPrototypeGetSize(obj);
+        pushLimited(
+          entries,
+          MapPrototypeEntries(obj),
+          size,
+          ([key, value]) =>
+            `${formatValue(ctx, key)} => ${formatValue(ctx, value)}`,
+        );
+        ArrayPrototypePushApply(entries, ownEntries(ctx, obj, false));
+        return formatList(ctx, `${name}(${size}) `, entries, "{", "}");
+      }
+      if (ObjectPrototypeIsPrototypeOf(Set.prototype, obj)) {
+        const size = SetPrototypeGetSize(obj);
+        pushLimited(
+          entries,
+          SetPrototypeValues(obj),
+          size,
+          (value) => formatValue(ctx, value),
+        );
+        ArrayPrototypePushApply(entries, ownEntries(ctx, obj, false));
+        return formatList(ctx, `${name}(${size}) `, entries, "{", "}");
+      }
+
+      ArrayPrototypePushApply(entries, ownEntries(ctx, obj, false));
+      let prefix = "";
+      if (name === null) {
+        prefix = "[Object: null prototype] ";
+      } else if (name !== "Object") {
+        prefix = `${name} `;
+      }
+      return formatList(ctx, prefix, entries, "{", "}");
+    } finally {
+      ArrayPrototypePop(ctx.seen);
+    }
+  }
+
+  function formatValue(ctx, value) {
+    if (typeof value === "function") {
+      return formatFunction(ctx, value);
+    }
+    if (typeof value !== "object" || value === null) {
+      return formatPrimitive(ctx, value);
+    }
+    if (ArrayPrototypeIncludes(ctx.seen, value)) {
+      return stylize(ctx, "[Circular]", "special");
+    }
+    return formatObject(ctx, value);
+  }
+
+  function consoleOptions() {
+    return core.opSync("op_console_options");
+  }
+
+  // Formats a value for display, like Node's `util.inspect()`. Objects nested
+  // deeper than `depth` are abbreviated, eg. as `[Object]`.
+  function inspect(value, options = {}) {
+    const defaults = consoleOptions();
+    const ctx = {
+      depth: options.depth ?? defaults.depth,
+      colors: options.colors ?? defaults.colors,
+      seen: [],
+    };
+    return formatValue(ctx, value);
+  }
+
+  // Formats the arguments of a console method, applying `printf`-like
+  // substitutions if the first one is a string.
+  function formatArgs(args, options) {
+    const first = args[0];
+    let rest = args;
+    const parts = [];
+    if (typeof first === "string") {
+      rest = ArrayPrototypeSlice(args, 1);
+      const str = StringPrototypeReplace(
+        first,
+        /%[sdifoOc%]/g,
+        (specifier) => {
+          if (specifier === "%%") {
+            return "%";
+          }
+          if (rest.length === 0) {
+            return specifier;
+          }
+          const arg = ArrayPrototypeShift(rest);
+          switch (specifier) {
+            case "%s":
+              return typeof arg === "string" ? arg : inspect(arg, options);
+            case "%d":
+            case "%i":
+              return typeof arg === "bigint"
+                ? `${arg}n`
+                : String(NumberParseInt(arg));
+            case "%f":
+              return String(NumberParseFloat(arg));
+            case "%c":
+              return "";
+          }
+          return inspect(arg, options);
+        },
+      );
+      ArrayPrototypePush(parts, str);
+    }
+    for (const arg of new SafeArrayIterator(rest)) {
+      ArrayPrototypePush(
+        parts,
+        typeof arg === "string" ? arg : inspect(arg, options),
+      );
+    }
+    return ArrayPrototypeJoin(parts, " ");
+  }
+
+  let groupIndent = "";
+  const counts = new SafeMap();
+  const timers = new SafeMap();
+
+  function print(str, isErr) {
+    if (groupIndent !== "") {
+      str = ArrayPrototypeJoin(
+        ArrayPrototypeMap(
+          StringPrototypeSplit(str, "\n"),
+          (line) => `${groupIndent}${line}`,
+        ),
+        "\n",
+      );
+    }
+    core.print(`${str}\n`, isErr);
+  }
+
+  function trace(...args) {
+    const err = {
+      name: "Trace",
+      message: formatArgs(args),
+    };
+    // Omits this function from the stack.
+    ErrorCaptureStackTrace(err, trace);
+    print(err.stack, true);
+  }
+
+  const console = {
+    log(...args) {
+      print(formatArgs(args), false);
+    },
+    info(...args) {
+      print(formatArgs(args), false);
+    },
+    debug(...args) {
+      print(formatArgs(args), false);
+    },
+    warn(...args) {
+      print(formatArgs(args), true);
+    },
+    error(...args) {
+      print(formatArgs(args), true);
+    },
+    dir(value, options = {}) {
+      print(inspect(value, options), false);
+    },
+    assert(condition = false, ...args) {
+      if (condition) {
+        return;
+      }
+      if (args.length === 0) {
+        print("Assertion failed", true);
+      } else if (typeof args[0] === "string") {
+        args[0] = `Assertion failed: ${args[0]}`;
+        print(formatArgs(args), true);
+      } else {
+        print(formatArgs(["Assertion failed:", ...args]), true);
+      }
+    },
+    trace,
+    group(...args) {
+      if (args.length > 0) {
+        print(formatArgs(args), false);
+      }
+      groupIndent += "  ";
+    },
+    groupCollapsed(...args) {
+      console.group(...args);
+    },
+    groupEnd() {
+      groupIndent = StringPrototypeSlice(groupIndent, 2);
+    },
+    count(label = "default") {
+      label = String(label);
+      const count = (MapPrototypeGet(counts, label) ?? 0) + 1;
+      MapPrototypeSet(counts, label, count);
+      print(`${label}: ${count}`, false);
+    },
+    countReset(label = "default") {
+      MapPrototypeDelete(counts, String(label));
+    },
+    time(label = "default") {
+      label = String(label);
+      if (MapPrototypeGet(timers, label) !== undefined) {
+        print(`Timer "${label}" already exists`, true);
+        return;
+      }
+      MapPrototypeSet(timers, label, core.now());
+    },
+    timeLog(label = "default", ...args) {
+      label = String(label);
+      const start = MapPrototypeGet(timers, label);
+      if (start === undefined) {
+        print(`Timer "${label}" does not exist`, true);
+        return;
+      }
+      const elapsed = core.now() - start;
+      print(formatArgs([`${label}: ${elapsed}ms`, ...args]), false);
+    },
+    timeEnd(label = "default") {
+      label = String(label);
+      console.timeLog(label);
+      MapPrototypeDelete(timers, label);
+    },
+  };
+
+  // Forward calls to V8's console too, so they show up in the inspector.
+  const v8Console = window.console;
+  if (v8Console !== undefined) {
+    for (const key of new SafeArrayIterator(ObjectKeys(console))) {
+      if (typeof v8Console[key] === "function") {
+        console[key] = FunctionPrototypeBind(
+          core.callConsole,
+          console,
+          v8Console[key],
+          console[key],
+        );
+      }
+    }
+  }
+
+  ObjectDefineProperty(window, "console", {
+    value: console,
+    writable: true,
+    enumerable: false,
+    configurable: true,
+  });
+  core.inspect = inspect;
+})(globalThis);
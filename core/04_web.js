@@ -0,0 +1,461 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+"use strict";
+
+((window) => {
+  const {
+    ArrayBufferIsView,
+    ArrayBufferPrototype,
+    ArrayPrototypeFilter,
+    ArrayPrototypeFind,
+    ArrayPrototypeFindIndex,
+    ArrayPrototypeMap,
+    ArrayPrototypePush,
+    ArrayPrototypeSome,
+    ArrayPrototypeSort,
+    ArrayPrototypeValues,
+    Error,
+    FunctionPrototypeCall,
+    ObjectDefineProperty,
+    ObjectKeys,
+    ObjectPrototypeIsPrototypeOf,
+    RangeError,
+    SafeArrayIterator,
+    SafeStringIterator,
+    String,
+    StringPrototypeCodePointAt,
+    StringPrototypeSlice,
+    StringPrototypeToLowerCase,
+    StringPrototypeTrim,
+    Symbol,
+    SymbolIterator,
+    SymbolToStringTag,
+    TypedArrayPrototypeGetByteLength,
+    TypeError,
+    Uint8Array,
+  } = window.__bootstrap.primordials;
+  const core = window.Deno.core;
+
+  core.registerErrorBuilder("InvalidCharacterError", (msg) => {
+    const err = new Error(msg);
+    err.name = "InvalidCharacterError";
+    return err;
+  });
+
+  const _parts = Symbol("parts");
+  const _searchParams = Symbol("searchParams");
+  const _list = Symbol("list");
+  const _url = Symbol("url");
+  const _update = Symbol("update");
+
+  class URLSearchParams {
+    [_list] = [];
+    // The `URL` whose query this object reflects, if any.
+    [_url] = null;
+
+    constructor(init = "") {
+      if (typeof init === "object" && init !== null) {
+        if (init[SymbolIterator] !== undefined) {
+          for (const pair of init) {
+            const entry = [...new SafeArrayIterator(pair)];
+            if (entry.length !== 2) {
+              throw new TypeError("Each query pair must be a [name, value] pair");
+            }
+            ArrayPrototypePush(this[_list], [String(entry[0]), String(entry[1])]);
+          }
+        } else {
+          for (const key of new SafeArrayIterator(ObjectKeys(init))) {
+            ArrayPrototypePush(this[_list], [key, String(init[key])]);
+          }
+        }
+      } else {
+        let query = String(init);
+        if (query[0] === "?") {
+          query = StringPrototypeSlice(query, 1);
+        }
+        this[_list] = core.opSync("op_url_parse_search_params", query);
+      }
+    }
+
+    [_update]() {
+      const url = this[_url];
+      if (url !== null) {
+        url[_parts] = core.opSync(
+          "op_url_set",
+          url[_parts].href,
+          ["search", this.toString()],
+        );
+      }
+    }
+
+    append(name, value) {
+      ArrayPrototypePush(this[_list], [String(name), String(value)]);
+      this[_update]();
+    }
+
+    delete(name) {
+      name = String(name);
+      this[_list] = ArrayPrototypeFilter(this[_list], (e) => e[0] !== name);
+      this[_update]();
+    }
+
+    get(name) {
+      name = String(name);
+      const entry = ArrayPrototypeFind(this[_list], (e) => e[0] === name);
+      return entry === undefined ? null : entry[1];
+    }
+
+    getAll(name) {
+      name = String(name);
+      return ArrayPrototypeMap(
+        ArrayPrototypeFilter(this[_list], (e) => e[0] === name),
+        (e) => e[1],
+      );
+    }
+
+    has(name) {
+      name = String(name);
+      return ArrayPrototypeSome(this[_list], (e) => e[0] === name);
+    }
+
+    set(name, value) {
+      name = String(name);
+      value = String(value);
+      const list = this[_list];
+      const index = ArrayPrototypeFindIndex(list, (e) => e[0] === name);
+      if (index === -1) {
+        ArrayPrototypePush(list, [name, value]);
+      } else {
+        list[index][1] = value;
+        this[_list] = ArrayPrototypeFilter(
+          list,
+          (e, i) => i <= index || e[0] !== name,
+        );
+      }
+      this[_update]();
+    }
+
+    sort() {
+      // Array.prototype.sort is stable, as the spec requires here. Names are
+      // compared by UTF-16 code units.
+      ArrayPrototypeSort(
+        this[_list],
+        (a, b) => (a[0] === b[0] ? 0 : a[0] < b[0] ? -1 : 1),
+      );
+      this[_update]();
+    }
+
+    forEach(callback, thisArg = undefined) {
+      if (typeof callback !== "function") {
+        throw new TypeError("The callback must be a function");
+      }
+      for (let i = 0; i < this[_list].length; i++) {
+        const [name, value] = this[_list][i];
+        FunctionPrototypeCall(callback, thisArg, value, name, this);
+      }
+    }
+
+    keys() {
+      return ArrayPrototypeValues(ArrayPrototypeMap(this[_list], (e) => e[0]));
+    }
+
+    values() {
+      return ArrayPrototypeValues(ArrayPrototypeMap(this[_list], (e) => e[1]));
+    }
+
+    entries() {
+      return ArrayPrototypeValues(
+        ArrayPrototypeMap(this[_list], (e) => [e[0], e[1]]),
+      );
+    }
+
+    [SymbolIterator]() {
+      return this.entries();
+    }
+
+    toString() {
+      return core.opSync("op_url_stringify_search_params", this[_list]);
+    }
+
+    get [SymbolToStringTag]() {
+      return "URLSearchParams";
+    }
+  }
+
+  class URL {
+    [_parts];
+    [_searchParams] = null;
+
+    constructor(url, base = undefined) {
+      this[_parts] = core.opSync(
+        "op_url_parse",
+        String(url),
+        base === undefined ? null : String(base),
+      );
+    }
+
+    [_update](setter, value) {
+      this[_parts] = core.opSync(
+        "op_url_set",
+        this[_parts].href,
+        [setter, String(value)],
+      );
+      const searchParams = this[_searchParams];
+      if (
+        searchParams !== null && (setter === "href" || setter === "search")
+      ) {
+        searchParams[_list] = core.opSync(
+          "op_url_parse_search_params",
+          StringPrototypeSlice(this[_parts].search, 1),
+        );
+      }
+    }
+
+    get href() {
+      return this[_parts].href;
+    }
+
+    set href(value) {
+      this[_update]("href", value);
+    }
+
+    get origin() {
+      return this[_parts].origin;
+    }
+
+    get protocol() {
+      return this[_parts].protocol;
+    }
+
+    set protocol(value) {
+      this[_update]("protocol", value);
+    }
+
+    get username() {
+      return this[_parts].username;
+    }
+
+    set username(value) {
+      this[_update]("username", value);
+    }
+
+    get password() {
+      return this[_parts].password;
+    }
+
+    set password(value) {
+      this[_update]("password", value);
+    }
+
+    get host() {
+      return this[_parts].host;
+    }
+
+    set host(value) {
+      this[_update]("host", value);
+    }
+
+    get hostname() {
+      return this[_parts].hostname;
+    }
+
+    set hostname(value) {
+      this[_update]("hostname", value);
+    }
+
+    get port() {
+      return this[_parts].port;
+    }
+
+    set port(value) {
+      this[_update]("port", value);
+    }
+
+    get pathname() {
+      return this[_parts].pathname;
+    }
+
+    set pathname(value) {
+      this[_update]("pathname", value);
+    }
+
+    get search() {
+      return this[_parts].search;
+    }
+
+    set search(value) {
+      this[_update]("search", value);
+    }
+
+    get searchParams() {
+      if (this[_searchParams] === null) {
+        const searchParams = new URLSearchParams(this[_parts].search);
+        searchParams[_url] = this;
+        this[_searchParams] = searchParams;
+      }
+      return this[_searchParams];
+    }
+
+    get hash() {
+      return this[_parts].hash;
+    }
+
+    set hash(value) {
+      this[_update]("hash", value);
+    }
+
+    toString() {
+      return this[_parts].href;
+    }
+
+    toJSON() {
+      return this[_parts].href;
+    }
+
+    get [SymbolToStringTag]() {
+      return "URL";
+    }
+  }
+
+  class TextEncoder {
+    get encoding() {
+      return "utf-8";
+    }
+
+    encode(input = "") {
+      return core.encode(String(input));
+    }
+
+    encodeInto(source, destination) {
+      let read = 0;
+      let written = 0;
+      const length = TypedArrayPrototypeGetByteLength(destination);
+      for (const char of new SafeStringIterator(String(source))) {
+        let codePoint = StringPrototypeCodePointAt(char, 0);
+        if (codePoint >= 0xd800 && codePoint <= 0xdfff) {
+          // Lone surrogates are encoded as U+FFFD, like encode() does.
+          codePoint = 0xfffd;
+        }
+        const size = codePoint < 0x80
+          ? 1
+          : codePoint < 0x800
+          ? 2
+          : codePoint < 0x10000
+          ? 3
+          : 4;
+        if (written + size > length) {
+          break;
+        }
+        if (size === 1) {
+          destination[written] = codePoint;
+        } else {
+          const prefix = [0, 0, 0xc0, 0xe0, 0xf0][size];
+          destination[written] = prefix | (codePoint >> (6 * (size - 1)));
+          for (let i = 1; i < size; i++) {
+            destination[written + i] = 0x80 |
+              ((codePoint >> (6 * (size - 1 - i))) & 0x3f);
+          }
+        }
+        read += char.length;
+        written += size;
+      }
+      return { read, written };
+    }
+
+    get [SymbolToStringTag]() {
+      return "TextEncoder";
+    }
+  }
+
+  const encodingLabels = {
+    "unicode-1-1-utf-8": "utf-8",
+    "unicode11utf8": "utf-8",
+    "unicode20utf8": "utf-8",
+    "utf-8": "utf-8",
+    "utf8": "utf-8",
+    "x-unicode20utf8": "utf-8",
+    "csunicode": "utf-16le",
+    "iso-10646-ucs-2": "utf-16le",
+    "ucs-2": "utf-16le",
+    "unicode": "utf-16le",
+    "unicodefeff": "utf-16le",
+    "utf-16": "utf-16le",
+    "utf-16le": "utf-16le",
+  };
+
+  const _encoding = Symbol("encoding");
+  const _fatal = Symbol("fatal");
+  const _ignoreBOM = Symbol("ignoreBOM");
+
+  class TextDecoder {
+    [_encoding];
+    [_fatal];
+    [_ignoreBOM];
+
+    constructor(label = "utf-8", options = {}) {
+      const encoding = encodingLabels[
+        StringPrototypeToLowerCase(StringPrototypeTrim(String(label)))
+      ];
+      if (encoding === undefined) {
+        throw new RangeError(
+          `The encoding label provided ('${label}') is invalid or unsupported.`,
+        );
+      }
+      this[_encoding] = encoding;
+      this[_fatal] = !!options.fatal;
+      this[_ignoreBOM] = !!options.ignoreBOM;
+    }
+
+    get encoding() {
+      return this[_encoding];
+    }
+
+    get fatal() {
+      return this[_fatal];
+    }
+
+    get ignoreBOM() {
+      return this[_ignoreBOM];
+    }
+
+    decode(input = new Uint8Array(), options = {}) {
+      if (options.stream) {
+        throw new TypeError("Streaming decoding is not supported");
+      }
+      let bytes;
+      if (ArrayBufferIsView(input)) {
+        bytes = new Uint8Array(input.buffer, input.byteOffset, input.byteLength);
+      } else if (ObjectPrototypeIsPrototypeOf(ArrayBufferPrototype, input)) {
+        bytes = new Uint8Array(input);
+      } else {
+        throw new TypeError("The input must be an ArrayBuffer or a view on one");
+      }
+      return core.opSync("op_text_decode", bytes, {
+        encoding: this[_encoding],
+        fatal: this[_fatal],
+        ignoreBom: this[_ignoreBOM],
+      });
+    }
+
+    get [SymbolToStringTag]() {
+      return "TextDecoder";
+    }
+  }
+
+  function atob(data) {
+    return core.opSync("op_base64_decode", String(data));
+  }
+
+  function btoa(data) {
+    return core.opSync("op_base64_encode", String(data));
+  }
+
+  const globals = { URL, URLSearchParams, TextEncoder, TextDecoder, atob, btoa };
+  for (const name of new SafeArrayIterator(ObjectKeys(globals))) {
+    ObjectDefineProperty(window, name, {
+      value: globals[name],
+      writable: true,
+      enumerable: false,
+      configurable: true,
+    });
+  }
+})(globalThis);
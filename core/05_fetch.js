@@ -0,0 +1,314 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+"use strict";
+
+((window) => {
+  const {
+    ArrayBufferIsView,
+    ArrayBufferPrototype,
+    ArrayPrototypeFilter,
+    ArrayPrototypeJoin,
+    ArrayPrototypeMap,
+    ArrayPrototypePush,
+    ArrayPrototypeSome,
+    ArrayPrototypeValues,
+    FunctionPrototypeCall,
+    JSONParse,
+    ObjectCreate,
+    ObjectDefineProperty,
+    ObjectKeys,
+    ObjectPrototypeIsPrototypeOf,
+    PromisePrototypeCatch,
+    SafeArrayIterator,
+    String,
+    StringPrototypeToLowerCase,
+    Symbol,
+    SymbolAsyncIterator,
+    SymbolIterator,
+    SymbolToStringTag,
+    TypedArrayPrototypeSet,
+    TypedArrayPrototypeSubarray,
+    TypeError,
+    Uint8Array,
+  } = window.__bootstrap.primordials;
+  const core = window.Deno.core;
+
+  // Size of the buffers response bodies are read into.
+  const READ_BUFFER_SIZE = 64 * 1024;
+
+  const _list = Symbol("list");
+
+  // Header names are lowercased; values of repeated headers are combined by
+  // `get()` but kept separate when iterating, so Set-Cookie survives.
+  class Headers {
+    [_list] = [];
+
+    constructor(init = undefined) {
+      if (init === undefined || init === null) {
+        return;
+      }
+      if (typeof init !== "object") {
+        throw new TypeError("Headers must be initialized with an object");
+      }
+      if (init[SymbolIterator] !== undefined) {
+        for (const pair of init) {
+          const entry = [...new SafeArrayIterator(pair)];
+          if (entry.length !== 2) {
+            throw new TypeError("Each header must be a [name, value] pair");
+          }
+          this.append(entry[0], entry[1]);
+        }
+      } else {
+        for (const name of new SafeArrayIterator(ObjectKeys(init))) {
+          this.append(name, init[name]);
+        }
+      }
+    }
+
+    append(name, value) {
+      ArrayPrototypePush(this[_list], [
+        StringPrototypeToLowerCase(String(name)),
+        String(value),
+      ]);
+    }
+
+    delete(name) {
+      name = StringPrototypeToLowerCase(String(name));
+      this[_list] = ArrayPrototypeFilter(this[_list], (e) => e[0] !== name);
+    }
+
+    get(name) {
+      name = StringPrototypeToLowerCase(String(name));
+      const values = ArrayPrototypeMap(
+        ArrayPrototypeFilter(this[_list], (e) => e[0] === name),
+        (e) => e[1],
+      );
+      return values.length === 0 ? null : ArrayPrototypeJoin(values, ", ");
+    }
+
+    has(name) {
+      name = StringPrototypeToLowerCase(String(name));
+      return ArrayPrototypeSome(this[_list], (e) => e[0] === name);
+    }
+
+    set(name, value) {
+      this.delete(name);
+      this.append(name, value);
+    }
+
+    forEach(callback, thisArg = undefined) {
+      if (typeof callback !== "function") {
+        throw new TypeError("The callback must be a function");
+      }
+      for (let i = 0; i < this[_list].length; i++) {
+        const [name, value] = this[_list][i];
+        FunctionPrototypeCall(callback, thisArg, value, name, this);
+      }
+    }
+
+    keys() {
+      return ArrayPrototypeValues(ArrayPrototypeMap(this[_list], (e) => e[0]));
+    }
+
+    values() {
+      return ArrayPrototypeValues(ArrayPrototypeMap(this[_list], (e) => e[1]));
+    }
+
+    entries() {
+      return ArrayPrototypeValues(
+        ArrayPrototypeMap(this[_list], (e) => [e[0], e[1]]),
+      );
+    }
+
+    [SymbolIterator]() {
+      return this.entries();
+    }
+
+    get [SymbolToStringTag]() {
+      return "Headers";
+    }
+  }
+
+  const _inner = Symbol("inner");
+  const _headers = Symbol("headers");
+  const _bodyUsed = Symbol("bodyUsed");
+
+  class Response {
+    [_inner];
+    [_headers];
+    [_bodyUsed] = false;
+
+    constructor() {
+      throw new TypeError("Responses can only be created by fetch()");
+    }
+
+    get status() {
+      return this[_inner].status;
+    }
+
+    get statusText() {
+      return this[_inner].statusText;
+    }
+
+    get ok() {
+      return this[_inner].status >= 200 && this[_inner].status <= 299;
+    }
+
+    get url() {
+      return this[_inner].url;
+    }
+
+    get headers() {
+      return this[_headers];
+    }
+
+    get bodyUsed() {
+      return this[_bodyUsed];
+    }
+
+    // Yields the body in chunks, as it is received.
+    async *[SymbolAsyncIterator]() {
+      if (this[_bodyUsed]) {
+        throw new TypeError("The body has already been consumed");
+      }
+      this[_bodyUsed] = true;
+      const rid = this[_inner].responseRid;
+      try {
+        while (true) {
+          const buf = new Uint8Array(READ_BUFFER_SIZE);
+          const read = await core.read(rid, buf);
+          if (read === 0) {
+            break;
+          }
+          yield TypedArrayPrototypeSubarray(buf, 0, read);
+        }
+      } finally {
+        core.tryClose(rid);
+      }
+    }
+
+    async arrayBuffer() {
+      const chunks = [];
+      let length = 0;
+      for await (const chunk of this) {
+        ArrayPrototypePush(chunks, chunk);
+        length += chunk.length;
+      }
+      const body = new Uint8Array(length);
+      let offset = 0;
+      for (const chunk of new SafeArrayIterator(chunks)) {
+        TypedArrayPrototypeSet(body, chunk, offset);
+        offset += chunk.length;
+      }
+      return body.buffer;
+    }
+
+    async text() {
+      return core.decode(new Uint8Array(await this.arrayBuffer()));
+    }
+
+    async json() {
+      return JSONParse(await this.text());
+    }
+
+    // Discards the body without reading it.
+    cancel() {
+      this[_bodyUsed] = true;
+      core.tryClose(this[_inner].responseRid);
+    }
+
+    get [SymbolToStringTag]() {
+      return "Response";
+    }
+  }
+
+  function createResponse(inner) {
+    const response = ObjectCreate(Response.prototype);
+    response[_inner] = inner;
+    response[_headers] = new Headers(inner.headers);
+    response[_bodyUsed] = false;
+    return response;
+  }
+
+  function toBytes(chunk) {
+    if (typeof chunk === "string") {
+      return core.encode(chunk);
+    }
+    if (ArrayBufferIsView(chunk)) {
+      return new Uint8Array(chunk.buffer, chunk.byteOffset, chunk.byteLength);
+    }
+    if (ObjectPrototypeIsPrototypeOf(ArrayBufferPrototype, chunk)) {
+      return new Uint8Array(chunk);
+    }
+    return null;
+  }
+
+  async function writeBody(rid, stream) {
+    for await (const chunk of stream) {
+      const bytes = toBytes(chunk);
+      if (bytes === null) {
+        throw new TypeError("Body chunks must be strings or buffers");
+      }
+      if (bytes.length > 0) {
+        await core.write(rid, bytes);
+      }
+    }
+    await core.shutdown(rid);
+  }
+
+  // `body` can be a string, a buffer, or an iterable or async iterable of
+  // strings and buffers, which is streamed.
+  async function fetch(input, init = {}) {
+    const url = String(input);
+    const method = init.method === undefined ? "GET" : String(init.method);
+    const headers = new Headers(init.headers);
+    const body = init.body ?? null;
+    let bytes = null;
+    let stream = null;
+    if (body !== null) {
+      bytes = toBytes(body);
+      if (bytes !== null) {
+        if (typeof body === "string" && !headers.has("content-type")) {
+          headers.set("content-type", "text/plain;charset=UTF-8");
+        }
+      } else if (
+        body[SymbolAsyncIterator] !== undefined ||
+        body[SymbolIterator] !== undefined
+      ) {
+        stream = body;
+      } else {
+        throw new TypeError("Unsupported body type");
+      }
+    }
+
+    const { requestRid, requestBodyRid } = core.opSync("op_fetch", {
+      method,
+      url,
+      headers: headers[_list],
+      hasBodyStream: stream !== null,
+    }, bytes);
+    const responsePromise = core.opAsync("op_fetch_send", requestRid);
+    if (stream !== null) {
+      try {
+        await writeBody(requestBodyRid, stream);
+      } catch (err) {
+        // Sending fails too once the request is closed.
+        PromisePrototypeCatch(responsePromise, () => {});
+        core.tryClose(requestRid);
+        throw err;
+      } finally {
+        core.tryClose(requestBodyRid);
+      }
+    }
+    return createResponse(await responsePromise);
+  }
+
+  const globals = { fetch, Headers, Response };
+  for (const name of new SafeArrayIterator(ObjectKeys(globals))) {
+    ObjectDefineProperty(window, name, {
+      value: globals[name],
+      writable: true,
+      enumerable: false,
+      configurable: true,
+    });
+  }
+})(globalThis);
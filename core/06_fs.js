@@ -0,0 +1,74 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+"use strict";
+
+((window) => {
+  const {
+    ArrayBufferIsView,
+    Error,
+    ObjectFreeze,
+    SafeArrayIterator,
+    String,
+    TypeError,
+    Uint8Array,
+  } = window.__bootstrap.primordials;
+  const core = window.Deno.core;
+
+  for (
+    const name of new SafeArrayIterator([
+      "NotFound",
+      "PermissionDenied",
+      "AlreadyExists",
+    ])
+  ) {
+    try {
+      core.registerErrorBuilder(name, (msg) => {
+        const err = new Error(msg);
+        err.name = name;
+        return err;
+      });
+    } catch {
+      // Already registered by another extension.
+    }
+  }
+
+  function readFileSync(path) {
+    return core.opSync("op_fs_read_file", String(path));
+  }
+
+  function readTextFileSync(path) {
+    return core.decode(readFileSync(path));
+  }
+
+  function writeFileSync(path, data) {
+    if (!ArrayBufferIsView(data)) {
+      throw new TypeError("The data must be a Uint8Array");
+    }
+    const bytes = new Uint8Array(data.buffer, data.byteOffset, data.byteLength);
+    core.opSync("op_fs_write_file", String(path), bytes);
+  }
+
+  function writeTextFileSync(path, text) {
+    core.opSync("op_fs_write_file", String(path), core.encode(String(text)));
+  }
+
+  // Returns `{ isFile, isDirectory, size, mtime }`, following symlinks.
+  // `mtime` is in milliseconds since the epoch, or null.
+  function statSync(path) {
+    return core.opSync("op_fs_stat", String(path));
+  }
+
+  // Returns `{ name, isFile, isDirectory, isSymlink }` for each entry, sorted
+  // by name.
+  function readDirSync(path) {
+    return core.opSync("op_fs_read_dir", String(path));
+  }
+
+  core.fs = ObjectFreeze({
+    readFileSync,
+    readTextFileSync,
+    writeFileSync,
+    writeTextFileSync,
+    statSync,
+    readDirSync,
+  });
+})(globalThis);
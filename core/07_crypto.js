@@ -0,0 +1,66 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+"use strict";
+
+((window) => {
+  const {
+    ArrayBufferIsView,
+    ArrayPrototypeIncludes,
+    Error,
+    ObjectDefineProperty,
+    ObjectFreeze,
+    TypedArrayPrototypeGetSymbolToStringTag,
+    TypeError,
+    Uint8Array,
+  } = window.__bootstrap.primordials;
+  const core = window.Deno.core;
+
+  try {
+    core.registerErrorBuilder("QuotaExceededError", (msg) => {
+      const err = new Error(msg);
+      err.name = "QuotaExceededError";
+      return err;
+    });
+  } catch {
+    // Already registered by another extension.
+  }
+
+  const integerArrayTypes = [
+    "Int8Array",
+    "Uint8Array",
+    "Uint8ClampedArray",
+    "Int16Array",
+    "Uint16Array",
+    "Int32Array",
+    "Uint32Array",
+    "BigInt64Array",
+    "BigUint64Array",
+  ];
+
+  function getRandomValues(view) {
+    if (!ArrayBufferIsView(view)) {
+      throw new TypeError("The argument must be an integer typed array");
+    }
+    const type = TypedArrayPrototypeGetSymbolToStringTag(view);
+    if (!ArrayPrototypeIncludes(integerArrayTypes, type)) {
+      const err = new Error(
+        "The provided ArrayBufferView is not an integer array type",
+      );
+      err.name = "TypeMismatchError";
+      throw err;
+    }
+    const bytes = new Uint8Array(view.buffer, view.byteOffset, view.byteLength);
+    core.opSync("op_crypto_get_random_values", bytes);
+    return view;
+  }
+
+  function randomUUID() {
+    return core.opSync("op_crypto_random_uuid");
+  }
+
+  ObjectDefineProperty(window, "crypto", {
+    value: ObjectFreeze({ getRandomValues, randomUUID }),
+    writable: true,
+    enumerable: false,
+    configurable: true,
+  });
+})(globalThis);
@@ -1,14 +1,24 @@
 // Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
 
 use crate::error::is_instance_of_error;
+use crate::error::JsStackFrame;
 use crate::modules::ModuleMap;
+use crate::ops::serialize_op_result;
+use crate::ops::OpCall;
 use crate::resolve_url_or_path;
+use crate::runtime::new_context;
+use crate::sanitizer;
+use crate::slow_ops::DetectSlowOp;
+use crate::CancelFuture;
+use crate::CancelHandle;
+use crate::JsCallable;
 use crate::JsRuntime;
 use crate::Op;
 use crate::OpId;
 use crate::OpPayload;
 use crate::OpResult;
 use crate::OpTable;
+use crate::PendingOpsOverflow;
 use crate::PromiseId;
 use crate::ZeroCopyBuf;
 use anyhow::Error;
@@ -18,6 +28,7 @@ use serde::Serialize;
 use serde_v8::to_v8;
 use std::cell::RefCell;
 use std::option::Option;
+use std::time::Instant;
 use url::Url;
 use v8::HandleScope;
 use v8::Local;
@@ -31,6 +42,9 @@ const UNDEFINED_OP_ID_MSG: &str =
 This error is often caused by a typo in an op name, or not calling
 JsRuntime::sync_ops_cache() after JsRuntime initialization.";
 
+const SHUTTING_DOWN_MSG: &str =
+  "Can not dispatch ops, the runtime is shutting down";
+
 lazy_static::lazy_static! {
   pub static ref EXTERNAL_REFERENCES: v8::ExternalReferences =
     v8::ExternalReferences::new(&[
@@ -46,18 +60,39 @@ lazy_static::lazy_static! {
       v8::ExternalReference {
         function: unref_op.map_fn_to()
       },
+      v8::ExternalReference {
+        function: cancel_op.map_fn_to()
+      },
       v8::ExternalReference {
         function: set_macrotask_callback.map_fn_to()
       },
       v8::ExternalReference {
         function: set_nexttick_callback.map_fn_to()
       },
+      v8::ExternalReference {
+        function: add_before_exit_callback.map_fn_to()
+      },
       v8::ExternalReference {
         function: set_promise_reject_callback.map_fn_to()
       },
       v8::ExternalReference {
         function: set_uncaught_exception_callback.map_fn_to()
       },
+      v8::ExternalReference {
+        function: set_unhandled_rejection_handler.map_fn_to()
+      },
+      v8::ExternalReference {
+        function: create_callable.map_fn_to()
+      },
+      v8::ExternalReference {
+        function: create_context.map_fn_to()
+      },
+      v8::ExternalReference {
+        function: run_in_context.map_fn_to()
+      },
+      v8::ExternalReference {
+        function: dispose_context.map_fn_to()
+      },
       v8::ExternalReference {
         function: run_microtasks.map_fn_to()
       },
@@ -113,12 +148,25 @@ pub fn script_origin<'a>(
   s: &mut v8::HandleScope<'a>,
   resource_name: v8::Local<'a, v8::String>,
 ) -> v8::ScriptOrigin<'a> {
-  let source_map_url = v8::String::new(s, "").unwrap();
+  script_origin_at(s, resource_name, 0, 0, "")
+}
+
+/// Like `script_origin()`, for a script that starts at the given 0-based line
+/// and column of its resource, with an optional source map URL (empty for
+/// none).
+pub fn script_origin_at<'a>(
+  s: &mut v8::HandleScope<'a>,
+  resource_name: v8::Local<'a, v8::String>,
+  line_offset: i32,
+  column_offset: i32,
+  source_map_url: &str,
+) -> v8::ScriptOrigin<'a> {
+  let source_map_url = v8::String::new(s, source_map_url).unwrap();
   v8::ScriptOrigin::new(
     s,
     resource_name.into(),
-    0,
-    0,
+    line_offset,
+    column_offset,
     false,
     123,
     source_map_url.into(),
@@ -170,6 +218,7 @@ pub fn initialize_context<'s>(
   set_func(scope, core_val, "opcallAsync", opcall_async);
   set_func(scope, core_val, "refOp", ref_op);
   set_func(scope, core_val, "unrefOp", unref_op);
+  set_func(scope, core_val, "cancelOp", cancel_op);
   set_func(
     scope,
     core_val,
@@ -182,6 +231,12 @@ pub fn initialize_context<'s>(
     "setNextTickCallback",
     set_nexttick_callback,
   );
+  set_func(
+    scope,
+    core_val,
+    "addBeforeExitCallback",
+    add_before_exit_callback,
+  );
   set_func(
     scope,
     core_val,
@@ -194,6 +249,16 @@ pub fn initialize_context<'s>(
     "setUncaughtExceptionCallback",
     set_uncaught_exception_callback,
   );
+  set_func(
+    scope,
+    core_val,
+    "setUnhandledRejectionHandler",
+    set_unhandled_rejection_handler,
+  );
+  set_func(scope, core_val, "createCallable", create_callable);
+  set_func(scope, core_val, "createContext", create_context);
+  set_func(scope, core_val, "runInContext", run_in_context);
+  set_func(scope, core_val, "disposeContext", dispose_context);
   set_func(scope, core_val, "runMicrotasks", run_microtasks);
   set_func(scope, core_val, "hasTickScheduled", has_tick_scheduled);
   set_func(
@@ -342,6 +407,69 @@ pub extern "C" fn host_initialize_import_meta_object_callback(
   meta.create_data_property(scope, main_key.into(), main_val.into());
 }
 
+const PROMISE_CREATION_STACK_KEY: &str = "Deno#promiseCreationStack";
+
+/// Installed with `RuntimeOptions::capture_promise_creation_stacks`. Stores
+/// an `Error` on each new promise so the stack at creation time can be
+/// recovered with `get_promise_creation_frames()`.
+pub extern "C" fn promise_hook(
+  type_: v8::PromiseHookType,
+  promise: v8::Local<v8::Promise>,
+  _parent: v8::Local<v8::Value>,
+) {
+  if !matches!(type_, v8::PromiseHookType::Init) {
+    return;
+  }
+  let scope = &mut unsafe { v8::CallbackScope::new(promise) };
+  let scope = &mut v8::HandleScope::new(scope);
+  let key = v8::String::new(scope, PROMISE_CREATION_STACK_KEY).unwrap();
+  let key = v8::Private::for_api(scope, Some(key));
+  let message = v8::String::empty(scope);
+  let error = v8::Exception::error(scope, message);
+  promise.set_private(scope, key, error);
+}
+
+/// Returns the stack that was captured by `promise_hook()` when `promise`
+/// was created, innermost frame first.
+pub fn get_promise_creation_frames(
+  scope: &mut v8::HandleScope,
+  promise: v8::Local<v8::Promise>,
+) -> Vec<JsStackFrame> {
+  let key = v8::String::new(scope, PROMISE_CREATION_STACK_KEY).unwrap();
+  let key = v8::Private::for_api(scope, Some(key));
+  let stack_trace = match promise
+    .get_private(scope, key)
+    .filter(|error| error.is_native_error())
+    .and_then(|error| v8::Exception::get_stack_trace(scope, error))
+  {
+    Some(stack_trace) => stack_trace,
+    None => return vec![],
+  };
+  let mut frames = vec![];
+  for i in 0..stack_trace.get_frame_count() {
+    let frame = match stack_trace.get_frame(scope, i) {
+      Some(frame) => frame,
+      None => continue,
+    };
+    let file_name = frame
+      .get_script_name_or_source_url(scope)
+      .map(|name| name.to_rust_string_lossy(scope));
+    let mut js_frame = JsStackFrame::from_location(
+      file_name,
+      Some(frame.get_line_number() as i64),
+      Some(frame.get_column() as i64),
+    );
+    js_frame.function_name = frame
+      .get_function_name(scope)
+      .map(|name| name.to_rust_string_lossy(scope))
+      .filter(|name| !name.is_empty());
+    js_frame.is_eval = frame.is_eval();
+    js_frame.is_constructor = frame.is_constructor();
+    frames.push(js_frame);
+  }
+  frames
+}
+
 pub extern "C" fn promise_reject_callback(message: v8::PromiseRejectMessage) {
   use v8::PromiseRejectEvent::*;
 
@@ -418,7 +546,9 @@ pub extern "C" fn promise_reject_callback(message: v8::PromiseRejectMessage) {
           .insert(promise_global, error_global);
       }
       PromiseHandlerAddedAfterReject => {
-        state.pending_promise_exceptions.remove(&promise_global);
+        state
+          .pending_promise_exceptions
+          .shift_remove(&promise_global);
       }
       PromiseRejectAfterResolved => {}
       PromiseResolveAfterResolved => {
@@ -434,7 +564,9 @@ fn opcall_sync<'s>(
   mut rv: v8::ReturnValue,
 ) {
   let state_rc = JsRuntime::state(scope);
-  let state = state_rc.borrow_mut();
+  // Not borrowed mutably, so that `OpResult::Scratch` can read the scratch
+  // buffer when converting the result.
+  let state = state_rc.borrow();
 
   let op_id = match v8::Local::<v8::Integer>::try_from(args.get(0))
     .map(|l| l.value() as OpId)
@@ -460,10 +592,17 @@ fn opcall_sync<'s>(
     return;
   }
 
+  if state.shutting_down {
+    throw_error(scope, SHUTTING_DOWN_MSG);
+    return;
+  }
+
   // Deserializable args (may be structured args or ZeroCopyBuf)
   let a = args.get(1);
   let b = args.get(2);
 
+  let sanitized = sanitizer::start_op(scope, &state.op_state);
+  let started = state.slow_op_detector.as_ref().map(|_| Instant::now());
   let payload = OpPayload {
     scope,
     a,
@@ -472,6 +611,14 @@ fn opcall_sync<'s>(
     promise_id: 0,
   };
   let op = OpTable::route_op(op_id, state.op_state.clone(), payload);
+  if let Some(sanitized) = &sanitized {
+    sanitizer::end_op(&state.op_state, sanitized);
+  }
+  if let (Some(detector), Some(started)) = (&state.slow_op_detector, started) {
+    let op_state = state.op_state.borrow();
+    let op_name = op_state.op_table.op_name(op_id).unwrap_or_default();
+    detector.check_sync(op_id, op_name, started.elapsed());
+  }
   match op {
     Op::Sync(result) => {
       state.op_state.borrow().tracker.track_sync(op_id);
@@ -528,10 +675,34 @@ fn opcall_async<'s>(
     }
   };
 
+  if state.shutting_down {
+    throw_error(scope, SHUTTING_DOWN_MSG);
+    return;
+  }
+
+  if let Some(max_pending_ops) = state.max_pending_ops {
+    if state.pending_ops_overflow == PendingOpsOverflow::Throw
+      && state.pending_ops.len() >= max_pending_ops
+    {
+      throw_range_error(
+        scope,
+        format!(
+          "Too many pending async ops, the limit is {}",
+          max_pending_ops
+        ),
+      );
+      return;
+    }
+  }
+
   // Deserializable args (may be structured args or ZeroCopyBuf)
   let a = args.get(2);
   let b = args.get(3);
+  // Set when the op may be canceled with `Deno.core.cancelOp()`.
+  let cancelable = args.get(4).is_true();
 
+  let sanitized = sanitizer::start_op(scope, &state.op_state);
+  let started = state.slow_op_detector.as_ref().map(|_| Instant::now());
   let payload = OpPayload {
     scope,
     a,
@@ -540,18 +711,74 @@ fn opcall_async<'s>(
     promise_id,
   };
   let op = OpTable::route_op(op_id, state.op_state.clone(), payload);
+  if let Some(sanitized) = &sanitized {
+    sanitizer::end_op(&state.op_state, sanitized);
+  }
   match op {
     Op::Sync(result) => match result {
-      OpResult::Ok(_) => throw_type_error(
-        scope,
-        format!("Can not call a sync op [{}] with opAsync()", op_id),
-      ),
+      OpResult::Ok(_) | OpResult::Scalar(_) | OpResult::Scratch => {
+        throw_type_error(
+          scope,
+          format!("Can not call a sync op [{}] with opAsync()", op_id),
+        )
+      }
       OpResult::Err(_) => rv.set(result.to_v8(scope).unwrap()),
     },
-    Op::Async(fut) => {
+    Op::Async(mut fut) => {
       state.op_state.borrow().tracker.track_async(op_id);
-      state.pending_ops.push(fut);
-      state.have_unpolled_ops = true;
+      state.pending_op_ids.insert(promise_id, op_id);
+      if cancelable {
+        let cancel_handle = CancelHandle::new_rc();
+        state
+          .op_cancel_handles
+          .insert(promise_id, cancel_handle.clone());
+        let op_state = state.op_state.clone();
+        fut = OpCall::lazy(async move {
+          match fut.or_cancel(cancel_handle).await {
+            Ok(result) => result,
+            Err(canceled) => {
+              let result =
+                serialize_op_result::<()>(Err(canceled.into()), op_state);
+              (promise_id, op_id, result)
+            }
+          }
+        });
+      }
+      if let Some((stack, _)) = sanitized {
+        let op_state = state.op_state.clone();
+        if let Some(sanitizer) = op_state.borrow_mut().sanitizer.as_mut() {
+          sanitizer.track_op(promise_id, stack.clone());
+        }
+        fut = OpCall::lazy(sanitizer::TrackResources {
+          inner: Box::pin(fut),
+          op_state,
+          stack,
+        });
+      }
+      if let (Some(detector), Some(started)) =
+        (state.slow_op_detector.clone(), started)
+      {
+        let op_name = state
+          .op_state
+          .borrow()
+          .op_table
+          .op_name(op_id)
+          .unwrap_or_default()
+          .to_string();
+        fut = OpCall::lazy(DetectSlowOp {
+          inner: Box::pin(fut),
+          detector,
+          op_id,
+          op_name,
+          started,
+        });
+      }
+      if state.has_pending_ops_capacity() {
+        state.pending_ops.push(fut);
+        state.have_unpolled_ops = true;
+      } else {
+        state.queued_ops.push_back(fut);
+      }
     }
     Op::NotFound => {
       throw_type_error(scope, format!("Unknown op id: {}", op_id));
@@ -603,6 +830,32 @@ fn unref_op<'s>(
   state.unrefed_ops.insert(promise_id);
 }
 
+/// Cancels an async op called with `Deno.core.opAsync()` and a `signal`: its
+/// future is dropped and its promise rejected with a `Canceled` error.
+fn cancel_op<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  args: v8::FunctionCallbackArguments,
+  _rv: v8::ReturnValue,
+) {
+  let state_rc = JsRuntime::state(scope);
+  let mut state = state_rc.borrow_mut();
+
+  let promise_id = match v8::Local::<v8::Integer>::try_from(args.get(0))
+    .map(|l| l.value() as PromiseId)
+    .map_err(Error::from)
+  {
+    Ok(promise_id) => promise_id,
+    Err(err) => {
+      throw_type_error(scope, format!("invalid promise id: {}", err));
+      return;
+    }
+  };
+
+  if let Some(cancel_handle) = state.op_cancel_handles.remove(&promise_id) {
+    cancel_handle.cancel();
+  }
+}
+
 fn has_tick_scheduled(
   scope: &mut v8::HandleScope,
   _args: v8::FunctionCallbackArguments,
@@ -658,6 +911,19 @@ fn set_macrotask_callback(
   }
 }
 
+fn add_before_exit_callback(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  _rv: v8::ReturnValue,
+) {
+  if let Ok(cb) = arg0_to_cb(scope, args) {
+    JsRuntime::state(scope)
+      .borrow_mut()
+      .js_before_exit_cbs
+      .push(cb);
+  }
+}
+
 fn set_promise_reject_callback(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -692,6 +958,159 @@ fn set_uncaught_exception_callback(
   }
 }
 
+fn set_unhandled_rejection_handler(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut rv: v8::ReturnValue,
+) {
+  if let Ok(new) = arg0_to_cb(scope, args) {
+    if let Some(old) = JsRuntime::state(scope)
+      .borrow_mut()
+      .js_unhandled_rejection_cb
+      .replace(new)
+    {
+      let old = v8::Local::new(scope, old);
+      rv.set(old.into());
+    }
+  }
+}
+
+fn create_callable(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut rv: v8::ReturnValue,
+) {
+  if let Ok(cb) = arg0_to_cb(scope, args) {
+    let state_rc = JsRuntime::state(scope);
+    let mut state = state_rc.borrow_mut();
+    let id = state.next_js_callable_id;
+    state.next_js_callable_id += 1;
+    state.js_callables.insert(JsCallable(id), cb);
+    rv.set(v8::Integer::new_from_unsigned(scope, id).into());
+  }
+}
+
+/// Creates a context with its own global object, without `Deno.core`, in
+/// which code can be run with `runInContext()`. The own enumerable
+/// properties of the optional first argument are copied onto the new global.
+/// Returns an id for the context, which is retained until `disposeContext()`
+/// is called.
+///
+/// The new global object can't be accessed from the calling context, as the
+/// contexts have different security tokens, so values must be passed in and
+/// out through the sandbox object and return values.
+fn create_context(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut rv: v8::ReturnValue,
+) {
+  let sandbox = args.get(0);
+  let sandbox = if sandbox.is_null_or_undefined() {
+    None
+  } else {
+    match v8::Local::<v8::Object>::try_from(sandbox) {
+      Ok(sandbox) => Some(sandbox),
+      Err(_) => {
+        throw_type_error(scope, "Sandbox must be an object");
+        return;
+      }
+    }
+  };
+
+  let disallow_code_generation_from_strings = JsRuntime::state(scope)
+    .borrow()
+    .disallow_code_generation_from_strings;
+  let context =
+    new_context(scope, false, disallow_code_generation_from_strings);
+  {
+    let scope = &mut v8::ContextScope::new(scope, context);
+    let global = context.global(scope);
+    if let Some(sandbox) = sandbox {
+      let keys = sandbox.get_own_property_names(scope).unwrap();
+      for i in 0..keys.length() {
+        let key = keys.get_index(scope, i).unwrap();
+        if let Some(value) = sandbox.get(scope, key) {
+          global.set(scope, key, value);
+        }
+      }
+    }
+  }
+
+  let state_rc = JsRuntime::state(scope);
+  let mut state = state_rc.borrow_mut();
+  let id = state.next_sub_context_id;
+  state.next_sub_context_id += 1;
+  state
+    .sub_contexts
+    .insert(id, v8::Global::new(scope, context));
+  rv.set(v8::Integer::new_from_unsigned(scope, id).into());
+}
+
+/// Runs a script in a context created with `createContext()` and returns its
+/// completion value. Exceptions, including syntax errors, are rethrown in the
+/// calling context; they are instances of the other context's error classes.
+fn run_in_context<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  args: v8::FunctionCallbackArguments<'s>,
+  mut rv: v8::ReturnValue,
+) {
+  let source = match v8::Local::<v8::String>::try_from(args.get(0)) {
+    Ok(s) => s,
+    Err(_) => {
+      throw_type_error(scope, "Missing first argument");
+      return;
+    }
+  };
+  let context = v8::Local::<v8::Integer>::try_from(args.get(1))
+    .ok()
+    .and_then(|id| {
+      let state_rc = JsRuntime::state(scope);
+      let state = state_rc.borrow();
+      state.sub_contexts.get(&(id.value() as u32)).cloned()
+    });
+  let context = match context {
+    Some(context) => v8::Local::new(scope, context),
+    None => {
+      throw_type_error(scope, "Invalid or disposed context id");
+      return;
+    }
+  };
+  let name = match v8::Local::<v8::String>::try_from(args.get(2)) {
+    Ok(name) => name,
+    Err(_) => v8::String::new(scope, crate::DUMMY_SPECIFIER).unwrap(),
+  };
+
+  let scope = &mut v8::ContextScope::new(scope, context);
+  let tc_scope = &mut v8::TryCatch::new(scope);
+  let origin = script_origin(tc_scope, name);
+  let result = v8::Script::compile(tc_scope, source, Some(&origin))
+    .and_then(|script| script.run(tc_scope));
+  match result {
+    Some(value) => rv.set(value),
+    None => {
+      tc_scope.rethrow();
+    }
+  }
+}
+
+/// Drops the runtime's reference to a context created with `createContext()`.
+/// Returns false if it was already disposed.
+fn dispose_context(
+  scope: &mut v8::HandleScope,
+  args: v8::FunctionCallbackArguments,
+  mut rv: v8::ReturnValue,
+) {
+  let removed = match v8::Local::<v8::Integer>::try_from(args.get(0)) {
+    Ok(id) => JsRuntime::state(scope)
+      .borrow_mut()
+      .sub_contexts
+      .remove(&(id.value() as u32))
+      .is_some(),
+    Err(_) => false,
+  };
+  rv.set(v8::Boolean::new(scope, removed).into());
+}
+
 fn arg0_to_cb(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -714,15 +1133,43 @@ fn eval_context(
     }
   };
 
-  let url = match v8::Local::<v8::String>::try_from(args.get(1)) {
-    Ok(s) => match resolve_url_or_path(&s.to_rust_string_lossy(scope)) {
+  // The second argument is either the filename or an options bag.
+  #[derive(Default, Deserialize)]
+  #[serde(default, rename_all = "camelCase")]
+  struct EvalContextOptions {
+    filename: Option<String>,
+    line_offset: i32,
+    column_offset: i32,
+    source_map_url: Option<String>,
+  }
+
+  let options_arg = args.get(1);
+  let options = if let Ok(s) = v8::Local::<v8::String>::try_from(options_arg) {
+    EvalContextOptions {
+      filename: Some(s.to_rust_string_lossy(scope)),
+      ..Default::default()
+    }
+  } else if options_arg.is_object() {
+    match serde_v8::from_v8(scope, options_arg) {
+      Ok(options) => options,
+      Err(err) => {
+        throw_type_error(scope, &format!("Invalid options: {}", err));
+        return;
+      }
+    }
+  } else {
+    EvalContextOptions::default()
+  };
+
+  let url = match options.filename {
+    Some(filename) => match resolve_url_or_path(&filename) {
       Ok(s) => Some(s),
       Err(err) => {
         throw_type_error(scope, &format!("Invalid specifier: {}", err));
         return;
       }
     },
-    Err(_) => None,
+    None => None,
   };
 
   #[derive(Serialize)]
@@ -742,7 +1189,13 @@ fn eval_context(
     url.as_ref().map_or(crate::DUMMY_SPECIFIER, Url::as_str),
   )
   .unwrap();
-  let origin = script_origin(tc_scope, name);
+  let origin = script_origin_at(
+    tc_scope,
+    name,
+    options.line_offset,
+    options.column_offset,
+    options.source_map_url.as_deref().unwrap_or_default(),
+  );
   let maybe_script = v8::Script::compile(tc_scope, source, Some(&origin));
 
   if maybe_script.is_none() {
@@ -891,12 +1344,78 @@ fn encode(
       return;
     }
   };
-  let text_str = text.to_rust_string_lossy(scope);
-  let zbuf: ZeroCopyBuf = text_str.into_bytes().into();
+  let encoding = match get_encoding(scope, args.get(1)) {
+    Some(encoding) => encoding,
+    None => return,
+  };
+
+  let bytes = match encoding {
+    Encoding::Utf8 => {
+      // Fast path: an ASCII string is its own UTF-8 encoding.
+      let mut bytes = None;
+      if text.contains_only_onebyte() {
+        let mut buf = vec![0; text.length()];
+        write_one_byte(scope, text, &mut buf);
+        if buf.is_ascii() {
+          bytes = Some(buf);
+        }
+      }
+      bytes.unwrap_or_else(|| text.to_rust_string_lossy(scope).into_bytes())
+    }
+    Encoding::Latin1 => {
+      // Like Node.js, code units above U+00FF are truncated to their low byte.
+      let mut buf = vec![0; text.length()];
+      write_one_byte(scope, text, &mut buf);
+      buf
+    }
+    Encoding::Utf16Le => {
+      let mut units = vec![0; text.length()];
+      text.write(scope, &mut units, 0, v8::WriteOptions::NO_NULL_TERMINATION);
+      units.iter().flat_map(|unit| unit.to_le_bytes()).collect()
+    }
+  };
+  let zbuf: ZeroCopyBuf = bytes.into();
 
   rv.set(to_v8(scope, zbuf).unwrap())
 }
 
+/// Text encodings supported by `Deno.core.encode()` and `Deno.core.decode()`.
+#[derive(Clone, Copy)]
+enum Encoding {
+  Utf8,
+  Latin1,
+  Utf16Le,
+}
+
+/// Parses an optional encoding label, throwing a `TypeError` and returning
+/// `None` if it isn't supported.
+fn get_encoding(
+  scope: &mut v8::HandleScope,
+  label: v8::Local<v8::Value>,
+) -> Option<Encoding> {
+  if label.is_undefined() {
+    return Some(Encoding::Utf8);
+  }
+  let label = label.to_rust_string_lossy(scope).to_ascii_lowercase();
+  match label.as_str() {
+    "utf-8" | "utf8" => Some(Encoding::Utf8),
+    "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+    "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+    _ => {
+      throw_type_error(scope, format!("Unsupported encoding: {}", label));
+      None
+    }
+  }
+}
+
+fn write_one_byte(
+  scope: &mut v8::HandleScope,
+  text: v8::Local<v8::String>,
+  buf: &mut [u8],
+) {
+  text.write_one_byte(scope, buf, 0, v8::WriteOptions::NO_NULL_TERMINATION);
+}
+
 fn decode(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -910,16 +1429,12 @@ fn decode(
     }
   };
   let buf = &zero_copy;
+  let encoding = match get_encoding(scope, args.get(1)) {
+    Some(encoding) => encoding,
+    None => return,
+  };
 
-  // Strip BOM
-  let buf =
-    if buf.len() >= 3 && buf[0] == 0xef && buf[1] == 0xbb && buf[2] == 0xbf {
-      &buf[3..]
-    } else {
-      buf
-    };
-
-  // If `String::new_from_utf8()` returns `None`, this means that the
+  // If creating the string returns `None`, this means that the
   // length of the decoded string would be longer than what V8 can
   // handle. In this case we return `RangeError`.
   //
@@ -927,7 +1442,45 @@ fn decode(
   // - https://encoding.spec.whatwg.org/#dom-textdecoder-decode
   // - https://github.com/denoland/deno/issues/6649
   // - https://github.com/v8/v8/blob/d68fb4733e39525f9ff0a9222107c02c28096e2a/include/v8.h#L3277-L3278
-  match v8::String::new_from_utf8(scope, buf, v8::NewStringType::Normal) {
+  let maybe_text = match encoding {
+    Encoding::Utf8 => {
+      // Strip BOM
+      let buf =
+        if buf.len() >= 3 && buf[0] == 0xef && buf[1] == 0xbb && buf[2] == 0xbf
+        {
+          &buf[3..]
+        } else {
+          buf
+        };
+      // Fast path: ASCII is copied into a one-byte string as is, skipping
+      // UTF-8 decoding.
+      if buf.is_ascii() {
+        v8::String::new_from_one_byte(scope, buf, v8::NewStringType::Normal)
+      } else {
+        v8::String::new_from_utf8(scope, buf, v8::NewStringType::Normal)
+      }
+    }
+    Encoding::Latin1 => {
+      v8::String::new_from_one_byte(scope, buf, v8::NewStringType::Normal)
+    }
+    Encoding::Utf16Le => {
+      if buf.len() % 2 != 0 {
+        throw_type_error(scope, "UTF-16 input must have an even length");
+        return;
+      }
+      let mut units: Vec<u16> = buf
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect();
+      // Strip BOM
+      if units.first() == Some(&0xfeff) {
+        units.remove(0);
+      }
+      v8::String::new_from_two_byte(scope, &units, v8::NewStringType::Normal)
+    }
+  };
+
+  match maybe_text {
     Some(text) => rv.set(text.into()),
     None => {
       let msg = v8::String::new(scope, "string too long").unwrap();
@@ -939,6 +1492,19 @@ fn decode(
 
 struct SerializeDeserialize<'a> {
   host_objects: Option<v8::Local<'a, v8::Array>>,
+  /// Set when the serialized bytes may outlive the process, see
+  /// `serialize_persistent()`. SharedArrayBuffers and WebAssembly modules are
+  /// only passed around in memory, so they can't be serialized.
+  persistent: bool,
+}
+
+impl<'a> SerializeDeserialize<'a> {
+  fn throw_not_persistable(&self, scope: &mut v8::HandleScope, what: &str) {
+    let message = format!("{} can't be serialized persistently", what);
+    let message = v8::String::new(scope, &message).unwrap();
+    let error = v8::Exception::error(scope, message);
+    scope.throw_exception(error);
+  }
 }
 
 impl<'a> v8::ValueSerializerImpl for SerializeDeserialize<'a> {
@@ -957,6 +1523,10 @@ impl<'a> v8::ValueSerializerImpl for SerializeDeserialize<'a> {
     scope: &mut HandleScope<'s>,
     shared_array_buffer: Local<'s, SharedArrayBuffer>,
   ) -> Option<u32> {
+    if self.persistent {
+      self.throw_not_persistable(scope, "SharedArrayBuffers");
+      return None;
+    }
     let state_rc = JsRuntime::state(scope);
     let state = state_rc.borrow_mut();
     if let Some(shared_array_buffer_store) = &state.shared_array_buffer_store {
@@ -973,6 +1543,10 @@ impl<'a> v8::ValueSerializerImpl for SerializeDeserialize<'a> {
     scope: &mut HandleScope<'_>,
     module: Local<v8::WasmModuleObject>,
   ) -> Option<u32> {
+    if self.persistent {
+      self.throw_not_persistable(scope, "WebAssembly modules");
+      return None;
+    }
     let state_rc = JsRuntime::state(scope);
     let state = state_rc.borrow_mut();
     if let Some(compiled_wasm_module_store) = &state.compiled_wasm_module_store
@@ -1012,6 +1586,10 @@ impl<'a> v8::ValueDeserializerImpl for SerializeDeserialize<'a> {
     scope: &mut HandleScope<'s>,
     transfer_id: u32,
   ) -> Option<Local<'s, SharedArrayBuffer>> {
+    if self.persistent {
+      self.throw_not_persistable(scope, "SharedArrayBuffers");
+      return None;
+    }
     let state_rc = JsRuntime::state(scope);
     let state = state_rc.borrow_mut();
     if let Some(shared_array_buffer_store) = &state.shared_array_buffer_store {
@@ -1029,6 +1607,10 @@ impl<'a> v8::ValueDeserializerImpl for SerializeDeserialize<'a> {
     scope: &mut HandleScope<'s>,
     clone_id: u32,
   ) -> Option<Local<'s, v8::WasmModuleObject>> {
+    if self.persistent {
+      self.throw_not_persistable(scope, "WebAssembly modules");
+      return None;
+    }
     let state_rc = JsRuntime::state(scope);
     let state = state_rc.borrow_mut();
     if let Some(compiled_wasm_module_store) = &state.compiled_wasm_module_store
@@ -1107,7 +1689,10 @@ fn serialize(
     None => None,
   };
 
-  let serialize_deserialize = Box::new(SerializeDeserialize { host_objects });
+  let serialize_deserialize = Box::new(SerializeDeserialize {
+    host_objects,
+    persistent: false,
+  });
   let mut value_serializer =
     v8::ValueSerializer::new(scope, serialize_deserialize);
 
@@ -1219,7 +1804,10 @@ fn deserialize(
     None => None,
   };
 
-  let serialize_deserialize = Box::new(SerializeDeserialize { host_objects });
+  let serialize_deserialize = Box::new(SerializeDeserialize {
+    host_objects,
+    persistent: false,
+  });
   let mut value_deserializer =
     v8::ValueDeserializer::new(scope, serialize_deserialize, &zero_copy);
 
@@ -1278,6 +1866,47 @@ fn deserialize(
   };
 }
 
+/// Serializes `value` into bytes that can be stored and later deserialized
+/// with `deserialize_persistent()`, possibly in another process. Returns None,
+/// possibly with an exception thrown, if the value can't be serialized.
+pub(crate) fn serialize_persistent(
+  scope: &mut v8::HandleScope,
+  value: v8::Local<v8::Value>,
+) -> Option<Vec<u8>> {
+  let serialize_deserialize = Box::new(SerializeDeserialize {
+    host_objects: None,
+    persistent: true,
+  });
+  let mut value_serializer =
+    v8::ValueSerializer::new(scope, serialize_deserialize);
+  value_serializer.write_header();
+  match value_serializer.write_value(scope.get_current_context(), value) {
+    Some(true) => Some(value_serializer.release()),
+    _ => None,
+  }
+}
+
+/// Returns None, possibly with an exception thrown, if `bytes` can't be
+/// deserialized.
+pub(crate) fn deserialize_persistent<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  bytes: &[u8],
+) -> Option<v8::Local<'s, v8::Value>> {
+  let serialize_deserialize = Box::new(SerializeDeserialize {
+    host_objects: None,
+    persistent: true,
+  });
+  let mut value_deserializer =
+    v8::ValueDeserializer::new(scope, serialize_deserialize, bytes);
+  let parsed_header = value_deserializer
+    .read_header(scope.get_current_context())
+    .unwrap_or_default();
+  if !parsed_header {
+    return None;
+  }
+  value_deserializer.read_value(scope.get_current_context())
+}
+
 fn queue_microtask(
   scope: &mut v8::HandleScope,
   args: v8::FunctionCallbackArguments,
@@ -1430,12 +2059,24 @@ fn is_proxy(
   rv.set(v8::Boolean::new(scope, args.get(0).is_proxy()).into())
 }
 
+fn throw_error(scope: &mut v8::HandleScope, message: impl AsRef<str>) {
+  let message = v8::String::new(scope, message.as_ref()).unwrap();
+  let exception = v8::Exception::error(scope, message);
+  scope.throw_exception(exception);
+}
+
 fn throw_type_error(scope: &mut v8::HandleScope, message: impl AsRef<str>) {
   let message = v8::String::new(scope, message.as_ref()).unwrap();
   let exception = v8::Exception::type_error(scope, message);
   scope.throw_exception(exception);
 }
 
+fn throw_range_error(scope: &mut v8::HandleScope, message: impl AsRef<str>) {
+  let message = v8::String::new(scope, message.as_ref()).unwrap();
+  let exception = v8::Exception::range_error(scope, message);
+  scope.throw_exception(exception);
+}
+
 fn memory_usage(
   scope: &mut v8::HandleScope,
   _args: v8::FunctionCallbackArguments,
@@ -0,0 +1,242 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use futures::channel::mpsc;
+use futures::stream::StreamExt;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+  static ref GLOBAL_BROADCAST_BUS: BroadcastBus = BroadcastBus::new();
+}
+
+/// A publish/subscribe bus for small messages between the runtimes of a
+/// process, and between runtimes and the embedder. A message published to a
+/// topic is delivered to every other subscription to that topic, in the
+/// order messages were published. Clones share their subscriptions.
+///
+/// JavaScript code reaches the bus passed as `RuntimeOptions::broadcast_bus`
+/// through `Deno.core.broadcastSubscribe(topic)`.
+///
+/// Messages are queued for each subscription until they are received, up to
+/// the bus' capacity (`DEFAULT_BROADCAST_CAPACITY` unless created with
+/// `BroadcastBus::with_capacity()`). Messages published while a subscription's
+/// queue is full are dropped for that subscription, so a subscription that is
+/// never read from can't grow without bound, but should still be dropped.
+#[derive(Clone, Default)]
+pub struct BroadcastBus {
+  inner: Arc<Mutex<BroadcastBusInner>>,
+}
+
+/// The number of messages queued for a subscription before further messages
+/// are dropped for it, see `BroadcastBus`.
+pub const DEFAULT_BROADCAST_CAPACITY: usize = 1024;
+
+type Subscriber = (u64, mpsc::Sender<Arc<[u8]>>);
+
+struct BroadcastBusInner {
+  topics: HashMap<String, Vec<Subscriber>>,
+  next_subscription_id: u64,
+  capacity: usize,
+}
+
+impl Default for BroadcastBusInner {
+  fn default() -> Self {
+    Self {
+      topics: HashMap::new(),
+      next_subscription_id: 0,
+      capacity: DEFAULT_BROADCAST_CAPACITY,
+    }
+  }
+}
+
+impl BroadcastBus {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Creates a bus queueing at most `capacity` messages per subscription.
+  /// Panics if `capacity` is 0.
+  pub fn with_capacity(capacity: usize) -> Self {
+    assert!(capacity > 0, "BroadcastBus capacity must be at least 1");
+    let inner = BroadcastBusInner {
+      capacity,
+      ..Default::default()
+    };
+    Self {
+      inner: Arc::new(Mutex::new(inner)),
+    }
+  }
+
+  /// Returns the process-wide bus.
+  pub fn global() -> Self {
+    GLOBAL_BROADCAST_BUS.clone()
+  }
+
+  pub fn subscribe(&self, topic: &str) -> BroadcastSubscription {
+    let mut inner = self.inner.lock().unwrap();
+    // The channel holds one more message than its buffer per sender, and
+    // the bus holds the only sender.
+    let (sender, receiver) = mpsc::channel(inner.capacity - 1);
+    let id = inner.next_subscription_id;
+    inner.next_subscription_id += 1;
+    inner
+      .topics
+      .entry(topic.to_string())
+      .or_default()
+      .push((id, sender));
+    BroadcastSubscription {
+      bus: self.clone(),
+      topic: topic.to_string(),
+      id,
+      receiver,
+    }
+  }
+
+  /// Publishes a message to all subscriptions to `topic`. Returns the number
+  /// of subscriptions it was delivered to, which excludes subscriptions whose
+  /// queue is full.
+  pub fn publish(&self, topic: &str, data: &[u8]) -> usize {
+    self.send(topic, None, data.into())
+  }
+
+  fn send(&self, topic: &str, from: Option<u64>, data: Arc<[u8]>) -> usize {
+    let mut inner = self.inner.lock().unwrap();
+    let subscriptions = match inner.topics.get_mut(topic) {
+      Some(subscriptions) => subscriptions,
+      None => return 0,
+    };
+    subscriptions
+      .iter_mut()
+      .filter(|(id, _)| Some(*id) != from)
+      .filter_map(|(_, sender)| sender.try_send(data.clone()).ok())
+      .count()
+  }
+
+  fn unsubscribe(&self, topic: &str, id: u64) {
+    let mut inner = self.inner.lock().unwrap();
+    if let Some(subscriptions) = inner.topics.get_mut(topic) {
+      subscriptions.retain(|(subscription_id, _)| *subscription_id != id);
+      if subscriptions.is_empty() {
+        inner.topics.remove(topic);
+      }
+    }
+  }
+}
+
+/// A subscription to a topic of a `BroadcastBus`, see
+/// `BroadcastBus::subscribe()`. Unsubscribes when dropped.
+pub struct BroadcastSubscription {
+  bus: BroadcastBus,
+  topic: String,
+  id: u64,
+  receiver: mpsc::Receiver<Arc<[u8]>>,
+}
+
+impl BroadcastSubscription {
+  pub fn topic(&self) -> &str {
+    &self.topic
+  }
+
+  /// Publishes a message to the other subscriptions to the topic. Returns the
+  /// number of subscriptions it was delivered to.
+  pub fn publish(&self, data: &[u8]) -> usize {
+    self.publisher().publish(data)
+  }
+
+  /// Returns a handle publishing as this subscription, which can be used
+  /// while a `recv()` is pending.
+  pub(crate) fn publisher(&self) -> BroadcastPublisher {
+    BroadcastPublisher {
+      bus: self.bus.clone(),
+      topic: self.topic.clone(),
+      id: self.id,
+    }
+  }
+
+  /// Resolves with the next message published to the topic.
+  pub async fn recv(&mut self) -> Arc<[u8]> {
+    // The bus holds the sender until this subscription is dropped.
+    self.receiver.next().await.unwrap()
+  }
+
+  /// Returns the next message if one has already been published.
+  pub fn try_recv(&mut self) -> Option<Arc<[u8]>> {
+    self.receiver.try_next().ok().flatten()
+  }
+}
+
+impl Drop for BroadcastSubscription {
+  fn drop(&mut self) {
+    self.bus.unsubscribe(&self.topic, self.id);
+  }
+}
+
+pub(crate) struct BroadcastPublisher {
+  bus: BroadcastBus,
+  topic: String,
+  id: u64,
+}
+
+impl BroadcastPublisher {
+  pub fn publish(&self, data: &[u8]) -> usize {
+    self.bus.send(&self.topic, Some(self.id), data.into())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_broadcast_bus() {
+    let bus = BroadcastBus::new();
+    let mut a = bus.subscribe("topic");
+    let mut b = bus.subscribe("topic");
+    let mut other = bus.subscribe("other");
+
+    assert_eq!(a.publish(b"hello"), 1);
+    assert_eq!(bus.publish("topic", b"world"), 2);
+    assert_eq!(&*a.try_recv().unwrap(), b"world");
+    assert_eq!(a.try_recv(), None);
+    assert_eq!(&*futures::executor::block_on(b.recv()), b"hello");
+    assert_eq!(&*b.try_recv().unwrap(), b"world");
+    assert_eq!(other.try_recv(), None);
+
+    drop(b);
+    assert_eq!(bus.publish("topic", b"!"), 1);
+    drop(a);
+    assert_eq!(bus.publish("topic", b"!"), 0);
+    assert!(!bus.inner.lock().unwrap().topics.contains_key("topic"));
+
+    let mut global = BroadcastBus::global().subscribe("test_broadcast_bus");
+    assert_eq!(
+      BroadcastBus::global().publish("test_broadcast_bus", b"!"),
+      1
+    );
+    assert_eq!(&*global.try_recv().unwrap(), b"!");
+  }
+
+  #[test]
+  fn test_broadcast_bus_capacity() {
+    let bus = BroadcastBus::with_capacity(2);
+    let mut a = bus.subscribe("topic");
+    let mut b = bus.subscribe("topic");
+
+    assert_eq!(bus.publish("topic", b"1"), 2);
+    assert_eq!(bus.publish("topic", b"2"), 2);
+    assert_eq!(&*b.try_recv().unwrap(), b"1");
+    // `a` is full, so only `b` gets the message.
+    assert_eq!(bus.publish("topic", b"3"), 1);
+
+    assert_eq!(&*a.try_recv().unwrap(), b"1");
+    assert_eq!(&*a.try_recv().unwrap(), b"2");
+    assert_eq!(a.try_recv(), None);
+    // `b` is full again.
+    assert_eq!(bus.publish("topic", b"4"), 1);
+    assert_eq!(&*a.try_recv().unwrap(), b"4");
+    assert_eq!(&*b.try_recv().unwrap(), b"2");
+    assert_eq!(&*b.try_recv().unwrap(), b"3");
+    assert_eq!(b.try_recv(), None);
+  }
+}
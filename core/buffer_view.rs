@@ -0,0 +1,292 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::generic_error;
+use crate::error::type_error;
+use anyhow::Error;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde_v8::BufferSource;
+use std::cell::RefCell;
+use std::ops::Deref;
+use std::ops::DerefMut;
+use std::ptr::NonNull;
+use std::slice;
+
+thread_local! {
+  /// Byte ranges currently borrowed through a `BufferRef` or `BufferRefMut`,
+  /// as `(start, end, mutable)` addresses.
+  static BORROWED_RANGES: RefCell<Vec<(usize, usize, bool)>> =
+    RefCell::new(Vec::new());
+}
+
+/// A view of the bytes of an `ArrayBuffer` or an `ArrayBufferView` (a typed
+/// array or a `DataView`) passed in from JavaScript.
+///
+/// The view keeps the backing store alive, so its memory stays valid even if
+/// the buffer is detached by JavaScript. The bytes are accessed through
+/// `borrow()` and `borrow_mut()`, which enforce Rust's aliasing rules across
+/// all views of the same memory on the current thread, like a `RefCell`.
+/// JavaScript can still modify the bytes if it runs while a borrow is held,
+/// eg. because a function is called from Rust or an async op is awaiting.
+///
+/// Ops can take a `BufferView` as an argument, it deserializes through
+/// `serde_v8::BufferSource`.
+///
+/// Buffers backed by a `SharedArrayBuffer` are rejected, because other threads
+/// can modify their contents at any time.
+pub struct BufferView {
+  backing_store: v8::SharedRef<v8::BackingStore>,
+  byte_offset: usize,
+  byte_length: usize,
+}
+
+impl BufferView {
+  pub fn new(
+    scope: &mut v8::HandleScope,
+    value: v8::Local<v8::Value>,
+  ) -> Result<Self, Error> {
+    let source = BufferSource::from_v8(scope, value).ok_or_else(|| {
+      type_error("Expected an ArrayBuffer or ArrayBufferView")
+    })?;
+    Self::from_source(source)
+  }
+
+  fn from_source(source: BufferSource) -> Result<Self, Error> {
+    if source.backing_store.is_shared() {
+      return Err(type_error(
+        "Buffers backed by a SharedArrayBuffer are not supported",
+      ));
+    }
+    Ok(Self {
+      backing_store: source.backing_store,
+      byte_offset: source.byte_offset,
+      byte_length: source.byte_length,
+    })
+  }
+
+  pub fn len(&self) -> usize {
+    self.byte_length
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.byte_length == 0
+  }
+
+  /// Borrows the bytes immutably. Fails if any part of them is mutably
+  /// borrowed through another view.
+  pub fn borrow(&self) -> Result<BufferRef<'_>, Error> {
+    let range = self.acquire(false)?;
+    Ok(BufferRef {
+      bytes: unsafe { slice::from_raw_parts(self.data(), self.byte_length) },
+      _range: range,
+    })
+  }
+
+  /// Borrows the bytes mutably. Fails if any part of them is borrowed through
+  /// another view.
+  pub fn borrow_mut(&mut self) -> Result<BufferRefMut<'_>, Error> {
+    let range = self.acquire(true)?;
+    Ok(BufferRefMut {
+      bytes: unsafe {
+        slice::from_raw_parts_mut(self.data(), self.byte_length)
+      },
+      _range: range,
+    })
+  }
+
+  /// A pointer to the first byte of the view. Dangling if the view is empty,
+  /// as the backing store may have no data.
+  fn data(&self) -> *mut u8 {
+    match self.backing_store.data() {
+      Some(data) if self.byte_length > 0 => unsafe {
+        (data.as_ptr() as *mut u8).add(self.byte_offset)
+      },
+      _ => NonNull::dangling().as_ptr(),
+    }
+  }
+
+  fn acquire(&self, mutable: bool) -> Result<BorrowedRange, Error> {
+    let start = self.data() as usize;
+    let range = (start, start + self.byte_length, mutable);
+    if self.byte_length == 0 {
+      // Empty ranges can't alias anything.
+      return Ok(BorrowedRange(None));
+    }
+    BORROWED_RANGES.with(|borrowed| {
+      let mut borrowed = borrowed.borrow_mut();
+      let conflict =
+        borrowed.iter().any(|(other_start, other_end, other_mut)| {
+          (mutable || *other_mut)
+            && range.0 < *other_end
+            && *other_start < range.1
+        });
+      if conflict {
+        return Err(generic_error("Buffer is already borrowed"));
+      }
+      borrowed.push(range);
+      Ok(BorrowedRange(Some(range)))
+    })
+  }
+}
+
+impl<'de> Deserialize<'de> for BufferView {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    let source = BufferSource::deserialize(deserializer)?;
+    Self::from_source(source).map_err(serde::de::Error::custom)
+  }
+}
+
+/// Releases its entry in `BORROWED_RANGES` when dropped.
+struct BorrowedRange(Option<(usize, usize, bool)>);
+
+impl Drop for BorrowedRange {
+  fn drop(&mut self) {
+    if let Some(range) = self.0 {
+      BORROWED_RANGES.with(|borrowed| {
+        let mut borrowed = borrowed.borrow_mut();
+        if let Some(index) = borrowed.iter().position(|r| *r == range) {
+          borrowed.swap_remove(index);
+        }
+      });
+    }
+  }
+}
+
+/// An immutable borrow of the bytes of a `BufferView`.
+pub struct BufferRef<'a> {
+  bytes: &'a [u8],
+  _range: BorrowedRange,
+}
+
+impl Deref for BufferRef<'_> {
+  type Target = [u8];
+  fn deref(&self) -> &[u8] {
+    self.bytes
+  }
+}
+
+/// A mutable borrow of the bytes of a `BufferView`.
+pub struct BufferRefMut<'a> {
+  bytes: &'a mut [u8],
+  _range: BorrowedRange,
+}
+
+impl Deref for BufferRefMut<'_> {
+  type Target = [u8];
+  fn deref(&self) -> &[u8] {
+    self.bytes
+  }
+}
+
+impl DerefMut for BufferRefMut<'_> {
+  fn deref_mut(&mut self) -> &mut [u8] {
+    self.bytes
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::op_async;
+  use crate::op_sync;
+  use crate::JsRuntime;
+  use crate::OpState;
+  use std::rc::Rc;
+
+  #[test]
+  fn test_buffer_view() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let values = runtime
+      .execute_script(
+        "buffers.js",
+        r#"
+        const buffer = new ArrayBuffer(8);
+        new Uint8Array(buffer).set([0, 1, 2, 3, 4, 5, 6, 7]);
+        globalThis.buffer = buffer;
+        [buffer, new Uint8Array(buffer, 2, 4), new DataView(buffer, 4)]
+        "#,
+      )
+      .unwrap();
+    let scope = &mut runtime.handle_scope();
+    let values = v8::Local::new(scope, values);
+    let values = v8::Local::<v8::Array>::try_from(values).unwrap();
+    let buffer = values.get_index(scope, 0).unwrap();
+    let uint8_array = values.get_index(scope, 1).unwrap();
+    let data_view = values.get_index(scope, 2).unwrap();
+
+    let buffer = BufferView::new(scope, buffer).unwrap();
+    let mut uint8_array = BufferView::new(scope, uint8_array).unwrap();
+    let data_view = BufferView::new(scope, data_view).unwrap();
+    assert_eq!(&*buffer.borrow().unwrap(), &[0, 1, 2, 3, 4, 5, 6, 7]);
+    assert_eq!(&*data_view.borrow().unwrap(), &[4, 5, 6, 7]);
+
+    {
+      let mut bytes = uint8_array.borrow_mut().unwrap();
+      bytes[0] = 42;
+      // Overlapping borrows are rejected while the mutable borrow is held.
+      assert!(buffer.borrow().is_err());
+      assert!(data_view.borrow().is_err());
+    }
+    let whole = buffer.borrow().unwrap();
+    assert_eq!(whole[2], 42);
+    // Shared borrows can overlap.
+    assert!(data_view.borrow().is_ok());
+    assert!(uint8_array.borrow_mut().is_err());
+    drop(whole);
+    assert!(uint8_array.borrow_mut().is_ok());
+
+    let number = v8::Number::new(scope, 1.0).into();
+    assert!(BufferView::new(scope, number).is_err());
+  }
+
+  #[tokio::test]
+  async fn test_buffer_view_op_args() {
+    fn op_fill(
+      _: &mut OpState,
+      mut view: BufferView,
+      value: u8,
+    ) -> Result<usize, Error> {
+      view.borrow_mut()?.fill(value);
+      Ok(view.len())
+    }
+
+    async fn op_sum(
+      _: Rc<RefCell<OpState>>,
+      view: BufferView,
+      _: (),
+    ) -> Result<u32, Error> {
+      Ok(view.borrow()?.iter().map(|byte| *byte as u32).sum())
+    }
+
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime.register_op("op_fill", op_sync(op_fill));
+    runtime.register_op("op_sum", op_async(op_sum));
+    runtime.sync_ops_cache();
+    let promise = runtime
+      .execute_script(
+        "buffer_view_ops.js",
+        r#"
+        const buffer = new ArrayBuffer(4);
+        if (Deno.core.opSync("op_fill", buffer, 7) !== 4) {
+          throw new Error("unexpected length");
+        }
+        Deno.core.opSync("op_fill", new Uint8Array(buffer).subarray(2), 1);
+        try {
+          Deno.core.opSync("op_fill", new SharedArrayBuffer(1), 0);
+          throw new Error("shared buffers should be rejected");
+        } catch (err) {
+          if (!(err instanceof TypeError)) throw err;
+        }
+        Deno.core.opAsync("op_sum", new DataView(buffer))
+        "#,
+      )
+      .unwrap();
+    let sum = runtime.resolve_value(promise).await.unwrap();
+    let scope = &mut runtime.handle_scope();
+    let sum = v8::Local::new(scope, sum);
+    assert_eq!(sum.integer_value(scope), Some(16));
+  }
+}
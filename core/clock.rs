@@ -0,0 +1,145 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use futures::channel::oneshot;
+use futures::future::BoxFuture;
+use futures::future::FutureExt;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Poll;
+use std::task::Waker;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The source of time for ops that implement timers and timeouts, available
+/// as `OpState::clock`. Runtimes use a `RealClock` unless another clock is
+/// passed as `RuntimeOptions::clock`, eg. a `ManualClock` so tests of
+/// timer-heavy scripts can fast-forward time instead of waiting.
+pub trait Clock: Send + Sync {
+  fn now(&self) -> Instant;
+
+  /// Resolves once `now()` has reached `deadline`.
+  fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()>;
+}
+
+/// The system's monotonic clock. Each sleep is backed by a thread, so
+/// embedders running a timer-heavy workload under an async runtime should
+/// implement `Clock` with that runtime's timers instead.
+#[derive(Clone, Copy, Default)]
+pub struct RealClock;
+
+impl Clock for RealClock {
+  fn now(&self) -> Instant {
+    Instant::now()
+  }
+
+  fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+    let now = Instant::now();
+    if deadline <= now {
+      return futures::future::ready(()).boxed();
+    }
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+      thread::sleep(deadline - now);
+      let _ = tx.send(());
+    });
+    rx.map(|_| ()).boxed()
+  }
+}
+
+struct ManualClockState {
+  now: Instant,
+  sleepers: Vec<Waker>,
+}
+
+/// A clock that only moves when told to with `advance()`. Clones share the
+/// same time.
+#[derive(Clone)]
+pub struct ManualClock {
+  state: Arc<Mutex<ManualClockState>>,
+}
+
+impl Default for ManualClock {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl ManualClock {
+  /// Creates a clock stopped at the current time.
+  pub fn new() -> Self {
+    Self {
+      state: Arc::new(Mutex::new(ManualClockState {
+        now: Instant::now(),
+        sleepers: Vec::new(),
+      })),
+    }
+  }
+
+  /// Moves the clock forward, waking the sleeps whose deadline has been
+  /// reached.
+  pub fn advance(&self, duration: Duration) {
+    let sleepers = {
+      let mut state = self.state.lock().unwrap();
+      state.now += duration;
+      std::mem::take(&mut state.sleepers)
+    };
+    // Sleeps that are still pending register themselves again when polled.
+    for waker in sleepers {
+      waker.wake();
+    }
+  }
+}
+
+impl Clock for ManualClock {
+  fn now(&self) -> Instant {
+    self.state.lock().unwrap().now
+  }
+
+  fn sleep_until(&self, deadline: Instant) -> BoxFuture<'static, ()> {
+    let state = self.state.clone();
+    futures::future::poll_fn(move |cx| {
+      let mut state = state.lock().unwrap();
+      if state.now >= deadline {
+        Poll::Ready(())
+      } else {
+        state.sleepers.push(cx.waker().clone());
+        Poll::Pending
+      }
+    })
+    .boxed()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use futures::task::noop_waker_ref;
+  use std::task::Context;
+
+  #[test]
+  fn test_manual_clock() {
+    let clock = ManualClock::new();
+    let start = clock.now();
+    let mut sleep = clock.sleep_until(start + Duration::from_secs(60));
+    let mut cx = Context::from_waker(noop_waker_ref());
+    assert!(sleep.poll_unpin(&mut cx).is_pending());
+
+    clock.advance(Duration::from_secs(30));
+    assert_eq!(clock.now() - start, Duration::from_secs(30));
+    assert!(sleep.poll_unpin(&mut cx).is_pending());
+
+    clock.clone().advance(Duration::from_secs(30));
+    assert!(sleep.poll_unpin(&mut cx).is_ready());
+  }
+
+  #[test]
+  fn test_real_clock() {
+    let clock = RealClock;
+    let start = clock.now();
+    futures::executor::block_on(
+      clock.sleep_until(start + Duration::from_millis(10)),
+    );
+    assert!(clock.now() - start >= Duration::from_millis(10));
+  }
+}
@@ -0,0 +1,191 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::disk_cache::hash_parts;
+use crate::disk_cache::write_atomically;
+use anyhow::Error;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+/// Stores V8 code caches for classic scripts and ES modules, so that
+/// compiling the same source again, in this or another runtime, can skip
+/// most of the work. A store is meant to be shared by all runtimes of a
+/// process, see `RuntimeOptions::code_cache_store`.
+///
+/// Entries are looked up by the script's name or module's URL together with
+/// `code_cache_source_hash()` of the source.
+pub trait CodeCacheStore: Send + Sync {
+  /// Returns the code cache stored for the source, if any.
+  fn get(&self, specifier: &str, source_hash: &str) -> Option<Vec<u8>>;
+
+  /// Stores a code cache for the source. Errors are logged and otherwise
+  /// ignored by the runtime.
+  fn put(
+    &self,
+    specifier: &str,
+    source_hash: &str,
+    data: &[u8],
+  ) -> Result<(), Error>;
+}
+
+/// Returns the hash a source's code cache is stored under. It covers the V8
+/// version too, since code caches can't be used across versions.
+pub fn code_cache_source_hash(source: &str) -> String {
+  hash_parts(&[v8::V8::get_version(), source])
+}
+
+lazy_static::lazy_static! {
+  static ref SHARED_MEMORY_CODE_CACHE_STORE: Arc<MemoryCodeCacheStore> =
+    Arc::new(MemoryCodeCacheStore::new());
+}
+
+/// A `CodeCacheStore` keeping entries in memory, for the lifetime of the
+/// process. Entries are keyed by the source hash alone, so the same bundle
+/// loaded under different URLs, eg. one per tenant, shares an entry.
+#[derive(Default)]
+pub struct MemoryCodeCacheStore {
+  entries: Mutex<HashMap<String, Vec<u8>>>,
+  hits: AtomicUsize,
+  misses: AtomicUsize,
+}
+
+/// Counters of a `MemoryCodeCacheStore`, see `MemoryCodeCacheStore::stats()`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CodeCacheStats {
+  pub entries: usize,
+  /// The total size of the stored code caches.
+  pub bytes: usize,
+  pub hits: usize,
+  pub misses: usize,
+}
+
+impl MemoryCodeCacheStore {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Returns the process-wide store, eg. to pass to every runtime of an
+  /// isolate pool so that each new isolate skips compiling the modules and
+  /// scripts the others have already compiled.
+  pub fn shared() -> Arc<Self> {
+    SHARED_MEMORY_CODE_CACHE_STORE.clone()
+  }
+
+  pub fn stats(&self) -> CodeCacheStats {
+    let entries = self.entries.lock().unwrap();
+    CodeCacheStats {
+      entries: entries.len(),
+      bytes: entries.values().map(|data| data.len()).sum(),
+      hits: self.hits.load(Ordering::Relaxed),
+      misses: self.misses.load(Ordering::Relaxed),
+    }
+  }
+}
+
+impl CodeCacheStore for MemoryCodeCacheStore {
+  fn get(&self, _specifier: &str, source_hash: &str) -> Option<Vec<u8>> {
+    let entries = self.entries.lock().unwrap();
+    let data = entries.get(source_hash).cloned();
+    let counter = if data.is_some() {
+      &self.hits
+    } else {
+      &self.misses
+    };
+    counter.fetch_add(1, Ordering::Relaxed);
+    data
+  }
+
+  fn put(
+    &self,
+    _specifier: &str,
+    source_hash: &str,
+    data: &[u8],
+  ) -> Result<(), Error> {
+    let mut entries = self.entries.lock().unwrap();
+    entries.insert(source_hash.to_string(), data.to_vec());
+    Ok(())
+  }
+}
+
+/// A `CodeCacheStore` storing each entry as a file in a directory, so that
+/// code caches survive restarts and can be shared between processes.
+pub struct DiskCodeCacheStore {
+  dir: PathBuf,
+}
+
+impl DiskCodeCacheStore {
+  /// The directory is created when the first entry is stored.
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self { dir: dir.into() }
+  }
+
+  fn path(&self, specifier: &str, source_hash: &str) -> PathBuf {
+    let hash = hash_parts(&[specifier, source_hash]);
+    self.dir.join(format!("{}.bin", hash))
+  }
+}
+
+impl CodeCacheStore for DiskCodeCacheStore {
+  fn get(&self, specifier: &str, source_hash: &str) -> Option<Vec<u8>> {
+    fs::read(self.path(specifier, source_hash)).ok()
+  }
+
+  fn put(
+    &self,
+    specifier: &str,
+    source_hash: &str,
+    data: &[u8],
+  ) -> Result<(), Error> {
+    fs::create_dir_all(&self.dir)?;
+    write_atomically(&self.path(specifier, source_hash), data)?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn disk_code_cache_store() {
+    let dir = std::env::temp_dir()
+      .join(format!("deno_core_code_cache_{}", std::process::id()));
+    let store = DiskCodeCacheStore::new(&dir);
+    let hash = code_cache_source_hash("1 + 1");
+    assert_ne!(hash, code_cache_source_hash("1 + 2"));
+
+    assert_eq!(store.get("file:///a.js", &hash), None);
+    store.put("file:///a.js", &hash, &[1, 2, 3]).unwrap();
+    assert_eq!(store.get("file:///a.js", &hash), Some(vec![1, 2, 3]));
+    assert_eq!(store.get("file:///b.js", &hash), None);
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn memory_code_cache_store() {
+    assert!(Arc::ptr_eq(
+      &MemoryCodeCacheStore::shared(),
+      &MemoryCodeCacheStore::shared()
+    ));
+
+    let store = MemoryCodeCacheStore::new();
+    let hash = code_cache_source_hash("1 + 1");
+    assert_eq!(store.get("file:///a.js", &hash), None);
+    store.put("file:///a.js", &hash, &[1, 2, 3]).unwrap();
+    // Shared by sources with the same hash, whatever their URL.
+    assert_eq!(store.get("file:///b.js", &hash), Some(vec![1, 2, 3]));
+    assert_eq!(
+      store.stats(),
+      CodeCacheStats {
+        entries: 1,
+        bytes: 3,
+        hits: 1,
+        misses: 1,
+      }
+    );
+  }
+}
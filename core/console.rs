@@ -0,0 +1,143 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::include_js_files;
+use crate::op_sync;
+use crate::Extension;
+use crate::OpState;
+use anyhow::Error;
+use serde::Serialize;
+
+/// Defaults of the console installed by `init_console()`. Both can be
+/// overridden for a single value with `Deno.core.inspect(value, options)` or
+/// `console.dir(value, options)`.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ConsoleOptions {
+  /// Objects nested deeper than this are abbreviated, eg. as `[Object]`.
+  pub depth: u32,
+  /// Highlights values with ANSI escape codes.
+  pub colors: bool,
+}
+
+impl Default for ConsoleOptions {
+  fn default() -> Self {
+    Self {
+      depth: 2,
+      colors: false,
+    }
+  }
+}
+
+/// An extension that replaces the global `console` with one that formats
+/// values like Node's `util.inspect()` and writes to `Deno.core.print()`, see
+/// `RuntimeOptions::print_fn`. Calls are also forwarded to V8's console, so
+/// they show up in the inspector. The formatter is available as
+/// `Deno.core.inspect()`.
+pub fn init_console(options: ConsoleOptions) -> Extension {
+  Extension::builder()
+    .js(include_js_files!(
+      prefix "deno:core",
+      "03_console.js",
+    ))
+    .ops(vec![("op_console_options", op_sync(op_console_options))])
+    .state(move |state| {
+      state.put(options);
+      Ok(())
+    })
+    .build()
+}
+
+fn op_console_options(
+  state: &mut OpState,
+  _: (),
+  _: (),
+) -> Result<ConsoleOptions, Error> {
+  Ok(*state.borrow::<ConsoleOptions>())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::JsRuntime;
+  use crate::RuntimeOptions;
+  use std::cell::RefCell;
+  use std::rc::Rc;
+
+  fn run(options: ConsoleOptions, source: &str) -> Vec<(String, bool)> {
+    let output = Rc::new(RefCell::new(Vec::new()));
+    let output_ = output.clone();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      extensions: vec![init_console(options)],
+      print_fn: Some(Rc::new(move |msg: &str, is_err| {
+        output_.borrow_mut().push((msg.to_string(), is_err));
+      })),
+      ..Default::default()
+    });
+    runtime.execute_script("console_test.js", source).unwrap();
+    drop(runtime);
+    Rc::try_unwrap(output).unwrap().into_inner()
+  }
+
+  #[test]
+  fn test_console() {
+    let output = run(
+      Default::default(),
+      r#"
+      const circular = { a: [1, "two", -0, 3n, undefined, null] };
+      circular.self = circular;
+      console.log("%s is %d", "answer", 42, circular);
+      console.error(new Map([["k", { deep: { deeper: { deepest: 1 } } }]]));
+      console.group("group");
+      console.info(new Set([Symbol("s")]), class Foo {}, function () {});
+      console.groupEnd();
+      console.warn(Object.create(null), [1, , 3], new Uint8Array(2));
+      console.assert(true, "not printed");
+      console.assert(false, "printed");
+      "#,
+    );
+    let expected = vec![
+      (
+        r#"answer is 42 { a: [ 1, "two", -0, 3n, undefined, null ], self: [Circular] }"#,
+        false,
+      ),
+      (r#"Map(1) { "k" => { deep: { deeper: [Object] } } }"#, true),
+      ("group", false),
+      (
+        "  Set(1) { Symbol(s) } [class Foo] [Function (anonymous)]",
+        false,
+      ),
+      (
+        "[Object: null prototype] {} [ 1, <empty item>, 3 ] Uint8Array(2) [ 0, 0 ]",
+        true,
+      ),
+      ("Assertion failed: printed", true),
+    ];
+    let expected: Vec<_> = expected
+      .into_iter()
+      .map(|(msg, is_err)| (format!("{}\n", msg), is_err))
+      .collect();
+    assert_eq!(output, expected);
+  }
+
+  #[test]
+  fn test_console_options() {
+    let output = run(
+      ConsoleOptions {
+        depth: 0,
+        colors: true,
+      },
+      r#"
+      console.log({ a: { b: 1 } });
+      console.dir({ a: { b: 1 } }, { depth: 1, colors: false });
+      console.log(Deno.core.inspect("str", { colors: false }));
+      "#,
+    );
+    assert_eq!(
+      output,
+      vec![
+        ("{ a: \x1b[36m[Object]\x1b[39m }\n".to_string(), false),
+        ("{ a: { b: 1 } }\n".to_string(), false),
+        ("\"str\"\n".to_string(), false),
+      ]
+    );
+  }
+}
@@ -0,0 +1,164 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::custom_error;
+use crate::include_js_files;
+use crate::op_sync;
+use crate::Extension;
+use crate::OpState;
+use crate::ZeroCopyBuf;
+use anyhow::Error;
+use rand::rngs::StdRng;
+use rand::thread_rng;
+use rand::Rng;
+use rand::SeedableRng;
+
+/// The most bytes `crypto.getRandomValues()` fills at once, as in browsers.
+const MAX_RANDOM_VALUES_LENGTH: usize = 65536;
+
+/// An extension providing a `crypto` global with `getRandomValues()` and
+/// `randomUUID()`, backed by a cryptographically secure generator. With a
+/// `seed`, the values are deterministic instead, eg. for reproducible tests.
+pub fn init_crypto(seed: Option<u64>) -> Extension {
+  Extension::builder()
+    .js(include_js_files!(
+      prefix "deno:core",
+      "07_crypto.js",
+    ))
+    .ops(vec![
+      (
+        "op_crypto_get_random_values",
+        op_sync(op_crypto_get_random_values),
+      ),
+      ("op_crypto_random_uuid", op_sync(op_crypto_random_uuid)),
+    ])
+    .state(move |state| {
+      if let Some(seed) = seed {
+        state.put(StdRng::seed_from_u64(seed));
+      }
+      Ok(())
+    })
+    .build()
+}
+
+fn fill_random(state: &mut OpState, buf: &mut [u8]) {
+  match state.try_borrow_mut::<StdRng>() {
+    Some(rng) => rng.fill(buf),
+    None => thread_rng().fill(buf),
+  }
+}
+
+fn op_crypto_get_random_values(
+  state: &mut OpState,
+  mut buf: ZeroCopyBuf,
+  _: (),
+) -> Result<(), Error> {
+  if buf.len() > MAX_RANDOM_VALUES_LENGTH {
+    return Err(custom_error(
+      "QuotaExceededError",
+      format!(
+        "The ArrayBufferView's byte length ({}) exceeds the number of bytes of entropy available via this API ({})",
+        buf.len(),
+        MAX_RANDOM_VALUES_LENGTH
+      ),
+    ));
+  }
+  fill_random(state, &mut buf);
+  Ok(())
+}
+
+/// Returns a random (version 4) UUID, eg.
+/// `"36b8f84d-df4e-4d49-b662-bcde71a8764f"`.
+fn op_crypto_random_uuid(
+  state: &mut OpState,
+  _: (),
+  _: (),
+) -> Result<String, Error> {
+  let mut bytes = [0u8; 16];
+  fill_random(state, &mut bytes);
+  bytes[6] = (bytes[6] & 0x0f) | 0x40;
+  bytes[8] = (bytes[8] & 0x3f) | 0x80;
+  let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+  Ok(format!(
+    "{}-{}-{}-{}-{}",
+    &hex[..8],
+    &hex[8..12],
+    &hex[12..16],
+    &hex[16..20],
+    &hex[20..]
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::JsRuntime;
+  use crate::RuntimeOptions;
+
+  #[test]
+  fn test_seeded_random() {
+    let uuids = |seed| {
+      let mut state = OpState::new();
+      state.put(StdRng::seed_from_u64(seed));
+      let a = op_crypto_random_uuid(&mut state, (), ()).unwrap();
+      let b = op_crypto_random_uuid(&mut state, (), ()).unwrap();
+      (a, b)
+    };
+    let (a, b) = uuids(1);
+    assert_ne!(a, b);
+    assert_eq!(uuids(1), (a.clone(), b));
+    assert_ne!(uuids(2).0, a);
+
+    assert_eq!(a.len(), 36);
+    assert_eq!(&a[14..15], "4");
+    assert!(matches!(&a[19..20], "8" | "9" | "a" | "b"));
+  }
+
+  #[test]
+  fn test_crypto() {
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      extensions: vec![init_crypto(None)],
+      ..Default::default()
+    });
+    runtime
+      .execute_script(
+        "crypto_test.js",
+        r#"
+        function assertThrows(fn, name) {
+          try {
+            fn();
+          } catch (err) {
+            if (err.name !== name) throw err;
+            return;
+          }
+          throw new Error("expected an error");
+        }
+
+        const values = new Uint32Array(16);
+        if (crypto.getRandomValues(values) !== values) {
+          throw new Error("getRandomValues() should return its argument");
+        }
+        if (values.every((value) => value === 0)) {
+          throw new Error("values weren't filled");
+        }
+        crypto.getRandomValues(new Uint8Array(65536));
+        assertThrows(
+          () => crypto.getRandomValues(new Uint8Array(65537)),
+          "QuotaExceededError",
+        );
+        assertThrows(
+          () => crypto.getRandomValues(new Float64Array(1)),
+          "TypeMismatchError",
+        );
+
+        const uuid = crypto.randomUUID();
+        if (!/^[0-9a-f]{8}-[0-9a-f]{4}-4[0-9a-f]{3}-[89ab][0-9a-f]{3}-[0-9a-f]{12}$/.test(uuid)) {
+          throw new Error(`invalid UUID: ${uuid}`);
+        }
+        if (uuid === crypto.randomUUID()) {
+          throw new Error("UUIDs should be unique");
+        }
+        "#,
+      )
+      .unwrap();
+  }
+}
@@ -0,0 +1,81 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+//! Helpers shared by the caches that store their entries on disk.
+
+use sha2::Digest;
+use sha2::Sha256;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+
+/// Returns the hex SHA-256 hash of `parts`. Each part is prefixed with its
+/// length, so that different splits of the same bytes hash differently.
+pub(crate) fn hash_parts(parts: &[&str]) -> String {
+  let mut hasher = Sha256::new();
+  for part in parts {
+    hasher.update(part.len().to_le_bytes());
+    hasher.update(part);
+  }
+  format!("{:x}", hasher.finalize())
+}
+
+/// Writes `data` to `path` through a temporary file in the same directory,
+/// so that a concurrent reader never sees a partially written file. The
+/// temporary file's name is unique to the write, so concurrent writers of the
+/// same path, in this process or another, don't interleave their writes.
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> io::Result<()> {
+  static NEXT_TMP_ID: AtomicUsize = AtomicUsize::new(0);
+  let tmp_path = path.with_extension(format!(
+    "{}.{}.tmp",
+    std::process::id(),
+    NEXT_TMP_ID.fetch_add(1, Ordering::Relaxed)
+  ));
+  let result =
+    fs::write(&tmp_path, data).and_then(|()| fs::rename(&tmp_path, path));
+  if result.is_err() {
+    let _ = fs::remove_file(&tmp_path);
+  }
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::thread;
+
+  #[test]
+  fn test_hash_parts() {
+    assert_eq!(hash_parts(&["a", "b"]), hash_parts(&["a", "b"]));
+    assert_ne!(hash_parts(&["ab", ""]), hash_parts(&["a", "b"]));
+  }
+
+  #[test]
+  fn test_write_atomically_concurrently() {
+    let dir = std::env::temp_dir()
+      .join(format!("deno_core_disk_cache_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("entry.bin");
+    let threads: Vec<_> = (0..8u8)
+      .map(|i| {
+        let path = path.clone();
+        thread::spawn(move || {
+          for _ in 0..20 {
+            write_atomically(&path, &[i; 64 * 1024]).unwrap();
+          }
+        })
+      })
+      .collect();
+    for thread in threads {
+      thread.join().unwrap();
+    }
+    // The entry was written whole by one of the writers.
+    let data = fs::read(&path).unwrap();
+    assert_eq!(data.len(), 64 * 1024);
+    assert!(data.iter().all(|byte| *byte == data[0]));
+    // No temporary files are left behind.
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
@@ -48,6 +48,32 @@ function main() {
   assert(Deno.core.decode(new Uint8Array(fixture1)) === "𝓽𝓮𝔁𝓽");
   assert(Deno.core.decode(new Uint8Array(fixture2)) === "Hello �� World");
 
+  // Other encodings.
+  assertArrayEquals(
+    Array.from(Deno.core.encode("caf\u00e9", "latin1")),
+    [99, 97, 102, 0xe9],
+  );
+  assert(
+    Deno.core.decode(new Uint8Array([99, 97, 102, 0xe9]), "latin1") ===
+      "caf\u00e9",
+  );
+  assertArrayEquals(
+    Array.from(Deno.core.encode("h\u20ac", "utf-16le")),
+    [104, 0, 0xac, 0x20],
+  );
+  assert(
+    Deno.core.decode(new Uint8Array([104, 0, 0xac, 0x20]), "utf-16le") ===
+      "h\u20ac",
+  );
+  assert(Deno.core.decode(Deno.core.encode("\u00e9"), "utf-8") === "\u00e9");
+  let unsupported = false;
+  try {
+    Deno.core.encode("", "utf-32");
+  } catch (e) {
+    unsupported = e instanceof TypeError;
+  }
+  assert(unsupported);
+
   // See https://github.com/denoland/deno/issues/6649
   let thrown = false;
   try {
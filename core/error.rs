@@ -86,10 +86,44 @@ pub fn get_custom_error_class(error: &Error) -> Option<&'static str> {
   error.downcast_ref::<CustomError>().map(|e| e.class)
 }
 
+/// Attaches extra fields (eg. `errno` or `path`) to an error. When the error
+/// is returned by an op, the fields are set as properties on the thrown JS
+/// error object. `properties` must serialize to a JSON object; other values
+/// are ignored.
+pub fn with_properties(
+  error: Error,
+  properties: impl serde::Serialize,
+) -> Error {
+  let properties = match serde_json::to_value(properties) {
+    Ok(serde_json::Value::Object(properties)) => properties,
+    _ => serde_json::Map::new(),
+  };
+  ErrorWithProperties { error, properties }.into()
+}
+
+/// An error with extra fields, created with `with_properties()`. The error
+/// class and message are those of the wrapped error.
+#[derive(Debug)]
+pub struct ErrorWithProperties {
+  pub error: Error,
+  pub properties: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Display for ErrorWithProperties {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    <Error as Display>::fmt(&self.error, f)
+  }
+}
+
+impl std::error::Error for ErrorWithProperties {}
+
 /// A `JsError` represents an exception coming from V8, with stack frames and
 /// line numbers. The deno_cli crate defines another `JsError` type, which wraps
 /// the one defined here, that adds source map support and colorful formatting.
-#[derive(Debug, PartialEq, Clone)]
+///
+/// `JsError` serializes with camelCase field names, matching `JsStackFrame`.
+#[derive(Debug, PartialEq, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
 pub struct JsError {
   pub message: String,
   pub source_line: Option<String>,
@@ -99,9 +133,18 @@ pub struct JsError {
   pub end_column: Option<i64>,   // 0-based
   pub frames: Vec<JsStackFrame>,
   pub stack: Option<String>,
+  /// Own enumerable properties of the thrown value that hold primitives, eg.
+  /// `code` in `throw Object.assign(new Error("boom"), { code: 42 })`. `None`
+  /// if the thrown value is not an object. Use
+  /// `JsRuntime::take_last_exception()` to inspect the value itself.
+  pub properties: Option<serde_json::Map<String, serde_json::Value>>,
+  /// For unhandled promise rejections, where the rejected promise was
+  /// created, innermost frame first. Only recorded with
+  /// `RuntimeOptions::capture_promise_creation_stacks`.
+  pub promise_creation_frames: Vec<JsStackFrame>,
 }
 
-#[derive(Debug, PartialEq, Clone, serde::Deserialize)]
+#[derive(Debug, PartialEq, Clone, serde::Deserialize, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct JsStackFrame {
   pub type_name: Option<String>,
@@ -239,10 +282,55 @@ impl JsError {
       end_column: msg.get_end_column().try_into().ok(),
       frames,
       stack,
+      properties: get_own_primitive_properties(scope, exception),
+      promise_creation_frames: vec![],
     }
   }
 }
 
+/// Collects the own enumerable properties of `value` that hold strings,
+/// numbers, booleans or null. Other values are skipped, so cyclic or exotic
+/// payloads can't make the conversion fail.
+fn get_own_primitive_properties(
+  scope: &mut v8::HandleScope,
+  value: v8::Local<v8::Value>,
+) -> Option<serde_json::Map<String, serde_json::Value>> {
+  let object = v8::Local::<v8::Object>::try_from(value).ok()?;
+  let names = object.get_own_property_names(scope)?;
+  let mut properties = serde_json::Map::new();
+  for i in 0..names.length() {
+    let key = match names.get_index(scope, i) {
+      Some(key) => key,
+      None => continue,
+    };
+    let value = match object.get(scope, key) {
+      Some(value) => value,
+      None => continue,
+    };
+    let value = if value.is_string() {
+      serde_json::Value::String(value.to_rust_string_lossy(scope))
+    } else if value.is_int32() {
+      serde_json::Value::from(value.int32_value(scope).unwrap_or_default())
+    } else if value.is_number() {
+      match value
+        .number_value(scope)
+        .and_then(serde_json::Number::from_f64)
+      {
+        Some(number) => serde_json::Value::Number(number),
+        None => continue,
+      }
+    } else if value.is_boolean() {
+      serde_json::Value::Bool(value.is_true())
+    } else if value.is_null() {
+      serde_json::Value::Null
+    } else {
+      continue;
+    };
+    properties.insert(key.to_rust_string_lossy(scope), value);
+  }
+  Some(properties)
+}
+
 impl std::error::Error for JsError {}
 
 fn format_source_loc(
@@ -257,22 +345,37 @@ fn format_source_loc(
 
 impl Display for JsError {
   fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-    if let Some(stack) = &self.stack {
-      let stack_lines = stack.lines();
-      if stack_lines.count() > 1 {
-        return write!(f, "{}", stack);
+    match &self.stack {
+      Some(stack) if stack.lines().count() > 1 => write!(f, "{}", stack)?,
+      _ => {
+        write!(f, "{}", self.message)?;
+        if let Some(script_resource_name) = &self.script_resource_name {
+          if self.line_number.is_some() && self.start_column.is_some() {
+            let source_loc = format_source_loc(
+              script_resource_name,
+              self.line_number.unwrap(),
+              self.start_column.unwrap(),
+            );
+            write!(f, "\n    at {}", source_loc)?;
+          }
+        }
       }
     }
 
-    write!(f, "{}", self.message)?;
-    if let Some(script_resource_name) = &self.script_resource_name {
-      if self.line_number.is_some() && self.start_column.is_some() {
+    if !self.promise_creation_frames.is_empty() {
+      write!(f, "\nPromise created at:")?;
+      for frame in &self.promise_creation_frames {
         let source_loc = format_source_loc(
-          script_resource_name,
-          self.line_number.unwrap(),
-          self.start_column.unwrap(),
+          frame.file_name.as_deref().unwrap_or("<anonymous>"),
+          frame.line_number.unwrap_or(0),
+          frame.column_number.unwrap_or(0),
         );
-        write!(f, "\n    at {}", source_loc)?;
+        match &frame.function_name {
+          Some(function_name) => {
+            write!(f, "\n    at {} ({})", function_name, source_loc)?
+          }
+          None => write!(f, "\n    at {}", source_loc)?,
+        }
       }
     }
     Ok(())
@@ -318,6 +421,198 @@ pub(crate) fn is_instance_of_error<'s>(
   false
 }
 
+/// Returned by the event loop when more than one promise was rejected without
+/// a handler since the last poll. The errors are in the order in which the
+/// promises were rejected.
+#[derive(Debug)]
+pub struct UnhandledRejections(pub Vec<Error>);
+
+impl std::error::Error for UnhandledRejections {}
+
+impl Display for UnhandledRejections {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "{} unhandled promise rejections", self.0.len())?;
+    for err in &self.0 {
+      write!(f, "\n\n{}", err)?;
+    }
+    Ok(())
+  }
+}
+
+/// Why execution of an isolate was terminated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminationReason {
+  /// The `Watchdog` time budget was exceeded.
+  Timeout,
+  /// `RuntimeOptions::memory_limit` was exceeded.
+  MemoryLimit,
+  /// The runtime is shutting down.
+  Shutdown,
+  /// Execution was killed by an operator.
+  AdminKill,
+  /// An embedder-specific reason.
+  Other(String),
+}
+
+impl Display for TerminationReason {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match self {
+      Self::Timeout => f.write_str("time budget exceeded"),
+      Self::MemoryLimit => f.write_str("isolate memory limit exceeded"),
+      Self::Shutdown => f.write_str("runtime is shutting down"),
+      Self::AdminKill => f.write_str("killed by administrator"),
+      Self::Other(reason) => f.write_str(reason),
+    }
+  }
+}
+
+/// The error returned when execution was terminated with a known reason. It
+/// displays as the wrapped JS error; use `get_termination_reason()` to tell
+/// the causes apart.
+#[derive(Debug)]
+pub struct ExecutionTerminated {
+  pub reason: TerminationReason,
+  pub error: Error,
+}
+
+impl Display for ExecutionTerminated {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    <Error as Display>::fmt(&self.error, f)
+  }
+}
+
+impl std::error::Error for ExecutionTerminated {}
+
+/// If this error was caused by terminating execution with a reason, return
+/// the reason. In all other cases this function returns `None`.
+pub fn get_termination_reason(error: &Error) -> Option<&TerminationReason> {
+  error
+    .downcast_ref::<ExecutionTerminated>()
+    .map(|e| &e.reason)
+}
+
+/// Where and why a script or module failed to compile, eg. because of a
+/// syntax error.
+#[derive(Debug, PartialEq, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CompileDiagnostic {
+  pub message: String,
+  pub script_resource_name: Option<String>,
+  pub line_number: Option<i64>,
+  pub start_column: Option<i64>, // 0-based
+  pub end_column: Option<i64>,   // 0-based
+  /// The full source line the error was reported on.
+  pub source_line: Option<String>,
+}
+
+impl CompileDiagnostic {
+  pub fn from_v8_exception(
+    scope: &mut v8::HandleScope,
+    exception: v8::Local<v8::Value>,
+  ) -> Self {
+    let scope = &mut v8::HandleScope::new(scope);
+    let msg = v8::Exception::create_message(scope, exception);
+    Self {
+      message: msg.get(scope).to_rust_string_lossy(scope),
+      script_resource_name: msg
+        .get_script_resource_name(scope)
+        .and_then(|v| v8::Local::<v8::String>::try_from(v).ok())
+        .map(|v| v.to_rust_string_lossy(scope)),
+      line_number: msg.get_line_number(scope).and_then(|v| v.try_into().ok()),
+      start_column: msg.get_start_column().try_into().ok(),
+      end_column: msg.get_end_column().try_into().ok(),
+      source_line: msg
+        .get_source_line(scope)
+        .map(|v| v.to_rust_string_lossy(scope)),
+    }
+  }
+
+  /// Renders the source line with a caret underline below the offending
+  /// range, eg.
+  ///
+  /// ```text
+  /// let x = ;
+  ///         ^
+  /// ```
+  pub fn snippet(&self) -> Option<String> {
+    let source_line = self.source_line.as_ref()?;
+    let start = self.start_column.unwrap_or(0).max(0) as usize;
+    let end = self.end_column.unwrap_or(0).max(0) as usize;
+    // Keep tabs so the caret lines up with the source line.
+    let padding: String = source_line
+      .chars()
+      .take(start)
+      .map(|c| if c == '\t' { '\t' } else { ' ' })
+      .collect();
+    let carets = "^".repeat(end.saturating_sub(start).max(1));
+    Some(format!("{}\n{}{}", source_line, padding, carets))
+  }
+}
+
+impl Display for CompileDiagnostic {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    write!(f, "{}", self.message)?;
+    if let (Some(script_resource_name), Some(line_number), Some(start_column)) = (
+      &self.script_resource_name,
+      self.line_number,
+      self.start_column,
+    ) {
+      let source_loc =
+        format_source_loc(script_resource_name, line_number, start_column);
+      write!(f, "\n    at {}", source_loc)?;
+    }
+    if let Some(snippet) = self.snippet() {
+      write!(f, "\n\n{}", snippet)?;
+    }
+    Ok(())
+  }
+}
+
+/// The error returned when a script or module fails to compile. It displays
+/// as the wrapped JS error; `diagnostic` describes the failure in detail.
+#[derive(Debug)]
+pub struct CompileError {
+  pub diagnostic: CompileDiagnostic,
+  pub error: Error,
+}
+
+impl Display for CompileError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    <Error as Display>::fmt(&self.error, f)
+  }
+}
+
+impl std::error::Error for CompileError {}
+
+/// The error a module load fails with when the source of a fetched module
+/// doesn't match its entry in the `ModuleLockMap`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleIntegrityError {
+  pub specifier: String,
+  /// `None` if the module has no entry in the lock map.
+  pub expected: Option<String>,
+  pub actual: String,
+}
+
+impl Display for ModuleIntegrityError {
+  fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+    match &self.expected {
+      Some(expected) => write!(
+        f,
+        "Integrity check failed for module \"{}\": expected hash {}, found {}",
+        self.specifier, expected, self.actual
+      ),
+      None => write!(
+        f,
+        "Integrity check failed for module \"{}\": module is not in the lock map",
+        self.specifier
+      ),
+    }
+  }
+}
+
+impl std::error::Error for ModuleIntegrityError {}
+
 // TODO(piscisaureus): rusty_v8 should implement the Error trait on
 // values of type v8::Global<T>.
 pub(crate) struct ErrWithV8Handle {
@@ -375,4 +670,61 @@ mod tests {
     let err = bad_resource_id();
     assert_eq!(err.to_string(), "Bad resource ID");
   }
+
+  #[test]
+  fn test_with_properties() {
+    let err = with_properties(
+      type_error("No such file"),
+      serde_json::json!({ "errno": 2, "path": "/foo" }),
+    );
+    assert_eq!(err.to_string(), "No such file");
+    let err = err.downcast_ref::<ErrorWithProperties>().unwrap();
+    assert_eq!(get_custom_error_class(&err.error), Some("TypeError"));
+    assert_eq!(err.properties["errno"], 2);
+    assert_eq!(err.properties["path"], "/foo");
+  }
+
+  #[test]
+  fn test_compile_diagnostic_snippet() {
+    let diagnostic = CompileDiagnostic {
+      message: "Uncaught SyntaxError: Unexpected token ';'".to_string(),
+      script_resource_name: Some("file:///a.js".to_string()),
+      line_number: Some(2),
+      start_column: Some(9),
+      end_column: Some(10),
+      source_line: Some("\tlet x = ;".to_string()),
+    };
+    assert_eq!(diagnostic.snippet().unwrap(), "\tlet x = ;\n\t        ^");
+    assert_eq!(
+      diagnostic.to_string(),
+      "Uncaught SyntaxError: Unexpected token ';'\n    at file:///a.js:2:9\n\n\tlet x = ;\n\t        ^"
+    );
+  }
+
+  #[test]
+  fn test_js_error_serialize() {
+    let js_error = JsError {
+      message: "Uncaught Error: foo".to_string(),
+      source_line: Some("throw new Error(\"foo\");".to_string()),
+      script_resource_name: Some("file:///a.js".to_string()),
+      line_number: Some(1),
+      start_column: Some(0),
+      end_column: Some(1),
+      frames: vec![JsStackFrame::from_location(
+        Some("file:///a.js".to_string()),
+        Some(1),
+        Some(7),
+      )],
+      stack: None,
+      properties: None,
+      promise_creation_frames: vec![],
+    };
+    let value = serde_json::to_value(&js_error).unwrap();
+    assert_eq!(value["message"], "Uncaught Error: foo");
+    assert_eq!(value["scriptResourceName"], "file:///a.js");
+    assert_eq!(value["startColumn"], 0);
+    assert_eq!(value["frames"][0]["fileName"], "file:///a.js");
+    assert_eq!(value["frames"][0]["columnNumber"], 7);
+    assert_eq!(value["frames"][0]["isToplevel"], serde_json::Value::Null);
+  }
 }
@@ -1,4 +1,5 @@
 use crate::OpFn;
+use crate::OpMetadata;
 use crate::OpState;
 use anyhow::Error;
 
@@ -6,12 +7,14 @@ pub type SourcePair = (&'static str, Box<SourceLoadFn>);
 pub type SourceLoadFn = dyn Fn() -> Result<String, Error>;
 pub type OpPair = (&'static str, Box<OpFn>);
 pub type OpMiddlewareFn = dyn Fn(&'static str, Box<OpFn>) -> Box<OpFn>;
+pub type OpMetadataPair = (&'static str, OpMetadata);
 pub type OpStateFn = dyn Fn(&mut OpState) -> Result<(), Error>;
 
 #[derive(Default)]
 pub struct Extension {
   js_files: Option<Vec<SourcePair>>,
   ops: Option<Vec<OpPair>>,
+  op_metadata: Vec<OpMetadataPair>,
   opstate_fn: Option<Box<OpStateFn>>,
   middleware_fn: Option<Box<OpMiddlewareFn>>,
   initialized: bool,
@@ -44,6 +47,12 @@ impl Extension {
     self.ops.take()
   }
 
+  /// Metadata declared for the extension's ops, set after they are
+  /// registered.
+  pub fn init_op_metadata(&mut self) -> Vec<OpMetadataPair> {
+    std::mem::take(&mut self.op_metadata)
+  }
+
   /// Allows setting up the initial op-state of an isolate at startup.
   pub fn init_state(&self, state: &mut OpState) -> Result<(), Error> {
     match &self.opstate_fn {
@@ -63,6 +72,7 @@ impl Extension {
 pub struct ExtensionBuilder {
   js: Vec<SourcePair>,
   ops: Vec<OpPair>,
+  op_metadata: Vec<OpMetadataPair>,
   state: Option<Box<OpStateFn>>,
   middleware: Option<Box<OpMiddlewareFn>>,
 }
@@ -78,6 +88,13 @@ impl ExtensionBuilder {
     self
   }
 
+  /// Declares metadata for ops of this extension, by op name. See
+  /// `OpMetadata`.
+  pub fn op_metadata(&mut self, metadata: Vec<OpMetadataPair>) -> &mut Self {
+    self.op_metadata.extend(metadata);
+    self
+  }
+
   pub fn state<F>(&mut self, opstate_fn: F) -> &mut Self
   where
     F: Fn(&mut OpState) -> Result<(), Error> + 'static,
@@ -100,6 +117,7 @@ impl ExtensionBuilder {
     Extension {
       js_files,
       ops,
+      op_metadata: std::mem::take(&mut self.op_metadata),
       opstate_fn: self.state.take(),
       middleware_fn: self.middleware.take(),
       initialized: false,
@@ -0,0 +1,415 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::type_error;
+use crate::include_js_files;
+use crate::op_async;
+use crate::op_sync;
+use crate::AsyncRefCell;
+use crate::AsyncResult;
+use crate::ByteString;
+use crate::CancelHandle;
+use crate::CancelTryFuture;
+use crate::Extension;
+use crate::OpMetadata;
+use crate::OpState;
+use crate::RcRef;
+use crate::Resource;
+use crate::ResourceId;
+use crate::ZeroCopyBuf;
+use anyhow::Error;
+use futures::channel::mpsc;
+use futures::SinkExt;
+use futures::TryFutureExt;
+use reqwest::header::HeaderName;
+use reqwest::header::HeaderValue;
+use reqwest::Body;
+use reqwest::Client;
+use reqwest::Method;
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+use serde::Serialize;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io;
+use std::rc::Rc;
+use url::Url;
+
+/// An extension providing a minimal `fetch()` global, for embedders that want
+/// scripts to make HTTP requests without the full Deno runtime. Request and
+/// response bodies are streamed through the resource table. Only http: and
+/// https: URLs are supported, and there is no `Request` class, `AbortSignal`
+/// or `ReadableStream`: responses are read with `text()`, `json()`,
+/// `arrayBuffer()` or by iterating over them with `for await`.
+///
+/// `op_fetch` declares the `"net"` permission in its metadata, so that a
+/// `PermissionsHandler` can restrict it. The ops use `reqwest`, so the event
+/// loop must be polled from within a Tokio runtime.
+///
+/// Requires the `fetch` feature.
+pub fn init_fetch() -> Extension {
+  Extension::builder()
+    .js(include_js_files!(
+      prefix "deno:core",
+      "05_fetch.js",
+    ))
+    .ops(vec![
+      ("op_fetch", op_sync(op_fetch)),
+      ("op_fetch_send", op_async(op_fetch_send)),
+    ])
+    .op_metadata(vec![
+      (
+        "op_fetch",
+        OpMetadata {
+          is_async: false,
+          args: vec!["args: FetchArgs", "body: Uint8Array | null"],
+          permission: Some("net"),
+        },
+      ),
+      (
+        "op_fetch_send",
+        OpMetadata {
+          is_async: true,
+          args: vec!["rid: number"],
+          permission: None,
+        },
+      ),
+    ])
+    .state(|state| {
+      let client = Client::builder()
+        .user_agent(concat!("deno_core/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+      state.put(client);
+      Ok(())
+    })
+    .build()
+}
+
+type BodySender = mpsc::Sender<Result<Vec<u8>, io::Error>>;
+
+/// A request created by `op_fetch` that hasn't been sent yet, or is being
+/// sent. Closing it cancels a pending `op_fetch_send`.
+struct FetchRequestResource {
+  request: RefCell<Option<RequestBuilder>>,
+  cancel: CancelHandle,
+}
+
+impl Resource for FetchRequestResource {
+  fn name(&self) -> Cow<str> {
+    "fetchRequest".into()
+  }
+
+  fn close(self: Rc<Self>) {
+    self.cancel.cancel()
+  }
+}
+
+/// The body of a request, written by JS while the request is being sent.
+/// `shutdown()` ends the body.
+struct FetchRequestBodyResource {
+  sender: AsyncRefCell<Option<BodySender>>,
+  cancel: CancelHandle,
+}
+
+impl Resource for FetchRequestBodyResource {
+  fn name(&self) -> Cow<str> {
+    "fetchRequestBody".into()
+  }
+
+  fn write(self: Rc<Self>, buf: ZeroCopyBuf) -> AsyncResult<usize> {
+    Box::pin(async move {
+      let mut sender = RcRef::map(&self, |r| &r.sender).borrow_mut().await;
+      let cancel = RcRef::map(&self, |r| &r.cancel);
+      let sender = Option::as_mut(&mut sender)
+        .ok_or_else(|| type_error("The request body has been closed"))?;
+      let len = buf.len();
+      sender
+        .send(Ok(buf.to_vec()))
+        .map_err(|_| type_error("The request is no longer being sent"))
+        .try_or_cancel(cancel)
+        .await?;
+      Ok(len)
+    })
+  }
+
+  fn shutdown(self: Rc<Self>) -> AsyncResult<()> {
+    Box::pin(async move {
+      RcRef::map(&self, |r| &r.sender).borrow_mut().await.take();
+      Ok(())
+    })
+  }
+
+  fn close(self: Rc<Self>) {
+    self.cancel.cancel()
+  }
+}
+
+struct ResponseBody {
+  response: reqwest::Response,
+  /// The part of the last chunk that didn't fit in the buffer of a read.
+  pending: Vec<u8>,
+}
+
+struct FetchResponseBodyResource {
+  body: AsyncRefCell<ResponseBody>,
+  cancel: CancelHandle,
+}
+
+impl Resource for FetchResponseBodyResource {
+  fn name(&self) -> Cow<str> {
+    "fetchResponseBody".into()
+  }
+
+  fn read(self: Rc<Self>, mut buf: ZeroCopyBuf) -> AsyncResult<usize> {
+    Box::pin(async move {
+      let mut body = RcRef::map(&self, |r| &r.body).borrow_mut().await;
+      while body.pending.is_empty() {
+        let cancel = RcRef::map(&self, |r| &r.cancel);
+        let chunk = body
+          .response
+          .chunk()
+          .map_err(Error::from)
+          .try_or_cancel(cancel)
+          .await?;
+        match chunk {
+          Some(chunk) => body.pending = chunk.to_vec(),
+          None => return Ok(0),
+        }
+      }
+      let len = buf.len().min(body.pending.len());
+      buf[..len].copy_from_slice(&body.pending[..len]);
+      body.pending.drain(..len);
+      Ok(len)
+    })
+  }
+
+  fn close(self: Rc<Self>) {
+    self.cancel.cancel()
+  }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FetchArgs {
+  method: ByteString,
+  url: String,
+  headers: Vec<(ByteString, ByteString)>,
+  /// Whether the body is streamed through a `FetchRequestBodyResource`,
+  /// rather than passed to `op_fetch`.
+  has_body_stream: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FetchReturn {
+  request_rid: ResourceId,
+  request_body_rid: Option<ResourceId>,
+}
+
+fn op_fetch(
+  state: &mut OpState,
+  args: FetchArgs,
+  body: Option<ZeroCopyBuf>,
+) -> Result<FetchReturn, Error> {
+  let url = Url::parse(&args.url).map_err(|_| type_error("Invalid URL"))?;
+  if !matches!(url.scheme(), "http" | "https") {
+    return Err(type_error(format!(
+      "URL scheme '{}' is not supported",
+      url.scheme()
+    )));
+  }
+  let method = Method::from_bytes(&args.method)
+    .map_err(|_| type_error("Invalid HTTP method"))?;
+
+  let mut request = state.borrow::<Client>().request(method, url);
+  for (name, value) in args.headers {
+    let name = HeaderName::from_bytes(&name)
+      .map_err(|_| type_error("Invalid header name"))?;
+    let value = HeaderValue::from_bytes(&value)
+      .map_err(|_| type_error("Invalid header value"))?;
+    request = request.header(name, value);
+  }
+
+  let mut request_body_rid = None;
+  if let Some(body) = body {
+    request = request.body(body.to_vec());
+  } else if args.has_body_stream {
+    // Writes wait until the previous chunk has been sent.
+    let (sender, receiver) = mpsc::channel(0);
+    request = request.body(Body::wrap_stream(receiver));
+    request_body_rid =
+      Some(state.resource_table.add(FetchRequestBodyResource {
+        sender: AsyncRefCell::new(Some(sender)),
+        cancel: Default::default(),
+      }));
+  }
+
+  let request_rid = state.resource_table.add(FetchRequestResource {
+    request: RefCell::new(Some(request)),
+    cancel: Default::default(),
+  });
+  Ok(FetchReturn {
+    request_rid,
+    request_body_rid,
+  })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FetchResponse {
+  status: u16,
+  status_text: String,
+  headers: Vec<(ByteString, ByteString)>,
+  url: String,
+  response_rid: ResourceId,
+}
+
+async fn op_fetch_send(
+  state: Rc<RefCell<OpState>>,
+  rid: ResourceId,
+  _: (),
+) -> Result<FetchResponse, Error> {
+  let resource = state
+    .borrow()
+    .resource_table
+    .get::<FetchRequestResource>(rid)?;
+  let request = resource
+    .request
+    .borrow_mut()
+    .take()
+    .ok_or_else(|| type_error("The request has already been sent"))?;
+  let cancel = RcRef::map(&resource, |r| &r.cancel);
+  let result = request
+    .send()
+    .map_err(Error::from)
+    .try_or_cancel(cancel)
+    .await;
+  state.borrow_mut().resource_table.close(rid).ok();
+  let response = result?;
+
+  let status = response.status();
+  let headers = response
+    .headers()
+    .iter()
+    .map(|(name, value)| {
+      (
+        ByteString(name.as_str().as_bytes().to_vec()),
+        ByteString(value.as_bytes().to_vec()),
+      )
+    })
+    .collect();
+  let url = response.url().to_string();
+  let response_rid =
+    state
+      .borrow_mut()
+      .resource_table
+      .add(FetchResponseBodyResource {
+        body: AsyncRefCell::new(ResponseBody {
+          response,
+          pending: Vec::new(),
+        }),
+        cancel: Default::default(),
+      });
+  Ok(FetchResponse {
+    status: status.as_u16(),
+    status_text: status.canonical_reason().unwrap_or("").to_string(),
+    headers,
+    url,
+    response_rid,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::JsRuntime;
+  use crate::RuntimeOptions;
+  use std::io::BufRead;
+  use std::io::BufReader;
+  use std::io::Read;
+  use std::io::Write;
+  use std::net::TcpListener;
+  use std::thread;
+
+  /// Serves a single request, responding with its request line and body.
+  fn serve_once() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn(move || {
+      let (mut stream, _) = listener.accept().unwrap();
+      let mut reader = BufReader::new(stream.try_clone().unwrap());
+      let mut request_line = String::new();
+      reader.read_line(&mut request_line).unwrap();
+      let mut content_length = 0;
+      loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+          break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+          if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().unwrap();
+          }
+        }
+      }
+      let mut body = vec![0; content_length];
+      reader.read_exact(&mut body).unwrap();
+      let body = format!(
+        "{} {}",
+        request_line.trim_end(),
+        String::from_utf8(body).unwrap()
+      );
+      write!(
+        stream,
+        "HTTP/1.1 201 Created\r\nx-test: 1\r\nx-test: 2\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        body.len(),
+        body
+      )
+      .unwrap();
+    });
+    format!("http://{}/path", addr)
+  }
+
+  #[tokio::test]
+  async fn test_fetch() {
+    let url = serve_once();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      extensions: vec![init_fetch()],
+      ..Default::default()
+    });
+    let promise = runtime
+      .execute_script(
+        "fetch_test.js",
+        &format!(
+          r#"
+          (async () => {{
+            const res = await fetch("{}", {{ method: "POST", body: "hello" }});
+            if (res.status !== 201 || res.statusText !== "Created" || !res.ok) {{
+              throw new Error(`unexpected status: ${{res.status}}`);
+            }}
+            if (res.headers.get("X-Test") !== "1, 2") {{
+              throw new Error("unexpected headers");
+            }}
+            const text = await res.text();
+            if (text !== "POST /path HTTP/1.1 hello" || !res.bodyUsed) {{
+              throw new Error(`unexpected body: ${{text}}`);
+            }}
+            try {{
+              await fetch("file:///etc/passwd");
+              throw new Error("file: URLs should be rejected");
+            }} catch (err) {{
+              if (!(err instanceof TypeError)) throw err;
+            }}
+          }})()
+          "#,
+          url
+        ),
+      )
+      .unwrap();
+    runtime.resolve_value(promise).await.unwrap();
+    assert_eq!(
+      runtime.op_state().borrow().resource_table.names().count(),
+      0
+    );
+  }
+}
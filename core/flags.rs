@@ -1,7 +1,83 @@
 // Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
 
+use std::sync::Mutex;
+
+lazy_static::lazy_static! {
+  // The value of V8's process-wide `--disallow-code-generation-from-strings`
+  // flag set through `v8_set_flags()`. Locked while the flag is changed.
+  static ref DISALLOW_CODE_GENERATION_FLAG: Mutex<bool> = Mutex::new(false);
+}
+
 /// Pass the command line arguments to v8.
 /// Returns a vector of command line arguments that V8 did not understand.
 pub fn v8_set_flags(args: Vec<String>) -> Vec<String> {
+  let mut disallow_code_generation =
+    DISALLOW_CODE_GENERATION_FLAG.lock().unwrap();
+  for arg in &args {
+    if let Some(value) = parse_disallow_code_generation_flag(arg) {
+      *disallow_code_generation = value;
+    }
+  }
   v8::V8::set_flags_from_command_line(args)
 }
+
+/// Parses `--disallow-code-generation-from-strings` and its negations the way
+/// V8 does: underscores and dashes are interchangeable, and `no` or `no-`
+/// negates the flag.
+fn parse_disallow_code_generation_flag(arg: &str) -> Option<bool> {
+  let flag = arg
+    .strip_prefix("--")
+    .or_else(|| arg.strip_prefix('-'))?
+    .replace('_', "-");
+  match flag.as_str() {
+    "disallow-code-generation-from-strings" => Some(true),
+    "no-disallow-code-generation-from-strings"
+    | "nodisallow-code-generation-from-strings" => Some(false),
+    _ => None,
+  }
+}
+
+/// Runs `f` with code generation from strings disallowed if `disallow` is set.
+/// V8 only reads `--disallow-code-generation-from-strings` when a context is
+/// created, so `f` should create the context. The flag is then restored to
+/// the value set through `v8_set_flags()`.
+///
+/// The flag is process-wide, so every context creation holds the lock, even
+/// when it doesn't change the flag: otherwise a context created on another
+/// thread while the flag is set would disallow code generation too.
+pub(crate) fn with_code_generation_disallowed<R>(
+  disallow: bool,
+  f: impl FnOnce() -> R,
+) -> R {
+  let embedder_value = DISALLOW_CODE_GENERATION_FLAG.lock().unwrap();
+  if !disallow || *embedder_value {
+    return f();
+  }
+  v8::V8::set_flags_from_string("--disallow-code-generation-from-strings");
+  let result = f();
+  v8::V8::set_flags_from_string("--no-disallow-code-generation-from-strings");
+  result
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_disallow_code_generation_flag() {
+    let parse = parse_disallow_code_generation_flag;
+    assert_eq!(parse("--disallow-code-generation-from-strings"), Some(true));
+    assert_eq!(parse("--disallow_code_generation_from_strings"), Some(true));
+    assert_eq!(parse("-disallow-code-generation-from-strings"), Some(true));
+    assert_eq!(
+      parse("--no-disallow-code-generation-from-strings"),
+      Some(false)
+    );
+    assert_eq!(
+      parse("--nodisallow_code_generation_from_strings"),
+      Some(false)
+    );
+    assert_eq!(parse("--disallow-code-generation"), None);
+    assert_eq!(parse("disallow-code-generation-from-strings"), None);
+  }
+}
@@ -0,0 +1,385 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::custom_error;
+use crate::error::type_error;
+use crate::include_js_files;
+use crate::op_sync;
+use crate::Extension;
+use crate::OpMetadata;
+use crate::OpState;
+use crate::ZeroCopyBuf;
+use anyhow::Error;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+/// Directories of the host filesystem made available to scripts by
+/// `init_fs()`, each mounted at a virtual absolute path, eg. `/data`.
+///
+/// Paths can't escape the root they resolve to, neither with `..` segments
+/// nor through symlinks under the root that point outside of it; paths that
+/// aren't under a mount are denied.
+#[derive(Debug, Clone, Default)]
+pub struct FsRoots {
+  /// Longest prefix first.
+  mounts: Vec<FsMount>,
+}
+
+#[derive(Debug, Clone)]
+struct FsMount {
+  prefix: String,
+  root: PathBuf,
+  read_only: bool,
+}
+
+impl FsRoots {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Mounts the directory `root` at `prefix`, eg. `/data` or `/` to make it
+  /// the only root.
+  pub fn mount(&mut self, prefix: &str, root: impl Into<PathBuf>) -> &mut Self {
+    self.add_mount(prefix, root.into(), false)
+  }
+
+  /// Like `mount()`, but denies writes.
+  pub fn mount_read_only(
+    &mut self,
+    prefix: &str,
+    root: impl Into<PathBuf>,
+  ) -> &mut Self {
+    self.add_mount(prefix, root.into(), true)
+  }
+
+  fn add_mount(
+    &mut self,
+    prefix: &str,
+    root: PathBuf,
+    read_only: bool,
+  ) -> &mut Self {
+    self.mounts.push(FsMount {
+      prefix: prefix.trim_end_matches('/').to_string(),
+      root,
+      read_only,
+    });
+    self
+      .mounts
+      .sort_by_key(|mount| std::cmp::Reverse(mount.prefix.len()));
+    self
+  }
+
+  /// Maps the virtual `path` onto the host filesystem, with symlinks resolved.
+  /// The path doesn't need to exist, but its parent directory does.
+  pub fn resolve(&self, path: &str, write: bool) -> Result<PathBuf, Error> {
+    let denied = || {
+      custom_error(
+        "PermissionDenied",
+        format!("Access to \"{}\" is not allowed", path),
+      )
+    };
+    if !path.starts_with('/') {
+      return Err(type_error(format!("Path must be absolute: \"{}\"", path)));
+    }
+    let mut segments = Vec::new();
+    for segment in path.split('/') {
+      match segment {
+        "" | "." => {}
+        ".." => {
+          segments.pop().ok_or_else(denied)?;
+        }
+        segment => segments.push(segment),
+      }
+    }
+    let normalized = format!("/{}", segments.join("/"));
+
+    let mount = self
+      .mounts
+      .iter()
+      .find(
+        |mount| match normalized.strip_prefix(mount.prefix.as_str()) {
+          Some(rest) => rest.is_empty() || rest.starts_with('/'),
+          None => false,
+        },
+      )
+      .ok_or_else(denied)?;
+    if write && mount.read_only {
+      return Err(denied());
+    }
+    let root = mount.root.canonicalize().map_err(fs_error)?;
+    let target =
+      root.join(normalized[mount.prefix.len()..].trim_start_matches('/'));
+    // Resolve symlinks, so the check below sees where they point to.
+    let resolved = if target.symlink_metadata().is_ok() {
+      target.canonicalize().map_err(fs_error)?
+    } else {
+      match (target.parent(), target.file_name()) {
+        (Some(parent), Some(name)) => {
+          parent.canonicalize().map_err(fs_error)?.join(name)
+        }
+        _ => return Err(denied()),
+      }
+    };
+    if !resolved.starts_with(&root) {
+      return Err(denied());
+    }
+    Ok(resolved)
+  }
+}
+
+/// An extension giving scripts synchronous access to the directories in
+/// `roots`, through `Deno.core.fs`: `readFileSync(path)`,
+/// `readTextFileSync(path)`, `writeFileSync(path, data)`,
+/// `writeTextFileSync(path, text)`, `statSync(path)` and `readDirSync(path)`.
+/// Paths are the virtual paths the roots are mounted at.
+///
+/// I/O errors are thrown as errors named after their kind, eg. `NotFound`.
+/// The ops declare the `"read"` or `"write"` permission in their metadata.
+///
+/// Requires the `fs` feature.
+pub fn init_fs(roots: FsRoots) -> Extension {
+  Extension::builder()
+    .js(include_js_files!(
+      prefix "deno:core",
+      "06_fs.js",
+    ))
+    .ops(vec![
+      ("op_fs_read_file", op_sync(op_fs_read_file)),
+      ("op_fs_write_file", op_sync(op_fs_write_file)),
+      ("op_fs_stat", op_sync(op_fs_stat)),
+      ("op_fs_read_dir", op_sync(op_fs_read_dir)),
+    ])
+    .op_metadata(vec![
+      ("op_fs_read_file", metadata(vec!["path: string"], "read")),
+      (
+        "op_fs_write_file",
+        metadata(vec!["path: string", "data: Uint8Array"], "write"),
+      ),
+      ("op_fs_stat", metadata(vec!["path: string"], "read")),
+      ("op_fs_read_dir", metadata(vec!["path: string"], "read")),
+    ])
+    .state(move |state| {
+      state.put(roots.clone());
+      Ok(())
+    })
+    .build()
+}
+
+fn metadata(args: Vec<&'static str>, permission: &'static str) -> OpMetadata {
+  OpMetadata {
+    is_async: false,
+    args,
+    permission: Some(permission),
+  }
+}
+
+/// Maps an I/O error to an error class registered by `06_fs.js`.
+fn fs_error(err: io::Error) -> Error {
+  let class = match err.kind() {
+    io::ErrorKind::NotFound => "NotFound",
+    io::ErrorKind::PermissionDenied => "PermissionDenied",
+    io::ErrorKind::AlreadyExists => "AlreadyExists",
+    io::ErrorKind::InvalidInput => "TypeError",
+    _ => "Error",
+  };
+  custom_error(class, err.to_string())
+}
+
+fn op_fs_read_file(
+  state: &mut OpState,
+  path: String,
+  _: (),
+) -> Result<ZeroCopyBuf, Error> {
+  let path = state.borrow::<FsRoots>().resolve(&path, false)?;
+  Ok(fs::read(path).map_err(fs_error)?.into())
+}
+
+fn op_fs_write_file(
+  state: &mut OpState,
+  path: String,
+  data: ZeroCopyBuf,
+) -> Result<(), Error> {
+  let path = state.borrow::<FsRoots>().resolve(&path, true)?;
+  fs::write(path, &data[..]).map_err(fs_error)
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FsStat {
+  is_file: bool,
+  is_directory: bool,
+  size: u64,
+  /// Milliseconds since the epoch, if the platform supports it.
+  mtime: Option<f64>,
+}
+
+fn op_fs_stat(
+  state: &mut OpState,
+  path: String,
+  _: (),
+) -> Result<FsStat, Error> {
+  let path = state.borrow::<FsRoots>().resolve(&path, false)?;
+  let metadata = fs::metadata(path).map_err(fs_error)?;
+  let mtime = metadata
+    .modified()
+    .ok()
+    .and_then(|mtime| mtime.duration_since(UNIX_EPOCH).ok())
+    .map(|mtime| mtime.as_secs_f64() * 1000.0);
+  Ok(FsStat {
+    is_file: metadata.is_file(),
+    is_directory: metadata.is_dir(),
+    size: metadata.len(),
+    mtime,
+  })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FsDirEntry {
+  name: String,
+  is_file: bool,
+  is_directory: bool,
+  is_symlink: bool,
+}
+
+fn op_fs_read_dir(
+  state: &mut OpState,
+  path: String,
+  _: (),
+) -> Result<Vec<FsDirEntry>, Error> {
+  let path = state.borrow::<FsRoots>().resolve(&path, false)?;
+  let mut entries = Vec::new();
+  for entry in fs::read_dir(path).map_err(fs_error)? {
+    let entry = entry.map_err(fs_error)?;
+    let file_type = entry.file_type().map_err(fs_error)?;
+    entries.push(FsDirEntry {
+      name: entry.file_name().to_string_lossy().into_owned(),
+      is_file: file_type.is_file(),
+      is_directory: file_type.is_dir(),
+      is_symlink: file_type.is_symlink(),
+    });
+  }
+  entries.sort_by(|a, b| a.name.cmp(&b.name));
+  Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::JsRuntime;
+  use crate::RuntimeOptions;
+  use std::path::Path;
+
+  fn setup(name: &str) -> (PathBuf, FsRoots) {
+    let dir = std::env::temp_dir().join(format!(
+      "deno_core_fs_{}_{}",
+      name,
+      std::process::id()
+    ));
+    fs::create_dir_all(dir.join("data/sub")).unwrap();
+    fs::create_dir_all(dir.join("config")).unwrap();
+    fs::write(dir.join("config/app.json"), r#"{"name":"app"}"#).unwrap();
+    fs::write(dir.join("secret.txt"), "secret").unwrap();
+    let mut roots = FsRoots::new();
+    roots
+      .mount("/data", dir.join("data"))
+      .mount_read_only("/config/", dir.join("config"));
+    (dir.canonicalize().unwrap(), roots)
+  }
+
+  #[test]
+  fn test_fs_roots() {
+    let (dir, roots) = setup("roots");
+    let resolve = |path: &str, write: bool| roots.resolve(path, write);
+    let class = |path: &str, write: bool| {
+      crate::error::get_custom_error_class(&resolve(path, write).unwrap_err())
+    };
+
+    assert_eq!(resolve("/data", false).unwrap(), dir.join("data"));
+    assert_eq!(
+      resolve("/data/sub/../new.txt", true).unwrap(),
+      dir.join("data/new.txt")
+    );
+    assert_eq!(
+      resolve("/config/app.json", false).unwrap(),
+      dir.join("config/app.json")
+    );
+    assert_eq!(class("/config/app.json", true), Some("PermissionDenied"));
+    assert_eq!(
+      class("/data/../secret.txt", false),
+      Some("PermissionDenied")
+    );
+    assert_eq!(class("/../data", false), Some("PermissionDenied"));
+    assert_eq!(class("/datafile", false), Some("PermissionDenied"));
+    assert_eq!(class("/data/missing/new.txt", true), Some("NotFound"));
+    assert!(resolve("data", false).is_err());
+
+    #[cfg(unix)]
+    {
+      let link = dir.join("data/link.txt");
+      std::os::unix::fs::symlink(dir.join("secret.txt"), &link).unwrap();
+      assert_eq!(class("/data/link.txt", false), Some("PermissionDenied"));
+      std::os::unix::fs::symlink(
+        Path::new("/nonexistent"),
+        dir.join("data/dangling"),
+      )
+      .unwrap();
+      assert!(resolve("/data/dangling", true).is_err());
+    }
+    fs::remove_dir_all(dir).unwrap();
+  }
+
+  #[test]
+  fn test_fs_ops() {
+    let (dir, roots) = setup("ops");
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      extensions: vec![init_fs(roots)],
+      ..Default::default()
+    });
+    runtime
+      .execute_script(
+        "fs_test.js",
+        r#"
+        function assertEquals(actual, expected) {
+          if (actual !== expected) {
+            throw new Error(`expected ${expected}, got ${actual}`);
+          }
+        }
+        function assertThrows(fn, name) {
+          try {
+            fn();
+          } catch (err) {
+            assertEquals(err.name, name);
+            return;
+          }
+          throw new Error("expected an error");
+        }
+        const fs = Deno.core.fs;
+
+        assertEquals(fs.readTextFileSync("/config/app.json"), '{"name":"app"}');
+        fs.writeTextFileSync("/data/out.txt", "hello");
+        fs.writeFileSync("/data/sub/out.bin", new Uint8Array([1, 2, 3]));
+        assertEquals(fs.readFileSync("/data/sub/out.bin")[2], 3);
+        const stat = fs.statSync("/data/out.txt");
+        assertEquals(stat.isFile, true);
+        assertEquals(stat.isDirectory, false);
+        assertEquals(stat.size, 5);
+        assertEquals(typeof stat.mtime, "number");
+        const entries = fs.readDirSync("/data");
+        assertEquals(entries.map((e) => e.name).join(), "out.txt,sub");
+        assertEquals(entries[1].isDirectory, true);
+        assertThrows(() => fs.readFileSync("/data/missing.txt"), "NotFound");
+        assertThrows(() => fs.readFileSync("/secret.txt"), "PermissionDenied");
+        assertThrows(
+          () => fs.writeTextFileSync("/config/app.json", ""),
+          "PermissionDenied",
+        );
+        "#,
+      )
+      .unwrap();
+    drop(runtime);
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
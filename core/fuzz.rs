@@ -0,0 +1,205 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+//! Entry points for fuzzing the boundary between Rust and `01_core.js`, used
+//! by the cargo-fuzz targets in `core/fuzz`. Only compiled with
+//! `--cfg fuzzing`, which cargo-fuzz sets.
+//!
+//! The input bytes are decoded into arbitrary JavaScript values (see
+//! `decode_value()`), so every input is valid and the fuzzer explores the
+//! values themselves rather than a serialization format. Errors thrown by
+//! JavaScript are expected and returned; panics and crashes are bugs.
+//!
+//! Run a target from the `core` directory with eg.
+//! `cargo +nightly fuzz run op_responses`.
+
+use crate::runtime::exception_to_err_result;
+use crate::JsRuntime;
+use anyhow::Error;
+
+/// Maximum nesting of decoded arrays and objects.
+const MAX_DEPTH: usize = 4;
+/// Maximum number of values passed to a single call into JavaScript.
+const MAX_ARGS: usize = 64;
+
+/// Calls the callback that resolves async ops (`Deno.core.opresolve`) with
+/// values decoded from `data`, as if ops had completed with arbitrary
+/// promise ids and results, then runs microtasks.
+pub fn feed_op_responses(
+  runtime: &mut JsRuntime,
+  mut data: &[u8],
+) -> Result<(), Error> {
+  let state_rc = JsRuntime::state(runtime.v8_isolate());
+  let js_recv_cb_handle = state_rc.borrow().js_recv_cb.clone().unwrap();
+  let scope = &mut runtime.handle_scope();
+  let mut args = vec![];
+  while !data.is_empty() && args.len() < MAX_ARGS {
+    args.push(decode_value(scope, &mut data, 0));
+  }
+
+  let tc_scope = &mut v8::TryCatch::new(scope);
+  let js_recv_cb = js_recv_cb_handle.open(tc_scope);
+  let this = v8::undefined(tc_scope).into();
+  js_recv_cb.call(tc_scope, this, &args);
+  if let Some(exception) = tc_scope.exception() {
+    return exception_to_err_result(tc_scope, exception, false);
+  }
+  tc_scope.perform_microtask_checkpoint();
+  Ok(())
+}
+
+/// Dispatches an op through `Deno.core.opcallSync()` or
+/// `Deno.core.opcallAsync()`, with the op id, promise id and arguments decoded
+/// from `data`. Async ops complete when the event loop is polled.
+pub fn dispatch_op(
+  runtime: &mut JsRuntime,
+  mut data: &[u8],
+) -> Result<(), Error> {
+  let scope = &mut runtime.handle_scope();
+  let is_async = take(&mut data, 1).first().map_or(false, |b| b & 1 == 1);
+  let op_id = match *take(&mut data, 2) {
+    [a, b] => u16::from_le_bytes([a, b]),
+    _ => 0,
+  };
+  let mut args =
+    vec![v8::Integer::new_from_unsigned(scope, op_id.into()).into()];
+  if is_async {
+    // The promise id is left arbitrary too.
+    args.push(decode_value(scope, &mut data, 0));
+  }
+  args.push(decode_value(scope, &mut data, 0));
+  args.push(decode_value(scope, &mut data, 0));
+
+  let tc_scope = &mut v8::TryCatch::new(scope);
+  let name = if is_async {
+    "opcallAsync"
+  } else {
+    "opcallSync"
+  };
+  let opcall = get_core_fn(tc_scope, name);
+  let this = v8::undefined(tc_scope).into();
+  opcall.call(tc_scope, this, &args);
+  match tc_scope.exception() {
+    None => Ok(()),
+    Some(exception) => exception_to_err_result(tc_scope, exception, false),
+  }
+}
+
+fn get_core_fn<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  name: &str,
+) -> v8::Local<'s, v8::Function> {
+  let mut value: v8::Local<v8::Value> =
+    scope.get_current_context().global(scope).into();
+  for key in ["Deno", "core", name] {
+    let key = v8::String::new(scope, key).unwrap();
+    let object = v8::Local::<v8::Object>::try_from(value).unwrap();
+    value = object.get(scope, key.into()).unwrap();
+  }
+  v8::Local::<v8::Function>::try_from(value).unwrap()
+}
+
+/// Removes and returns up to `n` bytes from the front of `data`.
+fn take<'a>(data: &mut &'a [u8], n: usize) -> &'a [u8] {
+  let (head, tail) = data.split_at(n.min(data.len()));
+  *data = tail;
+  head
+}
+
+/// Decodes a JavaScript value from the front of `data`. A tag byte selects the
+/// type, followed by its contents; running out of bytes yields `undefined`.
+pub fn decode_value<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  data: &mut &[u8],
+  depth: usize,
+) -> v8::Local<'s, v8::Value> {
+  let tag = match take(data, 1).first() {
+    Some(tag) => *tag,
+    None => return v8::undefined(scope).into(),
+  };
+  let len = |data: &mut &[u8]| take(data, 1).first().map_or(0, |b| *b as usize);
+  match tag % 9 {
+    0 => v8::undefined(scope).into(),
+    1 => v8::null(scope).into(),
+    2 => v8::Boolean::new(scope, tag & 0x10 != 0).into(),
+    3 => {
+      let mut bytes = [0; 4];
+      let src = take(data, 4);
+      bytes[..src.len()].copy_from_slice(src);
+      v8::Integer::new(scope, i32::from_le_bytes(bytes)).into()
+    }
+    4 => {
+      let mut bytes = [0; 8];
+      let src = take(data, 8);
+      bytes[..src.len()].copy_from_slice(src);
+      v8::Number::new(scope, f64::from_le_bytes(bytes)).into()
+    }
+    5 => {
+      let n = len(data);
+      let bytes = take(data, n);
+      v8::String::new_from_utf8(scope, bytes, v8::NewStringType::Normal)
+        .unwrap()
+        .into()
+    }
+    6 if depth < MAX_DEPTH => {
+      let n = len(data) % 16;
+      let elements: Vec<_> = (0..n)
+        .map(|_| decode_value(scope, data, depth + 1))
+        .collect();
+      v8::Array::new_with_elements(scope, &elements).into()
+    }
+    7 if depth < MAX_DEPTH => {
+      let object = v8::Object::new(scope);
+      for _ in 0..len(data) % 16 {
+        let key = decode_value(scope, data, depth + 1);
+        let value = decode_value(scope, data, depth + 1);
+        object.set(scope, key, value);
+      }
+      object.into()
+    }
+    8 => {
+      let n = len(data);
+      let bytes = take(data, n).to_vec().into_boxed_slice();
+      let backing_store =
+        v8::ArrayBuffer::new_backing_store_from_boxed_slice(bytes);
+      let buffer = v8::ArrayBuffer::with_backing_store(
+        scope,
+        &backing_store.make_shared(),
+      );
+      v8::Uint8Array::new(scope, buffer, 0, n).unwrap().into()
+    }
+    _ => v8::undefined(scope).into(),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_fuzz_entry_points() {
+    let mut runtime = JsRuntime::new(Default::default());
+    // Malformed responses for promise ids that don't exist must not crash.
+    let _ = feed_op_responses(&mut runtime, &[3, 7, 0, 0, 0, 5, 2, b'h', b'i']);
+    let _ = feed_op_responses(&mut runtime, &[6, 3, 1, 8, 2, 1, 2, 7]);
+    // Invalid arguments are returned to JS as an error result.
+    let op_id = runtime
+      .op_state()
+      .borrow()
+      .op_table
+      .op_infos()
+      .into_iter()
+      .find(|info| info.name == "op_close")
+      .unwrap()
+      .id;
+    let [a, b] = (op_id as u16).to_le_bytes();
+    dispatch_op(&mut runtime, &[0, a, b, 5, 1, b'x']).unwrap();
+    let err = dispatch_op(&mut runtime, &[0, 0xff, 0xff]).unwrap_err();
+    assert!(err.to_string().contains("Unknown op id: 65535"), "{}", err);
+    let _ = dispatch_op(&mut runtime, &[1, 0xff, 0xff, 4]);
+
+    let scope = &mut runtime.handle_scope();
+    let value = decode_value(scope, &mut &[7, 1, 5, 1, b'a', 3, 42][..], 0);
+    let json = v8::json::stringify(scope, value).unwrap();
+    assert_eq!(json.to_rust_string_lossy(scope), r#"{"a":42}"#);
+  }
+}
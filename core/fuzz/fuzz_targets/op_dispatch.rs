@@ -0,0 +1,17 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+#![no_main]
+use deno_core::fuzz::dispatch_op;
+use deno_core::JsRuntime;
+use libfuzzer_sys::fuzz_target;
+use std::task::Context;
+
+fuzz_target!(|data: &[u8]| {
+  let mut runtime = JsRuntime::new(Default::default());
+  if dispatch_op(&mut runtime, data).is_ok() {
+    // Let async ops complete, without waiting for ones that never do.
+    let waker = deno_core::futures::task::noop_waker();
+    let mut cx = Context::from_waker(&waker);
+    let _ = runtime.poll_event_loop(&mut cx, false);
+  }
+});
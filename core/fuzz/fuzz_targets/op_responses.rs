@@ -0,0 +1,11 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+#![no_main]
+use deno_core::fuzz::feed_op_responses;
+use deno_core::JsRuntime;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let mut runtime = JsRuntime::new(Default::default());
+  let _ = feed_op_responses(&mut runtime, data);
+});
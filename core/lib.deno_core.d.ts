@@ -14,13 +14,23 @@ declare namespace Deno {
       b?: any,
     ): any;
 
-    /** Call an op in Rust, and asynchronously receive the result. */
+    /**
+     * Call an op in Rust, and asynchronously receive the result. The op is
+     * canceled when `options.signal` aborts.
+     */
     function opAsync(
       opName: string,
       a?: any,
       b?: any,
+      options?: { signal?: AbortSignal },
     ): Promise<any>;
 
+    /**
+     * Cancel an async op called with a `signal`, given the id saved on its
+     * promise. Its promise rejects with a `Canceled` error.
+     */
+    function cancelOp(promiseId: number): void;
+
     /** Mark following promise as "ref", ie. event loop won't exit
      * until all "ref" promises are resolved. All async ops are "ref" by default. */
     function refOp(promiseId: number): void;
@@ -35,6 +45,22 @@ declare namespace Deno {
      */
     function ops(): Record<string, number>;
 
+    /**
+     * Retrieve all registered ops, ordered by op id, along with the metadata
+     * declared for them. `metadata` is null for ops registered without any.
+     */
+    function opMetadata(): OpInfo[];
+
+    export interface OpInfo {
+      id: number;
+      name: string;
+      metadata: {
+        isAsync: boolean;
+        args: string[];
+        permission: string | null;
+      } | null;
+    }
+
     /**
      * Retrieve a list of all open resources, in the form of a map that maps
      * resource id to the resource name.
@@ -53,6 +79,19 @@ declare namespace Deno {
      */
     function tryClose(rid: number): void;
 
+    /**
+     * Call `fn(rid)`, closing the resource if it throws or if the promise it
+     * returns rejects. The result of `fn` is returned as is.
+     */
+    function closeOnError<T>(rid: number, fn: (rid: number) => T): T;
+
+    /**
+     * Tie a finalizer added with `OpState::add_finalizer()` to the lifetime of
+     * `object`. The finalizer runs some time after `object` is garbage
+     * collected.
+     */
+    function registerFinalizer(object: object, finalizerId: number): void;
+
     /**
      * Read from a (stream) resource that implements read()
      */
@@ -68,11 +107,83 @@ declare namespace Deno {
      */
     function shutdown(rid: number): Promise<void>;
 
-    /** Get heap stats for current isolate/worker */
-    function heapStats(): Record<string, number>;
+    /**
+     * Subscribe to a topic of the broadcast bus shared with other runtimes.
+     * `recv()` resolves with null once the subscription is closed.
+     */
+    function broadcastSubscribe(topic: string): BroadcastSubscription;
 
-    /** Encode a string to its Uint8Array representation. */
-    function encode(input: string): Uint8Array;
+    interface BroadcastSubscription {
+      rid: number;
+      /** Returns the number of subscriptions the message was delivered to. */
+      publish(data: Uint8Array): number;
+      recv(): Promise<Uint8Array | null>;
+      close(): void;
+    }
+
+    /**
+     * Milliseconds elapsed since the runtime was created, from a monotonic
+     * clock. Also available as `performance.now()`.
+     */
+    function now(): number;
+
+    /**
+     * Logs a message through the `log` crate of the embedder, under the
+     * target "js", or "js::<target>" if a target is given. `fields` are
+     * appended to the message as `key=value` pairs, with the values
+     * serialized as JSON. When deno_core is built with the "tracing" feature,
+     * a `tracing` event carrying the target and fields is emitted instead.
+     */
+    function log(
+      level: "error" | "warn" | "info" | "debug" | "trace",
+      target: string | null | undefined,
+      message: string,
+      fields?: Record<string, unknown>,
+    ): void;
+
+    /** Returns a logger with a method per level, logging under `target`. */
+    function createLogger(target: string): Logger;
+
+    export interface Logger {
+      error(message: string, fields?: Record<string, unknown>): void;
+      warn(message: string, fields?: Record<string, unknown>): void;
+      info(message: string, fields?: Record<string, unknown>): void;
+      debug(message: string, fields?: Record<string, unknown>): void;
+      trace(message: string, fields?: Record<string, unknown>): void;
+    }
+
+    /**
+     * Formats a value for display like `console.log()` does. Only available
+     * with the extension returned by `init_console()`.
+     */
+    function inspect(
+      value: unknown,
+      options?: { depth?: number; colors?: boolean },
+    ): string;
+
+    /** Get memory usage of the current isolate/worker, in bytes. */
+    function memoryUsage(): MemoryUsage;
+
+    export interface MemoryUsage {
+      /** Physical memory used by the V8 heap. */
+      rss: number;
+      heapTotal: number;
+      heapUsed: number;
+      /** Memory held outside the V8 heap, eg. by ArrayBuffers. */
+      external: number;
+    }
+
+    /** Text encodings supported by `encode()` and `decode()`. */
+    type Encoding = "utf-8" | "latin1" | "utf-16le";
+
+    /**
+     * Encode a string to its Uint8Array representation, as UTF-8 by default.
+     * Encoding as latin1 truncates characters above U+00FF to their low byte.
+     */
+    function encode(input: string, encoding?: Encoding): Uint8Array;
+
+    /** Decode a string from its bytes, as UTF-8 by default. */
+    function decode(input: Uint8Array, encoding?: Encoding): string;
 
     /**
      * Set a callback that will be called when the WebAssembly streaming APIs
@@ -116,9 +227,24 @@ declare namespace Deno {
       cb: () => bool,
     ): void;
 
+    /**
+     * Add a callback that will be called when the embedder shuts the runtime
+     * down gracefully, after pending ops have completed. Ops can't be called
+     * from it.
+     */
+    function addBeforeExitCallback(
+      cb: () => void,
+    ): void;
+
     /**
      * Set a callback that will be called when a promise without a .catch
-     * handler is rejected. Returns the old handler or undefined.
+     * handler is rejected, when a handler is added to such a promise later,
+     * and when an already settled promise is rejected or resolved again.
+     * `type` is one of the `promiseRejectEvents`; together with the promise
+     * this is enough to implement the "unhandledrejection" and
+     * "rejectionhandled" events. Setting a callback takes over reporting of
+     * unhandled rejections from the runtime. Returns the old handler or
+     * undefined.
      */
     function setPromiseRejectCallback(
       cb: PromiseRejectCallback,
@@ -130,16 +256,70 @@ declare namespace Deno {
       reason: any,
     ) => void;
 
+    /** Values of the `type` argument of a `PromiseRejectCallback`. */
+    const promiseRejectEvents: {
+      readonly rejectWithNoHandler: 0;
+      readonly handlerAddedAfterReject: 1;
+      readonly rejectAfterResolved: 2;
+      readonly resolveAfterResolved: 3;
+    };
+
     /**
      * Set a callback that will be called when an exception isn't caught
-     * by any try/catch handlers. Currently only invoked when the callback
-     * to setPromiseRejectCallback() throws an exception but that is expected
-     * to change in the future. Returns the old handler or undefined.
+     * by any try/catch handlers: exceptions thrown by callbacks run from the
+     * event loop (op completions, macrotasks and next ticks), promise
+     * rejections not handled by setUnhandledRejectionHandler() or the
+     * embedder, and exceptions thrown by the callback to
+     * setPromiseRejectCallback(). If the callback returns normally the
+     * exception is considered handled; if it throws, the thrown exception
+     * fails the runtime. Returns the old handler or undefined.
      */
     function setUncaughtExceptionCallback(
       cb: UncaughtExceptionCallback,
     ): undefined | UncaughtExceptionCallback;
 
     export type UncaughtExceptionCallback = (err: any) => void;
+
+    /**
+     * Retain a function so it can be called from Rust later. Returns an id
+     * that ops accept as a `JsCallable`.
+     */
+    function createCallable(cb: (...args: any[]) => any): number;
+
+    /**
+     * Create a context with a separate global object and no `Deno.core`,
+     * like Node's `vm.createContext()`. The own enumerable properties of
+     * `sandbox` are copied onto the new global. Returns an id that is valid
+     * until `disposeContext()` is called.
+     */
+    function createContext(sandbox?: Record<string, unknown>): number;
+
+    /**
+     * Run a script in a context created with `createContext()` and return
+     * its completion value. Exceptions are rethrown in the calling context.
+     */
+    function runInContext(
+      code: string,
+      contextId: number,
+      filename?: string,
+    ): any;
+
+    /** Release a context created with `createContext()`. */
+    function disposeContext(contextId: number): boolean;
+
+    /**
+     * Set a handler that is called from the event loop for every promise that
+     * was rejected without a handler and still has none. Returning true marks
+     * the rejection as handled; otherwise it fails the runtime like it would
+     * without a handler. Returns the old handler or undefined.
+     */
+    function setUnhandledRejectionHandler(
+      cb: UnhandledRejectionHandler,
+    ): undefined | UnhandledRejectionHandler;
+
+    export type UnhandledRejectionHandler = (
+      promise: Promise,
+      reason: any,
+    ) => boolean;
   }
 }
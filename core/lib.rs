@@ -2,10 +2,23 @@
 mod async_cancel;
 mod async_cell;
 mod bindings;
+mod broadcast;
+mod buffer_view;
+mod clock;
+mod code_cache;
+mod console;
+mod crypto;
+mod disk_cache;
 pub mod error;
 mod error_codes;
 mod extensions;
+#[cfg(feature = "fetch")]
+mod fetch;
 mod flags;
+#[cfg(feature = "fs")]
+mod fs;
+#[cfg(any(test, fuzzing))]
+pub mod fuzz;
 mod gotham_state;
 mod inspector;
 mod module_specifier;
@@ -13,10 +26,23 @@ mod modules;
 mod normalize_path;
 mod ops;
 mod ops_builtin;
+mod ops_codec;
 mod ops_json;
 mod ops_metrics;
+pub mod plugin_api;
+mod repl;
 mod resources;
 mod runtime;
+mod runtime_handle;
+mod runtime_template;
+mod sanitizer;
+mod slow_ops;
+pub mod test_util;
+mod trace;
+mod transpile;
+mod watchdog;
+#[cfg(feature = "web")]
+mod web;
 
 // Re-exports
 pub use anyhow;
@@ -44,59 +70,138 @@ pub use crate::async_cell::AsyncRefCell;
 pub use crate::async_cell::AsyncRefFuture;
 pub use crate::async_cell::RcLike;
 pub use crate::async_cell::RcRef;
+pub use crate::broadcast::BroadcastBus;
+pub use crate::broadcast::BroadcastSubscription;
+pub use crate::broadcast::DEFAULT_BROADCAST_CAPACITY;
+pub use crate::buffer_view::BufferRef;
+pub use crate::buffer_view::BufferRefMut;
+pub use crate::buffer_view::BufferView;
+pub use crate::clock::Clock;
+pub use crate::clock::ManualClock;
+pub use crate::clock::RealClock;
+pub use crate::code_cache::code_cache_source_hash;
+pub use crate::code_cache::CodeCacheStats;
+pub use crate::code_cache::CodeCacheStore;
+pub use crate::code_cache::DiskCodeCacheStore;
+pub use crate::code_cache::MemoryCodeCacheStore;
+pub use crate::console::init_console;
+pub use crate::console::ConsoleOptions;
+pub use crate::crypto::init_crypto;
+#[cfg(feature = "fetch")]
+pub use crate::fetch::init_fetch;
 pub use crate::flags::v8_set_flags;
+#[cfg(feature = "fs")]
+pub use crate::fs::init_fs;
+#[cfg(feature = "fs")]
+pub use crate::fs::FsRoots;
 pub use crate::inspector::InspectorSessionProxy;
 pub use crate::inspector::JsRuntimeInspector;
 pub use crate::inspector::LocalInspectorSession;
+pub use crate::module_specifier::register_custom_scheme;
 pub use crate::module_specifier::resolve_import;
 pub use crate::module_specifier::resolve_path;
 pub use crate::module_specifier::resolve_url;
 pub use crate::module_specifier::resolve_url_or_path;
+pub use crate::module_specifier::CustomScheme;
 pub use crate::module_specifier::ModuleResolutionError;
 pub use crate::module_specifier::ModuleSpecifier;
+pub use crate::module_specifier::MountResolver;
 pub use crate::module_specifier::DUMMY_SPECIFIER;
+pub use crate::modules::module_source_hash;
 pub use crate::modules::FsModuleLoader;
+pub use crate::modules::MemoryModuleLoader;
+pub use crate::modules::ModuleGraphLimits;
 pub use crate::modules::ModuleId;
+pub use crate::modules::ModuleLoadEvent;
+pub use crate::modules::ModuleLoadEventFn;
 pub use crate::modules::ModuleLoadId;
 pub use crate::modules::ModuleLoader;
+pub use crate::modules::ModuleLockMap;
 pub use crate::modules::ModuleSource;
 pub use crate::modules::ModuleSourceFuture;
 pub use crate::modules::NoopModuleLoader;
 pub use crate::runtime::CompiledWasmModuleStore;
+pub use crate::runtime::CompletionPolicy;
+pub use crate::runtime::CoreLockMode;
 pub use crate::runtime::SharedArrayBufferStore;
 // TODO(bartlomieju): this struct should be implementation
 // detail nad not be public
 pub use crate::modules::RecursiveModuleLoad;
 pub use crate::normalize_path::normalize_path;
 pub use crate::ops::serialize_op_result;
+pub use crate::ops::FinalizerFn;
+pub use crate::ops::FinalizerId;
 pub use crate::ops::Op;
 pub use crate::ops::OpAsyncFuture;
 pub use crate::ops::OpCall;
+pub use crate::ops::OpCallInfo;
 pub use crate::ops::OpFn;
 pub use crate::ops::OpId;
+pub use crate::ops::OpInfo;
+pub use crate::ops::OpMetadata;
 pub use crate::ops::OpPayload;
 pub use crate::ops::OpResult;
+pub use crate::ops::OpScalar;
 pub use crate::ops::OpState;
 pub use crate::ops::OpTable;
+pub use crate::ops::PermissionsHandler;
 pub use crate::ops::PromiseId;
 pub use crate::ops_builtin::op_close;
 pub use crate::ops_builtin::op_print;
 pub use crate::ops_builtin::op_resources;
+pub use crate::ops_codec::op_async_with_codec;
+pub use crate::ops_codec::op_sync_with_codec;
+pub use crate::ops_codec::JsonCodec;
+pub use crate::ops_codec::OpCodec;
 pub use crate::ops_json::op_async;
+pub use crate::ops_json::op_async_scalar;
 pub use crate::ops_json::op_sync;
+pub use crate::ops_json::op_sync_scalar;
+pub use crate::ops_json::op_sync_scratch;
 pub use crate::ops_json::void_op_async;
 pub use crate::ops_json::void_op_sync;
+pub use crate::repl::Completion;
 pub use crate::resources::AsyncResult;
 pub use crate::resources::Resource;
 pub use crate::resources::ResourceId;
 pub use crate::resources::ResourceTable;
+pub use crate::runtime::init_v8_platform;
+pub use crate::runtime::AfterTickFn;
+pub use crate::runtime::BeforeTickFn;
 pub use crate::runtime::GetErrorClassFn;
+pub use crate::runtime::HeapLimitDiagnostics;
+pub use crate::runtime::InterruptHandle;
+pub use crate::runtime::JsCallable;
 pub use crate::runtime::JsErrorCreateFn;
 pub use crate::runtime::JsRuntime;
+pub use crate::runtime::MemoryLimit;
+pub use crate::runtime::PendingOpInfo;
+pub use crate::runtime::PendingOpsOverflow;
+pub use crate::runtime::PendingStateDump;
+pub use crate::runtime::PrintFn;
 pub use crate::runtime::RuntimeOptions;
+pub use crate::runtime::RuntimeStats;
+pub use crate::runtime::ScriptOriginOptions;
+pub use crate::runtime::ScriptSource;
 pub use crate::runtime::Snapshot;
+pub use crate::runtime::TerminationHandle;
+pub use crate::runtime::UnhandledRejectionFn;
+pub use crate::runtime_handle::JsRuntimeHandle;
+pub use crate::runtime_template::RuntimeTemplate;
+pub use crate::slow_ops::SlowOp;
+pub use crate::slow_ops::SlowOpDetector;
+pub use crate::slow_ops::SlowOpFn;
+pub use crate::transpile::transpile_cache_key;
+pub use crate::transpile::DiskTranspileCache;
+pub use crate::transpile::MediaTranspiler;
+pub use crate::transpile::MediaType;
+pub use crate::transpile::TranspileCache;
+pub use crate::watchdog::Watchdog;
+#[cfg(feature = "web")]
+pub use crate::web::init_web;
 // pub use crate::runtime_modules::include_js_files!;
 pub use crate::extensions::Extension;
+pub use crate::extensions::OpMetadataPair;
 pub use crate::extensions::OpMiddlewareFn;
 pub use crate::extensions::OpPair;
 
@@ -1,10 +1,12 @@
 // Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
 
 use crate::normalize_path;
+use std::collections::HashMap;
 use std::env::current_dir;
 use std::error::Error;
 use std::fmt;
 use std::path::PathBuf;
+use std::sync::RwLock;
 use url::ParseError;
 use url::Url;
 
@@ -17,6 +19,7 @@ pub enum ModuleResolutionError {
   InvalidBaseUrl(ParseError),
   InvalidPath(PathBuf),
   ImportPrefixMissing(String, Option<String>),
+  OutsideMountPoint(String),
 }
 use ModuleResolutionError::*;
 
@@ -46,6 +49,11 @@ impl fmt::Display for ModuleResolutionError {
           None => format!(""),
         }
       ),
+      OutsideMountPoint(ref specifier) => write!(
+        f,
+        "Module specifier \"{}\" resolves outside of its mount point",
+        specifier
+      ),
     }
   }
 }
@@ -53,6 +61,70 @@ impl fmt::Display for ModuleResolutionError {
 /// Resolved module specifier
 pub type ModuleSpecifier = Url;
 
+lazy_static::lazy_static! {
+  static ref CUSTOM_SCHEMES: RwLock<HashMap<String, CustomScheme>> =
+    Default::default();
+}
+
+/// How `resolve_import()` resolves relative specifiers imported from modules
+/// whose URL has a custom scheme, see `register_custom_scheme()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CustomScheme {
+  /// Everything after the scheme is a path, eg. `plugin:foo/mod.js`, from
+  /// which `./util.js` resolves to `plugin:foo/util.js` and `/util.js` to
+  /// `plugin:util.js`. URLs with an authority, like `plugin://foo/mod.js`,
+  /// already resolve that way and are left to the URL parser.
+  Path,
+  /// Relative specifiers resolve against a fixed base URL, eg. so that the
+  /// modules of an opaque `tenant:acme` URL import `./lib.js` from
+  /// `https://example.com/tenant/lib.js`.
+  Base(Url),
+}
+
+/// Registers how relative imports resolve from modules with URLs of the
+/// custom `scheme`, for the whole process. Without registration, relative
+/// imports from URLs like `plugin:foo/mod.js` fail, as such URLs can't be
+/// used as a base by the URL parser.
+pub fn register_custom_scheme(scheme: &str, resolution: CustomScheme) {
+  CUSTOM_SCHEMES
+    .write()
+    .unwrap()
+    .insert(scheme.to_ascii_lowercase(), resolution);
+}
+
+fn resolve_custom_scheme(
+  specifier: &str,
+  base: &Url,
+) -> Option<Result<ModuleSpecifier, ModuleResolutionError>> {
+  let schemes = CUSTOM_SCHEMES.read().unwrap();
+  match schemes.get(base.scheme())? {
+    CustomScheme::Path if base.cannot_be_a_base() => {
+      let base_path = base.path();
+      let path = match specifier.strip_prefix('/') {
+        Some(path) => path.to_string(),
+        None => {
+          let dir = &base_path[..base_path.rfind('/').map_or(0, |i| i + 1)];
+          format!("{}{}", dir, specifier)
+        }
+      };
+      let mut segments = vec![];
+      for segment in path.split('/') {
+        match segment {
+          "." => {}
+          ".." => {
+            segments.pop();
+          }
+          segment => segments.push(segment),
+        }
+      }
+      let url = format!("{}:{}", base.scheme(), segments.join("/"));
+      Some(Url::parse(&url).map_err(InvalidUrl))
+    }
+    CustomScheme::Path => None,
+    CustomScheme::Base(base) => Some(base.join(specifier).map_err(InvalidUrl)),
+  }
+}
+
 /// Resolves module using this algorithm:
 /// <https://html.spec.whatwg.org/multipage/webappapis.html#resolve-a-module-specifier>
 pub fn resolve_import(
@@ -96,6 +168,9 @@ pub fn resolve_import(
       } else {
         Url::parse(base).map_err(InvalidBaseUrl)?
       };
+      if let Some(result) = resolve_custom_scheme(specifier, &base) {
+        return result;
+      }
       base.join(specifier).map_err(InvalidUrl)?
     }
 
@@ -109,6 +184,92 @@ pub fn resolve_import(
   Ok(url)
 }
 
+/// Maps virtual specifiers onto roots defined by the embedder, eg.
+/// `/app/main.js` onto `file:///srv/tenant-1/main.js` or `@scripts/util.js`
+/// onto `memory:///scripts/util.js`, for sandboxed script hosts. Call
+/// `resolve()` from `ModuleLoader::resolve()`; loading the resolved URLs is
+/// up to the loader.
+///
+/// Specifiers can't escape the root they resolve to: neither virtual
+/// specifiers with `..` segments, nor relative or absolute path imports from
+/// modules under a root. Full URLs are resolved as is.
+#[derive(Debug, Clone, Default)]
+pub struct MountResolver {
+  /// Longest prefix first.
+  mounts: Vec<(String, ModuleSpecifier)>,
+}
+
+impl MountResolver {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Mounts `root` at `prefix`, eg. `/app` or `@scripts`. `root` must be a
+  /// hierarchical URL; a trailing slash is added to its path if missing.
+  pub fn mount(
+    &mut self,
+    prefix: &str,
+    mut root: ModuleSpecifier,
+  ) -> &mut Self {
+    if !root.path().ends_with('/') {
+      let path = format!("{}/", root.path());
+      root.set_path(&path);
+    }
+    self
+      .mounts
+      .push((prefix.trim_end_matches('/').to_string(), root));
+    self
+      .mounts
+      .sort_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()));
+    self
+  }
+
+  pub fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+  ) -> Result<ModuleSpecifier, ModuleResolutionError> {
+    for (prefix, root) in &self.mounts {
+      let path = match specifier.strip_prefix(prefix.as_str()) {
+        Some(path) if path.is_empty() || path.starts_with('/') => path,
+        _ => continue,
+      };
+      let url = root
+        .join(path.trim_start_matches('/'))
+        .map_err(InvalidUrl)?;
+      return within(url, root, specifier);
+    }
+
+    let url = resolve_import(specifier, referrer)?;
+    // Roots may be nested, so confine the import to the innermost one
+    // containing the referrer.
+    let referrer_root = self
+      .mounts
+      .iter()
+      .map(|(_, root)| root)
+      .filter(|root| referrer.starts_with(root.as_str()))
+      .max_by_key(|root| root.as_str().len());
+    match referrer_root {
+      Some(root) if Url::parse(specifier).is_err() => {
+        within(url, root, specifier)
+      }
+      _ => Ok(url),
+    }
+  }
+}
+
+fn within(
+  url: ModuleSpecifier,
+  root: &ModuleSpecifier,
+  specifier: &str,
+) -> Result<ModuleSpecifier, ModuleResolutionError> {
+  if url.as_str().starts_with(root.as_str()) {
+    Ok(url)
+  } else {
+    Err(OutsideMountPoint(specifier.to_string()))
+  }
+}
+
 /// Converts a string representing an absolute URL into a ModuleSpecifier.
 pub fn resolve_url(
   url_str: &str,
@@ -515,4 +676,134 @@ mod tests {
     let expected = resolve_url("http://deno.land/x/mod.ts").unwrap();
     assert_eq!(actual, expected);
   }
+
+  #[test]
+  fn test_resolve_import_custom_scheme() {
+    let err = resolve_import("./b.js", "test-plugin:foo/a.js").unwrap_err();
+    assert!(matches!(err, InvalidUrl(_)));
+
+    register_custom_scheme("TEST-PLUGIN", CustomScheme::Path);
+    let base = Url::parse("https://example.com/tenant/").unwrap();
+    register_custom_scheme("test-tenant", CustomScheme::Base(base));
+
+    let tests = vec![
+      ("./b.js", "test-plugin:foo/a.js", "test-plugin:foo/b.js"),
+      (
+        "../b.js",
+        "test-plugin:foo/bar/a.js",
+        "test-plugin:foo/b.js",
+      ),
+      ("../../../b.js", "test-plugin:foo/a.js", "test-plugin:b.js"),
+      ("/b.js?v=1", "test-plugin:foo/a.js", "test-plugin:b.js?v=1"),
+      ("./b.js", "test-plugin:a.js", "test-plugin:b.js"),
+      ("./b.js", "test-plugin://foo/a.js", "test-plugin://foo/b.js"),
+      (
+        "./lib.js",
+        "test-tenant:acme",
+        "https://example.com/tenant/lib.js",
+      ),
+      ("/lib.js", "test-tenant:acme", "https://example.com/lib.js"),
+      ("test-tenant:other", "test-plugin:a.js", "test-tenant:other"),
+    ];
+    for (specifier, base, expected) in tests {
+      let url = resolve_import(specifier, base).unwrap();
+      assert_eq!(url.as_str(), expected, "{} from {}", specifier, base);
+    }
+    assert!(matches!(
+      resolve_import("b.js", "test-plugin:foo/a.js"),
+      Err(ImportPrefixMissing(..))
+    ));
+  }
+
+  #[test]
+  fn test_mount_resolver() {
+    let mut resolver = MountResolver::new();
+    resolver
+      .mount("/app", Url::parse("file:///srv/tenant-1").unwrap())
+      .mount(
+        "/app/vendor/",
+        Url::parse("https://example.com/v/").unwrap(),
+      )
+      .mount("@scripts", Url::parse("memory:///scripts/").unwrap());
+
+    let tests = vec![
+      ("/app/main.js", "", "file:///srv/tenant-1/main.js"),
+      ("/app/a/../b.js", "", "file:///srv/tenant-1/b.js"),
+      ("/app/vendor/x.js", "", "https://example.com/v/x.js"),
+      ("@scripts/util.js", "", "memory:///scripts/util.js"),
+      (
+        "./b.js",
+        "file:///srv/tenant-1/a/a.js",
+        "file:///srv/tenant-1/a/b.js",
+      ),
+      (
+        "../b.js",
+        "file:///srv/tenant-1/a/a.js",
+        "file:///srv/tenant-1/b.js",
+      ),
+      (
+        "@scripts/util.js",
+        "file:///srv/tenant-1/a.js",
+        "memory:///scripts/util.js",
+      ),
+      (
+        "https://deno.land/x.js",
+        "file:///srv/tenant-1/a.js",
+        "https://deno.land/x.js",
+      ),
+      ("./b.js", "file:///other/a.js", "file:///other/b.js"),
+    ];
+    for (specifier, referrer, expected) in tests {
+      let url = resolver.resolve(specifier, referrer).unwrap();
+      assert_eq!(url.as_str(), expected, "{} from {}", specifier, referrer);
+    }
+
+    let escapes = vec![
+      ("/app/../etc/passwd", ""),
+      ("/app/%2e%2e/etc/passwd", ""),
+      ("/app/http://example.com/x.js", ""),
+      ("@scripts/../../x.js", ""),
+      ("../../b.js", "file:///srv/tenant-1/a/a.js"),
+      ("/etc/passwd", "file:///srv/tenant-1/a.js"),
+    ];
+    for (specifier, referrer) in escapes {
+      assert_eq!(
+        resolver.resolve(specifier, referrer),
+        Err(OutsideMountPoint(specifier.to_string())),
+        "{} from {}",
+        specifier,
+        referrer
+      );
+    }
+    assert!(matches!(
+      resolver.resolve("@scriptsx/a.js", "file:///srv/tenant-1/a.js"),
+      Err(ImportPrefixMissing(..))
+    ));
+  }
+
+  #[test]
+  fn test_mount_resolver_nested_roots() {
+    // The outer root is mounted under the longer prefix, so it comes first
+    // in prefix order even though the inner root is the closer match.
+    let mut resolver = MountResolver::new();
+    resolver
+      .mount("/app", Url::parse("file:///srv/app").unwrap())
+      .mount("/p", Url::parse("file:///srv/app/plugins").unwrap());
+
+    let url = resolver
+      .resolve("./b.js", "file:///srv/app/plugins/x/a.js")
+      .unwrap();
+    assert_eq!(url.as_str(), "file:///srv/app/plugins/x/b.js");
+    let url = resolver.resolve("./b.js", "file:///srv/app/a.js").unwrap();
+    assert_eq!(url.as_str(), "file:///srv/app/b.js");
+
+    assert_eq!(
+      resolver.resolve("../b.js", "file:///srv/app/plugins/a.js"),
+      Err(OutsideMountPoint("../b.js".to_string()))
+    );
+    assert_eq!(
+      resolver.resolve("../../b.js", "file:///srv/app/a/a.js"),
+      Err(OutsideMountPoint("../../b.js".to_string()))
+    );
+  }
 }
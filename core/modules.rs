@@ -1,17 +1,30 @@
 // Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
 
 use crate::bindings;
+use crate::code_cache::code_cache_source_hash;
+use crate::code_cache::CodeCacheStore;
 use crate::error::generic_error;
+use crate::error::range_error;
+use crate::error::ModuleIntegrityError;
 use crate::module_specifier::ModuleSpecifier;
-use crate::runtime::exception_to_err_result;
+use crate::runtime::compile_exception_to_err_result;
+use crate::runtime::ScriptSource;
+use crate::trace::trace_event;
+use crate::transpile::transpile_cache_key;
+use crate::transpile::MediaTranspiler;
+use crate::transpile::MediaType;
+use crate::transpile::TranspileCache;
 use crate::OpState;
 use anyhow::Error;
 use futures::future::FutureExt;
+use futures::future::TryFutureExt;
 use futures::stream::FuturesUnordered;
 use futures::stream::Stream;
 use futures::stream::StreamFuture;
 use futures::stream::TryStreamExt;
 use log::debug;
+use sha2::Digest;
+use sha2::Sha256;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
@@ -21,8 +34,11 @@ use std::pin::Pin;
 use std::rc::Rc;
 use std::sync::atomic::AtomicI32;
 use std::sync::atomic::Ordering;
+use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
 
 lazy_static::lazy_static! {
   pub static ref NEXT_LOAD_ID: AtomicI32 = AtomicI32::new(0);
@@ -42,17 +58,117 @@ pub type ModuleLoadId = i32;
 /// "`https://example.com/b.ts`" may point to "`https://example.com/c.ts`"
 /// By keeping track of specified and found URL we can alias modules and avoid
 /// recompiling the same code 3 times.
-// TODO(bartlomieju): I have a strong opinion we should store all redirects
-// that happened; not only first and final target. It would simplify a lot
-// of things throughout the codebase otherwise we may end up requesting
-// intermediate redirects from file loader.
+///
+/// Loaders that follow redirects should report every URL in between in
+/// `redirects`, so that all of them are aliased to the found module and
+/// `ModuleGraphLimits::max_redirects` can be enforced.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ModuleSource {
   pub code: String,
   pub module_url_specified: String,
   pub module_url_found: String,
+  /// The intermediate URLs of the redirect chain from `module_url_specified`
+  /// to `module_url_found`, in order and excluding both ends.
+  pub redirects: Vec<String>,
+}
+
+/// Limits on the size of the module graph a `JsRuntime` is allowed to load.
+///
+/// These are enforced while modules are being registered, so an untrusted
+/// script can't exhaust memory by importing an unbounded graph. A `None` value
+/// means the corresponding limit is not enforced.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct ModuleGraphLimits {
+  /// Maximum number of modules that can be registered in the module map.
+  pub max_modules: Option<usize>,
+  /// Maximum combined size, in bytes, of the source code of all registered
+  /// modules, as returned by the `ModuleLoader` (before transpiling).
+  pub max_total_source_bytes: Option<usize>,
+  /// Maximum number of dynamic imports (`import()` calls) that can be started.
+  pub max_dynamic_imports: Option<usize>,
+  /// Maximum number of redirects followed to load a single module, as reported
+  /// by the `ModuleLoader` in `ModuleSource`.
+  pub max_redirects: Option<usize>,
+}
+
+/// Known hashes of module sources, checked like a lockfile: the source of
+/// every module fetched by the `ModuleLoader` is hashed with
+/// `module_source_hash()` and the load fails with a `ModuleIntegrityError` if
+/// it doesn't match. Modules whose source is passed to
+/// `JsRuntime::load_main_module()` or `JsRuntime::load_side_module()` aren't
+/// checked.
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct ModuleLockMap {
+  /// Hashes by module URL, after redirects.
+  pub hashes: HashMap<String, String>,
+  /// Also fail to load modules that have no entry in `hashes`.
+  pub deny_unlisted: bool,
+}
+
+impl ModuleLockMap {
+  pub(crate) fn check(
+    &self,
+    module_source: &ModuleSource,
+  ) -> Result<(), ModuleIntegrityError> {
+    let specifier = &module_source.module_url_found;
+    let expected = self.hashes.get(specifier);
+    if expected.is_none() && !self.deny_unlisted {
+      return Ok(());
+    }
+    let actual = module_source_hash(&module_source.code);
+    if expected == Some(&actual) {
+      return Ok(());
+    }
+    Err(ModuleIntegrityError {
+      specifier: specifier.clone(),
+      expected: expected.cloned(),
+      actual,
+    })
+  }
 }
 
+/// Returns the hex encoded SHA-256 hash of a module's source, as used in a
+/// `ModuleLockMap`.
+pub fn module_source_hash(code: &str) -> String {
+  format!("{:x}", Sha256::digest(code.as_bytes()))
+}
+
+/// A step in loading, linking or evaluating a module, reported to the
+/// callback set with `RuntimeOptions::module_load_event_fn`.
+///
+/// Events are delivered synchronously as the steps happen. Modules are fetched
+/// concurrently, so the fetch events of different modules can interleave.
+#[derive(Debug, Clone, Copy)]
+pub enum ModuleLoadEvent<'a> {
+  /// A specifier is about to be resolved against its referrer.
+  ResolveStart {
+    specifier: &'a str,
+    referrer: &'a str,
+  },
+  /// The source of a module has been requested from the `ModuleLoader`.
+  FetchStart { specifier: &'a ModuleSpecifier },
+  /// The source of a module has been received from the `ModuleLoader`.
+  /// `found` differs from `specifier` if the module was redirected.
+  FetchFinish {
+    specifier: &'a str,
+    found: &'a str,
+    bytes: usize,
+  },
+  /// A module has been compiled and registered in the module map.
+  Compile {
+    specifier: &'a str,
+    id: ModuleId,
+    duration: Duration,
+  },
+  /// A module and all of its dependencies have been instantiated.
+  Instantiate { id: ModuleId, duration: Duration },
+  /// Evaluation of a module has started. If it uses top-level await,
+  /// evaluation finishes in a later tick of the event loop.
+  Evaluate { id: ModuleId },
+}
+
+pub type ModuleLoadEventFn = dyn Fn(&ModuleLoadEvent);
+
 pub type PrepareLoadFuture =
   dyn Future<Output = (ModuleLoadId, Result<RecursiveModuleLoad, Error>)>;
 pub type ModuleSourceFuture = dyn Future<Output = Result<ModuleSource, Error>>;
@@ -164,6 +280,7 @@ impl ModuleLoader for FsModuleLoader {
         code,
         module_url_specified: module_specifier.to_string(),
         module_url_found: module_specifier.to_string(),
+        redirects: vec![],
       };
       Ok(module)
     }
@@ -171,6 +288,85 @@ impl ModuleLoader for FsModuleLoader {
   }
 }
 
+/// Module loader serving modules from memory, eg. for tests or for embedders
+/// that bundle all their JavaScript at build time.
+///
+/// Specifiers are resolved against their referrer like with
+/// `FsModuleLoader`, except for aliases added with `add_alias()`, which are
+/// matched verbatim and can thus be bare specifiers.
+#[derive(Default)]
+pub struct MemoryModuleLoader {
+  modules: HashMap<ModuleSpecifier, String>,
+  aliases: HashMap<String, ModuleSpecifier>,
+}
+
+impl MemoryModuleLoader {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  /// Adds a module, replacing any previous module with the same specifier.
+  pub fn add_module(
+    &mut self,
+    specifier: ModuleSpecifier,
+    code: impl Into<String>,
+  ) -> &mut Self {
+    self.modules.insert(specifier, code.into());
+    self
+  }
+
+  /// Makes imports of `alias`, either verbatim or after being resolved to a
+  /// URL, load the module added as `target`. Both specifiers refer to the same
+  /// module instance.
+  pub fn add_alias(
+    &mut self,
+    alias: impl Into<String>,
+    target: ModuleSpecifier,
+  ) -> &mut Self {
+    self.aliases.insert(alias.into(), target);
+    self
+  }
+}
+
+impl ModuleLoader for MemoryModuleLoader {
+  fn resolve(
+    &self,
+    specifier: &str,
+    referrer: &str,
+    _is_main: bool,
+  ) -> Result<ModuleSpecifier, Error> {
+    if let Some(target) = self.aliases.get(specifier) {
+      return Ok(target.clone());
+    }
+    Ok(crate::resolve_import(specifier, referrer)?)
+  }
+
+  fn load(
+    &self,
+    module_specifier: &ModuleSpecifier,
+    _maybe_referrer: Option<ModuleSpecifier>,
+    _is_dyn_import: bool,
+  ) -> Pin<Box<ModuleSourceFuture>> {
+    let found = self
+      .aliases
+      .get(module_specifier.as_str())
+      .unwrap_or(module_specifier);
+    let result = match self.modules.get(found) {
+      Some(code) => Ok(ModuleSource {
+        code: code.clone(),
+        module_url_specified: module_specifier.to_string(),
+        module_url_found: found.to_string(),
+        redirects: vec![],
+      }),
+      None => Err(generic_error(format!(
+        "Module not found: \"{}\"",
+        module_specifier
+      ))),
+    };
+    async move { result }.boxed_local()
+  }
+}
+
 /// Describes the entrypoint of a recursive module load.
 #[derive(Debug)]
 enum LoadInit {
@@ -203,6 +399,7 @@ pub struct RecursiveModuleLoad {
   // of time to avoid already-borrowed errors.
   pub op_state: Rc<RefCell<OpState>>,
   pub loader: Rc<dyn ModuleLoader>,
+  load_event_fn: Option<Rc<ModuleLoadEventFn>>,
   pub pending: FuturesUnordered<Pin<Box<ModuleSourceFuture>>>,
   pub visited: HashSet<ModuleSpecifier>,
 }
@@ -234,6 +431,7 @@ impl RecursiveModuleLoad {
   fn new(init: LoadInit, module_map_rc: Rc<RefCell<ModuleMap>>) -> Self {
     let op_state = module_map_rc.borrow().op_state.clone();
     let loader = module_map_rc.borrow().loader.clone();
+    let load_event_fn = module_map_rc.borrow().load_event_fn.clone();
     let mut load = Self {
       id: NEXT_LOAD_ID.fetch_add(1, Ordering::SeqCst),
       root_module_id: None,
@@ -242,6 +440,7 @@ impl RecursiveModuleLoad {
       module_map_rc: module_map_rc.clone(),
       op_state,
       loader,
+      load_event_fn,
       pending: FuturesUnordered::new(),
       visited: HashSet::new(),
     };
@@ -270,6 +469,12 @@ impl RecursiveModuleLoad {
     }
   }
 
+  fn emit(&self, event: ModuleLoadEvent) {
+    if let Some(load_event_fn) = &self.load_event_fn {
+      load_event_fn(&event);
+    }
+  }
+
   pub async fn prepare(&self) -> Result<(), Error> {
     let op_state = self.op_state.clone();
     let (module_specifier, maybe_referrer) = match self.init {
@@ -299,6 +504,25 @@ impl RecursiveModuleLoad {
       .await
   }
 
+  /// Describes a failed load of `specifier`, eg. "failed to load
+  /// file:///b.js while loading file:///a.js (dynamic import from
+  /// file:///main.js)". Attached as context to errors returned by
+  /// `ModuleLoader::load()`.
+  fn load_error_context(
+    &self,
+    specifier: &ModuleSpecifier,
+    maybe_referrer: Option<&ModuleSpecifier>,
+  ) -> String {
+    let mut context = format!("failed to load {}", specifier);
+    if let Some(referrer) = maybe_referrer {
+      context.push_str(&format!(" while loading {}", referrer));
+    }
+    if let LoadInit::DynamicImport(_, ref referrer) = self.init {
+      context.push_str(&format!(" (dynamic import from {})", referrer));
+    }
+    context
+  }
+
   pub fn is_currently_loading_main_module(&self) -> bool {
     !self.is_dynamic_import()
       && matches!(self.init, LoadInit::Main(..))
@@ -311,12 +535,20 @@ impl RecursiveModuleLoad {
     module_source: &ModuleSource,
   ) -> Result<(), Error> {
     // Register the module in the module map unless it's already there. If the
-    // specified URL and the "true" URL are different, register the alias.
+    // specified URL and the "true" URL are different, register the specified
+    // URL and every intermediate redirect as aliases.
+    self.module_map_rc.borrow().check_redirects(module_source)?;
     if module_source.module_url_specified != module_source.module_url_found {
-      self.module_map_rc.borrow_mut().alias(
-        &module_source.module_url_specified,
-        &module_source.module_url_found,
-      );
+      let mut module_map = self.module_map_rc.borrow_mut();
+      let redirected_urls =
+        std::iter::once(&module_source.module_url_specified)
+          .chain(&module_source.redirects);
+      for url in redirected_urls {
+        module_map.alias(url, &module_source.module_url_found);
+      }
+    }
+    if self.is_dynamic_import() && self.state == LoadState::LoadingRoot {
+      self.module_map_rc.borrow_mut().track_dynamic_import()?;
     }
     let maybe_module_id = self
       .module_map_rc
@@ -330,12 +562,21 @@ impl RecursiveModuleLoad {
         );
         id
       }
-      None => self.module_map_rc.borrow_mut().new_module(
-        scope,
-        self.is_currently_loading_main_module(),
-        &module_source.module_url_found,
-        &module_source.code,
-      )?,
+      None => {
+        if let Some(lock_map) = &self.module_map_rc.borrow().lock_map {
+          lock_map.check(module_source)?;
+        }
+        self
+          .module_map_rc
+          .borrow()
+          .check_limits(&module_source.module_url_found, &module_source.code)?;
+        self.module_map_rc.borrow_mut().new_module(
+          scope,
+          self.is_currently_loading_main_module(),
+          &module_source.module_url_found,
+          &module_source.code,
+        )?
+      }
     };
 
     // Recurse the module's imports. There are two cases for each import:
@@ -355,24 +596,32 @@ impl RecursiveModuleLoad {
       let imports = self
         .module_map_rc
         .borrow()
-        .get_children(module_id)
+        .get_info_by_id(&module_id)
         .unwrap()
+        .import_specifiers
         .clone();
-      for specifier in imports {
-        if !self.visited.contains(&specifier) {
+      for specifier in imports.iter() {
+        if !self.visited.contains(specifier) {
           if let Some(module_id) =
             self.module_map_rc.borrow().get_id(specifier.as_str())
           {
             already_registered.push_back((module_id, specifier.clone()));
           } else {
-            let fut = self.loader.load(
-              &specifier,
-              Some(referrer.clone()),
-              self.is_dynamic_import(),
+            let context = self.load_error_context(specifier, Some(&referrer));
+            trace_event!(
+              load_id = self.id,
+              specifier = %specifier,
+              referrer = %referrer,
+              "loading module"
             );
+            self.emit(ModuleLoadEvent::FetchStart { specifier });
+            let fut = self
+              .loader
+              .load(specifier, Some(referrer.clone()), self.is_dynamic_import())
+              .map_err(|err| err.context(context));
             self.pending.push(fut.boxed_local());
           }
-          self.visited.insert(specifier);
+          self.visited.insert(specifier.clone());
         }
       }
     }
@@ -402,10 +651,27 @@ impl Stream for RecursiveModuleLoad {
     // available.
     match inner.state {
       LoadState::Init => {
+        let (specifier, referrer) = match inner.init {
+          LoadInit::Main(ref specifier) | LoadInit::Side(ref specifier) => {
+            (specifier.as_str(), ".")
+          }
+          LoadInit::DynamicImport(ref specifier, ref referrer) => {
+            (specifier.as_str(), referrer.as_str())
+          }
+        };
+        inner.emit(ModuleLoadEvent::ResolveStart {
+          specifier,
+          referrer,
+        });
         let module_specifier = match inner.resolve_root() {
           Ok(url) => url,
           Err(error) => return Poll::Ready(Some(Err(error))),
         };
+        trace_event!(
+          load_id = inner.id,
+          specifier = %module_specifier,
+          "resolved root module"
+        );
         let load_fut = if let Some(_module_id) = inner.root_module_id {
           // The root module is already in the module map.
           // TODO(nayeemrmn): In this case we would ideally skip to
@@ -419,6 +685,7 @@ impl Stream for RecursiveModuleLoad {
             // The code will be discarded, since this module is already in the
             // module map.
             code: Default::default(),
+            redirects: vec![],
           })
           .boxed()
         } else {
@@ -428,9 +695,19 @@ impl Stream for RecursiveModuleLoad {
             }
             _ => None,
           };
+          let context = inner.load_error_context(&module_specifier, None);
+          trace_event!(
+            load_id = inner.id,
+            specifier = %module_specifier,
+            "loading module"
+          );
+          inner.emit(ModuleLoadEvent::FetchStart {
+            specifier: &module_specifier,
+          });
           inner
             .loader
             .load(&module_specifier, maybe_referrer, inner.is_dynamic_import())
+            .map_err(|err| err.context(context))
             .boxed_local()
         };
         inner.pending.push(load_fut);
@@ -440,7 +717,24 @@ impl Stream for RecursiveModuleLoad {
       LoadState::LoadingRoot | LoadState::LoadingImports => {
         match inner.pending.try_poll_next_unpin(cx)? {
           Poll::Ready(None) => unreachable!(),
-          Poll::Ready(Some(info)) => Poll::Ready(Some(Ok(info))),
+          Poll::Ready(Some(info)) => {
+            trace_event!(
+              load_id = inner.id,
+              specifier = %info.module_url_found,
+              "loaded module"
+            );
+            // A root module that is already registered isn't fetched.
+            let fetched = inner.state == LoadState::LoadingImports
+              || inner.root_module_id.is_none();
+            if fetched {
+              inner.emit(ModuleLoadEvent::FetchFinish {
+                specifier: &info.module_url_specified,
+                found: &info.module_url_found,
+                bytes: info.code.len(),
+              });
+            }
+            Poll::Ready(Some(Ok(info)))
+          }
           Poll::Pending => Poll::Pending,
         }
       }
@@ -453,8 +747,10 @@ pub struct ModuleInfo {
   pub id: ModuleId,
   // Used in "bindings.rs" for "import.meta.main" property value.
   pub main: bool,
-  pub name: String,
-  pub import_specifiers: Vec<ModuleSpecifier>,
+  /// Shared with the module map's name table, see `ModuleMap::intern()`.
+  pub name: Rc<str>,
+  /// Shared so that walking the graph doesn't copy the specifiers.
+  pub import_specifiers: Rc<Vec<ModuleSpecifier>>,
 }
 
 /// A symbolic module entity.
@@ -462,7 +758,7 @@ enum SymbolicModule {
   /// This module is an alias to another module.
   /// This is useful such that multiple names could point to
   /// the same underlying module (particularly due to redirects).
-  Alias(String),
+  Alias(Rc<str>),
   /// This module associates with a V8 module by id.
   Mod(ModuleId),
 }
@@ -473,14 +769,34 @@ pub struct ModuleMap {
   ids_by_handle: HashMap<v8::Global<v8::Module>, ModuleId>,
   handles_by_id: HashMap<ModuleId, v8::Global<v8::Module>>,
   info: HashMap<ModuleId, ModuleInfo>,
-  by_name: HashMap<String, SymbolicModule>,
+  by_name: HashMap<Rc<str>, SymbolicModule>,
+  /// Module names and alias targets, so that each name is allocated once and
+  /// shared by `by_name`, aliases and the module's `ModuleInfo`.
+  names: HashSet<Rc<str>>,
+  /// The specifier each import of a module resolved to when it was compiled,
+  /// by the import's specifier as written, so that instantiating the module
+  /// doesn't resolve them again.
+  resolved_imports: HashMap<ModuleId, HashMap<String, ModuleSpecifier>>,
   next_module_id: ModuleId,
 
+  // Enforcement of the module graph size limits
+  pub(crate) limits: ModuleGraphLimits,
+  pub(crate) lock_map: Option<ModuleLockMap>,
+  total_source_bytes: usize,
+  dynamic_import_count: usize,
+
   // Handling of futures for loading module sources
   pub loader: Rc<dyn ModuleLoader>,
+  pub(crate) load_event_fn: Option<Rc<ModuleLoadEventFn>>,
+  pub(crate) media_transpiler: Option<Rc<dyn MediaTranspiler>>,
+  pub(crate) transpile_cache: Option<Rc<dyn TranspileCache>>,
+  pub(crate) code_cache_store: Option<Arc<dyn CodeCacheStore>>,
   op_state: Rc<RefCell<OpState>>,
   pub(crate) dynamic_import_map:
     HashMap<ModuleLoadId, v8::Global<v8::PromiseResolver>>,
+  /// The specifier and referrer of each dynamic import in
+  /// `dynamic_import_map`, for diagnostics.
+  pub(crate) dynamic_import_specifiers: HashMap<ModuleLoadId, String>,
   pub(crate) preparing_dynamic_imports:
     FuturesUnordered<Pin<Box<PrepareLoadFuture>>>,
   pub(crate) pending_dynamic_imports:
@@ -497,15 +813,43 @@ impl ModuleMap {
       handles_by_id: HashMap::new(),
       info: HashMap::new(),
       by_name: HashMap::new(),
+      names: HashSet::new(),
+      resolved_imports: HashMap::new(),
       next_module_id: 1,
+      limits: ModuleGraphLimits::default(),
+      lock_map: None,
+      total_source_bytes: 0,
+      dynamic_import_count: 0,
       loader,
+      load_event_fn: None,
+      media_transpiler: None,
+      transpile_cache: None,
+      code_cache_store: None,
       op_state,
       dynamic_import_map: HashMap::new(),
+      dynamic_import_specifiers: HashMap::new(),
       preparing_dynamic_imports: FuturesUnordered::new(),
       pending_dynamic_imports: FuturesUnordered::new(),
     }
   }
 
+  /// Drops all modules and pending dynamic imports, eg. when their context
+  /// is replaced. Module ids are not reused.
+  pub(crate) fn clear(&mut self) {
+    self.ids_by_handle.clear();
+    self.handles_by_id.clear();
+    self.info.clear();
+    self.by_name.clear();
+    self.names.clear();
+    self.resolved_imports.clear();
+    self.total_source_bytes = 0;
+    self.dynamic_import_count = 0;
+    self.dynamic_import_map.clear();
+    self.dynamic_import_specifiers.clear();
+    self.preparing_dynamic_imports = FuturesUnordered::new();
+    self.pending_dynamic_imports = FuturesUnordered::new();
+  }
+
   /// Get module id, following all aliases in case of module specifier
   /// that had been redirected.
   pub fn get_id(&self, name: &str) -> Option<ModuleId> {
@@ -521,6 +865,109 @@ impl ModuleMap {
     }
   }
 
+  /// Transpiles the source of a module if its media type needs it and a
+  /// `MediaTranspiler` is set. Returns `None` if the source is used as is.
+  fn transpile(
+    &self,
+    name: &str,
+    source: &str,
+  ) -> Result<Option<String>, Error> {
+    let transpiler = match &self.media_transpiler {
+      Some(transpiler) => transpiler,
+      None => return Ok(None),
+    };
+    let specifier = match crate::resolve_url(name) {
+      Ok(specifier) => specifier,
+      Err(_) => return Ok(None),
+    };
+    let media_type = MediaType::from_specifier(&specifier);
+    if !media_type.needs_transpile() {
+      return Ok(None);
+    }
+    let cache = match (&self.transpile_cache, transpiler.version()) {
+      (Some(cache), Some(version)) => {
+        let key = transpile_cache_key(&version, &specifier, source);
+        if let Some(code) = cache.get(&key) {
+          return Ok(Some(code));
+        }
+        Some((cache, key))
+      }
+      _ => None,
+    };
+    let code = transpiler.transpile(&specifier, media_type, source)?;
+    if let Some((cache, key)) = cache {
+      if let Err(err) = cache.set(&key, &code) {
+        debug!("Failed to cache transpiled module {}: {}", specifier, err);
+      }
+    }
+    Ok(Some(code))
+  }
+
+  /// Checks that loading a module didn't take more redirects than allowed by
+  /// `ModuleGraphLimits::max_redirects`.
+  pub(crate) fn check_redirects(
+    &self,
+    module_source: &ModuleSource,
+  ) -> Result<(), Error> {
+    let redirect_count = if module_source.redirects.is_empty()
+      && module_source.module_url_specified == module_source.module_url_found
+    {
+      0
+    } else {
+      module_source.redirects.len() + 1
+    };
+    match self.limits.max_redirects {
+      Some(max_redirects) if redirect_count > max_redirects => {
+        Err(range_error(format!(
+          "Cannot load module \"{}\": redirect limit of {} exceeded",
+          module_source.module_url_specified, max_redirects
+        )))
+      }
+      _ => Ok(()),
+    }
+  }
+
+  /// Checks that registering a module with the given source would not exceed
+  /// any of the configured `ModuleGraphLimits`.
+  pub(crate) fn check_limits(
+    &self,
+    name: &str,
+    source: &str,
+  ) -> Result<(), Error> {
+    if let Some(max_modules) = self.limits.max_modules {
+      if self.info.len() >= max_modules {
+        return Err(range_error(format!(
+          "Cannot load module \"{}\": module graph limit of {} modules exceeded",
+          name, max_modules
+        )));
+      }
+    }
+    if let Some(max_bytes) = self.limits.max_total_source_bytes {
+      if self.total_source_bytes + source.len() > max_bytes {
+        return Err(range_error(format!(
+          "Cannot load module \"{}\": module graph limit of {} source bytes exceeded",
+          name, max_bytes
+        )));
+      }
+    }
+    Ok(())
+  }
+
+  /// Counts a new dynamic import, erroring if it would exceed
+  /// `ModuleGraphLimits::max_dynamic_imports`.
+  pub(crate) fn track_dynamic_import(&mut self) -> Result<(), Error> {
+    if let Some(max_dynamic_imports) = self.limits.max_dynamic_imports {
+      if self.dynamic_import_count >= max_dynamic_imports {
+        return Err(range_error(format!(
+          "Dynamic import limit of {} exceeded",
+          max_dynamic_imports
+        )));
+      }
+    }
+    self.dynamic_import_count += 1;
+    Ok(())
+  }
+
   // Create and compile an ES module.
   pub(crate) fn new_module(
     &mut self,
@@ -529,25 +976,89 @@ impl ModuleMap {
     name: &str,
     source: &str,
   ) -> Result<ModuleId, Error> {
+    self.new_module_from_source(scope, main, name, ScriptSource::Utf8(source))
+  }
+
+  /// Like `new_module()`, for a source in any `ScriptSource` encoding. Only
+  /// UTF-8 sources are transpiled and use the code cache store.
+  pub(crate) fn new_module_from_source(
+    &mut self,
+    scope: &mut v8::HandleScope,
+    main: bool,
+    name: &str,
+    source: ScriptSource,
+  ) -> Result<ModuleId, Error> {
+    // `ModuleGraphLimits::max_total_source_bytes` counts the source as
+    // loaded, like `check_limits()`, not the transpiled code.
+    let source_len = source.byte_len();
+    let transpiled = match source {
+      ScriptSource::Utf8(source) => self.transpile(name, source)?,
+      _ => None,
+    };
+    let source = match &transpiled {
+      Some(code) => ScriptSource::Utf8(code),
+      None => source,
+    };
     let name_str = v8::String::new(scope, name).unwrap();
-    let source_str = v8::String::new(scope, source).unwrap();
+    let source_str = source.to_v8_string(scope).unwrap();
 
     let origin = bindings::module_origin(scope, name_str);
-    let source = v8::script_compiler::Source::new(source_str, Some(&origin));
+    let code_cache = match (&self.code_cache_store, source) {
+      (Some(store), ScriptSource::Utf8(source)) => {
+        let source_hash = code_cache_source_hash(source);
+        let cached_data = store.get(name, &source_hash);
+        Some((store, source_hash, cached_data))
+      }
+      _ => None,
+    };
+    let (source, options) = match &code_cache {
+      Some((_, _, Some(cached_data))) => (
+        v8::script_compiler::Source::new_with_cached_data(
+          source_str,
+          Some(&origin),
+          v8::CachedData::new(cached_data),
+        ),
+        v8::script_compiler::CompileOptions::ConsumeCodeCache,
+      ),
+      _ => (
+        v8::script_compiler::Source::new(source_str, Some(&origin)),
+        v8::script_compiler::CompileOptions::NoCompileOptions,
+      ),
+    };
 
     let tc_scope = &mut v8::TryCatch::new(scope);
 
-    let maybe_module = v8::script_compiler::compile_module(tc_scope, source);
+    // TODO: compile on a V8 worker thread while the rest of the graph is
+    // fetched (`ScriptCompiler::StartStreaming()`), finalizing here. The v8
+    // crate doesn't bind the streaming compile APIs yet.
+    let compile_start = Instant::now();
+    let maybe_module = v8::script_compiler::compile_module2(
+      tc_scope,
+      source,
+      options,
+      v8::script_compiler::NoCacheReason::NoReason,
+    );
+    let compile_duration = compile_start.elapsed();
 
     if tc_scope.has_caught() {
       assert!(maybe_module.is_none());
       let e = tc_scope.exception().unwrap();
-      return exception_to_err_result(tc_scope, e, false);
+      return compile_exception_to_err_result(tc_scope, e);
     }
 
     let module = maybe_module.unwrap();
 
+    if let Some((store, source_hash, None)) = &code_cache {
+      let unbound_module_script = module.get_unbound_module_script(tc_scope);
+      if let Some(data) = unbound_module_script.create_code_cache() {
+        if let Err(err) = store.put(name, source_hash, &data) {
+          debug!("Failed to store code cache for {}: {}", name, err);
+        }
+      }
+    }
+
     let mut import_specifiers: Vec<ModuleSpecifier> = vec![];
+    let mut resolved_imports: HashMap<String, ModuleSpecifier> = HashMap::new();
     let module_requests = module.get_module_requests();
     for i in 0..module_requests.length() {
       let module_request = v8::Local::<v8::ModuleRequest>::try_from(
@@ -557,8 +1068,23 @@ impl ModuleMap {
       let import_specifier = module_request
         .get_specifier()
         .to_rust_string_lossy(tc_scope);
+      if let Some(module_specifier) = resolved_imports.get(&import_specifier) {
+        import_specifiers.push(module_specifier.clone());
+        continue;
+      }
+      self.emit(ModuleLoadEvent::ResolveStart {
+        specifier: &import_specifier,
+        referrer: name,
+      });
       let module_specifier =
         self.loader.resolve(&import_specifier, name, false)?;
+      trace_event!(
+        specifier = %import_specifier,
+        referrer = name,
+        resolved = %module_specifier,
+        "resolved import"
+      );
+      resolved_imports.insert(import_specifier, module_specifier.clone());
       import_specifiers.push(module_specifier);
     }
 
@@ -576,26 +1102,48 @@ impl ModuleMap {
     let handle = v8::Global::<v8::Module>::new(tc_scope, module);
     let id = self.next_module_id;
     self.next_module_id += 1;
-    self
-      .by_name
-      .insert(name.to_string(), SymbolicModule::Mod(id));
+    let name = self.intern(name);
+    self.by_name.insert(name.clone(), SymbolicModule::Mod(id));
+    self.resolved_imports.insert(id, resolved_imports);
     self.handles_by_id.insert(id, handle.clone());
     self.ids_by_handle.insert(handle, id);
+    self.total_source_bytes += source_len;
     self.info.insert(
       id,
       ModuleInfo {
         id,
         main,
-        name: name.to_string(),
-        import_specifiers,
+        name: name.clone(),
+        import_specifiers: Rc::new(import_specifiers),
       },
     );
+    self.emit(ModuleLoadEvent::Compile {
+      specifier: &name,
+      id,
+      duration: compile_duration,
+    });
 
     Ok(id)
   }
 
+  pub(crate) fn emit(&self, event: ModuleLoadEvent) {
+    if let Some(load_event_fn) = &self.load_event_fn {
+      load_event_fn(&event);
+    }
+  }
+
   pub fn get_children(&self, id: ModuleId) -> Option<&Vec<ModuleSpecifier>> {
-    self.info.get(&id).map(|i| &i.import_specifiers)
+    self.info.get(&id).map(|i| &*i.import_specifiers)
+  }
+
+  /// Returns the shared allocation holding `name`, creating it if needed.
+  fn intern(&mut self, name: &str) -> Rc<str> {
+    if let Some(name) = self.names.get(name) {
+      return name.clone();
+    }
+    let name: Rc<str> = Rc::from(name);
+    self.names.insert(name.clone());
+    name
   }
 
   pub fn is_registered(&self, specifier: &ModuleSpecifier) -> bool {
@@ -603,9 +1151,9 @@ impl ModuleMap {
   }
 
   pub fn alias(&mut self, name: &str, target: &str) {
-    self
-      .by_name
-      .insert(name.to_string(), SymbolicModule::Alias(target.to_string()));
+    let name = self.intern(name);
+    let target = self.intern(target);
+    self.by_name.insert(name, SymbolicModule::Alias(target));
   }
 
   #[cfg(test)]
@@ -667,6 +1215,10 @@ impl ModuleMap {
       .borrow_mut()
       .dynamic_import_map
       .insert(load.id, resolver_handle);
+    module_map_rc.borrow_mut().dynamic_import_specifiers.insert(
+      load.id,
+      format!("{} (imported from {})", specifier, referrer),
+    );
     let resolve_result = module_map_rc
       .borrow()
       .loader
@@ -688,6 +1240,14 @@ impl ModuleMap {
       .push(fut);
   }
 
+  pub(crate) fn pending_dynamic_import_count(&self) -> usize {
+    self.preparing_dynamic_imports.len() + self.pending_dynamic_imports.len()
+  }
+
+  pub(crate) fn module_count(&self) -> usize {
+    self.info.len()
+  }
+
   pub fn has_pending_dynamic_imports(&self) -> bool {
     !(self.preparing_dynamic_imports.is_empty()
       && self.pending_dynamic_imports.is_empty())
@@ -700,10 +1260,17 @@ impl ModuleMap {
     specifier: &str,
     referrer: &str,
   ) -> Option<v8::Local<'s, v8::Module>> {
-    let resolved_specifier = self
-      .loader
-      .resolve(specifier, referrer, false)
-      .expect("Module should have been already resolved");
+    let cached = self
+      .get_id(referrer)
+      .and_then(|referrer_id| self.resolved_imports.get(&referrer_id))
+      .and_then(|resolved_imports| resolved_imports.get(specifier));
+    let resolved_specifier = match cached {
+      Some(resolved_specifier) => resolved_specifier.clone(),
+      None => self
+        .loader
+        .resolve(specifier, referrer, false)
+        .expect("Module should have been already resolved"),
+    };
 
     if let Some(id) = self.get_id(resolved_specifier.as_str()) {
       if let Some(handle) = self.get_handle(id) {
@@ -726,6 +1293,7 @@ mod tests {
   use crate::RuntimeOptions;
   use futures::future::FutureExt;
   use parking_lot::Mutex;
+  use std::cell::Cell;
   use std::fmt;
   use std::future::Future;
   use std::io;
@@ -737,7 +1305,7 @@ mod tests {
   // even though we are only using poll() in these tests and not Tokio, we must
   // nevertheless run it in the tokio executor. Ideally run_in_task can be
   // removed in the future.
-  use crate::runtime::tests::run_in_task;
+  use crate::test_util::run_in_task;
 
   #[derive(Default)]
   struct MockLoader {
@@ -820,6 +1388,7 @@ mod tests {
           code: src.0.to_owned(),
           module_url_specified: inner.url.clone(),
           module_url_found: src.1.to_owned(),
+          redirects: vec![],
         })),
         None => Poll::Ready(Err(MockError::LoadErr.into())),
       }
@@ -947,6 +1516,144 @@ mod tests {
     assert_eq!(modules.get_children(d_id), Some(&vec![]));
   }
 
+  #[test]
+  fn test_module_graph_limits() {
+    let loader = MockLoader::new();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(loader),
+      module_graph_limits: ModuleGraphLimits {
+        max_modules: Some(3),
+        ..Default::default()
+      },
+      ..Default::default()
+    });
+    let spec = crate::resolve_url("file:///a.js").unwrap();
+    let a_id_fut = runtime.load_main_module(&spec, None);
+    let err = futures::executor::block_on(a_id_fut).unwrap_err();
+    assert_eq!(
+      crate::error::get_custom_error_class(&err),
+      Some("RangeError")
+    );
+    assert!(err.to_string().contains("limit of 3 modules exceeded"));
+
+    let loader = MockLoader::new();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(loader),
+      module_graph_limits: ModuleGraphLimits {
+        max_total_source_bytes: Some(A_SRC.len()),
+        ..Default::default()
+      },
+      ..Default::default()
+    });
+    let a_id_fut = runtime.load_main_module(&spec, None);
+    let err = futures::executor::block_on(a_id_fut).unwrap_err();
+    assert!(err.to_string().contains("source bytes exceeded"));
+  }
+
+  #[test]
+  fn test_max_dynamic_imports() {
+    let mut loader = MemoryModuleLoader::new();
+    for name in ["a", "b", "c"] {
+      loader.add_module(
+        crate::resolve_url(&format!("file:///{}.js", name)).unwrap(),
+        format!("export default '{}';", name),
+      );
+    }
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(Rc::new(loader)),
+      module_graph_limits: ModuleGraphLimits {
+        max_dynamic_imports: Some(2),
+        ..Default::default()
+      },
+      ..Default::default()
+    });
+    let script = r#"
+      (async () => {
+        for (const name of ["a", "b"]) {
+          const mod = await import(`./${name}.js`);
+          if (mod.default !== name) throw new Error(`unexpected ${name}`);
+        }
+        try {
+          await import("./c.js");
+        } catch (err) {
+          if (!(err instanceof RangeError)) throw err;
+          return err.message;
+        }
+        throw new Error("the third dynamic import should be rejected");
+      })()
+    "#;
+    let run = |runtime: &mut JsRuntime| {
+      let promise = runtime.execute_script("file:///main.js", script).unwrap();
+      let message =
+        futures::executor::block_on(runtime.resolve_value(promise)).unwrap();
+      let message = runtime.from_v8::<String>(&message).unwrap();
+      assert!(
+        message.contains("Dynamic import limit of 2 exceeded"),
+        "{}",
+        message
+      );
+    };
+    run(&mut runtime);
+    // The count starts over when the module map is cleared.
+    runtime.reset().unwrap();
+    run(&mut runtime);
+  }
+
+  #[test]
+  fn test_module_load_events() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let events_ = events.clone();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(MockLoader::new()),
+      module_load_event_fn: Some(Rc::new(move |event: &ModuleLoadEvent| {
+        let event = match event {
+          ModuleLoadEvent::ResolveStart { specifier, .. } => {
+            format!("resolve {}", specifier)
+          }
+          ModuleLoadEvent::FetchStart { specifier } => {
+            format!("fetch {}", specifier)
+          }
+          ModuleLoadEvent::FetchFinish {
+            specifier, bytes, ..
+          } => {
+            assert!(*bytes > 0);
+            format!("fetched {}", specifier)
+          }
+          ModuleLoadEvent::Compile { specifier, .. } => {
+            format!("compile {}", specifier)
+          }
+          ModuleLoadEvent::Instantiate { id, .. } => {
+            format!("instantiate {}", id)
+          }
+          ModuleLoadEvent::Evaluate { id } => format!("evaluate {}", id),
+        };
+        events_.borrow_mut().push(event);
+      })),
+      ..Default::default()
+    });
+    let spec = crate::resolve_url("file:///c.js").unwrap();
+    let c_id =
+      futures::executor::block_on(runtime.load_side_module(&spec, None))
+        .unwrap();
+    let _ = runtime.mod_evaluate(c_id);
+    futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+    assert_eq!(
+      *events.borrow(),
+      vec![
+        "resolve file:///c.js".to_string(),
+        "fetch file:///c.js".to_string(),
+        "fetched file:///c.js".to_string(),
+        "resolve /d.js".to_string(),
+        "compile file:///c.js".to_string(),
+        "fetch file:///d.js".to_string(),
+        "fetched file:///d.js".to_string(),
+        "compile file:///d.js".to_string(),
+        format!("instantiate {}", c_id),
+        format!("evaluate {}", c_id),
+      ]
+    );
+  }
+
   const CIRCULAR1_SRC: &str = r#"
     import "/circular2.js";
     Deno.core.print("circular1");
@@ -1076,6 +1783,8 @@ mod tests {
 
     runtime.instantiate_module(mod_a).unwrap();
     assert_eq!(dispatch_count.load(Ordering::Relaxed), 0);
+    // The import was resolved when compiling mod_a.
+    assert_eq!(resolve_count.load(Ordering::SeqCst), 1);
 
     let _ = runtime.mod_evaluate(mod_a);
     assert_eq!(dispatch_count.load(Ordering::Relaxed), 1);
@@ -1137,6 +1846,11 @@ mod tests {
       if let Poll::Ready(Ok(_)) = result {
         unreachable!();
       }
+      if let Poll::Ready(Err(err)) = result {
+        assert!(err.to_string().contains(
+          "failed to load file:///foo.js (dynamic import from file:///dyn_import2.js): entity not found"
+        ));
+      }
       assert_eq!(count.load(Ordering::Relaxed), 4);
     })
   }
@@ -1174,6 +1888,7 @@ mod tests {
         module_url_specified: specifier.to_string(),
         module_url_found: specifier.to_string(),
         code: "export function b() { return 'b' }".to_owned(),
+        redirects: vec![],
       };
       async move { Ok(info) }.boxed()
     }
@@ -1320,6 +2035,7 @@ mod tests {
           module_url_specified: specifier.to_string(),
           module_url_found: specifier.to_string(),
           code: code.to_owned(),
+          redirects: vec![],
         };
         async move { Ok(info) }.boxed()
       }
@@ -1455,6 +2171,13 @@ mod tests {
       assert!(modules.is_alias("file:///redirect2.js"));
       assert!(!modules.is_alias("file:///dir/redirect2.js"));
       assert_eq!(modules.get_id("file:///redirect2.js"), Some(redirect2_id));
+      // The alias target and the module's name share one allocation.
+      let target = match modules.by_name.get("file:///redirect2.js") {
+        Some(SymbolicModule::Alias(target)) => target.clone(),
+        _ => unreachable!(),
+      };
+      let info = modules.get_info_by_id(&redirect2_id).unwrap();
+      assert!(Rc::ptr_eq(&target, &info.name));
 
       let redirect3_id = modules.get_id("file:///redirect3.js").unwrap();
       assert!(modules.is_alias("file:///dir/redirect3.js"));
@@ -1648,11 +2371,13 @@ mod tests {
             module_url_specified: "file:///main_module.js".to_string(),
             module_url_found: "file:///main_module.js".to_string(),
             code: "if (!import.meta.main) throw Error();".to_owned(),
+            redirects: vec![],
           }),
           "file:///side_module.js" => Ok(ModuleSource {
             module_url_specified: "file:///side_module.js".to_string(),
             module_url_found: "file:///side_module.js".to_string(),
             code: "if (import.meta.main) throw Error();".to_owned(),
+            redirects: vec![],
           }),
           _ => unreachable!(),
         };
@@ -1692,4 +2417,368 @@ mod tests {
     let _ = runtime.mod_evaluate(side_id);
     futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
   }
+
+  #[test]
+  fn memory_module_loader() {
+    let main_specifier = crate::resolve_url("file:///main.js").unwrap();
+    let lib_specifier = crate::resolve_url("file:///lib.js").unwrap();
+    let mut loader = MemoryModuleLoader::new();
+    loader
+      .add_module(
+        main_specifier.clone(),
+        r#"
+        import { count as a } from "./lib.js";
+        import { count as b } from "lib";
+        import { count as c } from "./alias.js";
+        if (a !== 1 || b !== 1 || c !== 1) throw Error("duplicate module");
+        "#,
+      )
+      .add_module(
+        lib_specifier.clone(),
+        "export const count = ++globalThis.n;",
+      )
+      .add_alias("lib", lib_specifier.clone())
+      .add_alias("file:///alias.js", lib_specifier);
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(Rc::new(loader)),
+      ..Default::default()
+    });
+    runtime
+      .execute_script("init.js", "globalThis.n = 0;")
+      .unwrap();
+
+    let main_id = futures::executor::block_on(
+      runtime.load_main_module(&main_specifier, None),
+    )
+    .unwrap();
+    let receiver = runtime.mod_evaluate(main_id);
+    futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+    futures::executor::block_on(receiver).unwrap().unwrap();
+
+    let missing = crate::resolve_url("file:///missing.js").unwrap();
+    let err =
+      futures::executor::block_on(runtime.load_side_module(&missing, None))
+        .unwrap_err();
+    assert_eq!(err.to_string(), "Module not found: \"file:///missing.js\"");
+  }
+
+  #[test]
+  fn media_transpiler() {
+    struct StripTypes {
+      transpiled: RefCell<Vec<(String, MediaType)>>,
+    }
+    impl MediaTranspiler for StripTypes {
+      fn transpile(
+        &self,
+        specifier: &ModuleSpecifier,
+        media_type: MediaType,
+        code: &str,
+      ) -> Result<String, Error> {
+        self
+          .transpiled
+          .borrow_mut()
+          .push((specifier.to_string(), media_type));
+        Ok(code.replace(": number", ""))
+      }
+    }
+
+    let main_specifier = crate::resolve_url("file:///main.js").unwrap();
+    let mut loader = MemoryModuleLoader::new();
+    loader
+      .add_module(
+        main_specifier.clone(),
+        "import { double } from './lib.ts'; globalThis.result = double(21);",
+      )
+      .add_module(
+        crate::resolve_url("file:///lib.ts").unwrap(),
+        "export function double(n: number): number { return n * 2; }",
+      );
+    let transpiler = Rc::new(StripTypes {
+      transpiled: RefCell::new(vec![]),
+    });
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(Rc::new(loader)),
+      media_transpiler: Some(transpiler.clone()),
+      ..Default::default()
+    });
+
+    let main_id = futures::executor::block_on(
+      runtime.load_main_module(&main_specifier, None),
+    )
+    .unwrap();
+    let receiver = runtime.mod_evaluate(main_id);
+    futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+    futures::executor::block_on(receiver).unwrap().unwrap();
+    runtime
+      .execute_script("check.js", "if (result !== 42) throw result;")
+      .unwrap();
+    assert_eq!(
+      *transpiler.transpiled.borrow(),
+      vec![("file:///lib.ts".to_string(), MediaType::TypeScript)]
+    );
+  }
+
+  #[test]
+  fn media_transpiler_source_bytes_limit() {
+    // Grows the code, so that counting the transpiled code would exceed the
+    // limit.
+    struct Pad;
+    impl MediaTranspiler for Pad {
+      fn transpile(
+        &self,
+        _specifier: &ModuleSpecifier,
+        _media_type: MediaType,
+        code: &str,
+      ) -> Result<String, Error> {
+        Ok(format!(
+          "{}\n// {}",
+          code.replace(": number", ""),
+          "x".repeat(100)
+        ))
+      }
+    }
+
+    let main_specifier = crate::resolve_url("file:///main.ts").unwrap();
+    let main_code = "import { n } from './lib.ts'; const m: number = n;";
+    let lib_code = "export const n: number = 1;";
+    let load = |max_total_source_bytes| {
+      let mut loader = MemoryModuleLoader::new();
+      loader
+        .add_module(main_specifier.clone(), main_code)
+        .add_module(crate::resolve_url("file:///lib.ts").unwrap(), lib_code);
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        module_loader: Some(Rc::new(loader)),
+        media_transpiler: Some(Rc::new(Pad)),
+        module_graph_limits: ModuleGraphLimits {
+          max_total_source_bytes: Some(max_total_source_bytes),
+          ..Default::default()
+        },
+        ..Default::default()
+      });
+      futures::executor::block_on(
+        runtime.load_main_module(&main_specifier, None),
+      )
+    };
+    let total = main_code.len() + lib_code.len();
+    load(total).unwrap();
+    let err = load(total - 1).unwrap_err();
+    assert!(err.to_string().contains("source bytes exceeded"));
+  }
+
+  #[test]
+  fn transpile_cache() {
+    struct CountingTranspiler(Cell<usize>);
+    impl MediaTranspiler for CountingTranspiler {
+      fn transpile(
+        &self,
+        _specifier: &ModuleSpecifier,
+        _media_type: MediaType,
+        code: &str,
+      ) -> Result<String, Error> {
+        self.0.set(self.0.get() + 1);
+        Ok(code.replace(": number", ""))
+      }
+
+      fn version(&self) -> Option<String> {
+        Some("1".to_string())
+      }
+    }
+
+    #[derive(Default)]
+    struct MemoryCache(RefCell<HashMap<String, String>>);
+    impl TranspileCache for MemoryCache {
+      fn get(&self, key: &str) -> Option<String> {
+        self.0.borrow().get(key).cloned()
+      }
+
+      fn set(&self, key: &str, code: &str) -> Result<(), Error> {
+        self
+          .0
+          .borrow_mut()
+          .insert(key.to_string(), code.to_string());
+        Ok(())
+      }
+    }
+
+    let specifier = crate::resolve_url("file:///main.ts").unwrap();
+    let transpiler = Rc::new(CountingTranspiler(Cell::new(0)));
+    let cache = Rc::new(MemoryCache::default());
+    for _ in 0..2 {
+      let mut loader = MemoryModuleLoader::new();
+      loader.add_module(specifier.clone(), "const n: number = 1;");
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        module_loader: Some(Rc::new(loader)),
+        media_transpiler: Some(transpiler.clone()),
+        transpile_cache: Some(cache.clone()),
+        ..Default::default()
+      });
+      futures::executor::block_on(runtime.load_main_module(&specifier, None))
+        .unwrap();
+    }
+    assert_eq!(transpiler.0.get(), 1);
+    assert_eq!(cache.0.borrow().len(), 1);
+  }
+
+  #[test]
+  fn code_cache_store() {
+    let main_specifier = crate::resolve_url("file:///main.js").unwrap();
+    let lib_specifier = crate::resolve_url("file:///lib.js").unwrap();
+    let main_code = "import { f } from './lib.js'; globalThis.result = f();";
+    let lib_code = "export function f() { return 42; }";
+    let store = Arc::new(crate::MemoryCodeCacheStore::new());
+    for _ in 0..2 {
+      let mut loader = MemoryModuleLoader::new();
+      loader
+        .add_module(main_specifier.clone(), main_code)
+        .add_module(lib_specifier.clone(), lib_code);
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        module_loader: Some(Rc::new(loader)),
+        code_cache_store: Some(store.clone()),
+        ..Default::default()
+      });
+      let id = futures::executor::block_on(
+        runtime.load_main_module(&main_specifier, None),
+      )
+      .unwrap();
+      let _ = runtime.mod_evaluate(id);
+      futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+      runtime
+        .execute_script("check.js", "if (result !== 42) throw new Error()")
+        .unwrap();
+    }
+    for (specifier, code) in
+      [("file:///main.js", main_code), ("file:///lib.js", lib_code)]
+    {
+      assert!(store
+        .get(specifier, &code_cache_source_hash(code))
+        .is_some());
+    }
+    let check_hash =
+      code_cache_source_hash("if (result !== 42) throw new Error()");
+    assert!(store.get("check.js", &check_hash).is_some());
+  }
+
+  #[test]
+  fn module_lock_map() {
+    let main_specifier = crate::resolve_url("file:///main.js").unwrap();
+    let lib_specifier = crate::resolve_url("file:///lib.js").unwrap();
+    let main_code = "import './lib.js';";
+    let load = |lock_map: ModuleLockMap| {
+      let mut loader = MemoryModuleLoader::new();
+      loader
+        .add_module(main_specifier.clone(), main_code)
+        .add_module(lib_specifier.clone(), "export {};");
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        module_loader: Some(Rc::new(loader)),
+        module_lock_map: Some(lock_map),
+        ..Default::default()
+      });
+      futures::executor::block_on(
+        runtime.load_main_module(&main_specifier, None),
+      )
+    };
+
+    let mut hashes = HashMap::new();
+    hashes.insert(main_specifier.to_string(), module_source_hash(main_code));
+    load(ModuleLockMap {
+      hashes: hashes.clone(),
+      deny_unlisted: false,
+    })
+    .unwrap();
+
+    let err = load(ModuleLockMap {
+      hashes: hashes.clone(),
+      deny_unlisted: true,
+    })
+    .unwrap_err();
+    let err = err.downcast_ref::<ModuleIntegrityError>().unwrap();
+    assert_eq!(err.specifier, "file:///lib.js");
+    assert_eq!(err.expected, None);
+
+    hashes.insert(lib_specifier.to_string(), module_source_hash("changed"));
+    let err = load(ModuleLockMap {
+      hashes,
+      deny_unlisted: false,
+    })
+    .unwrap_err();
+    let err = err.downcast_ref::<ModuleIntegrityError>().unwrap();
+    assert_eq!(err.expected, Some(module_source_hash("changed")));
+    assert_eq!(err.actual, module_source_hash("export {};"));
+  }
+
+  #[test]
+  fn redirect_chain() {
+    struct RedirectLoader;
+    impl ModuleLoader for RedirectLoader {
+      fn resolve(
+        &self,
+        specifier: &str,
+        referrer: &str,
+        _is_main: bool,
+      ) -> Result<ModuleSpecifier, Error> {
+        Ok(crate::resolve_import(specifier, referrer)?)
+      }
+
+      fn load(
+        &self,
+        module_specifier: &ModuleSpecifier,
+        _maybe_referrer: Option<ModuleSpecifier>,
+        _is_dyn_import: bool,
+      ) -> Pin<Box<ModuleSourceFuture>> {
+        let source = match module_specifier.as_str() {
+          "file:///main.js" => ModuleSource {
+            code: "import './a.js';".to_string(),
+            module_url_specified: "file:///main.js".to_string(),
+            module_url_found: "file:///main.js".to_string(),
+            redirects: vec![],
+          },
+          // a.js redirects to b.js, which redirects to c.js.
+          "file:///a.js" => ModuleSource {
+            code: "globalThis.loads = (globalThis.loads ?? 0) + 1;".to_string(),
+            module_url_specified: "file:///a.js".to_string(),
+            module_url_found: "file:///c.js".to_string(),
+            redirects: vec!["file:///b.js".to_string()],
+          },
+          specifier => unreachable!("unexpected load of {}", specifier),
+        };
+        async move { Ok(source) }.boxed_local()
+      }
+    }
+
+    let main_specifier = crate::resolve_url("file:///main.js").unwrap();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(Rc::new(RedirectLoader)),
+      ..Default::default()
+    });
+    let main_id = futures::executor::block_on(
+      runtime.load_main_module(&main_specifier, None),
+    )
+    .unwrap();
+    let module_map_rc = JsRuntime::module_map(runtime.v8_isolate());
+    {
+      let module_map = module_map_rc.borrow();
+      let c_id = module_map.get_id("file:///c.js").unwrap();
+      assert_ne!(c_id, main_id);
+      assert_eq!(module_map.get_id("file:///a.js"), Some(c_id));
+      assert_eq!(module_map.get_id("file:///b.js"), Some(c_id));
+    }
+    // The intermediate URL resolves to the already loaded module.
+    let b_specifier = crate::resolve_url("file:///b.js").unwrap();
+    futures::executor::block_on(runtime.load_side_module(&b_specifier, None))
+      .unwrap();
+
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      module_loader: Some(Rc::new(RedirectLoader)),
+      module_graph_limits: ModuleGraphLimits {
+        max_redirects: Some(1),
+        ..Default::default()
+      },
+      ..Default::default()
+    });
+    let err = futures::executor::block_on(
+      runtime.load_main_module(&main_specifier, None),
+    )
+    .unwrap_err();
+    assert!(err.to_string().contains("redirect limit of 1 exceeded"));
+  }
 }
@@ -1,10 +1,19 @@
 // Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
 
+use crate::async_cancel::CancelHandle;
+use crate::broadcast::BroadcastBus;
+use crate::clock::Clock;
+use crate::clock::RealClock;
 use crate::error::type_error;
+use crate::error::ErrorWithProperties;
 use crate::gotham_state::GothamState;
 use crate::ops_metrics::OpsTracker;
 use crate::resources::ResourceTable;
 use crate::runtime::GetErrorClassFn;
+use crate::runtime::PrintFn;
+use crate::sanitizer::Sanitizer;
+use crate::trace::trace_span;
+use crate::JsRuntime;
 use anyhow::Error;
 use futures::future::maybe_done;
 use futures::future::FusedFuture;
@@ -12,17 +21,19 @@ use futures::future::MaybeDone;
 use futures::ready;
 use futures::task::noop_waker;
 use futures::Future;
-use indexmap::IndexMap;
 use serde::de::DeserializeOwned;
 use serde::Serialize;
 use std::cell::RefCell;
-use std::iter::once;
+use std::collections::HashMap;
 use std::ops::Deref;
 use std::ops::DerefMut;
 use std::pin::Pin;
 use std::rc::Rc;
+use std::sync::Arc;
 use std::task::Context;
 use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
 
 /// Wrapper around a Future, which causes that Future to be polled immediately.
 /// (Background: ops are stored in a `FuturesUnordered` structure which polls
@@ -116,6 +127,13 @@ pub enum Op {
 
 pub enum OpResult {
   Ok(serde_v8::SerializablePkg),
+  /// A result converted to a V8 value directly instead of through serde_v8,
+  /// see `op_sync_scalar()`.
+  Scalar(OpScalar),
+  /// The bytes a sync op wrote to the runtime's scratch buffer, see
+  /// `op_sync_scratch()`. Converted to a `Uint8Array` before the dispatch
+  /// returns.
+  Scratch,
   Err(OpError),
 }
 
@@ -126,18 +144,74 @@ impl OpResult {
   ) -> Result<v8::Local<'a, v8::Value>, serde_v8::Error> {
     match self {
       Self::Ok(x) => x.to_v8(scope),
+      Self::Scalar(x) => Ok(x.to_v8(scope)),
+      Self::Scratch => {
+        let state_rc = JsRuntime::state(scope);
+        let op_state = state_rc.borrow().op_state.clone();
+        let op_state = op_state.borrow();
+        let bytes = &op_state.scratch;
+        let buffer = v8::ArrayBuffer::new(scope, bytes.len());
+        let backing_store = buffer.get_backing_store();
+        for (cell, byte) in backing_store.iter().zip(bytes) {
+          cell.set(*byte);
+        }
+        let array = v8::Uint8Array::new(scope, buffer, 0, bytes.len()).unwrap();
+        Ok(array.into())
+      }
       Self::Err(err) => serde_v8::to_v8(scope, err),
     }
   }
 }
 
+/// The result of an op that returns a single number or boolean, eg. a
+/// resource id or the number of bytes written.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpScalar {
+  Bool(bool),
+  I32(i32),
+  U32(u32),
+}
+
+impl OpScalar {
+  pub fn to_v8<'a>(
+    self,
+    scope: &mut v8::HandleScope<'a>,
+  ) -> v8::Local<'a, v8::Value> {
+    match self {
+      Self::Bool(value) => v8::Boolean::new(scope, value).into(),
+      Self::I32(value) => v8::Integer::new(scope, value).into(),
+      Self::U32(value) => v8::Integer::new_from_unsigned(scope, value).into(),
+    }
+  }
+}
+
+impl From<bool> for OpScalar {
+  fn from(value: bool) -> Self {
+    Self::Bool(value)
+  }
+}
+
+impl From<i32> for OpScalar {
+  fn from(value: i32) -> Self {
+    Self::I32(value)
+  }
+}
+
+impl From<u32> for OpScalar {
+  fn from(value: u32) -> Self {
+    Self::U32(value)
+  }
+}
+
 #[derive(Serialize)]
 #[serde(rename_all = "camelCase")]
 pub struct OpError {
   #[serde(rename = "$err_class_name")]
-  class_name: &'static str,
-  message: String,
-  code: Option<&'static str>,
+  pub(crate) class_name: &'static str,
+  pub(crate) message: String,
+  pub(crate) code: Option<&'static str>,
+  /// Extra fields attached with `error::with_properties()`.
+  pub(crate) properties: Option<serde_json::Map<String, serde_json::Value>>,
 }
 
 pub fn serialize_op_result<R: Serialize + 'static>(
@@ -146,20 +220,87 @@ pub fn serialize_op_result<R: Serialize + 'static>(
 ) -> OpResult {
   match result {
     Ok(v) => OpResult::Ok(v.into()),
-    Err(err) => OpResult::Err(OpError {
-      class_name: (state.borrow().get_error_class_fn)(&err),
-      message: err.to_string(),
-      code: crate::error_codes::get_error_code(&err),
-    }),
+    Err(err) => {
+      let (err, properties) = match err.downcast::<ErrorWithProperties>() {
+        Ok(err) => (err.error, Some(err.properties)),
+        Err(err) => (err, None),
+      };
+      OpResult::Err(OpError {
+        class_name: state.borrow().get_error_class(&err),
+        message: err.to_string(),
+        code: crate::error_codes::get_error_code(&err),
+        properties,
+      })
+    }
   }
 }
 
+/// Like `serialize_op_result()`, for ops returning an `OpScalar`.
+pub(crate) fn scalar_op_result<R: Into<OpScalar>>(
+  result: Result<R, Error>,
+  state: Rc<RefCell<OpState>>,
+) -> OpResult {
+  match result {
+    Ok(v) => OpResult::Scalar(v.into()),
+    Err(err) => serialize_op_result(Err::<(), Error>(err), state),
+  }
+}
+
+/// Describes an op call that is about to be dispatched. Passed to
+/// `PermissionsHandler::check_op`.
+#[derive(Debug, Clone, Copy)]
+pub struct OpCallInfo<'a> {
+  pub op_id: OpId,
+  pub op_name: &'a str,
+  /// Promise id of an op called with `Deno.core.opAsync()`; `None` for ops
+  /// called with `Deno.core.opSync()`.
+  pub promise_id: Option<PromiseId>,
+}
+
+/// A single enforcement point consulted on every op dispatch, before the op
+/// function runs.
+pub trait PermissionsHandler {
+  /// Returning an error denies the call. The error is surfaced to JS just
+  /// like an error returned by the op itself, so its class is determined by
+  /// `OpState::get_error_class()`.
+  fn check_op(
+    &self,
+    state: &mut OpState,
+    call: &OpCallInfo,
+  ) -> Result<(), Error>;
+}
+
+pub(crate) type ErrorClassMapping = (&'static str, Box<dyn Fn(&Error) -> bool>);
+
 /// Maintains the resources and ops inside a JS runtime.
 pub struct OpState {
   pub resource_table: ResourceTable,
   pub op_table: OpTable,
   pub get_error_class_fn: GetErrorClassFn,
+  /// Error classes registered with `JsRuntime::map_error_class()`, consulted
+  /// in order before `get_error_class_fn`.
+  pub(crate) error_class_mappings: Vec<ErrorClassMapping>,
+  pub permissions_handler: Option<Rc<dyn PermissionsHandler>>,
+  /// See `RuntimeOptions::print_fn`.
+  pub(crate) print_fn: Option<Rc<PrintFn>>,
+  /// The bus used by `Deno.core.broadcastSubscribe()`, see
+  /// `RuntimeOptions::broadcast_bus`.
+  pub broadcast_bus: Option<BroadcastBus>,
+  /// The clock ops implementing timers and timeouts should read the time
+  /// from, see `RuntimeOptions::clock`.
+  pub clock: Arc<dyn Clock>,
+  /// When the runtime was created, as returned by `clock`. `op_now` measures
+  /// from it.
+  pub(crate) time_origin: Instant,
+  /// See `RuntimeOptions::now_precision`.
+  pub(crate) now_precision: Option<Duration>,
   pub(crate) tracker: OpsTracker,
+  pub(crate) sanitizer: Option<Sanitizer>,
+  finalizers: HashMap<FinalizerId, Box<FinalizerFn>>,
+  next_finalizer_id: FinalizerId,
+  cancel_handle: Rc<CancelHandle>,
+  /// Reused for the responses of `op_sync_scratch()` ops.
+  pub(crate) scratch: Vec<u8>,
   gotham_state: GothamState,
 }
 
@@ -169,12 +310,85 @@ impl OpState {
       resource_table: Default::default(),
       op_table: OpTable::default(),
       get_error_class_fn: &|_| "Error",
+      error_class_mappings: Vec::new(),
+      permissions_handler: None,
+      print_fn: None,
+      broadcast_bus: None,
+      clock: Arc::new(RealClock),
+      time_origin: Instant::now(),
+      now_precision: None,
       tracker: OpsTracker {
         ops: RefCell::new(Vec::with_capacity(256)),
       },
+      sanitizer: None,
+      finalizers: HashMap::new(),
+      next_finalizer_id: 0,
+      cancel_handle: CancelHandle::new_rc(),
+      scratch: Vec::new(),
       gotham_state: Default::default(),
     }
   }
+
+  /// Returns the JS error class name registered for `err` with
+  /// `JsRuntime::map_error_class()`, if any.
+  pub fn get_mapped_error_class(&self, err: &Error) -> Option<&'static str> {
+    self
+      .error_class_mappings
+      .iter()
+      .find(|(_, matches)| matches(err))
+      .map(|(class_name, _)| *class_name)
+  }
+
+  /// Returns a handle that is canceled when the runtime is dropped, eg. at
+  /// the end of `JsRuntime::shutdown()`, while async ops may still be
+  /// pending. Ops wrap their futures with `.or_cancel()` to stop early and
+  /// clean up rather than being dropped mid-poll.
+  pub fn cancel_handle(&self) -> Rc<CancelHandle> {
+    self.cancel_handle.clone()
+  }
+
+  pub(crate) fn renew_cancel_handle(&mut self) {
+    self.cancel_handle = CancelHandle::new_rc();
+  }
+
+  /// Returns the JS error class name that `err` is surfaced as when returned
+  /// from an op.
+  pub fn get_error_class(&self, err: &Error) -> &'static str {
+    self
+      .get_mapped_error_class(err)
+      .unwrap_or_else(|| (self.get_error_class_fn)(err))
+  }
+
+  /// Registers a function to be called once the JS object it is tied to is
+  /// garbage collected, eg. to release the native state behind an object
+  /// returned by an op. The returned id is passed to
+  /// `Deno.core.registerFinalizer(object, id)` to tie it to the object.
+  ///
+  /// Finalizers run from the event loop, some time after the object is
+  /// collected, or never if the runtime is dropped first.
+  pub fn add_finalizer(
+    &mut self,
+    finalizer: impl FnOnce(&mut OpState) + 'static,
+  ) -> FinalizerId {
+    let id = self.next_finalizer_id;
+    self.next_finalizer_id += 1;
+    self.finalizers.insert(id, Box::new(finalizer));
+    id
+  }
+
+  /// Unregisters a finalizer without calling it, eg. because the native state
+  /// was released explicitly. Returns `false` if it was already removed or
+  /// has run.
+  pub fn remove_finalizer(&mut self, id: FinalizerId) -> bool {
+    self.finalizers.remove(&id).is_some()
+  }
+
+  /// Calls and unregisters a finalizer, if it wasn't removed already.
+  pub(crate) fn run_finalizer(&mut self, id: FinalizerId) {
+    if let Some(finalizer) = self.finalizers.remove(&id) {
+      finalizer(self);
+    }
+  }
 }
 
 impl Deref for OpState {
@@ -191,22 +405,120 @@ impl DerefMut for OpState {
   }
 }
 
-/// Collection for storing registered ops. The special 'get_op_catalog'
-/// op with OpId `0` is automatically added when the OpTable is created.
-pub struct OpTable(IndexMap<String, Rc<OpFn>>);
+pub type FinalizerId = u32;
+pub type FinalizerFn = dyn FnOnce(&mut OpState);
+
+/// Metadata describing an op, declared along with its registration, eg. with
+/// `ExtensionBuilder::op_metadata()`. It is informational only and isn't
+/// validated against the op function; tooling can use it to generate bindings
+/// or inspect a runtime.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpMetadata {
+  /// Whether the op is called with `Deno.core.opAsync()` rather than
+  /// `Deno.core.opSync()`.
+  pub is_async: bool,
+  /// Hints describing the arguments the op takes, eg. `"rid: number"`.
+  pub args: Vec<&'static str>,
+  /// The permission the op requires, eg. for a `PermissionsHandler`.
+  pub permission: Option<&'static str>,
+}
+
+/// A registered op, as returned by `OpTable::op_infos()`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OpInfo {
+  pub id: OpId,
+  pub name: String,
+  /// `None` if no metadata was declared for the op.
+  pub metadata: Option<OpMetadata>,
+}
+
+struct OpEntry {
+  name: String,
+  op_fn: Rc<OpFn>,
+  metadata: Option<OpMetadata>,
+}
+
+/// Collection for storing registered ops. Op ids index directly into the
+/// table, so routing an op is a single bounds-checked lookup. The special
+/// 'get_op_catalog' op with OpId `0` is automatically added when the OpTable
+/// is created.
+pub struct OpTable {
+  ops: Vec<OpEntry>,
+  ids_by_name: HashMap<String, OpId>,
+}
 
 impl OpTable {
   pub fn register_op<F>(&mut self, name: &str, op_fn: F) -> OpId
   where
     F: Fn(Rc<RefCell<OpState>>, OpPayload) -> Op + 'static,
   {
-    let (op_id, prev) = self.0.insert_full(name.to_owned(), Rc::new(op_fn));
+    let op_id = self.ops.len();
+    let prev = self.ids_by_name.insert(name.to_owned(), op_id);
     assert!(prev.is_none());
+    self.ops.push(OpEntry {
+      name: name.to_owned(),
+      op_fn: Rc::new(op_fn),
+      metadata: None,
+    });
     op_id
   }
 
+  pub fn has_op(&self, name: &str) -> bool {
+    self.ids_by_name.contains_key(name)
+  }
+
+  /// The number of registered ops, including the special op `0`. Op ids are
+  /// allocated densely, so it changes whenever an op is registered.
+  pub fn op_count(&self) -> usize {
+    self.ops.len()
+  }
+
   pub fn op_entries(state: Rc<RefCell<OpState>>) -> Vec<(String, OpId)> {
-    state.borrow().op_table.0.keys().cloned().zip(0..).collect()
+    state
+      .borrow()
+      .op_table
+      .ops
+      .iter()
+      .map(|entry| entry.name.clone())
+      .zip(0..)
+      .collect()
+  }
+
+  /// Returns the name an op was registered with.
+  pub fn op_name(&self, op_id: OpId) -> Option<&str> {
+    self.ops.get(op_id).map(|entry| entry.name.as_str())
+  }
+
+  /// Declares the metadata of a registered op. Returns `false` if there is no
+  /// op with the given name.
+  pub fn set_op_metadata(&mut self, name: &str, metadata: OpMetadata) -> bool {
+    match self.ids_by_name.get(name) {
+      Some(&op_id) => {
+        self.ops[op_id].metadata = Some(metadata);
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn op_metadata(&self, op_id: OpId) -> Option<&OpMetadata> {
+    self.ops.get(op_id)?.metadata.as_ref()
+  }
+
+  /// Returns all registered ops along with their metadata, ordered by id.
+  pub fn op_infos(&self) -> Vec<OpInfo> {
+    self
+      .ops
+      .iter()
+      .enumerate()
+      .map(|(id, entry)| OpInfo {
+        id,
+        name: entry.name.clone(),
+        metadata: entry.metadata.clone(),
+      })
+      .collect()
   }
 
   pub fn route_op(
@@ -214,16 +526,35 @@ impl OpTable {
     state: Rc<RefCell<OpState>>,
     payload: OpPayload,
   ) -> Op {
-    let op_fn = state
-      .borrow()
-      .op_table
-      .0
-      .get_index(op_id)
-      .map(|(_, op_fn)| op_fn.clone());
-    match op_fn {
-      Some(f) => (f)(state, payload),
-      None => Op::NotFound,
+    let (op_fn, maybe_handler) = {
+      let state = state.borrow();
+      match state.op_table.ops.get(op_id) {
+        Some(entry) => (entry.op_fn.clone(), state.permissions_handler.clone()),
+        None => return Op::NotFound,
+      }
+    };
+    let _span = trace_span!(
+      "op",
+      op_id,
+      op_name = %state.borrow().op_table.ops[op_id].name,
+      promise_id = payload.promise_id,
+    );
+    if let Some(handler) = maybe_handler {
+      let op_name = state.borrow().op_table.ops[op_id].name.clone();
+      let call = OpCallInfo {
+        op_id,
+        op_name: &op_name,
+        promise_id: match payload.promise_id {
+          0 => None,
+          promise_id => Some(promise_id),
+        },
+      };
+      let result = handler.check_op(&mut state.borrow_mut(), &call);
+      if let Err(err) = result {
+        return Op::Sync(serialize_op_result::<()>(Err(err), state));
+      }
     }
+    (op_fn)(state, payload)
   }
 }
 
@@ -232,7 +563,12 @@ impl Default for OpTable {
     fn dummy(_state: Rc<RefCell<OpState>>, _p: OpPayload) -> Op {
       unreachable!()
     }
-    Self(once(("ops".to_owned(), Rc::new(dummy) as _)).collect())
+    let mut op_table = Self {
+      ops: Vec::with_capacity(256),
+      ids_by_name: HashMap::new(),
+    };
+    op_table.register_op("ops", dummy);
+    op_table
   }
 }
 
@@ -254,6 +590,11 @@ mod tests {
       bar_id =
         op_table.register_op("bar", |_, _| Op::Sync(OpResult::Ok(123.into())));
       assert_eq!(bar_id, 2);
+      assert!(op_table.has_op("foo"));
+      assert!(!op_table.has_op("baz"));
+      assert_eq!(op_table.op_count(), 3);
+      assert_eq!(op_table.op_name(bar_id), Some("bar"));
+      assert_eq!(op_table.op_name(3), None);
     }
 
     let mut catalog_entries = OpTable::op_entries(state);
@@ -267,4 +608,30 @@ mod tests {
       ]
     );
   }
+
+  #[test]
+  fn op_table_metadata() {
+    let mut op_table = OpTable::default();
+    let foo_id =
+      op_table.register_op("foo", |_, _| Op::Sync(OpResult::Ok(321.into())));
+    assert!(op_table.op_metadata(foo_id).is_none());
+    assert!(op_table.set_op_metadata(
+      "foo",
+      OpMetadata {
+        is_async: false,
+        args: vec!["path: string"],
+        permission: Some("read"),
+      }
+    ));
+    assert!(!op_table.set_op_metadata("bar", OpMetadata::default()));
+
+    let metadata = op_table.op_metadata(foo_id).unwrap();
+    assert_eq!(metadata.args, vec!["path: string"]);
+    assert_eq!(metadata.permission, Some("read"));
+    let infos = op_table.op_infos();
+    assert_eq!(infos.len(), 2);
+    assert_eq!(infos[1].id, foo_id);
+    assert_eq!(infos[1].name, "foo");
+    assert!(infos[1].metadata.is_some());
+  }
 }
@@ -1,16 +1,28 @@
+use crate::broadcast::BroadcastPublisher;
+use crate::broadcast::BroadcastSubscription;
+use crate::error::custom_error;
 use crate::error::type_error;
 use crate::include_js_files;
 use crate::op_async;
 use crate::op_sync;
+use crate::ops::FinalizerId;
+use crate::ops::OpInfo;
+use crate::ops::OpMetadata;
 use crate::ops_metrics::OpMetrics;
 use crate::resources::ResourceId;
 use crate::void_op_async;
 use crate::void_op_sync;
+use crate::AsyncRefCell;
+use crate::CancelFuture;
+use crate::CancelHandle;
 use crate::Extension;
 use crate::OpState;
+use crate::RcRef;
 use crate::Resource;
 use crate::ZeroCopyBuf;
 use anyhow::Error;
+use serde::Deserialize;
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::io::{stderr, stdout, Write};
 use std::rc::Rc;
@@ -26,6 +38,7 @@ pub(crate) fn init_builtins() -> Extension {
     .ops(vec![
       ("op_close", op_sync(op_close)),
       ("op_try_close", op_sync(op_try_close)),
+      ("op_run_finalizer", op_sync(op_run_finalizer)),
       ("op_print", op_sync(op_print)),
       ("op_resources", op_sync(op_resources)),
       ("op_wasm_streaming_feed", op_sync(op_wasm_streaming_feed)),
@@ -35,16 +48,81 @@ pub(crate) fn init_builtins() -> Extension {
         op_sync(op_wasm_streaming_set_url),
       ),
       ("op_metrics", op_sync(op_metrics)),
+      ("op_metadata", op_sync(op_metadata)),
+      ("op_now", op_sync(op_now)),
+      ("op_log", op_sync(op_log)),
       ("op_void_sync", void_op_sync()),
       ("op_void_async", void_op_async()),
       // TODO(@AaronO): track IO metrics for builtin streams
       ("op_read", op_async(op_read)),
       ("op_write", op_async(op_write)),
       ("op_shutdown", op_async(op_shutdown)),
+      (
+        "op_broadcast_bus_subscribe",
+        op_sync(op_broadcast_bus_subscribe),
+      ),
+      (
+        "op_broadcast_bus_publish",
+        op_sync(op_broadcast_bus_publish),
+      ),
+      ("op_broadcast_bus_recv", op_async(op_broadcast_bus_recv)),
+    ])
+    .op_metadata(vec![
+      ("op_close", sync_metadata(vec!["rid: number"])),
+      ("op_try_close", sync_metadata(vec!["rid: number"])),
+      (
+        "op_print",
+        sync_metadata(vec!["msg: string", "isErr: boolean"]),
+      ),
+      ("op_resources", sync_metadata(vec![])),
+      ("op_metrics", sync_metadata(vec![])),
+      ("op_metadata", sync_metadata(vec![])),
+      ("op_now", sync_metadata(vec![])),
+      (
+        "op_log",
+        sync_metadata(vec![
+          "args: { level: string, target: string | null, message: string }",
+          "fields: Record<string, unknown> | null",
+        ]),
+      ),
+      (
+        "op_read",
+        async_metadata(vec!["rid: number", "buf: Uint8Array"]),
+      ),
+      (
+        "op_write",
+        async_metadata(vec!["rid: number", "buf: Uint8Array"]),
+      ),
+      ("op_shutdown", async_metadata(vec!["rid: number"])),
+      (
+        "op_broadcast_bus_subscribe",
+        sync_metadata(vec!["topic: string"]),
+      ),
+      (
+        "op_broadcast_bus_publish",
+        sync_metadata(vec!["rid: number", "data: Uint8Array"]),
+      ),
+      ("op_broadcast_bus_recv", async_metadata(vec!["rid: number"])),
     ])
     .build()
 }
 
+fn sync_metadata(args: Vec<&'static str>) -> OpMetadata {
+  OpMetadata {
+    is_async: false,
+    args,
+    permission: None,
+  }
+}
+
+fn async_metadata(args: Vec<&'static str>) -> OpMetadata {
+  OpMetadata {
+    is_async: true,
+    args,
+    permission: None,
+  }
+}
+
 /// Return map of resources with id as key
 /// and string representation as value.
 pub fn op_resources(
@@ -87,13 +165,35 @@ pub fn op_try_close(
   Ok(())
 }
 
+/// Run a finalizer registered with `OpState::add_finalizer()`. Called when the
+/// object it was tied to with `Deno.core.registerFinalizer()` is collected.
+pub fn op_run_finalizer(
+  state: &mut OpState,
+  id: FinalizerId,
+  _: (),
+) -> Result<(), Error> {
+  state.run_finalizer(id);
+  Ok(())
+}
+
+/// Return all registered ops along with the metadata declared for them.
+pub fn op_metadata(
+  state: &mut OpState,
+  _: (),
+  _: (),
+) -> Result<Vec<OpInfo>, Error> {
+  Ok(state.op_table.op_infos())
+}
+
 /// Builtin utility to print to stdout/stderr
 pub fn op_print(
-  _state: &mut OpState,
+  state: &mut OpState,
   msg: String,
   is_err: bool,
 ) -> Result<(), Error> {
-  if is_err {
+  if let Some(print_fn) = &state.print_fn {
+    print_fn(&msg, is_err);
+  } else if is_err {
     stderr().write_all(msg.as_bytes())?;
     stderr().flush().unwrap();
   } else {
@@ -176,6 +276,112 @@ pub fn op_metrics(
   Ok((aggregate, per_op))
 }
 
+/// Returns the milliseconds elapsed since the runtime was created, clamped to
+/// `RuntimeOptions::now_precision`.
+pub fn op_now(state: &mut OpState, _: (), _: ()) -> Result<f64, Error> {
+  let elapsed = state
+    .clock
+    .now()
+    .saturating_duration_since(state.time_origin);
+  let mut nanos = elapsed.as_nanos();
+  if let Some(precision) = state.now_precision {
+    let precision = precision.as_nanos();
+    if precision > 0 {
+      nanos -= nanos % precision;
+    }
+  }
+  Ok(nanos as f64 / 1_000_000.0)
+}
+
+#[derive(Deserialize)]
+pub struct LogArgs {
+  level: String,
+  target: Option<String>,
+  message: String,
+}
+
+type LogFields = serde_json::Map<String, serde_json::Value>;
+
+/// Logs a message from JS under the target "js", or "js::<target>" if a
+/// target is given, so that scripts can't pose as Rust modules.
+///
+/// The message is logged with the `log` crate, with `fields` appended as
+/// `key=value` pairs with the values serialized as JSON. With the "tracing"
+/// feature, a `tracing` event with the target "js" is emitted instead, with
+/// the full target in its `js.target` field and `fields` as a JSON object in
+/// its `fields` field.
+pub fn op_log(
+  _state: &mut OpState,
+  args: LogArgs,
+  fields: Option<LogFields>,
+) -> Result<(), Error> {
+  let level = args
+    .level
+    .parse::<log::Level>()
+    .map_err(|_| type_error(format!("Invalid log level: {}", args.level)))?;
+  let target = match args.target {
+    Some(target) => format!("js::{}", target),
+    None => "js".to_string(),
+  };
+  emit_log(level, &target, args.message, fields);
+  Ok(())
+}
+
+#[cfg(not(feature = "tracing"))]
+fn emit_log(
+  level: log::Level,
+  target: &str,
+  mut message: String,
+  fields: Option<LogFields>,
+) {
+  use std::fmt::Write as _;
+
+  let metadata = log::Metadata::builder().level(level).target(target).build();
+  if level > log::max_level() || !log::logger().enabled(&metadata) {
+    return;
+  }
+  for (key, value) in fields.into_iter().flatten() {
+    write!(message, " {}={}", key, value).unwrap();
+  }
+  log::logger().log(
+    &log::Record::builder()
+      .metadata(metadata)
+      .args(format_args!("{}", message))
+      .build(),
+  );
+}
+
+#[cfg(feature = "tracing")]
+fn emit_log(
+  level: log::Level,
+  target: &str,
+  message: String,
+  fields: Option<LogFields>,
+) {
+  let fields = fields
+    .map(|fields| tracing::field::display(serde_json::Value::Object(fields)));
+  // Event levels must be constants.
+  macro_rules! emit {
+    ($level:expr) => {
+      tracing::event!(
+        target: "js",
+        $level,
+        js.target = target,
+        fields = fields,
+        "{}",
+        message
+      )
+    };
+  }
+  match level {
+    log::Level::Error => emit!(tracing::Level::ERROR),
+    log::Level::Warn => emit!(tracing::Level::WARN),
+    log::Level::Info => emit!(tracing::Level::INFO),
+    log::Level::Debug => emit!(tracing::Level::DEBUG),
+    log::Level::Trace => emit!(tracing::Level::TRACE),
+  }
+}
+
 async fn op_read(
   state: Rc<RefCell<OpState>>,
   rid: ResourceId,
@@ -202,3 +408,67 @@ async fn op_shutdown(
   let resource = state.borrow().resource_table.get_any(rid)?;
   resource.shutdown().await
 }
+
+struct BroadcastSubscriptionResource {
+  publisher: BroadcastPublisher,
+  subscription: AsyncRefCell<BroadcastSubscription>,
+  cancel: CancelHandle,
+}
+
+impl Resource for BroadcastSubscriptionResource {
+  fn name(&self) -> Cow<str> {
+    "broadcastSubscription".into()
+  }
+
+  fn close(self: Rc<Self>) {
+    self.cancel.cancel();
+  }
+}
+
+pub fn op_broadcast_bus_subscribe(
+  state: &mut OpState,
+  topic: String,
+  _: (),
+) -> Result<ResourceId, Error> {
+  let bus = state.broadcast_bus.as_ref().ok_or_else(|| {
+    custom_error("NotSupported", "The broadcast bus is not enabled")
+  })?;
+  let subscription = bus.subscribe(&topic);
+  let resource = BroadcastSubscriptionResource {
+    publisher: subscription.publisher(),
+    subscription: AsyncRefCell::new(subscription),
+    cancel: Default::default(),
+  };
+  Ok(state.resource_table.add(resource))
+}
+
+pub fn op_broadcast_bus_publish(
+  state: &mut OpState,
+  rid: ResourceId,
+  data: ZeroCopyBuf,
+) -> Result<u32, Error> {
+  let resource = state
+    .resource_table
+    .get::<BroadcastSubscriptionResource>(rid)?;
+  Ok(resource.publisher.publish(&data) as u32)
+}
+
+/// Resolves with null once the subscription is closed.
+async fn op_broadcast_bus_recv(
+  state: Rc<RefCell<OpState>>,
+  rid: ResourceId,
+  _: (),
+) -> Result<Option<ZeroCopyBuf>, Error> {
+  let resource = state
+    .borrow()
+    .resource_table
+    .get::<BroadcastSubscriptionResource>(rid)?;
+  let mut subscription = RcRef::map(&resource, |r| &r.subscription)
+    .borrow_mut()
+    .await;
+  let cancel = RcRef::map(&resource, |r| &r.cancel);
+  match subscription.recv().or_cancel(cancel).await {
+    Ok(data) => Ok(Some(data.to_vec().into())),
+    Err(_) => Ok(None),
+  }
+}
@@ -0,0 +1,169 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::type_error;
+use crate::ops::OpCall;
+use crate::serialize_op_result;
+use crate::Op;
+use crate::OpFn;
+use crate::OpState;
+use crate::ZeroCopyBuf;
+use anyhow::Error;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::RefCell;
+use std::future::Future;
+use std::rc::Rc;
+
+/// Encodes the arguments and results of ops created with
+/// `op_sync_with_codec()` or `op_async_with_codec()` to and from bytes, so
+/// embedders can use a compact binary encoding instead of converting values
+/// with serde_v8.
+pub trait OpCodec {
+  fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error>;
+  fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error>;
+}
+
+/// Encodes op arguments and results as UTF-8 JSON.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct JsonCodec;
+
+impl OpCodec for JsonCodec {
+  fn decode<T: DeserializeOwned>(&self, bytes: &[u8]) -> Result<T, Error> {
+    Ok(serde_json::from_slice(bytes)?)
+  }
+
+  fn encode<T: Serialize>(&self, value: &T) -> Result<Vec<u8>, Error> {
+    Ok(serde_json::to_vec(value)?)
+  }
+}
+
+/// Like `op_sync()`, but the first argument is a `Uint8Array` decoded with
+/// `codec`, and the result is encoded with `codec` and returned to JavaScript
+/// as a `Uint8Array`. The second argument is still converted with serde_v8,
+/// eg. to pass a `ZeroCopyBuf` along. Errors are returned like from any other
+/// op.
+///
+/// ```js
+/// const result = decode(Deno.core.opSync("op_hello", encode(args)));
+/// ```
+pub fn op_sync_with_codec<C, F, A, B, R>(codec: C, op_fn: F) -> Box<OpFn>
+where
+  C: OpCodec + 'static,
+  F: Fn(&mut OpState, A, B) -> Result<R, Error> + 'static,
+  A: DeserializeOwned,
+  B: DeserializeOwned,
+  R: Serialize + 'static,
+{
+  Box::new(move |state, payload| -> Op {
+    let result = payload
+      .deserialize::<ZeroCopyBuf, B>()
+      .and_then(|(a, b)| Ok((decode_args(&codec, &a)?, b)))
+      .and_then(|(a, b)| op_fn(&mut state.borrow_mut(), a, b))
+      .and_then(|r| codec.encode(&r))
+      .map(ZeroCopyBuf::from);
+    Op::Sync(serialize_op_result(result, state))
+  })
+}
+
+/// Like `op_async()`, but with the argument and result encoded with `codec`,
+/// see `op_sync_with_codec()`.
+pub fn op_async_with_codec<C, F, A, B, R, RV>(codec: C, op_fn: F) -> Box<OpFn>
+where
+  C: OpCodec + Clone + 'static,
+  F: Fn(Rc<RefCell<OpState>>, A, B) -> R + 'static,
+  A: DeserializeOwned,
+  B: DeserializeOwned,
+  R: Future<Output = Result<RV, Error>> + 'static,
+  RV: Serialize + 'static,
+{
+  Box::new(move |state, payload| -> Op {
+    let op_id = payload.op_id;
+    let pid = payload.promise_id;
+    // Decode args, sync error on failure
+    let args = payload
+      .deserialize::<ZeroCopyBuf, B>()
+      .and_then(|(a, b)| Ok((decode_args(&codec, &a)?, b)));
+    let (a, b) = match args {
+      Ok(args) => args,
+      Err(err) => {
+        return Op::Sync(serialize_op_result(Err::<(), Error>(err), state))
+      }
+    };
+
+    use crate::futures::FutureExt;
+    let codec = codec.clone();
+    let fut = op_fn(state.clone(), a, b).map(move |result| {
+      let result = result.and_then(|r| codec.encode(&r)).map(ZeroCopyBuf::from);
+      (pid, op_id, serialize_op_result(result, state))
+    });
+    Op::Async(OpCall::eager(fut))
+  })
+}
+
+fn decode_args<C: OpCodec, T: DeserializeOwned>(
+  codec: &C,
+  bytes: &[u8],
+) -> Result<T, Error> {
+  codec
+    .decode(bytes)
+    .map_err(|e| type_error(format!("Error decoding args: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::JsRuntime;
+
+  #[derive(serde::Deserialize)]
+  struct Sum {
+    a: u32,
+    b: u32,
+  }
+
+  #[tokio::test]
+  async fn test_op_with_codec() {
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime.register_op(
+      "op_sum",
+      op_sync_with_codec(JsonCodec, |_, args: Sum, _: ()| Ok(args.a + args.b)),
+    );
+    runtime.register_op(
+      "op_sum_async",
+      op_async_with_codec(JsonCodec, |_, args: Sum, _: ()| async move {
+        Ok(vec![args.a, args.b])
+      }),
+    );
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script(
+        "codec.js",
+        r#"
+        const encode = (v) => Deno.core.encode(JSON.stringify(v));
+        const decode = (buf) => JSON.parse(Deno.core.decode(buf));
+        const sum = Deno.core.opSync("op_sum", encode({ a: 1, b: 2 }));
+        if (!(sum instanceof Uint8Array) || decode(sum) !== 3) {
+          throw new Error(`unexpected result: ${sum}`);
+        }
+        let threw = false;
+        try {
+          Deno.core.opSync("op_sum", encode({ a: 1 }));
+        } catch (e) {
+          threw = e instanceof TypeError &&
+            e.message.startsWith("Error decoding args");
+        }
+        if (!threw) throw new Error("invalid args were accepted");
+        (async () => {
+          const res = await Deno.core.opAsync(
+            "op_sum_async",
+            encode({ a: 3, b: 4 }),
+          );
+          if (decode(res).join() !== "3,4") {
+            throw new Error(`unexpected result: ${res}`);
+          }
+        })();
+        "#,
+      )
+      .unwrap();
+    runtime.run_event_loop(false).await.unwrap();
+  }
+}
@@ -1,6 +1,9 @@
 // Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
 
+use crate::ops::scalar_op_result;
 use crate::ops::OpCall;
+use crate::ops::OpResult;
+use crate::ops::OpScalar;
 use crate::serialize_op_result;
 use crate::Op;
 use crate::OpFn;
@@ -117,6 +120,77 @@ where
   })
 }
 
+/// Like `op_sync()`, for ops returning a single `u32`, `i32` or `bool`. The
+/// result is converted to a V8 value directly, skipping serde_v8 and the
+/// allocation it requires.
+pub fn op_sync_scalar<F, A, B, R>(op_fn: F) -> Box<OpFn>
+where
+  F: Fn(&mut OpState, A, B) -> Result<R, Error> + 'static,
+  A: DeserializeOwned,
+  B: DeserializeOwned,
+  R: Into<OpScalar>,
+{
+  Box::new(move |state, payload| -> Op {
+    let result = payload
+      .deserialize()
+      .and_then(|(a, b)| op_fn(&mut state.borrow_mut(), a, b));
+    Op::Sync(scalar_op_result(result, state))
+  })
+}
+
+/// Like `op_sync()`, for ops responding with bytes. Instead of returning
+/// them, `op_fn` writes them to a buffer that is reused across calls (and
+/// cleared before each one), so the response doesn't allocate on the Rust
+/// side. JS receives a copy of the bytes as a `Uint8Array`.
+pub fn op_sync_scratch<F, A, B>(op_fn: F) -> Box<OpFn>
+where
+  F: Fn(&mut OpState, A, B, &mut Vec<u8>) -> Result<(), Error> + 'static,
+  A: DeserializeOwned,
+  B: DeserializeOwned,
+{
+  Box::new(move |state, payload| -> Op {
+    let result = payload.deserialize().and_then(|(a, b)| {
+      let mut state = state.borrow_mut();
+      let mut scratch = std::mem::take(&mut state.scratch);
+      scratch.clear();
+      let result = op_fn(&mut state, a, b, &mut scratch);
+      state.scratch = scratch;
+      result
+    });
+    match result {
+      Ok(()) => Op::Sync(OpResult::Scratch),
+      Err(err) => Op::Sync(serialize_op_result(Err::<(), Error>(err), state)),
+    }
+  })
+}
+
+/// Like `op_async()`, for ops resolving with a single `u32`, `i32` or
+/// `bool`, see `op_sync_scalar()`.
+pub fn op_async_scalar<F, A, B, R, RV>(op_fn: F) -> Box<OpFn>
+where
+  F: Fn(Rc<RefCell<OpState>>, A, B) -> R + 'static,
+  A: DeserializeOwned,
+  B: DeserializeOwned,
+  R: Future<Output = Result<RV, Error>> + 'static,
+  RV: Into<OpScalar> + 'static,
+{
+  Box::new(move |state, payload| -> Op {
+    let op_id = payload.op_id;
+    let pid = payload.promise_id;
+    let (a, b) = match payload.deserialize() {
+      Ok(args) => args,
+      Err(err) => {
+        return Op::Sync(serialize_op_result(Err::<(), Error>(err), state))
+      }
+    };
+
+    use crate::futures::FutureExt;
+    let fut = op_fn(state.clone(), a, b)
+      .map(move |result| (pid, op_id, scalar_op_result(result, state)));
+    Op::Async(OpCall::eager(fut))
+  })
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -158,4 +232,89 @@ mod tests {
     assert!(e.contains("at async f1 (<init>:"));
     assert!(e.contains("at async f2 (<init>:"));
   }
+
+  #[tokio::test]
+  async fn scalar_ops() {
+    let mut runtime = crate::JsRuntime::new(Default::default());
+    runtime.register_op(
+      "op_len",
+      op_sync_scalar(|_, s: String, _: ()| Ok(s.len() as u32)),
+    );
+    runtime.register_op(
+      "op_is_even",
+      op_sync_scalar(|_, n: i32, _: ()| {
+        if n < 0 {
+          return Err(crate::error::range_error("negative"));
+        }
+        Ok(n % 2 == 0)
+      }),
+    );
+    runtime.register_op(
+      "op_negate",
+      op_async_scalar(|_, n: i32, _: ()| async move { Ok(-n) }),
+    );
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script(
+        "scalar.js",
+        r#"
+        if (Deno.core.opSync("op_len", "hello") !== 5) {
+          throw new Error("op_len");
+        }
+        if (Deno.core.opSync("op_is_even", 4) !== true) {
+          throw new Error("op_is_even");
+        }
+        try {
+          Deno.core.opSync("op_is_even", -1);
+          throw new Error("op_is_even should throw");
+        } catch (e) {
+          if (!(e instanceof RangeError)) throw e;
+        }
+        Deno.core.opAsync("op_negate", 7).then((n) => {
+          if (n !== -7) throw new Error("op_negate");
+          globalThis.negated = true;
+        });
+        "#,
+      )
+      .unwrap();
+    runtime.run_event_loop(false).await.unwrap();
+    let value = runtime.execute_script("check.js", "negated").unwrap();
+    assert!(runtime.from_v8::<bool>(&value).unwrap());
+  }
+
+  #[test]
+  fn scratch_ops() {
+    let mut runtime = crate::JsRuntime::new(Default::default());
+    runtime.register_op(
+      "op_repeat",
+      op_sync_scratch(|_, (byte, n): (u8, usize), _: (), buf| {
+        if n > 1024 {
+          return Err(crate::error::range_error("too long"));
+        }
+        buf.resize(n, byte);
+        Ok(())
+      }),
+    );
+    runtime.sync_ops_cache();
+    let value = runtime
+      .execute_script(
+        "scratch.js",
+        r#"
+        const a = Deno.core.opSync("op_repeat", [1, 3]);
+        const b = Deno.core.opSync("op_repeat", [2, 2]);
+        let threw = false;
+        try {
+          Deno.core.opSync("op_repeat", [0, 2048]);
+        } catch (e) {
+          threw = e instanceof RangeError;
+        }
+        [...a, ...b, a instanceof Uint8Array, threw]
+        "#,
+      )
+      .unwrap();
+    assert_eq!(
+      runtime.from_v8::<serde_json::Value>(&value).unwrap(),
+      serde_json::json!([1, 1, 1, 2, 2, true, true])
+    );
+  }
 }
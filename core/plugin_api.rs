@@ -0,0 +1,37 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+//! Interface between a runtime and native plugins loaded into it at runtime.
+//!
+//! A plugin is a dynamic library that exports an `InitFn` named
+//! `deno_plugin_init`, which registers the plugin's ops:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub fn deno_plugin_init(interface: &mut dyn Interface) {
+//!   interface
+//!     .register_op("op_hello", op_sync(op_hello))
+//!     .unwrap();
+//! }
+//! ```
+//!
+//! Ops are passed across the library boundary as Rust trait objects, so a
+//! plugin must be built with the same compiler and `deno_core` version as the
+//! runtime loading it.
+
+use crate::OpFn;
+use crate::OpId;
+use anyhow::Error;
+
+pub type InitFn = fn(&mut dyn Interface);
+
+pub const INIT_FN_NAME: &str = "deno_plugin_init";
+
+pub trait Interface {
+  /// Registers an op, eg. one created with `op_sync()` or `op_async()`, into
+  /// the live op table. Fails if an op with the same name already exists.
+  fn register_op(
+    &mut self,
+    name: &str,
+    op_fn: Box<OpFn>,
+  ) -> Result<OpId, Error>;
+}
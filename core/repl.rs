@@ -0,0 +1,426 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::bindings;
+use crate::runtime::compile_exception_to_err_result;
+use crate::runtime::exception_to_err_result;
+use anyhow::Error;
+use serde::Serialize;
+
+/// Script name of code evaluated with `eval_expression()`.
+const REPL_SPECIFIER: &str = "deno:repl";
+/// Objects nested deeper than this are abbreviated, eg. as `[Object]`.
+const MAX_DEPTH: usize = 4;
+/// Arrays and collections show at most this many entries.
+const MAX_ENTRIES: usize = 100;
+
+/// Evaluates `source` like an interactive console does and returns the result
+/// formatted with `inspect()`. See `JsRuntime::eval_expression()`.
+pub(crate) fn eval_expression(
+  scope: &mut v8::HandleScope,
+  source: &str,
+) -> Result<String, Error> {
+  let name = v8::String::new(scope, REPL_SPECIFIER).unwrap();
+  let origin = bindings::script_origin(scope, name);
+  let tc_scope = &mut v8::TryCatch::new(scope);
+
+  // Input like `{ a: 1 }` is an object literal rather than a block.
+  let mut script = None;
+  if let Some(wrapped) = wrap_object_literal(source) {
+    let wrapped = v8::String::new(tc_scope, &wrapped).unwrap();
+    script = v8::Script::compile(tc_scope, wrapped, Some(&origin));
+    tc_scope.reset();
+  }
+  let script = match script {
+    Some(script) => script,
+    None => {
+      let source = v8::String::new(tc_scope, source).unwrap();
+      match v8::Script::compile(tc_scope, source, Some(&origin)) {
+        Some(script) => script,
+        None => {
+          let exception = tc_scope.exception().unwrap();
+          return compile_exception_to_err_result(tc_scope, exception);
+        }
+      }
+    }
+  };
+
+  let global = tc_scope.get_current_context().global(tc_scope);
+  match script.run(tc_scope) {
+    Some(value) => {
+      let key = v8::String::new(tc_scope, "_").unwrap();
+      global.set(tc_scope, key.into(), value);
+      Ok(inspect(tc_scope, value))
+    }
+    None => {
+      assert!(tc_scope.has_caught());
+      let exception = tc_scope.exception().unwrap();
+      if !tc_scope.is_execution_terminating() {
+        let key = v8::String::new(tc_scope, "_error").unwrap();
+        global.set(tc_scope, key.into(), exception);
+      }
+      exception_to_err_result(tc_scope, exception, false)
+    }
+  }
+}
+
+fn wrap_object_literal(source: &str) -> Option<String> {
+  if source.trim_start().starts_with('{') && !source.trim_end().ends_with(';') {
+    Some(format!("({}\n)", source))
+  } else {
+    None
+  }
+}
+
+/// A property that can complete an expression, see
+/// `JsRuntime::completions()`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Completion {
+  pub name: String,
+  /// The `typeof` the property's value, or `"accessor"` for a property with
+  /// a getter or setter, which isn't invoked.
+  pub kind: String,
+}
+
+/// Lists the own and inherited property names of an object, with their kinds,
+/// as a flat `[name, kind, ...]` array.
+const LIST_PROPERTIES: &str = r#"(object) => {
+  const seen = new Set();
+  const properties = [];
+  for (; object !== null; object = Object.getPrototypeOf(object)) {
+    for (const name of Object.getOwnPropertyNames(object)) {
+      if (seen.has(name)) continue;
+      seen.add(name);
+      const desc = Object.getOwnPropertyDescriptor(object, name);
+      const kind = desc.get || desc.set ? "accessor" : typeof desc.value;
+      properties.push(name, kind);
+    }
+  }
+  return properties;
+}"#;
+
+/// Returns the properties that can complete the member expression at the end
+/// of `input`, eg. `Deno.co`, sorted by name.
+pub(crate) fn completions(
+  scope: &mut v8::HandleScope,
+  input: &str,
+) -> Vec<Completion> {
+  let start = input
+    .rfind(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$' || c == '.'))
+    .map_or(0, |i| i + input[i..].chars().next().unwrap().len_utf8());
+  let partial = &input[start..];
+  let (object_expr, prefix) = match partial.rfind('.') {
+    Some(i) => (Some(&partial[..i]), &partial[i + 1..]),
+    None => (None, partial),
+  };
+  // Only plain member expressions are evaluated, so completing doesn't run
+  // arbitrary code (except getters).
+  if let Some(expr) = object_expr {
+    if !expr.split('.').all(is_identifier) {
+      return vec![];
+    }
+  }
+
+  let tc_scope = &mut v8::TryCatch::new(scope);
+  let object: v8::Local<v8::Object> = match object_expr {
+    Some(expr) => {
+      let value = match run_script(tc_scope, expr) {
+        Some(value) if !value.is_null_or_undefined() => value,
+        _ => return vec![],
+      };
+      value.to_object(tc_scope).unwrap()
+    }
+    None => tc_scope.get_current_context().global(tc_scope),
+  };
+  let properties = run_script(tc_scope, LIST_PROPERTIES)
+    .and_then(|f| v8::Local::<v8::Function>::try_from(f).ok())
+    .and_then(|f| {
+      let this = v8::undefined(tc_scope).into();
+      f.call(tc_scope, this, &[object.into()])
+    })
+    .and_then(|properties| v8::Local::<v8::Array>::try_from(properties).ok());
+  let properties = match properties {
+    Some(properties) => properties,
+    None => return vec![],
+  };
+
+  let mut completions = vec![];
+  for i in (0..properties.length()).step_by(2) {
+    let name = properties.get_index(tc_scope, i).unwrap();
+    let name = name.to_rust_string_lossy(tc_scope);
+    if !name.starts_with(prefix) || !is_identifier(&name) {
+      continue;
+    }
+    let kind = properties.get_index(tc_scope, i + 1).unwrap();
+    completions.push(Completion {
+      name,
+      kind: kind.to_rust_string_lossy(tc_scope),
+    });
+  }
+  completions.sort_by(|a, b| a.name.cmp(&b.name));
+  completions
+}
+
+fn run_script<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  source: &str,
+) -> Option<v8::Local<'s, v8::Value>> {
+  let source = v8::String::new(scope, source)?;
+  v8::Script::compile(scope, source, None)?.run(scope)
+}
+
+/// Formats `value` on a single line for display, eg. `{ a: [ 1, "b" ] }`.
+/// Getters are invoked; exceptions they throw are ignored.
+pub(crate) fn inspect<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  value: v8::Local<'s, v8::Value>,
+) -> String {
+  let tc_scope = &mut v8::TryCatch::new(scope);
+  let mut out = String::new();
+  format_value(tc_scope, value, 0, &mut vec![], &mut out);
+  out
+}
+
+fn format_value<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  value: v8::Local<'s, v8::Value>,
+  depth: usize,
+  seen: &mut Vec<v8::Local<'s, v8::Object>>,
+  out: &mut String,
+) {
+  if value.is_string() {
+    let string = value.to_rust_string_lossy(scope);
+    out.push_str(&serde_json::to_string(&string).unwrap());
+    return;
+  }
+  if value.is_number() {
+    let number = value.number_value(scope).unwrap();
+    if number == 0.0 && number.is_sign_negative() {
+      out.push_str("-0");
+    } else {
+      out.push_str(&value.to_rust_string_lossy(scope));
+    }
+    return;
+  }
+  if value.is_big_int() {
+    out.push_str(&value.to_rust_string_lossy(scope));
+    out.push('n');
+    return;
+  }
+  if let Ok(symbol) = v8::Local::<v8::Symbol>::try_from(value) {
+    let description = symbol.description(scope);
+    out.push_str("Symbol(");
+    if !description.is_undefined() {
+      out.push_str(&description.to_rust_string_lossy(scope));
+    }
+    out.push(')');
+    return;
+  }
+  if let Ok(function) = v8::Local::<v8::Function>::try_from(value) {
+    let name = function.get_name(scope).to_rust_string_lossy(scope);
+    if name.is_empty() {
+      out.push_str("[Function (anonymous)]");
+    } else {
+      out.push_str(&format!("[Function: {}]", name));
+    }
+    return;
+  }
+  let object = match v8::Local::<v8::Object>::try_from(value) {
+    Ok(object) => object,
+    // undefined, null and booleans.
+    Err(_) => {
+      out.push_str(&value.to_rust_string_lossy(scope));
+      return;
+    }
+  };
+
+  if seen.iter().any(|other| other.strict_equals(value)) {
+    out.push_str("[Circular]");
+    return;
+  }
+  if value.is_native_error() {
+    let key = v8::String::new(scope, "stack").unwrap();
+    let stack = object
+      .get(scope, key.into())
+      .filter(|stack| stack.is_string())
+      .unwrap_or(value);
+    out.push_str(&stack.to_rust_string_lossy(scope));
+    return;
+  }
+  if value.is_date() || value.is_reg_exp() {
+    out.push_str(&value.to_rust_string_lossy(scope));
+    return;
+  }
+  if depth >= MAX_DEPTH {
+    out.push_str(if value.is_array() {
+      "[Array]"
+    } else {
+      "[Object]"
+    });
+    return;
+  }
+
+  seen.push(object);
+  if let Ok(promise) = v8::Local::<v8::Promise>::try_from(value) {
+    out.push_str("Promise { ");
+    match promise.state() {
+      v8::PromiseState::Pending => out.push_str("<pending>"),
+      state => {
+        if state == v8::PromiseState::Rejected {
+          out.push_str("<rejected> ");
+        }
+        let result = promise.result(scope);
+        format_value(scope, result, depth + 1, seen, out);
+      }
+    }
+    out.push_str(" }");
+  } else if let Ok(array) = v8::Local::<v8::Array>::try_from(value) {
+    format_list(scope, array, 1, "[", "]", depth, seen, out);
+  } else if let Ok(map) = v8::Local::<v8::Map>::try_from(value) {
+    out.push_str(&format!("Map({}) ", map.size()));
+    let entries = map.as_array(scope);
+    format_list(scope, entries, 2, "{", "}", depth, seen, out);
+  } else if value.is_set() {
+    // There is no binding to get the entries of a set.
+    let values = array_from(scope, value);
+    out.push_str(&format!("Set({}) ", values.length()));
+    format_list(scope, values, 1, "{", "}", depth, seen, out);
+  } else if value.is_typed_array() {
+    let constructor_name = constructor_name(scope, object);
+    let key = v8::String::new(scope, "length").unwrap();
+    let length = object
+      .get(scope, key.into())
+      .and_then(|length| length.uint32_value(scope))
+      .unwrap_or(0);
+    out.push_str(&format!("{}({}) ", constructor_name, length));
+    let elements = array_from(scope, value);
+    format_list(scope, elements, 1, "[", "]", depth, seen, out);
+  } else {
+    let constructor_name = constructor_name(scope, object);
+    if constructor_name != "Object" && !constructor_name.is_empty() {
+      out.push_str(&constructor_name);
+      out.push(' ');
+    }
+    format_properties(scope, object, depth, seen, out);
+  }
+  seen.pop();
+}
+
+/// Formats the elements of `array`, in groups of `group_size` for the key
+/// value pairs of maps.
+#[allow(clippy::too_many_arguments)]
+fn format_list<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  array: v8::Local<'s, v8::Array>,
+  group_size: u32,
+  open: &str,
+  close: &str,
+  depth: usize,
+  seen: &mut Vec<v8::Local<'s, v8::Object>>,
+  out: &mut String,
+) {
+  let count = array.length() / group_size;
+  if count == 0 {
+    out.push_str(open);
+    out.push_str(close);
+    return;
+  }
+  out.push_str(open);
+  out.push(' ');
+  for i in 0..count.min(MAX_ENTRIES as u32) {
+    if i > 0 {
+      out.push_str(", ");
+    }
+    for j in 0..group_size {
+      if j > 0 {
+        out.push_str(" => ");
+      }
+      let element = array
+        .get_index(scope, i * group_size + j)
+        .unwrap_or_else(|| v8::undefined(scope).into());
+      format_value(scope, element, depth + 1, seen, out);
+    }
+  }
+  if count as usize > MAX_ENTRIES {
+    out.push_str(&format!(
+      ", ... {} more items",
+      count as usize - MAX_ENTRIES
+    ));
+  }
+  out.push(' ');
+  out.push_str(close);
+}
+
+fn format_properties<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  object: v8::Local<'s, v8::Object>,
+  depth: usize,
+  seen: &mut Vec<v8::Local<'s, v8::Object>>,
+  out: &mut String,
+) {
+  let names = match object.get_own_property_names(scope) {
+    Some(names) if names.length() > 0 => names,
+    _ => {
+      out.push_str("{}");
+      return;
+    }
+  };
+  out.push_str("{ ");
+  for i in 0..names.length() {
+    if i > 0 {
+      out.push_str(", ");
+    }
+    let name = names.get_index(scope, i).unwrap();
+    let key = name.to_rust_string_lossy(scope);
+    if is_identifier(&key) {
+      out.push_str(&key);
+    } else {
+      out.push_str(&serde_json::to_string(&key).unwrap());
+    }
+    out.push_str(": ");
+    let value = object
+      .get(scope, name)
+      .unwrap_or_else(|| v8::undefined(scope).into());
+    format_value(scope, value, depth + 1, seen, out);
+  }
+  out.push_str(" }");
+}
+
+fn is_identifier(key: &str) -> bool {
+  let mut chars = key.chars();
+  matches!(chars.next(), Some(c) if c.is_alphabetic() || c == '_' || c == '$')
+    && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '$')
+}
+
+fn constructor_name(
+  scope: &mut v8::HandleScope,
+  object: v8::Local<v8::Object>,
+) -> String {
+  let key = v8::String::new(scope, "constructor").unwrap();
+  object
+    .get(scope, key.into())
+    .and_then(|constructor| {
+      v8::Local::<v8::Function>::try_from(constructor).ok()
+    })
+    .map(|constructor| constructor.get_name(scope).to_rust_string_lossy(scope))
+    .unwrap_or_default()
+}
+
+/// Calls `Array.from(value)`, returning an empty array if it throws.
+fn array_from<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  value: v8::Local<v8::Value>,
+) -> v8::Local<'s, v8::Array> {
+  let global = scope.get_current_context().global(scope);
+  let array_key = v8::String::new(scope, "Array").unwrap();
+  let from_key = v8::String::new(scope, "from").unwrap();
+  global
+    .get(scope, array_key.into())
+    .and_then(|array| v8::Local::<v8::Object>::try_from(array).ok())
+    .and_then(|array| {
+      let from = array.get(scope, from_key.into())?;
+      let from = v8::Local::<v8::Function>::try_from(from).ok()?;
+      from.call(scope, array.into(), &[value])
+    })
+    .and_then(|array| v8::Local::<v8::Array>::try_from(array).ok())
+    .unwrap_or_else(|| v8::Array::new(scope, 0))
+}
@@ -122,6 +122,11 @@ impl ResourceTable {
     rid
   }
 
+  /// Returns the id the next resource added to the table will get.
+  pub(crate) fn next_rid(&self) -> ResourceId {
+    self.next_rid
+  }
+
   /// Returns true if any resource with the given `rid` exists.
   pub fn has(&self, rid: ResourceId) -> bool {
     self.index.contains_key(&rid)
@@ -1,18 +1,46 @@
 // Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
 
+use crate::async_cancel::CancelHandle;
 use crate::bindings;
+use crate::broadcast::BroadcastBus;
+use crate::clock::Clock;
+use crate::code_cache::code_cache_source_hash;
+use crate::code_cache::CodeCacheStore;
 use crate::error::attach_handle_to_error;
 use crate::error::generic_error;
+use crate::error::range_error;
+use crate::error::type_error;
+use crate::error::CompileDiagnostic;
+use crate::error::CompileError;
 use crate::error::ErrWithV8Handle;
+use crate::error::ExecutionTerminated;
 use crate::error::JsError;
+use crate::error::TerminationReason;
+use crate::error::UnhandledRejections;
+use crate::flags;
 use crate::inspector::JsRuntimeInspector;
+use crate::located_script_name;
+use crate::module_specifier::resolve_url_or_path;
 use crate::module_specifier::ModuleSpecifier;
+use crate::modules::ModuleGraphLimits;
 use crate::modules::ModuleId;
+use crate::modules::ModuleLoadEvent;
+use crate::modules::ModuleLoadEventFn;
 use crate::modules::ModuleLoadId;
 use crate::modules::ModuleLoader;
+use crate::modules::ModuleLockMap;
 use crate::modules::ModuleMap;
 use crate::modules::NoopModuleLoader;
 use crate::ops::*;
+use crate::repl::Completion;
+use crate::sanitizer::Sanitizer;
+use crate::slow_ops::SlowOpDetector;
+use crate::trace::trace_event;
+use crate::trace::trace_span;
+use crate::transpile::MediaTranspiler;
+use crate::transpile::TranspileCache;
+use crate::watchdog::Watchdog;
+use crate::watchdog::WatchdogGuard;
 use crate::Extension;
 use crate::OpMiddlewareFn;
 use crate::OpPayload;
@@ -25,12 +53,17 @@ use futures::future::poll_fn;
 use futures::future::FutureExt;
 use futures::stream::FuturesUnordered;
 use futures::stream::StreamExt;
+use futures::task::ArcWake;
 use futures::task::AtomicWaker;
+use indexmap::IndexMap;
+use serde::Serialize;
 use std::any::Any;
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::collections::HashSet;
+use std::collections::VecDeque;
 use std::ffi::c_void;
+use std::future::Future;
 use std::mem::forget;
 use std::option::Option;
 use std::rc::Rc;
@@ -39,8 +72,12 @@ use std::sync::Mutex;
 use std::sync::Once;
 use std::task::Context;
 use std::task::Poll;
+use std::task::Waker;
+use std::time::Duration;
+use std::time::Instant;
 
 type PendingOpFuture = OpCall<(PromiseId, OpId, OpResult)>;
+type PendingPromiseFuture = OpCall<(v8::Global<v8::PromiseResolver>, OpResult)>;
 
 pub enum Snapshot {
   Static(&'static [u8]),
@@ -50,8 +87,37 @@ pub enum Snapshot {
 
 pub type JsErrorCreateFn = dyn Fn(JsError) -> Error;
 
+/// Called for every promise that was rejected without a handler. Returning
+/// true marks the rejection as handled so it doesn't fail the runtime.
+pub type UnhandledRejectionFn = dyn Fn(&JsError) -> bool;
+
+/// Receives the output of `Deno.core.print()`, and so of the console
+/// installed by `init_console()`, with whether it is meant for stderr.
+pub type PrintFn = dyn Fn(&str, bool);
+
 pub type GetErrorClassFn = &'static dyn for<'e> Fn(&'e Error) -> &'static str;
 
+type MacrotaskFn = dyn FnOnce(&mut JsRuntime) -> Result<(), Error>;
+
+/// Called at the start of every tick of the event loop, see
+/// `JsRuntime::set_before_tick_callback()`.
+pub type BeforeTickFn = dyn FnMut(&mut JsRuntime);
+
+/// Called at the end of every tick of the event loop with the time the tick
+/// took, see `JsRuntime::set_after_tick_callback()`.
+pub type AfterTickFn = dyn FnMut(&mut JsRuntime, Duration);
+
+/// A JS function retained by the runtime, created in JS with
+/// `Deno.core.createCallable(fn)`. It is passed to ops as a plain id, so an op
+/// can take it as an argument and store it, eg. in `OpState`, to be invoked
+/// later with `JsRuntime::call_callable()`. The function is retained until
+/// `JsRuntime::release_callable()` is called.
+#[derive(
+  Clone, Copy, Debug, Eq, Hash, PartialEq, serde::Serialize, serde::Deserialize,
+)]
+#[serde(transparent)]
+pub struct JsCallable(pub(crate) u32);
+
 /// Objects that need to live as long as the isolate
 #[derive(Default)]
 struct IsolateAllocations {
@@ -80,6 +146,12 @@ pub struct JsRuntime {
   has_snapshotted: bool,
   allocations: IsolateAllocations,
   extensions: Vec<Extension>,
+  watchdog: Option<Watchdog>,
+  before_tick: Option<Box<BeforeTickFn>>,
+  after_tick: Option<Box<AfterTickFn>>,
+  // Used to create a new context in `recycle_context()`.
+  has_startup_snapshot: bool,
+  freeze_intrinsics: bool,
 }
 
 struct DynImportModEvaluate {
@@ -90,6 +162,7 @@ struct DynImportModEvaluate {
 }
 
 struct ModEvaluate {
+  module_id: ModuleId,
   promise: v8::Global<v8::Promise>,
   sender: oneshot::Sender<Result<(), Error>>,
 }
@@ -131,6 +204,247 @@ impl<T> Clone for CrossIsolateStore<T> {
   }
 }
 
+/// Determines what happens when an async op is dispatched while
+/// `RuntimeOptions::max_pending_ops` ops are already pending.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PendingOpsOverflow {
+  /// Throw a `RangeError` in JS instead of dispatching the op.
+  Throw,
+  /// Dispatch the op, but don't poll it until the number of pending ops
+  /// drops below the limit.
+  Queue,
+}
+
+impl Default for PendingOpsOverflow {
+  fn default() -> Self {
+    Self::Throw
+  }
+}
+
+/// Determines when the event loop completes, see
+/// `RuntimeOptions::completion_policy`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CompletionPolicy {
+  /// Complete when no refed async ops are pending. Unrefed ops, eg. ones
+  /// passed to `Deno.core.unrefOp()`, are abandoned.
+  RefedOpsDone,
+  /// Complete only when no async ops are pending, refed or not.
+  AllOpsDone,
+  /// Never complete, for daemon-style embedders that keep polling the event
+  /// loop until they switch to another policy with
+  /// `JsRuntime::set_completion_policy()`.
+  Never,
+}
+
+impl Default for CompletionPolicy {
+  fn default() -> Self {
+    Self::RefedOpsDone
+  }
+}
+
+/// Determines how `JsRuntime::lock_core()` restricts access to `Deno.core`.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum CoreLockMode {
+  /// Freeze `Deno.core`, so its functions can't be replaced or extended.
+  Freeze,
+  /// Remove `Deno.core` from the global scope.
+  Remove,
+}
+
+/// Built-in policy for an isolate reaching its heap limit.
+///
+/// Instead of letting V8 crash the process, execution is terminated as soon
+/// as the heap approaches `max_heap_size`, and the script fails with a
+/// `RangeError: isolate memory limit exceeded` error.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryLimit {
+  /// Maximum heap size in bytes.
+  pub max_heap_size: usize,
+}
+
+/// Passed to callbacks set with
+/// `JsRuntime::add_near_heap_limit_callback_with_diagnostics()`, to record
+/// what filled the heap before the limit is raised or execution terminated.
+pub struct HeapLimitDiagnostics<'a> {
+  isolate: &'a mut v8::Isolate,
+  current_heap_limit: usize,
+  initial_heap_limit: usize,
+}
+
+impl HeapLimitDiagnostics<'_> {
+  pub fn current_heap_limit(&self) -> usize {
+    self.current_heap_limit
+  }
+
+  pub fn initial_heap_limit(&self) -> usize {
+    self.initial_heap_limit
+  }
+
+  pub fn heap_statistics(&mut self) -> v8::HeapStatistics {
+    let mut heap = v8::HeapStatistics::default();
+    self.isolate.get_heap_statistics(&mut heap);
+    heap
+  }
+
+  /// Writes a heap snapshot in the JSON format loaded by Chrome DevTools,
+  /// which expects a `.heapsnapshot` file extension. Taking the snapshot
+  /// allocates outside of the JS heap, but can take seconds for large heaps.
+  pub fn write_heap_snapshot(
+    &mut self,
+    writer: &mut dyn std::io::Write,
+  ) -> std::io::Result<()> {
+    let mut result = Ok(());
+    self.isolate.take_heap_snapshot(|chunk| {
+      result = writer.write_all(chunk);
+      result.is_ok()
+    });
+    result
+  }
+}
+
+/// Where a classic script executed with `JsRuntime::execute_script_with_origin()`
+/// comes from, eg. for a snippet extracted from a larger document.
+// TODO: support host-defined options once rusty_v8's `ScriptOrigin::new()`
+// accepts them; v0.36 doesn't.
+#[derive(Debug, Clone, Default)]
+pub struct ScriptOriginOptions {
+  /// The 0-based line of the resource at which the script starts.
+  pub line_offset: i32,
+  /// The 0-based column of the resource at which the script's first line
+  /// starts.
+  pub column_offset: i32,
+  /// URL of the script's source map, as with a `//# sourceMappingURL=`
+  /// comment. Used by the inspector.
+  pub source_map_url: Option<String>,
+}
+
+/// The source of a script or module, in the encoding it is already held in,
+/// so that it can be passed to V8 without converting it to UTF-8 first.
+#[derive(Debug, Clone, Copy)]
+pub enum ScriptSource<'a> {
+  Utf8(&'a str),
+  /// One byte per character, ie. Latin-1.
+  OneByte(&'a [u8]),
+  /// UTF-16 code units.
+  TwoByte(&'a [u16]),
+}
+
+impl<'a> ScriptSource<'a> {
+  pub(crate) fn to_v8_string<'s>(
+    self,
+    scope: &mut v8::HandleScope<'s, ()>,
+  ) -> Option<v8::Local<'s, v8::String>> {
+    match self {
+      Self::Utf8(source) => v8::String::new(scope, source),
+      Self::OneByte(source) => {
+        v8::String::new_from_one_byte(scope, source, v8::NewStringType::Normal)
+      }
+      Self::TwoByte(source) => {
+        v8::String::new_from_two_byte(scope, source, v8::NewStringType::Normal)
+      }
+    }
+  }
+
+  /// The size of the source in bytes.
+  pub(crate) fn byte_len(self) -> usize {
+    match self {
+      Self::Utf8(source) => source.len(),
+      Self::OneByte(source) => source.len(),
+      Self::TwoByte(source) => source.len() * 2,
+    }
+  }
+}
+
+impl<'a> From<&'a str> for ScriptSource<'a> {
+  fn from(source: &'a str) -> Self {
+    Self::Utf8(source)
+  }
+}
+
+/// A point-in-time view of a runtime's load, returned by `JsRuntime::stats()`.
+// TODO: report per-collection GC statistics (type, duration, freed bytes)
+// once rusty_v8 exposes `Isolate::AddGCPrologueCallback()` and
+// `Isolate::AddGCEpilogueCallback()`; v0.36 has no GC callbacks.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuntimeStats {
+  /// Async ops in flight that keep the event loop alive.
+  pub pending_refed_ops: usize,
+  /// Async ops in flight that don't keep the event loop alive.
+  pub pending_unrefed_ops: usize,
+  /// Async ops, included in the counts above, that are waiting to be polled
+  /// because `RuntimeOptions::max_pending_ops` was reached.
+  pub queued_ops: usize,
+  /// The configured `RuntimeOptions::max_pending_ops`.
+  pub max_pending_ops: Option<usize>,
+  /// Dynamic imports that are being prepared or loaded.
+  pub pending_dynamic_imports: usize,
+  /// ES modules registered in the module map.
+  pub module_count: usize,
+  /// Bytes of the V8 heap in use.
+  pub heap_used: usize,
+  /// Bytes of the V8 heap reserved.
+  pub heap_total: usize,
+  /// Bytes the V8 heap can grow to.
+  pub heap_limit: usize,
+  /// Bytes of memory held outside the V8 heap by JS objects, eg. the backing
+  /// stores of `ArrayBuffer`s.
+  pub external_memory: usize,
+}
+
+/// Work a runtime's event loop is waiting on, returned by
+/// `JsRuntime::dump_pending_state()`. Its `Display` implementation lists it
+/// in a human readable form.
+#[derive(Debug, Clone, Default, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingStateDump {
+  /// Async ops in flight, ordered by promise id.
+  pub ops: Vec<PendingOpInfo>,
+  /// Specifiers of the dynamic imports that are being prepared or loaded.
+  pub dynamic_imports: Vec<String>,
+  /// Modules whose evaluation, eg. a top-level await, hasn't finished.
+  pub module_evaluations: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingOpInfo {
+  pub promise_id: PromiseId,
+  pub op_id: OpId,
+  pub op_name: String,
+  /// Whether the op keeps the event loop alive.
+  pub refed: bool,
+}
+
+impl std::fmt::Display for PendingStateDump {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    writeln!(f, "Pending ops: {}", self.ops.len())?;
+    for op in &self.ops {
+      writeln!(
+        f,
+        "  promise {}: {} (op {}){}",
+        op.promise_id,
+        op.op_name,
+        op.op_id,
+        if op.refed { "" } else { ", unrefed" }
+      )?;
+    }
+    writeln!(f, "Pending dynamic imports: {}", self.dynamic_imports.len())?;
+    for specifier in &self.dynamic_imports {
+      writeln!(f, "  {}", specifier)?;
+    }
+    writeln!(
+      f,
+      "Pending module evaluations: {}",
+      self.module_evaluations.len()
+    )?;
+    for specifier in &self.module_evaluations {
+      writeln!(f, "  {}", specifier)?;
+    }
+    Ok(())
+  }
+}
+
 pub type SharedArrayBufferStore =
   CrossIsolateStore<v8::SharedRef<v8::BackingStore>>;
 
@@ -142,14 +456,22 @@ pub(crate) struct JsRuntimeState {
   pub global_context: Option<v8::Global<v8::Context>>,
   pub(crate) js_recv_cb: Option<v8::Global<v8::Function>>,
   pub(crate) js_sync_cb: Option<v8::Global<v8::Function>>,
+  pub(crate) js_register_error_class_cb: Option<v8::Global<v8::Function>>,
+  pub(crate) js_build_custom_error_cb: Option<v8::Global<v8::Function>>,
   pub(crate) js_macrotask_cbs: Vec<v8::Global<v8::Function>>,
   pub(crate) js_nexttick_cbs: Vec<v8::Global<v8::Function>>,
+  /// Callbacks added with `Deno.core.addBeforeExitCallback()`, called by
+  /// `JsRuntime::shutdown()`.
+  pub(crate) js_before_exit_cbs: Vec<v8::Global<v8::Function>>,
   pub(crate) js_promise_reject_cb: Option<v8::Global<v8::Function>>,
   pub(crate) js_uncaught_exception_cb: Option<v8::Global<v8::Function>>,
+  pub(crate) js_unhandled_rejection_cb: Option<v8::Global<v8::Function>>,
+  pub(crate) unhandled_rejection_fn: Option<Rc<UnhandledRejectionFn>>,
   pub(crate) has_tick_scheduled: bool,
   pub(crate) js_wasm_streaming_cb: Option<v8::Global<v8::Function>>,
+  /// Promises rejected without a handler, in the order they were rejected.
   pub(crate) pending_promise_exceptions:
-    HashMap<v8::Global<v8::Promise>, v8::Global<v8::Value>>,
+    IndexMap<v8::Global<v8::Promise>, v8::Global<v8::Value>>,
   pending_dyn_mod_evaluate: Vec<DynImportModEvaluate>,
   pending_mod_evaluate: Option<ModEvaluate>,
   /// A counter used to delay our dynamic import deadlock detection by one spin
@@ -157,16 +479,65 @@ pub(crate) struct JsRuntimeState {
   dyn_module_evaluate_idle_counter: u32,
   pub(crate) js_error_create_fn: Rc<JsErrorCreateFn>,
   pub(crate) pending_ops: FuturesUnordered<PendingOpFuture>,
+  /// Async ops dispatched while `max_pending_ops` was reached, in dispatch
+  /// order. Only used with `PendingOpsOverflow::Queue`.
+  pub(crate) queued_ops: VecDeque<PendingOpFuture>,
+  pub(crate) max_pending_ops: Option<usize>,
+  pub(crate) pending_ops_overflow: PendingOpsOverflow,
+  poll_time_budget: Option<Duration>,
+  /// Rust callbacks queued with `JsRuntime::queue_macrotask()`.
+  macrotasks: VecDeque<Box<MacrotaskFn>>,
+  completion_policy: CompletionPolicy,
+  /// When the time budget of the current poll runs out.
+  poll_deadline: Option<Instant>,
+  /// Set when a poll stopped resolving ops or running macrotasks because its
+  /// time budget ran out, so the event loop wakes itself up to continue.
+  poll_budget_exhausted: bool,
   pub(crate) unrefed_ops: HashSet<i32>,
+  /// Op ids of the async ops in `pending_ops` and `queued_ops`, by promise id.
+  pub(crate) pending_op_ids: HashMap<PromiseId, OpId>,
+  /// Cancel handles of the pending async ops called with a `signal`, by
+  /// promise id.
+  pub(crate) op_cancel_handles: HashMap<PromiseId, Rc<CancelHandle>>,
+  pub(crate) slow_op_detector: Option<SlowOpDetector>,
   pub(crate) have_unpolled_ops: bool,
+  /// Set by `JsRuntime::shutdown()`; new op dispatches throw.
+  pub(crate) shutting_down: bool,
+  /// Functions retained with `Deno.core.createCallable()`.
+  pub(crate) js_callables: HashMap<JsCallable, v8::Global<v8::Function>>,
+  pub(crate) next_js_callable_id: u32,
+  /// Contexts created with `Deno.core.createContext()`.
+  pub(crate) sub_contexts: HashMap<u32, v8::Global<v8::Context>>,
+  pub(crate) next_sub_context_id: u32,
+  /// Applies to the global context and to sub-contexts.
+  pub(crate) disallow_code_generation_from_strings: bool,
+  /// Futures driving promises created with `JsRuntime::resolve_promise_with()`.
+  pending_promise_futures: FuturesUnordered<PendingPromiseFuture>,
   pub(crate) op_state: Rc<RefCell<OpState>>,
+  /// The number of ops whose mappings were last published to JS by
+  /// `JsRuntime::sync_ops_cache()`, if any.
+  pub(crate) synced_op_count: Option<usize>,
+  /// Set together with terminating execution; taken when the termination
+  /// exception is converted. Shared with `TerminationHandle`s.
+  pub(crate) termination_reason: Arc<Mutex<Option<TerminationReason>>>,
+  /// Error for the exception created when execution is terminated with
+  /// `terminate_with_error()`. Shared with `TerminationHandle`s.
+  pub(crate) termination_error: Arc<Mutex<Option<Error>>>,
+  /// Message for the error created when execution is terminated, eg. by the
+  /// `Watchdog`. Taken when the termination exception is converted.
+  pub(crate) termination_message: Option<String>,
+  /// The exception most recently converted into an error.
+  pub(crate) last_exception: Option<v8::Global<v8::Value>>,
   pub(crate) shared_array_buffer_store: Option<SharedArrayBufferStore>,
   pub(crate) compiled_wasm_module_store: Option<CompiledWasmModuleStore>,
-  waker: AtomicWaker,
+  pub(crate) code_cache_store: Option<Arc<dyn CodeCacheStore>>,
+  waker: Arc<AtomicWaker>,
 }
 
 impl Drop for JsRuntime {
   fn drop(&mut self) {
+    self.cancel_pending_ops();
+
     // The Isolate object must outlive the Inspector object, but this is
     // currently not enforced by the type system.
     self.inspector.take();
@@ -191,6 +562,25 @@ impl Drop for JsRuntime {
   }
 }
 
+static DENO_INIT: Once = Once::new();
+
+/// Initializes V8 with `v8_platform` instead of the default multi-threaded
+/// platform, eg. `v8::new_single_threaded_default_platform()` for embedders
+/// that can't have V8 spawn worker threads, or a platform with a custom task
+/// runner or tracing controller.
+///
+/// V8 can only be initialized once per process. Must be called before the
+/// first `JsRuntime` is created; returns `false` and drops `v8_platform` if V8
+/// was already initialized.
+pub fn init_v8_platform(v8_platform: v8::SharedRef<v8::Platform>) -> bool {
+  let mut initialized = false;
+  DENO_INIT.call_once(|| {
+    v8_init(Some(v8_platform));
+    initialized = true;
+  });
+  initialized
+}
+
 fn v8_init(v8_platform: Option<v8::SharedRef<v8::Platform>>) {
   // Include 10MB ICU data file.
   #[repr(C, align(16))]
@@ -212,6 +602,140 @@ fn v8_init(v8_platform: Option<v8::SharedRef<v8::Platform>>) {
   v8::V8::set_flags_from_string(flags);
 }
 
+/// Creates a context, initializing it with the `Deno.core` bindings unless
+/// they come from a startup snapshot, with code generation from strings
+/// disallowed if `disallow_code_generation_from_strings` is set.
+pub(crate) fn new_context<'s>(
+  scope: &mut v8::HandleScope<'s, ()>,
+  initialize: bool,
+  disallow_code_generation_from_strings: bool,
+) -> v8::Local<'s, v8::Context> {
+  flags::with_code_generation_disallowed(
+    disallow_code_generation_from_strings,
+    || {
+      if initialize {
+        bindings::initialize_context(scope)
+      } else {
+        v8::Context::new(scope)
+      }
+    },
+  )
+}
+
+/// Terminates execution of a `JsRuntime`'s isolate with a reason. The error
+/// that results from the termination is an `ExecutionTerminated` carrying the
+/// reason, see `error::get_termination_reason()`.
+#[derive(Clone)]
+pub struct TerminationHandle {
+  isolate_handle: v8::IsolateHandle,
+  reason: Arc<Mutex<Option<TerminationReason>>>,
+  error: Arc<Mutex<Option<Error>>>,
+}
+
+impl TerminationHandle {
+  /// Terminates execution. Returns false if the isolate has been disposed.
+  pub fn terminate(&self, reason: TerminationReason) -> bool {
+    *self.reason.lock().unwrap() = Some(reason);
+    self.isolate_handle.terminate_execution()
+  }
+
+  /// Terminates execution, surfacing `err` instead of the generic
+  /// "execution terminated" error: the exception has the JS error class
+  /// `err` maps to (see `OpState::get_error_class()`) and its message. The
+  /// termination reason is `TerminationReason::Other` with the message.
+  /// Returns false if the isolate has been disposed.
+  pub fn terminate_with_error(&self, err: Error) -> bool {
+    let reason = TerminationReason::Other(err.to_string());
+    *self.error.lock().unwrap() = Some(err);
+    self.terminate(reason)
+  }
+}
+
+type InterruptFn = dyn FnOnce(&mut v8::HandleScope) + Send;
+
+/// Runs closures on a `JsRuntime`'s isolate thread from any thread, see
+/// `JsRuntime::interrupt_handle()`.
+#[derive(Clone)]
+pub struct InterruptHandle {
+  isolate_handle: v8::IsolateHandle,
+}
+
+impl InterruptHandle {
+  /// Queues `f` to run on the isolate thread, with a scope in the runtime's
+  /// context, at the next safepoint of the JavaScript currently executing,
+  /// or as soon as JavaScript is executed next. Returns false if the isolate
+  /// has been disposed. Closures still queued when the isolate is disposed
+  /// are leaked without being run.
+  ///
+  /// If the interrupt lands while the runtime's state is borrowed, eg. while
+  /// JavaScript runs from within a Rust callback that holds it, the closure
+  /// is kept and queued again the next time the runtime enters JavaScript
+  /// through `JsRuntime::handle_scope()`. Closures are dropped without being
+  /// run if the runtime has no context anymore, ie. after
+  /// `JsRuntime::snapshot()`.
+  pub fn interrupt<F>(&self, f: F) -> bool
+  where
+    F: FnOnce(&mut v8::HandleScope) + Send + 'static,
+  {
+    self.interrupt_boxed(Box::new(f))
+  }
+
+  fn interrupt_boxed(&self, f: Box<InterruptFn>) -> bool {
+    let data = Box::into_raw(Box::new(f));
+    let queued = self
+      .isolate_handle
+      .request_interrupt(interrupt_callback, data as *mut c_void);
+    if !queued {
+      drop(unsafe { Box::from_raw(data) });
+    }
+    queued
+  }
+}
+
+/// Interrupt closures that landed while the runtime's state was borrowed,
+/// kept in an isolate slot until `JsRuntime::handle_scope()` queues them
+/// again. They can't be queued again from the interrupt callback, since V8
+/// would run them again right away, in the same safepoint.
+#[derive(Default)]
+struct DeferredInterrupts(Vec<Box<InterruptFn>>);
+
+struct EventLoopWaker(Arc<AtomicWaker>);
+
+impl ArcWake for EventLoopWaker {
+  fn wake_by_ref(arc_self: &Arc<Self>) {
+    arc_self.0.wake();
+  }
+}
+
+extern "C" fn interrupt_callback(isolate: &mut v8::Isolate, data: *mut c_void) {
+  let f = unsafe { Box::from_raw(data as *mut Box<InterruptFn>) };
+  // JS is usually running, so this can't panic or open a root scope.
+  let state_rc = match isolate.get_slot::<Rc<RefCell<JsRuntimeState>>>() {
+    Some(state_rc) => state_rc.clone(),
+    None => return,
+  };
+  let context = match state_rc.try_borrow() {
+    Ok(state) => match state.global_context.clone() {
+      Some(context) => context,
+      None => return,
+    },
+    Err(_) => {
+      match isolate.get_slot_mut::<DeferredInterrupts>() {
+        Some(deferred) => deferred.0.push(*f),
+        None => {
+          isolate.set_slot(DeferredInterrupts(vec![*f]));
+        }
+      }
+      return;
+    }
+  };
+  let scope = &mut unsafe { v8::CallbackScope::new(isolate) };
+  let scope = &mut v8::HandleScope::new(scope);
+  let context = v8::Local::new(scope, context);
+  let scope = &mut v8::ContextScope::new(scope, context);
+  f(scope);
+}
+
 #[derive(Default)]
 pub struct RuntimeOptions {
   /// Allows a callback to be set whenever a V8 exception is made. This allows
@@ -223,6 +747,41 @@ pub struct RuntimeOptions {
   /// error in JavaScript.
   pub get_error_class_fn: Option<GetErrorClassFn>,
 
+  /// Records where each promise was created, so errors for unhandled
+  /// rejections can report it in `JsError::promise_creation_frames`. The
+  /// rejection-time stack is often useless for `await`-heavy code. This makes
+  /// creating promises noticeably slower.
+  pub capture_promise_creation_stacks: bool,
+
+  /// Observes promises that were rejected without a handler. By default any
+  /// such rejection fails the event loop; the callback can swallow it instead
+  /// to keep a long-lived runtime alive. Consulted after the JS handler set
+  /// with `Deno.core.setUnhandledRejectionHandler()`.
+  pub unhandled_rejection_fn: Option<Rc<UnhandledRejectionFn>>,
+
+  /// Consulted on every op dispatch; can deny an op call by returning an
+  /// error. See `PermissionsHandler`.
+  pub permissions_handler: Option<Rc<dyn PermissionsHandler>>,
+
+  /// Where `Deno.core.print()` writes to. By default output goes to the
+  /// process' stdout and stderr.
+  pub print_fn: Option<Rc<PrintFn>>,
+
+  /// The bus JS code can publish and subscribe to with
+  /// `Deno.core.broadcastSubscribe()`, eg. `BroadcastBus::global()` to reach
+  /// all runtimes of the process. Without it, subscribing throws.
+  pub broadcast_bus: Option<BroadcastBus>,
+
+  /// The clock ops read the time from, available as `OpState::clock`.
+  /// Defaults to a `RealClock`; tests can pass a `ManualClock` to
+  /// fast-forward time.
+  pub clock: Option<Arc<dyn Clock>>,
+
+  /// Rounds the time returned by `performance.now()` down to a multiple of
+  /// this, to mitigate timing attacks on untrusted code. By default the time
+  /// has the full resolution of the clock.
+  pub now_precision: Option<Duration>,
+
   /// Implementation of `ModuleLoader` which will be
   /// called when V8 requests to load ES modules.
   ///
@@ -230,6 +789,39 @@ pub struct RuntimeOptions {
   /// executed tries to load modules.
   pub module_loader: Option<Rc<dyn ModuleLoader>>,
 
+  /// Limits on the number of modules, their combined source size and the
+  /// number of dynamic imports. By default the module graph is unbounded.
+  pub module_graph_limits: ModuleGraphLimits,
+
+  /// Known hashes of module sources. When set, modules fetched with a hash
+  /// that doesn't match fail to load. See `ModuleLockMap`.
+  pub module_lock_map: Option<ModuleLockMap>,
+
+  /// Called as each module is resolved, fetched, compiled, instantiated and
+  /// evaluated, eg. to collect load-time metrics or report progress. See
+  /// `ModuleLoadEvent`.
+  pub module_load_event_fn: Option<Rc<ModuleLoadEventFn>>,
+
+  /// Transpiles modules whose `MediaType`, detected from their URL, is
+  /// TypeScript or JSX. Without it, such modules are compiled as JavaScript.
+  pub media_transpiler: Option<Rc<dyn MediaTranspiler>>,
+
+  /// Caches the output of `media_transpiler`, if it has a version. See
+  /// `DiskTranspileCache`.
+  pub transpile_cache: Option<Rc<dyn TranspileCache>>,
+
+  /// Stores V8 code caches for the classic scripts run with
+  /// `execute_script()` and the ES modules the runtime compiles, reusing them
+  /// when the same source is compiled again. Share one store between all
+  /// runtimes of a process to amortize compilation. See
+  /// `MemoryCodeCacheStore` and `DiskCodeCacheStore`.
+  pub code_cache_store: Option<Arc<dyn CodeCacheStore>>,
+
+  /// Only runs microtasks when the event loop is polled or
+  /// `JsRuntime::run_microtasks()` is called, rather than whenever a call
+  /// into JavaScript returns, eg. from `execute_script()`.
+  pub explicit_microtasks: bool,
+
   /// JsRuntime extensions, not to be confused with ES modules
   /// these are sets of ops and other JS code to be initialized.
   pub extensions: Vec<Extension>,
@@ -247,8 +839,15 @@ pub struct RuntimeOptions {
   /// Isolate creation parameters.
   pub create_params: Option<v8::CreateParams>,
 
+  /// Terminates execution with a `RangeError` when the heap limit is about to
+  /// be reached. Overrides the heap limits in `create_params` and replaces
+  /// any callback set with `add_near_heap_limit_callback()`.
+  pub memory_limit: Option<MemoryLimit>,
+
   /// V8 platform instance to use. Used when Deno initializes V8
   /// (which it only does once), otherwise it's silenty dropped.
+  /// See `init_v8_platform()` to provide the platform before creating any
+  /// runtime.
   pub v8_platform: Option<v8::SharedRef<v8::Platform>>,
 
   /// The store to use for transferring SharedArrayBuffers between isolates.
@@ -265,20 +864,64 @@ pub struct RuntimeOptions {
   /// [CompiledWasmModuleStore]. If no [CompiledWasmModuleStore] is specified,
   /// `WebAssembly.Module` objects cannot be serialized.
   pub compiled_wasm_module_store: Option<CompiledWasmModuleStore>,
+
+  /// Maximum number of async ops that can be pending at the same time. What
+  /// happens to ops dispatched beyond this limit is determined by
+  /// `pending_ops_overflow`. By default the number of pending ops is
+  /// unbounded.
+  pub max_pending_ops: Option<usize>,
+
+  /// See `PendingOpsOverflow`.
+  pub pending_ops_overflow: PendingOpsOverflow,
+
+  /// Time budget of a single tick of the event loop. Once it is exceeded,
+  /// the tick stops resolving async ops and running macrotasks and yields
+  /// back to the executor, waking itself up to continue on the next tick, so
+  /// that a busy runtime doesn't starve other tasks on the same thread. At
+  /// least one op or macrotask is processed per tick. By default a tick
+  /// drains everything that is ready.
+  pub poll_time_budget: Option<Duration>,
+
+  /// When the event loop completes. See `CompletionPolicy`.
+  pub completion_policy: CompletionPolicy,
+
+  /// Deep-freezes the JavaScript built-ins after the extensions' JS has been
+  /// initialized. See `JsRuntime::freeze_intrinsics()`.
+  pub freeze_intrinsics: bool,
+
+  /// Terminates execution if running JavaScript code, either from
+  /// `execute_script()` or a single tick of the event loop, takes longer
+  /// than this. See `Watchdog`.
+  pub watchdog_timeout: Option<Duration>,
+
+  /// Test mode: makes the event loop fail when it completes with async ops
+  /// still pending or resources opened by ops still open, reporting the
+  /// stacks that started or opened them. Capturing those stacks makes every
+  /// op call slower.
+  pub sanitizer: bool,
+
+  /// Calls back for sync ops that block the isolate, or async ops that take
+  /// to complete, longer than a threshold. See `SlowOpDetector`.
+  pub slow_op_detector: Option<SlowOpDetector>,
+
+  /// Disallows code generation from strings in the runtime's context. When
+  /// set, `eval()` and `new Function()` throw an `EvalError` instead of
+  /// compiling their argument. Useful for sandboxed embedders.
+  pub disallow_code_generation_from_strings: bool,
 }
 
 impl JsRuntime {
   /// Only constructor, configuration is done through `options`.
   pub fn new(mut options: RuntimeOptions) -> Self {
     let v8_platform = options.v8_platform.take();
-
-    static DENO_INIT: Once = Once::new();
     DENO_INIT.call_once(move || v8_init(v8_platform));
 
     let has_startup_snapshot = options.startup_snapshot.is_some();
 
+    let disallow_code_generation_from_strings =
+      options.disallow_code_generation_from_strings;
     let global_context;
-    let (mut isolate, maybe_snapshot_creator) = if options.will_snapshot {
+    let (isolate, maybe_snapshot_creator) = if options.will_snapshot {
       // TODO(ry) Support loading snapshots before snapshotting.
       assert!(options.startup_snapshot.is_none());
       let mut creator =
@@ -287,7 +930,8 @@ impl JsRuntime {
       let mut isolate = JsRuntime::setup_isolate(isolate);
       {
         let scope = &mut v8::HandleScope::new(&mut isolate);
-        let context = bindings::initialize_context(scope);
+        let context =
+          new_context(scope, true, disallow_code_generation_from_strings);
         global_context = v8::Global::new(scope, context);
         creator.set_default_context(context);
       }
@@ -298,33 +942,100 @@ impl JsRuntime {
         .take()
         .unwrap_or_else(v8::Isolate::create_params)
         .external_references(&**bindings::EXTERNAL_REFERENCES);
-      let snapshot_loaded = if let Some(snapshot) = options.startup_snapshot {
-        params = match snapshot {
-          Snapshot::Static(data) => params.snapshot_blob(data),
-          Snapshot::JustCreated(data) => params.snapshot_blob(data),
-          Snapshot::Boxed(data) => params.snapshot_blob(data),
+      if let Some(memory_limit) = options.memory_limit {
+        params = params.heap_limits(0, memory_limit.max_heap_size);
+      }
+      let snapshot_loaded =
+        if let Some(snapshot) = options.startup_snapshot.take() {
+          params = match snapshot {
+            Snapshot::Static(data) => params.snapshot_blob(data),
+            Snapshot::JustCreated(data) => params.snapshot_blob(data),
+            Snapshot::Boxed(data) => params.snapshot_blob(data),
+          };
+          true
+        } else {
+          false
         };
-        true
-      } else {
-        false
-      };
 
       let isolate = v8::Isolate::new(params);
       let mut isolate = JsRuntime::setup_isolate(isolate);
       {
         let scope = &mut v8::HandleScope::new(&mut isolate);
-        let context = if snapshot_loaded {
-          v8::Context::new(scope)
-        } else {
-          // If no snapshot is provided, we initialize the context with empty
-          // main source code and source maps.
-          bindings::initialize_context(scope)
-        };
+        // If no snapshot is provided, we initialize the context with empty
+        // main source code and source maps.
+        let context = new_context(
+          scope,
+          !snapshot_loaded,
+          disallow_code_generation_from_strings,
+        );
         global_context = v8::Global::new(scope, context);
       }
       (isolate, None)
     };
 
+    Self::from_parts(
+      isolate,
+      global_context,
+      maybe_snapshot_creator,
+      options,
+      has_startup_snapshot,
+    )
+  }
+
+  /// Creates a runtime around an isolate created by the embedder, eg. one
+  /// shared with another V8-based system, instead of creating one itself.
+  /// Installs the runtime's state, isolate callbacks and a new context with
+  /// the `Deno.core` bindings into `isolate`. The isolate is disposed when
+  /// the runtime is dropped.
+  ///
+  /// `RuntimeOptions::will_snapshot`, `startup_snapshot`, `create_params` and
+  /// `v8_platform` are not supported, as they apply to creating the isolate.
+  ///
+  /// # Safety
+  ///
+  /// - V8 and its ICU data must be initialized, either by the embedder or
+  ///   by creating a `JsRuntime` first.
+  /// - `isolate` must have been created on the current thread, and must not
+  ///   have any scope open or context entered.
+  /// - The embedder must not rely on the isolate's slots or callbacks it set
+  ///   (message listeners, the promise reject callback, host import callbacks,
+  ///   the near heap limit callback, etc.): they are replaced by the
+  ///   runtime's, which assume they are only invoked for its own context.
+  pub unsafe fn from_isolate(
+    isolate: v8::OwnedIsolate,
+    options: RuntimeOptions,
+  ) -> Self {
+    assert!(!options.will_snapshot, "will_snapshot is not supported");
+    assert!(
+      options.startup_snapshot.is_none(),
+      "startup_snapshot is not supported"
+    );
+    assert!(
+      options.create_params.is_none(),
+      "create_params is not supported"
+    );
+
+    let mut isolate = JsRuntime::setup_isolate(isolate);
+    let global_context = {
+      let scope = &mut v8::HandleScope::new(&mut isolate);
+      let context =
+        new_context(scope, true, options.disallow_code_generation_from_strings);
+      v8::Global::new(scope, context)
+    };
+
+    Self::from_parts(isolate, global_context, None, options, false)
+  }
+
+  fn from_parts(
+    mut isolate: v8::OwnedIsolate,
+    global_context: v8::Global<v8::Context>,
+    maybe_snapshot_creator: Option<v8::SnapshotCreator>,
+    mut options: RuntimeOptions,
+    has_startup_snapshot: bool,
+  ) -> Self {
+    if options.explicit_microtasks {
+      isolate.set_microtasks_policy(v8::MicrotasksPolicy::Explicit);
+    }
     let inspector =
       JsRuntimeInspector::new(&mut isolate, global_context.clone());
 
@@ -340,34 +1051,81 @@ impl JsRuntime {
     if let Some(get_error_class_fn) = options.get_error_class_fn {
       op_state.get_error_class_fn = get_error_class_fn;
     }
+    op_state.permissions_handler = options.permissions_handler;
+    op_state.print_fn = options.print_fn;
+    op_state.broadcast_bus = options.broadcast_bus;
+    if let Some(clock) = options.clock {
+      op_state.clock = clock;
+    }
+    op_state.time_origin = op_state.clock.now();
+    op_state.now_precision = options.now_precision;
+    if options.sanitizer {
+      op_state.sanitizer = Some(Sanitizer::default());
+    }
 
     let op_state = Rc::new(RefCell::new(op_state));
 
     isolate.set_slot(Rc::new(RefCell::new(JsRuntimeState {
       global_context: Some(global_context),
-      pending_promise_exceptions: HashMap::new(),
+      pending_promise_exceptions: IndexMap::new(),
       pending_dyn_mod_evaluate: vec![],
       pending_mod_evaluate: None,
       dyn_module_evaluate_idle_counter: 0,
       js_recv_cb: None,
       js_sync_cb: None,
+      js_register_error_class_cb: None,
+      js_build_custom_error_cb: None,
       js_macrotask_cbs: vec![],
       js_nexttick_cbs: vec![],
+      js_before_exit_cbs: vec![],
       js_promise_reject_cb: None,
       js_uncaught_exception_cb: None,
+      js_unhandled_rejection_cb: None,
+      unhandled_rejection_fn: options.unhandled_rejection_fn,
       has_tick_scheduled: false,
       js_wasm_streaming_cb: None,
       js_error_create_fn,
       pending_ops: FuturesUnordered::new(),
+      queued_ops: VecDeque::new(),
+      max_pending_ops: options.max_pending_ops,
+      pending_ops_overflow: options.pending_ops_overflow,
+      poll_time_budget: options.poll_time_budget,
+      macrotasks: VecDeque::new(),
+      completion_policy: options.completion_policy,
+      poll_deadline: None,
+      poll_budget_exhausted: false,
       unrefed_ops: HashSet::new(),
+      pending_op_ids: HashMap::new(),
+      op_cancel_handles: HashMap::new(),
+      slow_op_detector: options.slow_op_detector,
+      termination_reason: Arc::new(Mutex::new(None)),
+      termination_error: Arc::new(Mutex::new(None)),
+      termination_message: None,
+      last_exception: None,
       shared_array_buffer_store: options.shared_array_buffer_store,
       compiled_wasm_module_store: options.compiled_wasm_module_store,
+      code_cache_store: options.code_cache_store.clone(),
       op_state: op_state.clone(),
+      synced_op_count: None,
       have_unpolled_ops: false,
-      waker: AtomicWaker::new(),
+      shutting_down: false,
+      js_callables: HashMap::new(),
+      next_js_callable_id: 0,
+      sub_contexts: HashMap::new(),
+      next_sub_context_id: 0,
+      disallow_code_generation_from_strings: options
+        .disallow_code_generation_from_strings,
+      pending_promise_futures: FuturesUnordered::new(),
+      waker: Arc::new(AtomicWaker::new()),
     })));
 
-    let module_map = ModuleMap::new(loader, op_state);
+    let mut module_map = ModuleMap::new(loader, op_state);
+    module_map.limits = options.module_graph_limits;
+    module_map.lock_map = options.module_lock_map;
+    module_map.load_event_fn = options.module_load_event_fn;
+    module_map.media_transpiler = options.media_transpiler;
+    module_map.transpile_cache = options.transpile_cache;
+    module_map.code_cache_store = options.code_cache_store;
     isolate.set_slot(Rc::new(RefCell::new(module_map)));
 
     // Add builtins extension
@@ -375,6 +1133,14 @@ impl JsRuntime {
       .extensions
       .insert(0, crate::ops_builtin::init_builtins());
 
+    if options.capture_promise_creation_stacks {
+      isolate.set_promise_hook(bindings::promise_hook);
+    }
+
+    let watchdog = options
+      .watchdog_timeout
+      .map(|timeout| Watchdog::new(isolate.thread_safe_handle(), timeout));
+
     let mut js_runtime = Self {
       v8_isolate: Some(isolate),
       inspector: Some(inspector),
@@ -382,13 +1148,25 @@ impl JsRuntime {
       has_snapshotted: false,
       allocations: IsolateAllocations::default(),
       extensions: options.extensions,
+      watchdog,
+      before_tick: None,
+      after_tick: None,
+      has_startup_snapshot,
+      freeze_intrinsics: options.freeze_intrinsics,
     };
 
+    if options.memory_limit.is_some() {
+      js_runtime.install_memory_limit();
+    }
+
     // TODO(@AaronO): diff extensions inited in snapshot and those provided
     // for now we assume that snapshot and extensions always match
     if !has_startup_snapshot {
       js_runtime.init_extension_js().unwrap();
     }
+    if options.freeze_intrinsics {
+      js_runtime.freeze_intrinsics().unwrap();
+    }
     // Init extension ops
     js_runtime.init_extension_ops().unwrap();
     // Init callbacks (opresolve & syncOpsCache)
@@ -399,6 +1177,134 @@ impl JsRuntime {
     js_runtime
   }
 
+  /// Replaces the runtime's context with a fresh one, eg. after each request
+  /// to isolate requests from each other far more cheaply than creating a
+  /// new runtime. The isolate, its compiled code caches, registered ops and
+  /// `OpState` (including open resources) are kept.
+  ///
+  /// If the runtime was created from `RuntimeOptions::startup_snapshot`, the
+  /// new context is deserialized from the snapshot; otherwise the core
+  /// bindings and extension JS are initialized again.
+  /// `RuntimeOptions::freeze_intrinsics` is applied again too, but bootstrap
+  /// code run by the embedder after creating the runtime must be run again.
+  ///
+  /// Everything that belongs to the old context is dropped: loaded modules,
+  /// callbacks set from JS, `JsCallable`s, contexts created with
+  /// `Deno.core.createContext()` and unhandled rejections.
+  ///
+  /// Fails if async ops, promises created with `resolve_promise_with()`,
+  /// dynamic imports or module evaluations are still pending.
+  pub fn recycle_context(&mut self) -> Result<(), Error> {
+    if self.snapshot_creator.is_some() {
+      return Err(generic_error(
+        "Can not recycle the context of a runtime that will be snapshotted",
+      ));
+    }
+    let state_rc = Self::state(self.v8_isolate());
+    let module_map_rc = Self::module_map(self.v8_isolate());
+    {
+      let state = state_rc.borrow();
+      if !state.pending_ops.is_empty()
+        || !state.queued_ops.is_empty()
+        || !state.pending_promise_futures.is_empty()
+        || !state.pending_dyn_mod_evaluate.is_empty()
+        || state.pending_mod_evaluate.is_some()
+        || module_map_rc.borrow().has_pending_dynamic_imports()
+      {
+        return Err(generic_error(
+          "Can not recycle the context while work is pending in it",
+        ));
+      }
+    }
+
+    let has_startup_snapshot = self.has_startup_snapshot;
+    let disallow_code_generation_from_strings =
+      state_rc.borrow().disallow_code_generation_from_strings;
+    let global_context = {
+      let scope = &mut v8::HandleScope::new(self.v8_isolate());
+      let context = new_context(
+        scope,
+        !has_startup_snapshot,
+        disallow_code_generation_from_strings,
+      );
+      v8::Global::new(scope, context)
+    };
+
+    {
+      let mut state = state_rc.borrow_mut();
+      state.global_context = Some(global_context.clone());
+      state.js_macrotask_cbs.clear();
+      state.macrotasks.clear();
+      state.js_nexttick_cbs.clear();
+      state.js_before_exit_cbs.clear();
+      state.js_promise_reject_cb = None;
+      state.js_uncaught_exception_cb = None;
+      state.js_unhandled_rejection_cb = None;
+      state.js_wasm_streaming_cb = None;
+      state.has_tick_scheduled = false;
+      state.pending_promise_exceptions.clear();
+      state.js_callables.clear();
+      state.sub_contexts.clear();
+      state.last_exception = None;
+      state.synced_op_count = None;
+    }
+    module_map_rc.borrow_mut().clear();
+
+    // The inspector is bound to the old context.
+    self.inspector.take();
+    let inspector = JsRuntimeInspector::new(self.v8_isolate(), global_context);
+    self.inspector = Some(inspector);
+
+    if !self.has_startup_snapshot {
+      self.init_extension_js()?;
+    }
+    if self.freeze_intrinsics {
+      self.freeze_intrinsics()?;
+    }
+    self.init_cbs();
+    self.sync_ops_cache();
+    Ok(())
+  }
+
+  /// Resets the runtime to the state it was created in, without recreating
+  /// the isolate, eg. to sanitize pooled runtimes between untrusted
+  /// workloads. Unlike `recycle_context()`, pending work doesn't prevent the
+  /// reset: pending async ops are canceled as when dropping the runtime (see
+  /// `OpState::cancel_handle()`), all resources are closed, and pending
+  /// module evaluations, dynamic imports, promises created with
+  /// `resolve_promise_with()` and terminations are discarded. Then the
+  /// context is replaced as by `recycle_context()`.
+  pub fn reset(&mut self) -> Result<(), Error> {
+    if self.snapshot_creator.is_some() {
+      return Err(generic_error(
+        "Can not reset a runtime that will be snapshotted",
+      ));
+    }
+    self.v8_isolate().cancel_terminate_execution();
+    self.cancel_pending_ops();
+
+    let state_rc = Self::state(self.v8_isolate());
+    {
+      let mut state = state_rc.borrow_mut();
+      state.op_state.borrow_mut().renew_cancel_handle();
+      state.pending_promise_futures = FuturesUnordered::new();
+      state.pending_dyn_mod_evaluate.clear();
+      state.pending_mod_evaluate = None;
+      state.dyn_module_evaluate_idle_counter = 0;
+      state.unrefed_ops.clear();
+      state.pending_op_ids.clear();
+      state.op_cancel_handles.clear();
+      state.have_unpolled_ops = false;
+      state.shutting_down = false;
+      state.termination_message = None;
+      *state.termination_reason.lock().unwrap() = None;
+      *state.termination_error.lock().unwrap() = None;
+    }
+    Self::module_map(self.v8_isolate()).borrow_mut().clear();
+
+    self.recycle_context()
+  }
+
   pub fn global_context(&mut self) -> v8::Global<v8::Context> {
     let state = Self::state(self.v8_isolate());
     let state = state.borrow();
@@ -413,59 +1319,340 @@ impl JsRuntime {
     self.inspector.as_mut().unwrap()
   }
 
-  pub fn handle_scope(&mut self) -> v8::HandleScope {
-    let context = self.global_context();
-    v8::HandleScope::with_context(self.v8_isolate(), context)
+  /// Returns the watchdog created for `RuntimeOptions::watchdog_timeout`.
+  pub fn watchdog(&self) -> Option<&Watchdog> {
+    self.watchdog.as_ref()
   }
 
-  fn setup_isolate(mut isolate: v8::OwnedIsolate) -> v8::OwnedIsolate {
-    isolate.set_capture_stack_trace_for_uncaught_exceptions(true, 10);
-    isolate.set_promise_reject_callback(bindings::promise_reject_callback);
-    isolate.set_host_initialize_import_meta_object_callback(
-      bindings::host_initialize_import_meta_object_callback,
-    );
-    isolate.set_host_import_module_dynamically_callback(
-      bindings::host_import_module_dynamically_callback,
-    );
-    isolate
+  /// Keeps the watchdog busy while code runs, until the guard is dropped.
+  fn watchdog_guard(&self) -> Option<WatchdogGuard> {
+    self.watchdog.as_ref().map(Watchdog::busy)
   }
 
-  pub(crate) fn state(isolate: &v8::Isolate) -> Rc<RefCell<JsRuntimeState>> {
-    let s = isolate.get_slot::<Rc<RefCell<JsRuntimeState>>>().unwrap();
-    s.clone()
+  /// Sets a callback that runs at the start of every tick of the event loop,
+  /// before ops are polled and JavaScript callbacks run. Useful to integrate
+  /// per-frame work of a host loop. Replaces any previously set callback.
+  pub fn set_before_tick_callback<C>(&mut self, cb: C)
+  where
+    C: FnMut(&mut JsRuntime) + 'static,
+  {
+    self.before_tick = Some(Box::new(cb));
   }
 
-  pub(crate) fn module_map(isolate: &v8::Isolate) -> Rc<RefCell<ModuleMap>> {
-    let module_map = isolate.get_slot::<Rc<RefCell<ModuleMap>>>().unwrap();
-    module_map.clone()
+  /// Sets a callback that runs at the end of every tick of the event loop,
+  /// with the time the tick took. Replaces any previously set callback.
+  pub fn set_after_tick_callback<C>(&mut self, cb: C)
+  where
+    C: FnMut(&mut JsRuntime, Duration) + 'static,
+  {
+    self.after_tick = Some(Box::new(cb));
   }
 
-  /// Initializes JS of provided Extensions
-  fn init_extension_js(&mut self) -> Result<(), Error> {
-    // Take extensions to avoid double-borrow
-    let mut extensions: Vec<Extension> = std::mem::take(&mut self.extensions);
-    for m in extensions.iter_mut() {
-      let js_files = m.init_js();
-      for (filename, source) in js_files {
-        let source = source()?;
-        // TODO(@AaronO): use JsRuntime::execute_static() here to move src off heap
-        self.execute_script(filename, &source)?;
+  /// Converts a Rust value into a V8 value in the runtime's context, using
+  /// serde_v8. Supports primitives, strings, sequences, maps, structs,
+  /// options, byte buffers (as `Uint8Array`) and `serde_json::Value`.
+  pub fn to_v8<T: serde::Serialize>(
+    &mut self,
+    value: T,
+  ) -> Result<v8::Global<v8::Value>, Error> {
+    let scope = &mut self.handle_scope();
+    let value = serde_v8::to_v8(scope, value)?;
+    Ok(v8::Global::new(scope, value))
+  }
+
+  /// Converts a V8 value, eg. one returned by `execute_script()`, into a
+  /// Rust value using serde_v8. The inverse of `to_v8()`.
+  pub fn from_v8<T: serde::de::DeserializeOwned>(
+    &mut self,
+    value: &v8::Global<v8::Value>,
+  ) -> Result<T, Error> {
+    let scope = &mut self.handle_scope();
+    let value = v8::Local::new(scope, value);
+    Ok(serde_v8::from_v8(scope, value)?)
+  }
+
+  /// Calls a function retained with `Deno.core.createCallable()`, with
+  /// `undefined` as `this`. Returns the function's return value, or the
+  /// exception it threw as an error.
+  pub fn call_callable(
+    &mut self,
+    callable: JsCallable,
+    args: &[v8::Global<v8::Value>],
+  ) -> Result<v8::Global<v8::Value>, Error> {
+    let state_rc = Self::state(self.v8_isolate());
+    let function = state_rc
+      .borrow()
+      .js_callables
+      .get(&callable)
+      .cloned()
+      .ok_or_else(|| type_error("JsCallable has been released"))?;
+    let scope = &mut self.handle_scope();
+    let function = v8::Local::new(scope, function);
+    let args = args
+      .iter()
+      .map(|arg| v8::Local::new(scope, arg))
+      .collect::<Vec<_>>();
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    let this = v8::undefined(tc_scope).into();
+    match function.call(tc_scope, this, &args) {
+      Some(value) => Ok(v8::Global::new(tc_scope, value)),
+      None => {
+        let exception = tc_scope.exception().unwrap();
+        exception_to_err_result(tc_scope, exception, false)
       }
     }
-    // Restore extensions
-    self.extensions = extensions;
+  }
 
-    Ok(())
+  /// Drops the runtime's reference to a function retained with
+  /// `Deno.core.createCallable()`. Returns false if it was already released.
+  pub fn release_callable(&mut self, callable: JsCallable) -> bool {
+    let state_rc = Self::state(self.v8_isolate());
+    let removed = state_rc.borrow_mut().js_callables.remove(&callable);
+    removed.is_some()
   }
 
-  /// Initializes ops of provided Extensions
-  fn init_extension_ops(&mut self) -> Result<(), Error> {
-    let op_state = self.op_state();
-    // Take extensions to avoid double-borrow
-    let mut extensions: Vec<Extension> = std::mem::take(&mut self.extensions);
+  /// Creates a promise that is settled with the output of `future`, which is
+  /// polled by the event loop. The promise is resolved with the value
+  /// converted using serde_v8, or rejected with the error converted like an
+  /// error returned from an op. The event loop doesn't finish until the
+  /// future has completed.
+  pub fn resolve_promise_with<F, R>(
+    &mut self,
+    future: F,
+  ) -> v8::Global<v8::Promise>
+  where
+    F: Future<Output = Result<R, Error>> + 'static,
+    R: serde::Serialize + 'static,
+  {
+    let state_rc = Self::state(self.v8_isolate());
+    let op_state = state_rc.borrow().op_state.clone();
+    let scope = &mut self.handle_scope();
+    let resolver = v8::PromiseResolver::new(scope).unwrap();
+    let promise = resolver.get_promise(scope);
+    let promise = v8::Global::new(scope, promise);
+    let resolver = v8::Global::new(scope, resolver);
+    let future = future
+      .map(move |result| (resolver, serialize_op_result(result, op_state)));
+    let state = state_rc.borrow();
+    state.pending_promise_futures.push(OpCall::lazy(future));
+    state.waker.wake();
+    promise
+  }
 
-    // Middleware
-    let middleware: Vec<Box<OpMiddlewareFn>> = extensions
+  /// Parses `json` with V8's `JSON.parse()`. Faster than deserializing it
+  /// into a `serde_json::Value` first when the result is only needed in JS.
+  pub fn json_parse(
+    &mut self,
+    json: &str,
+  ) -> Result<v8::Global<v8::Value>, Error> {
+    let scope = &mut self.handle_scope();
+    let json = v8::String::new(scope, json)
+      .ok_or_else(|| range_error("JSON string is too long"))?;
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    match v8::json::parse(tc_scope, json) {
+      Some(value) => Ok(v8::Global::new(tc_scope, value)),
+      None => {
+        let exception = tc_scope.exception().unwrap();
+        exception_to_err_result(tc_scope, exception, false)
+      }
+    }
+  }
+
+  /// Serializes `value` with V8's `JSON.stringify()`. Fails for values that
+  /// can't be represented as JSON, eg. cyclic objects or BigInts.
+  pub fn json_stringify(
+    &mut self,
+    value: &v8::Global<v8::Value>,
+  ) -> Result<String, Error> {
+    let scope = &mut self.handle_scope();
+    let value = v8::Local::new(scope, value);
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    match v8::json::stringify(tc_scope, value) {
+      Some(json) => Ok(json.to_rust_string_lossy(tc_scope)),
+      None => match tc_scope.exception() {
+        Some(exception) => exception_to_err_result(tc_scope, exception, false),
+        None => Err(type_error("Value is not JSON-serializable")),
+      },
+    }
+  }
+
+  /// Serializes `value` with V8's structured clone algorithm into bytes that
+  /// can be stored, eg. to persist script state, and later restored with
+  /// `deserialize_value()` by any runtime using the same V8 version. Fails for
+  /// values that can't be cloned, eg. functions, and for SharedArrayBuffers and
+  /// WebAssembly modules, which can only be shared in memory.
+  pub fn serialize_value(
+    &mut self,
+    value: &v8::Global<v8::Value>,
+  ) -> Result<Vec<u8>, Error> {
+    let scope = &mut self.handle_scope();
+    let value = v8::Local::new(scope, value);
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    match bindings::serialize_persistent(tc_scope, value) {
+      Some(bytes) => Ok(bytes),
+      None => match tc_scope.exception() {
+        Some(exception) => exception_to_err_result(tc_scope, exception, false),
+        None => Err(type_error("Failed to serialize value")),
+      },
+    }
+  }
+
+  /// Restores a value serialized with `serialize_value()`.
+  pub fn deserialize_value(
+    &mut self,
+    bytes: &[u8],
+  ) -> Result<v8::Global<v8::Value>, Error> {
+    let scope = &mut self.handle_scope();
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    match bindings::deserialize_persistent(tc_scope, bytes) {
+      Some(value) => Ok(v8::Global::new(tc_scope, value)),
+      None => match tc_scope.exception() {
+        Some(exception) => exception_to_err_result(tc_scope, exception, false),
+        None => Err(range_error("could not deserialize value")),
+      },
+    }
+  }
+
+  /// Takes the value thrown by the exception that was most recently turned
+  /// into an error, eg. by `execute_script()` or the event loop. Lets custom
+  /// payloads thrown by scripts be inspected beyond `JsError::properties`.
+  pub fn take_last_exception(&mut self) -> Option<v8::Global<v8::Value>> {
+    Self::state(self.v8_isolate())
+      .borrow_mut()
+      .last_exception
+      .take()
+  }
+
+  /// Returns a handle that can terminate execution of this runtime's isolate
+  /// with a `TerminationReason` from any thread.
+  pub fn termination_handle(&mut self) -> TerminationHandle {
+    let isolate_handle = self.v8_isolate().thread_safe_handle();
+    let state_rc = Self::state(self.v8_isolate());
+    let state = state_rc.borrow();
+    TerminationHandle {
+      isolate_handle,
+      reason: state.termination_reason.clone(),
+      error: state.termination_error.clone(),
+    }
+  }
+
+  /// Queues `f` to run as a macrotask on a later tick of the event loop,
+  /// after the JS macrotasks ready on that tick, with a microtask checkpoint
+  /// after it. Macrotasks queued by a macrotask run on the next tick. An
+  /// error returned by `f` fails the event loop.
+  pub fn queue_macrotask<F>(&mut self, f: F)
+  where
+    F: FnOnce(&mut JsRuntime) -> Result<(), Error> + 'static,
+  {
+    let state_rc = Self::state(self.v8_isolate());
+    let mut state = state_rc.borrow_mut();
+    state.macrotasks.push_back(Box::new(f));
+    state.waker.wake();
+  }
+
+  /// Changes when the event loop completes, see `CompletionPolicy`. Wakes
+  /// the event loop so it can complete under the new policy.
+  pub fn set_completion_policy(&mut self, policy: CompletionPolicy) {
+    let state_rc = Self::state(self.v8_isolate());
+    let mut state = state_rc.borrow_mut();
+    state.completion_policy = policy;
+    state.waker.wake();
+  }
+
+  /// Returns a waker that schedules a poll of the event loop when woken, from
+  /// any thread. Lets events that happen outside of ops, eg. native callbacks
+  /// that queue work for the runtime, wake up a pending event loop.
+  pub fn waker(&mut self) -> Waker {
+    let state_rc = Self::state(self.v8_isolate());
+    let waker = state_rc.borrow().waker.clone();
+    futures::task::waker(Arc::new(EventLoopWaker(waker)))
+  }
+
+  /// Returns a handle that can be sent to other threads to run closures on
+  /// the isolate thread while JavaScript is executing, eg. to inspect the
+  /// state of a long-running script.
+  pub fn interrupt_handle(&mut self) -> InterruptHandle {
+    InterruptHandle {
+      isolate_handle: self.v8_isolate().thread_safe_handle(),
+    }
+  }
+
+  /// Terminates execution with an error, eg. from an op, see
+  /// `TerminationHandle::terminate_with_error()`.
+  pub fn terminate_with_error(&mut self, err: Error) -> bool {
+    self.termination_handle().terminate_with_error(err)
+  }
+
+  pub fn handle_scope(&mut self) -> v8::HandleScope {
+    self.requeue_deferred_interrupts();
+    let context = self.global_context();
+    v8::HandleScope::with_context(self.v8_isolate(), context)
+  }
+
+  /// Queues the interrupt closures that landed while the state was borrowed
+  /// again, see `DeferredInterrupts`.
+  fn requeue_deferred_interrupts(&mut self) {
+    let deferred = match self.v8_isolate().get_slot_mut::<DeferredInterrupts>()
+    {
+      Some(deferred) if !deferred.0.is_empty() => {
+        std::mem::take(&mut deferred.0)
+      }
+      _ => return,
+    };
+    let handle = self.interrupt_handle();
+    for f in deferred {
+      handle.interrupt_boxed(f);
+    }
+  }
+
+  fn setup_isolate(mut isolate: v8::OwnedIsolate) -> v8::OwnedIsolate {
+    isolate.set_capture_stack_trace_for_uncaught_exceptions(true, 10);
+    isolate.set_promise_reject_callback(bindings::promise_reject_callback);
+    isolate.set_host_initialize_import_meta_object_callback(
+      bindings::host_initialize_import_meta_object_callback,
+    );
+    isolate.set_host_import_module_dynamically_callback(
+      bindings::host_import_module_dynamically_callback,
+    );
+    isolate
+  }
+
+  pub(crate) fn state(isolate: &v8::Isolate) -> Rc<RefCell<JsRuntimeState>> {
+    let s = isolate.get_slot::<Rc<RefCell<JsRuntimeState>>>().unwrap();
+    s.clone()
+  }
+
+  pub(crate) fn module_map(isolate: &v8::Isolate) -> Rc<RefCell<ModuleMap>> {
+    let module_map = isolate.get_slot::<Rc<RefCell<ModuleMap>>>().unwrap();
+    module_map.clone()
+  }
+
+  /// Initializes JS of provided Extensions
+  fn init_extension_js(&mut self) -> Result<(), Error> {
+    // Take extensions to avoid double-borrow
+    let mut extensions: Vec<Extension> = std::mem::take(&mut self.extensions);
+    for m in extensions.iter_mut() {
+      let js_files = m.init_js();
+      for (filename, source) in js_files {
+        let source = source()?;
+        // TODO(@AaronO): use JsRuntime::execute_static() here to move src off heap
+        self.execute_script(filename, &source)?;
+      }
+    }
+    // Restore extensions
+    self.extensions = extensions;
+
+    Ok(())
+  }
+
+  /// Initializes ops of provided Extensions
+  fn init_extension_ops(&mut self) -> Result<(), Error> {
+    let op_state = self.op_state();
+    // Take extensions to avoid double-borrow
+    let mut extensions: Vec<Extension> = std::mem::take(&mut self.extensions);
+
+    // Middleware
+    let middleware: Vec<Box<OpMiddlewareFn>> = extensions
       .iter_mut()
       .filter_map(|e| e.init_middleware())
       .collect();
@@ -481,6 +1668,12 @@ impl JsRuntime {
       for (name, opfn) in ops {
         self.register_op(name, macroware(name, opfn));
       }
+      for (name, metadata) in e.init_op_metadata() {
+        op_state
+          .borrow_mut()
+          .op_table
+          .set_op_metadata(name, metadata);
+      }
     }
     // Restore extensions
     self.extensions = extensions;
@@ -505,23 +1698,118 @@ impl JsRuntime {
     let mut scope = self.handle_scope();
     let recv_cb = Self::grab_fn(&mut scope, "Deno.core.opresolve");
     let sync_cb = Self::grab_fn(&mut scope, "Deno.core.syncOpsCache");
+    let register_error_class_cb =
+      Self::grab_fn(&mut scope, "Deno.core.registerErrorClass");
+    let build_custom_error_cb =
+      Self::grab_fn(&mut scope, "Deno.core.buildCustomError");
     // Put global handles in state
     let state_rc = JsRuntime::state(&scope);
     let mut state = state_rc.borrow_mut();
     state.js_recv_cb.replace(recv_cb);
     state.js_sync_cb.replace(sync_cb);
+    state
+      .js_register_error_class_cb
+      .replace(register_error_class_cb);
+    state
+      .js_build_custom_error_cb
+      .replace(build_custom_error_cb);
+  }
+
+  /// Registers `constructor` as the JS error class for errors whose class
+  /// name is `class_name`. This is the Rust counterpart of
+  /// `Deno.core.registerErrorClass()`.
+  ///
+  /// Use `map_error_class()` to surface Rust errors as instances of the
+  /// registered class.
+  pub fn register_error_class(
+    &mut self,
+    class_name: &str,
+    constructor: v8::Global<v8::Function>,
+  ) -> Result<(), Error> {
+    let state_rc = Self::state(self.v8_isolate());
+    let register_cb = state_rc
+      .borrow()
+      .js_register_error_class_cb
+      .clone()
+      .unwrap();
+
+    let scope = &mut self.handle_scope();
+    let tc_scope = &mut v8::TryCatch::new(scope);
+    let register_cb = register_cb.open(tc_scope);
+    let this = v8::undefined(tc_scope).into();
+    let class_name = v8::String::new(tc_scope, class_name).unwrap();
+    let constructor = v8::Local::new(tc_scope, constructor);
+    register_cb.call(tc_scope, this, &[class_name.into(), constructor.into()]);
+
+    match tc_scope.exception() {
+      None => Ok(()),
+      Some(exception) => exception_to_err_result(tc_scope, exception, false),
+    }
+  }
+
+  /// Maps Rust errors of type `T` to the JS error class `class_name`. Errors
+  /// returned by ops and module loading that downcast to `T` become instances
+  /// of that class in JS. Mappings take precedence over
+  /// `RuntimeOptions::get_error_class_fn` and are consulted in the order they
+  /// were added.
+  pub fn map_error_class<T>(&mut self, class_name: &'static str)
+  where
+    T: std::fmt::Display + std::fmt::Debug + Send + Sync + 'static,
+  {
+    let op_state = self.op_state();
+    op_state.borrow_mut().error_class_mappings.push((
+      class_name,
+      Box::new(|err: &Error| err.downcast_ref::<T>().is_some()),
+    ));
   }
 
-  /// Ensures core.js has the latest op-name to op-id mappings
+  /// Ensures core.js has the latest op-name to op-id mappings. The mappings
+  /// are only published to JS again if ops were registered since the last
+  /// call.
   pub fn sync_ops_cache(&mut self) {
     let scope = &mut self.handle_scope();
     let state_rc = JsRuntime::state(scope);
+    let op_count = state_rc.borrow().op_state.borrow().op_table.op_count();
+    if state_rc.borrow().synced_op_count == Some(op_count) {
+      return;
+    }
+    state_rc.borrow_mut().synced_op_count = Some(op_count);
     let js_sync_cb_handle = state_rc.borrow().js_sync_cb.clone().unwrap();
     let js_sync_cb = js_sync_cb_handle.open(scope);
     let this = v8::undefined(scope).into();
     js_sync_cb.call(scope, this, &[]);
   }
 
+  /// Deep-freezes the JavaScript built-ins (`Object`, `Array`, `Promise`, ...)
+  /// and their prototypes, so code that runs afterwards can't pollute the
+  /// globals that core JS and extensions rely on.
+  ///
+  /// This should be called once bootstrap code has finished patching
+  /// built-ins. Assigning to a frozen property throws in strict mode code.
+  pub fn freeze_intrinsics(&mut self) -> Result<(), Error> {
+    self.execute_script(
+      &located_script_name!(),
+      "Deno.core.freezeIntrinsics()",
+    )?;
+    Ok(())
+  }
+
+  /// Restricts access to `Deno.core` from JavaScript, so that user scripts
+  /// can only reach ops through the wrapper functions set up during
+  /// bootstrap.
+  ///
+  /// This should be called once bootstrap code has finished. Methods that
+  /// evaluate code referring to `Deno.core`, like `freeze_intrinsics()`, must
+  /// be called before locking with `CoreLockMode::Remove`.
+  pub fn lock_core(&mut self, mode: CoreLockMode) -> Result<(), Error> {
+    let remove = mode == CoreLockMode::Remove;
+    self.execute_script(
+      &located_script_name!(),
+      &format!("Deno.core.lock({})", remove),
+    )?;
+    Ok(())
+  }
+
   /// Returns the runtime's op state, which can be used to maintain ops
   /// and access resources between op calls.
   pub fn op_state(&mut self) -> Rc<RefCell<OpState>> {
@@ -530,6 +1818,85 @@ impl JsRuntime {
     state.op_state.clone()
   }
 
+  /// Returns a snapshot of pending work and memory use, eg. for health
+  /// checks or autoscaling decisions.
+  pub fn stats(&mut self) -> RuntimeStats {
+    let state_rc = Self::state(self.v8_isolate());
+    let module_map_rc = Self::module_map(self.v8_isolate());
+    let mut heap = v8::HeapStatistics::default();
+    self.v8_isolate().get_heap_statistics(&mut heap);
+
+    let state = state_rc.borrow();
+    let module_map = module_map_rc.borrow();
+    let pending_ops = state.pending_ops.len() + state.queued_ops.len();
+    RuntimeStats {
+      pending_refed_ops: pending_ops.saturating_sub(state.unrefed_ops.len()),
+      pending_unrefed_ops: state.unrefed_ops.len(),
+      queued_ops: state.queued_ops.len(),
+      max_pending_ops: state.max_pending_ops,
+      pending_dynamic_imports: module_map.pending_dynamic_import_count(),
+      module_count: module_map.module_count(),
+      heap_used: heap.used_heap_size(),
+      heap_total: heap.total_heap_size(),
+      heap_limit: heap.heap_size_limit(),
+      external_memory: heap.external_memory(),
+    }
+  }
+
+  /// Lists the work the event loop is waiting on: pending async ops, dynamic
+  /// imports and module evaluations. Useful to find out why a runtime's event
+  /// loop never completes.
+  pub fn dump_pending_state(&mut self) -> PendingStateDump {
+    let state_rc = Self::state(self.v8_isolate());
+    let module_map_rc = Self::module_map(self.v8_isolate());
+    let state = state_rc.borrow();
+    let module_map = module_map_rc.borrow();
+    let op_state = state.op_state.borrow();
+
+    let mut ops: Vec<PendingOpInfo> = state
+      .pending_op_ids
+      .iter()
+      .map(|(&promise_id, &op_id)| PendingOpInfo {
+        promise_id,
+        op_id,
+        op_name: op_state
+          .op_table
+          .op_name(op_id)
+          .unwrap_or_default()
+          .to_string(),
+        refed: !state.unrefed_ops.contains(&promise_id),
+      })
+      .collect();
+    ops.sort_by_key(|op| op.promise_id);
+
+    let mut dynamic_imports: Vec<(ModuleLoadId, String)> = module_map
+      .dynamic_import_specifiers
+      .iter()
+      .map(|(id, specifier)| (*id, specifier.clone()))
+      .collect();
+    dynamic_imports.sort();
+
+    let module_name = |id: ModuleId| {
+      module_map
+        .get_info_by_id(&id)
+        .map(|info| info.name.to_string())
+        .unwrap_or_else(|| format!("<module {}>", id))
+    };
+    let module_evaluations = state
+      .pending_mod_evaluate
+      .iter()
+      .map(|evaluate| evaluate.module_id)
+      .chain(state.pending_dyn_mod_evaluate.iter().map(|e| e.module_id))
+      .map(module_name)
+      .collect();
+
+    PendingStateDump {
+      ops,
+      dynamic_imports: dynamic_imports.into_iter().map(|(_, s)| s).collect(),
+      module_evaluations,
+    }
+  }
+
   /// Executes traditional JavaScript code (traditional = not ES modules).
   ///
   /// The execution takes place on the current global context, so it is possible
@@ -551,23 +1918,185 @@ impl JsRuntime {
     name: &str,
     source_code: &str,
   ) -> Result<v8::Global<v8::Value>, Error> {
+    let _beat = self.watchdog_guard();
+    self.execute_script_with_store(
+      name,
+      source_code,
+      &ScriptOriginOptions::default(),
+    )
+  }
+
+  /// Like `execute_script()`, with line and column offsets and a source map
+  /// URL for the script, so that stack traces of a snippet embedded in a
+  /// larger document point into that document.
+  pub fn execute_script_with_origin(
+    &mut self,
+    name: &str,
+    source_code: &str,
+    origin: &ScriptOriginOptions,
+  ) -> Result<v8::Global<v8::Value>, Error> {
+    let _beat = self.watchdog_guard();
+    self.execute_script_with_store(name, source_code, origin)
+  }
+
+  /// Like `execute_script()`, for a source held in UTF-16 or one-byte
+  /// (Latin-1) encoding, avoiding a round trip through UTF-8.
+  /// `RuntimeOptions::code_cache_store` is only used for UTF-8 sources.
+  pub fn execute_script_source(
+    &mut self,
+    name: &str,
+    source: ScriptSource,
+  ) -> Result<v8::Global<v8::Value>, Error> {
+    let _beat = self.watchdog_guard();
+    let origin = ScriptOriginOptions::default();
+    match source {
+      ScriptSource::Utf8(source_code) => {
+        self.execute_script_with_store(name, source_code, &origin)
+      }
+      source => {
+        self
+          .execute_script_inner(name, source, &origin, None, false)
+          .0
+      }
+    }
+  }
+
+  /// Like `execute_script()`, compiling the script with a V8 code cache
+  /// produced by an earlier call, eg. to speed up running the same bootstrap
+  /// scripts across many isolates.
+  ///
+  /// Without `cached_data`, also returns a code cache for the script, or
+  /// `None` if it can't be serialized. The cache is created after the script
+  /// has run, so it covers the functions the script called. With
+  /// `cached_data`, no new cache is returned; if V8 rejects the data the
+  /// script is compiled from scratch, so caches should be keyed by the source
+  /// and `v8_version()`.
+  pub fn execute_script_with_cached_data(
+    &mut self,
+    name: &str,
+    source_code: &str,
+    cached_data: Option<&[u8]>,
+  ) -> (Result<v8::Global<v8::Value>, Error>, Option<Vec<u8>>) {
+    let _beat = self.watchdog_guard();
+    self.execute_script_inner(
+      name,
+      source_code,
+      &ScriptOriginOptions::default(),
+      cached_data,
+      cached_data.is_none(),
+    )
+  }
+
+  /// Evaluates `source` the way an interactive console does, and returns the
+  /// result formatted for display on a single line.
+  ///
+  /// Input starting with `{` is evaluated as an object literal if possible,
+  /// rather than as a block. The result is assigned to the global `_`, and an
+  /// uncaught exception to `_error`; the latter is also returned as an error.
+  pub fn eval_expression(&mut self, source: &str) -> Result<String, Error> {
+    let _beat = self.watchdog_guard();
+    crate::repl::eval_expression(&mut self.handle_scope(), source)
+  }
+
+  /// Returns the properties that can complete the member expression at the
+  /// end of `input`, eg. the properties of `Deno` starting with "co" for
+  /// `Deno.co`, to implement tab completion. Without a `.` the properties of
+  /// the global object are listed; top-level `let` and `const` declarations
+  /// are not included.
+  pub fn completions(&mut self, input: &str) -> Vec<Completion> {
+    crate::repl::completions(&mut self.handle_scope(), input)
+  }
+
+  /// Runs a classic script, using `RuntimeOptions::code_cache_store` if set.
+  fn execute_script_with_store(
+    &mut self,
+    name: &str,
+    source_code: &str,
+    origin: &ScriptOriginOptions,
+  ) -> Result<v8::Global<v8::Value>, Error> {
+    let store = Self::state(self.v8_isolate())
+      .borrow()
+      .code_cache_store
+      .clone();
+    let store = match store {
+      Some(store) => store,
+      None => {
+        return self
+          .execute_script_inner(name, source_code, origin, None, false)
+          .0
+      }
+    };
+    let source_hash = code_cache_source_hash(source_code);
+    let cached_data = store.get(name, &source_hash);
+    let (result, code_cache) = self.execute_script_inner(
+      name,
+      source_code,
+      origin,
+      cached_data.as_deref(),
+      cached_data.is_none(),
+    );
+    if let Some(code_cache) = code_cache {
+      if let Err(err) = store.put(name, &source_hash, &code_cache) {
+        log::debug!("Failed to store code cache for {}: {}", name, err);
+      }
+    }
+    result
+  }
+
+  /// Compiles and runs a classic script. With `cached_data`, compilation
+  /// consumes that code cache; with `produce_cache`, a code cache is created
+  /// after running the script, so that it covers the functions the script
+  /// compiled lazily.
+  fn execute_script_inner<'a>(
+    &mut self,
+    name: &str,
+    source_code: impl Into<ScriptSource<'a>>,
+    origin: &ScriptOriginOptions,
+    cached_data: Option<&[u8]>,
+    produce_cache: bool,
+  ) -> (Result<v8::Global<v8::Value>, Error>, Option<Vec<u8>>) {
     let scope = &mut self.handle_scope();
 
-    let source = v8::String::new(scope, source_code).unwrap();
+    let source = source_code.into().to_v8_string(scope).unwrap();
     let name = v8::String::new(scope, name).unwrap();
-    let origin = bindings::script_origin(scope, name);
+    let origin = bindings::script_origin_at(
+      scope,
+      name,
+      origin.line_offset,
+      origin.column_offset,
+      origin.source_map_url.as_deref().unwrap_or_default(),
+    );
 
     let tc_scope = &mut v8::TryCatch::new(scope);
 
-    let script = match v8::Script::compile(tc_scope, source, Some(&origin)) {
+    let (source, options) = match cached_data {
+      Some(data) => (
+        v8::script_compiler::Source::new_with_cached_data(
+          source,
+          Some(&origin),
+          v8::CachedData::new(data),
+        ),
+        v8::script_compiler::CompileOptions::ConsumeCodeCache,
+      ),
+      None => (
+        v8::script_compiler::Source::new(source, Some(&origin)),
+        v8::script_compiler::CompileOptions::NoCompileOptions,
+      ),
+    };
+    let script = match v8::script_compiler::compile(
+      tc_scope,
+      source,
+      options,
+      v8::script_compiler::NoCacheReason::NoReason,
+    ) {
       Some(script) => script,
       None => {
         let exception = tc_scope.exception().unwrap();
-        return exception_to_err_result(tc_scope, exception, false);
+        return (compile_exception_to_err_result(tc_scope, exception), None);
       }
     };
 
-    match script.run(tc_scope) {
+    let result = match script.run(tc_scope) {
       Some(value) => {
         let value_handle = v8::Global::new(tc_scope, value);
         Ok(value_handle)
@@ -577,7 +2106,16 @@ impl JsRuntime {
         let exception = tc_scope.exception().unwrap();
         exception_to_err_result(tc_scope, exception, false)
       }
-    }
+    };
+    let code_cache = if produce_cache {
+      script
+        .get_unbound_script(tc_scope)
+        .create_code_cache()
+        .map(|cache| cache.to_vec())
+    } else {
+      None
+    };
+    (result, code_cache)
   }
 
   /// Takes a snapshot. The isolate should have been created with will_snapshot
@@ -588,6 +2126,7 @@ impl JsRuntime {
   /// be a different type if `RuntimeOptions::js_error_create_fn` has been set.
   pub fn snapshot(&mut self) -> v8::StartupData {
     assert!(self.snapshot_creator.is_some());
+    let _span = trace_span!("snapshot");
     let state = Self::state(self.v8_isolate());
 
     // Note: create_blob() method must not be called from within a HandleScope.
@@ -606,6 +2145,9 @@ impl JsRuntime {
     // Drop other v8::Global handles before snapshotting
     std::mem::take(&mut state.borrow_mut().js_recv_cb);
     std::mem::take(&mut state.borrow_mut().js_sync_cb);
+    std::mem::take(&mut state.borrow_mut().js_register_error_class_cb);
+    std::mem::take(&mut state.borrow_mut().js_build_custom_error_cb);
+    std::mem::take(&mut state.borrow_mut().last_exception);
 
     let snapshot_creator = self.snapshot_creator.as_mut().unwrap();
     let snapshot = snapshot_creator
@@ -664,6 +2206,40 @@ impl JsRuntime {
       .add_near_heap_limit_callback(near_heap_limit_callback::<C>, data);
   }
 
+  /// Like `add_near_heap_limit_callback()`, but the closure is given access
+  /// to the isolate, eg. to write a heap snapshot or heap statistics before
+  /// raising the limit or terminating execution. The closure returns the new
+  /// limit.
+  pub fn add_near_heap_limit_callback_with_diagnostics<C>(&mut self, mut cb: C)
+  where
+    C: FnMut(&mut HeapLimitDiagnostics) -> usize + 'static,
+  {
+    let isolate: *mut v8::Isolate = &mut **self.v8_isolate();
+    self.add_near_heap_limit_callback(
+      move |current_heap_limit, initial_heap_limit| {
+        // SAFETY: V8 calls the callback on the isolate's thread, while the
+        // isolate is alive.
+        let isolate = unsafe { &mut *isolate };
+        cb(&mut HeapLimitDiagnostics {
+          isolate,
+          current_heap_limit,
+          initial_heap_limit,
+        })
+      },
+    );
+  }
+
+  /// Terminates execution with `TerminationReason::MemoryLimit` when the heap
+  /// limit is approached.
+  fn install_memory_limit(&mut self) {
+    let termination_handle = self.termination_handle();
+    self.add_near_heap_limit_callback(move |current_limit, _initial_limit| {
+      termination_handle.terminate(TerminationReason::MemoryLimit);
+      // Give V8 some headroom to unwind the stack after termination.
+      current_limit * 2
+    });
+  }
+
   pub fn remove_near_heap_limit_callback(&mut self, heap_limit: usize) {
     if let Some((_, cb)) = self.allocations.near_heap_limit_callback_data.take()
     {
@@ -673,6 +2249,15 @@ impl JsRuntime {
     }
   }
 
+  /// Runs all pending microtasks, such as promise reactions queued by JS
+  /// callbacks called outside of the event loop. Returns an error for
+  /// promises rejected without a handler in the process, the way the event
+  /// loop would. See `RuntimeOptions::explicit_microtasks`.
+  pub fn run_microtasks(&mut self) -> Result<(), Error> {
+    self.handle_scope().perform_microtask_checkpoint();
+    self.check_promise_exceptions()
+  }
+
   fn pump_v8_message_loop(&mut self) {
     let scope = &mut self.handle_scope();
     while v8::Platform::pump_message_loop(
@@ -689,58 +2274,201 @@ impl JsRuntime {
   /// Waits for the given value to resolve while polling the event loop.
   ///
   /// This future resolves when either the value is resolved or the event loop runs to
-  /// completion.
+  /// completion. A promise that has already settled resolves right away,
+  /// without polling the event loop. A rejection is returned as the error
+  /// and is not reported again as an unhandled rejection by the event loop.
+  /// Useful with the promises returned by `call_callable()` or async
+  /// functions run with `execute_script()`.
   pub async fn resolve_value(
     &mut self,
     global: v8::Global<v8::Value>,
   ) -> Result<v8::Global<v8::Value>, Error> {
     poll_fn(|cx| {
+      if let Some(result) = self.settled_value(&global) {
+        return Poll::Ready(result);
+      }
       let state = self.poll_event_loop(cx, false);
-
-      let mut scope = self.handle_scope();
-      let local = v8::Local::<v8::Value>::new(&mut scope, &global);
-
-      if let Ok(promise) = v8::Local::<v8::Promise>::try_from(local) {
-        match promise.state() {
-          v8::PromiseState::Pending => match state {
-            Poll::Ready(Ok(_)) => {
-              let msg = "Promise resolution is still pending but the event loop has already resolved.";
-              Poll::Ready(Err(generic_error(msg)))
-            },
-            Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
-            Poll::Pending => Poll::Pending,
-          },
-          v8::PromiseState::Fulfilled => {
-            let value = promise.result(&mut scope);
-            let value_handle = v8::Global::new(&mut scope, value);
-            Poll::Ready(Ok(value_handle))
-          }
-          v8::PromiseState::Rejected => {
-            let exception = promise.result(&mut scope);
-            Poll::Ready(exception_to_err_result(&mut scope, exception, false))
-          }
+      if let Some(result) = self.settled_value(&global) {
+        return Poll::Ready(result);
+      }
+      match state {
+        Poll::Ready(Ok(_)) => {
+          let msg = "Promise resolution is still pending but the event loop has already resolved.";
+          Poll::Ready(Err(generic_error(msg)))
         }
-      } else {
-        let value_handle = v8::Global::new(&mut scope, local);
-        Poll::Ready(Ok(value_handle))
+        Poll::Ready(Err(e)) => Poll::Ready(Err(e)),
+        Poll::Pending => Poll::Pending,
       }
     })
     .await
   }
 
-  /// Runs event loop to completion
-  ///
-  /// This future resolves when:
-  ///  - there are no more pending dynamic imports
-  ///  - there are no more pending ops
-  ///  - there are no more active inspector sessions (only if `wait_for_inspector` is set to true)
-  pub async fn run_event_loop(
+  /// Returns the result of `global` if it is not a pending promise.
+  fn settled_value(
     &mut self,
-    wait_for_inspector: bool,
+    global: &v8::Global<v8::Value>,
+  ) -> Option<Result<v8::Global<v8::Value>, Error>> {
+    let state_rc = Self::state(self.v8_isolate());
+    let scope = &mut self.handle_scope();
+    let local = v8::Local::new(scope, global);
+    let promise = match v8::Local::<v8::Promise>::try_from(local) {
+      Ok(promise) => promise,
+      Err(_) => return Some(Ok(global.clone())),
+    };
+    match promise.state() {
+      v8::PromiseState::Pending => None,
+      v8::PromiseState::Fulfilled => {
+        let value = promise.result(scope);
+        Some(Ok(v8::Global::new(scope, value)))
+      }
+      v8::PromiseState::Rejected => {
+        let promise_global = v8::Global::new(scope, promise);
+        state_rc
+          .borrow_mut()
+          .pending_promise_exceptions
+          .shift_remove(&promise_global);
+        let exception = promise.result(scope);
+        Some(exception_to_err_result(scope, exception, false))
+      }
+    }
+  }
+
+  /// Runs event loop to completion
+  ///
+  /// This future resolves when:
+  ///  - there are no more pending dynamic imports
+  ///  - there are no more pending ops
+  ///  - there are no more active inspector sessions (only if `wait_for_inspector` is set to true)
+  pub async fn run_event_loop(
+    &mut self,
+    wait_for_inspector: bool,
   ) -> Result<(), Error> {
     poll_fn(|cx| self.poll_event_loop(cx, wait_for_inspector)).await
   }
 
+  /// Shuts the runtime down gracefully, instead of dropping it while work is
+  /// in flight:
+  ///  - new op dispatches throw, so no new work is started
+  ///  - if `timeout` is set, the event loop is run until pending refed ops
+  ///    have completed, for at most `timeout` as measured by `OpState::clock`
+  ///  - the callbacks added with `Deno.core.addBeforeExitCallback()` are
+  ///    called in order
+  ///  - ops still pending are canceled through `OpState::cancel_handle()`
+  ///    and their own cancel handles, and polled once more so that they can
+  ///    observe it and finish; their results are discarded
+  ///  - the runtime is dropped: the remaining ops are dropped and all
+  ///    resources are closed
+  ///
+  /// Returns the first error returned by the event loop or thrown by a
+  /// callback. The runtime is torn down either way.
+  pub async fn shutdown(
+    mut self,
+    timeout: Option<Duration>,
+  ) -> Result<(), Error> {
+    let state_rc = Self::state(self.v8_isolate());
+    state_rc.borrow_mut().shutting_down = true;
+
+    let mut result = Ok(());
+    if let Some(timeout) = timeout {
+      let clock = state_rc.borrow().op_state.borrow().clock.clone();
+      let mut sleep = clock.sleep_until(clock.now() + timeout);
+      result = poll_fn(|cx| {
+        if let Poll::Ready(result) = self.poll_event_loop(cx, false) {
+          return Poll::Ready(result);
+        }
+        sleep.poll_unpin(cx).map(|_| Ok(()))
+      })
+      .await;
+    }
+
+    let js_before_exit_cbs =
+      std::mem::take(&mut state_rc.borrow_mut().js_before_exit_cbs);
+    {
+      let scope = &mut self.handle_scope();
+      for js_before_exit_cb in js_before_exit_cbs {
+        let js_before_exit_cb = js_before_exit_cb.open(scope);
+        let tc_scope = &mut v8::TryCatch::new(scope);
+        let this = v8::undefined(tc_scope).into();
+        js_before_exit_cb.call(tc_scope, this, &[]);
+        if let Some(exception) = tc_scope.exception() {
+          if result.is_ok() {
+            result = exception_to_err_result(tc_scope, exception, false);
+          }
+        }
+        if tc_scope.has_terminated() || tc_scope.is_execution_terminating() {
+          break;
+        }
+      }
+    }
+
+    let (op_state, op_cancel_handles) = {
+      let state = state_rc.borrow();
+      let op_cancel_handles: Vec<_> =
+        state.op_cancel_handles.values().cloned().collect();
+      (state.op_state.clone(), op_cancel_handles)
+    };
+    op_state.borrow().cancel_handle().cancel();
+    for cancel_handle in op_cancel_handles {
+      cancel_handle.cancel();
+    }
+    poll_fn(|cx| {
+      let mut state = state_rc.borrow_mut();
+      while let Poll::Ready(Some(_)) = state.pending_ops.poll_next_unpin(cx) {}
+      Poll::Ready(())
+    })
+    .await;
+    result
+  }
+
+  /// Cancels `OpState::cancel_handle()` and the cancel handles of the
+  /// pending async ops, then drops the ops and closes all resources so that
+  /// they can run their cleanup. The results of the ops are discarded.
+  ///
+  /// This runs when the runtime is dropped, so the ops aren't polled and
+  /// state that's already borrowed is skipped rather than panicking.
+  /// `shutdown()` gives the ops a chance to observe the cancellation first.
+  fn cancel_pending_ops(&mut self) {
+    let state_rc = Self::state(self.v8_isolate());
+    let (pending_ops, queued_ops, op_cancel_handles, op_state) =
+      match state_rc.try_borrow_mut() {
+        Ok(mut state) => (
+          std::mem::take(&mut state.pending_ops),
+          std::mem::take(&mut state.queued_ops),
+          std::mem::take(&mut state.op_cancel_handles),
+          state.op_state.clone(),
+        ),
+        Err(_) => return,
+      };
+    if let Ok(op_state) = op_state.try_borrow() {
+      op_state.cancel_handle().cancel();
+    }
+    for cancel_handle in op_cancel_handles.values() {
+      cancel_handle.cancel();
+    }
+    drop(pending_ops);
+    drop(queued_ops);
+
+    // Resources are taken out of the table one at a time, so that closing
+    // them can use the op state.
+    let rids: Vec<_> = match op_state.try_borrow() {
+      Ok(op_state) => op_state
+        .resource_table
+        .names()
+        .map(|(rid, _)| rid)
+        .collect(),
+      Err(_) => return,
+    };
+    for rid in rids {
+      let resource = match op_state.try_borrow_mut() {
+        Ok(mut op_state) => op_state.resource_table.take_any(rid),
+        Err(_) => return,
+      };
+      if let Ok(resource) = resource {
+        resource.close();
+      }
+    }
+  }
+
   /// Runs a single tick of event loop
   ///
   /// If `wait_for_inspector` is set to true event loop
@@ -749,6 +2477,60 @@ impl JsRuntime {
     &mut self,
     cx: &mut Context,
     wait_for_inspector: bool,
+  ) -> Poll<Result<(), Error>> {
+    let _span = trace_span!("poll_event_loop");
+    // The callbacks are taken out while they run so they can be given the
+    // runtime, and put back unless they were replaced in the meantime.
+    if let Some(mut before_tick) = self.before_tick.take() {
+      before_tick(self);
+      self.before_tick.get_or_insert(before_tick);
+    }
+    let started = Instant::now();
+    let poll = {
+      let _beat = self.watchdog_guard();
+      self.poll_event_loop_inner(cx, wait_for_inspector)
+    };
+    if let Some(mut after_tick) = self.after_tick.take() {
+      after_tick(self, started.elapsed());
+      self.after_tick.get_or_insert(after_tick);
+    }
+    match poll {
+      Poll::Ready(Ok(())) => Poll::Ready(self.check_sanitizer()),
+      poll => poll,
+    }
+  }
+
+  /// Fails if `RuntimeOptions::sanitizer` is set and async ops are pending or
+  /// resources opened by ops are still open.
+  fn check_sanitizer(&mut self) -> Result<(), Error> {
+    let state_rc = Self::state(self.v8_isolate());
+    let state = state_rc.borrow();
+    let mut op_state = state.op_state.borrow_mut();
+    let op_state = &mut *op_state;
+    let sanitizer = match op_state.sanitizer.as_mut() {
+      Some(sanitizer) => sanitizer,
+      None => return Ok(()),
+    };
+    let mut pending_ops: Vec<(PromiseId, String)> = state
+      .pending_op_ids
+      .iter()
+      .map(|(&promise_id, &op_id)| {
+        let op_name = op_state
+          .op_table
+          .op_name(op_id)
+          .unwrap_or_default()
+          .to_string();
+        (promise_id, op_name)
+      })
+      .collect();
+    pending_ops.sort();
+    sanitizer.check(&op_state.resource_table, &pending_ops)
+  }
+
+  fn poll_event_loop_inner(
+    &mut self,
+    cx: &mut Context,
+    wait_for_inspector: bool,
   ) -> Poll<Result<(), Error>> {
     // We always poll the inspector first
     let _ = self.inspector().poll_unpin(cx);
@@ -756,8 +2538,11 @@ impl JsRuntime {
     let state_rc = Self::state(self.v8_isolate());
     let module_map_rc = Self::module_map(self.v8_isolate());
     {
-      let state = state_rc.borrow();
+      let mut state = state_rc.borrow_mut();
       state.waker.register(cx.waker());
+      state.poll_deadline =
+        state.poll_time_budget.map(|budget| Instant::now() + budget);
+      state.poll_budget_exhausted = false;
     }
 
     self.pump_v8_message_loop();
@@ -765,8 +2550,10 @@ impl JsRuntime {
     // Ops
     {
       self.resolve_async_ops(cx)?;
+      self.resolve_promise_futures(cx)?;
       self.drain_nexttick()?;
       self.drain_macrotasks()?;
+      self.run_queued_macrotasks()?;
       self.check_promise_exceptions()?;
     }
 
@@ -789,8 +2576,23 @@ impl JsRuntime {
     let mut state = state_rc.borrow_mut();
     let module_map = module_map_rc.borrow();
 
-    let has_pending_refed_ops =
-      state.pending_ops.len() > state.unrefed_ops.len();
+    // Ops or macrotasks left for the next tick.
+    if state.poll_budget_exhausted {
+      state.waker.wake();
+      return Poll::Pending;
+    }
+
+    let pending_ops = state.pending_ops.len() + state.queued_ops.len();
+    let has_pending_refed_ops = match state.completion_policy {
+      CompletionPolicy::RefedOpsDone => {
+        pending_ops > state.unrefed_ops.len()
+          || !state.pending_promise_futures.is_empty()
+      }
+      CompletionPolicy::AllOpsDone => {
+        pending_ops > 0 || !state.pending_promise_futures.is_empty()
+      }
+      CompletionPolicy::Never => true,
+    };
     let has_pending_dyn_imports = module_map.has_pending_dynamic_imports();
     let has_pending_dyn_module_evaluation =
       !state.pending_dyn_mod_evaluate.is_empty();
@@ -798,6 +2600,7 @@ impl JsRuntime {
     let has_pending_background_tasks =
       self.v8_isolate().has_pending_background_tasks();
     let has_tick_scheduled = state.has_tick_scheduled;
+    let has_pending_macrotasks = !state.macrotasks.is_empty();
     let inspector_has_active_sessions = self
       .inspector
       .as_ref()
@@ -810,6 +2613,7 @@ impl JsRuntime {
       && !has_pending_module_evaluation
       && !has_pending_background_tasks
       && !has_tick_scheduled
+      && !has_pending_macrotasks
     {
       if wait_for_inspector && inspector_has_active_sessions {
         return Poll::Pending;
@@ -828,6 +2632,7 @@ impl JsRuntime {
     if state.have_unpolled_ops
       || has_pending_background_tasks
       || has_tick_scheduled
+      || has_pending_macrotasks
     {
       state.waker.wake();
     }
@@ -838,6 +2643,7 @@ impl JsRuntime {
         || has_pending_dyn_module_evaluation
         || has_pending_background_tasks
         || has_tick_scheduled
+        || has_pending_macrotasks
       {
         // pass, will be polled again
       } else {
@@ -851,6 +2657,7 @@ impl JsRuntime {
         || has_pending_dyn_imports
         || has_pending_background_tasks
         || has_tick_scheduled
+        || has_pending_macrotasks
       {
         // pass, will be polled again
       } else if state.dyn_module_evaluate_idle_counter >= 1 {
@@ -860,7 +2667,7 @@ Pending dynamic modules:\n".to_string();
           let module_info = module_map
             .get_info_by_id(&pending_evaluate.module_id)
             .unwrap();
-          msg.push_str(&format!("- {}", module_info.name.as_str()));
+          msg.push_str(&format!("- {}", module_info.name));
         }
         return Poll::Ready(Err(generic_error(msg)));
       } else {
@@ -884,11 +2691,36 @@ extern "C" fn near_heap_limit_callback<F>(
 where
   F: FnMut(usize, usize) -> usize,
 {
+  trace_event!(
+    current_heap_limit,
+    initial_heap_limit,
+    "near heap limit callback"
+  );
   let callback = unsafe { &mut *(data as *mut F) };
   callback(current_heap_limit, initial_heap_limit)
 }
 
 impl JsRuntimeState {
+  /// Returns true if the time budget of the current poll has run out. See
+  /// `RuntimeOptions::poll_time_budget`.
+  fn check_poll_budget(&mut self) -> bool {
+    if let Some(deadline) = self.poll_deadline {
+      if Instant::now() >= deadline {
+        self.poll_budget_exhausted = true;
+      }
+    }
+    self.poll_budget_exhausted
+  }
+
+  /// Returns true if a newly dispatched async op can be polled right away,
+  /// rather than having to wait in `queued_ops`.
+  pub(crate) fn has_pending_ops_capacity(&self) -> bool {
+    self.queued_ops.is_empty()
+      && self
+        .max_pending_ops
+        .map_or(true, |max| self.pending_ops.len() < max)
+  }
+
   /// Called by `bindings::host_import_module_dynamically_callback`
   /// after initiating new dynamic import load.
   pub fn notify_new_dynamic_import(&mut self) {
@@ -901,17 +2733,86 @@ pub(crate) fn exception_to_err_result<'s, T>(
   scope: &mut v8::HandleScope<'s>,
   exception: v8::Local<v8::Value>,
   in_promise: bool,
+) -> Result<T, Error> {
+  js_exception_to_err_result(scope, exception, in_promise, None)
+}
+
+/// Offers an exception thrown by JavaScript code run from the event loop to
+/// the callback set with `Deno.core.setUncaughtExceptionCallback()`. If the
+/// callback returns normally the exception is handled and `tc_scope` is reset;
+/// otherwise the exception, or the one thrown by the callback, is converted
+/// into an error.
+pub(crate) fn uncaught_exception_to_err_result(
+  tc_scope: &mut v8::TryCatch<v8::HandleScope>,
+  exception: v8::Local<v8::Value>,
+) -> Result<(), Error> {
+  let js_uncaught_exception_cb = JsRuntime::state(tc_scope)
+    .borrow()
+    .js_uncaught_exception_cb
+    .clone();
+  let js_uncaught_exception_cb = match js_uncaught_exception_cb {
+    Some(cb) if !tc_scope.is_execution_terminating() => cb,
+    _ => return exception_to_err_result(tc_scope, exception, false),
+  };
+  tc_scope.reset();
+  let this = v8::undefined(tc_scope).into();
+  js_uncaught_exception_cb
+    .open(tc_scope)
+    .call(tc_scope, this, &[exception]);
+  match tc_scope.exception() {
+    None => Ok(()),
+    Some(exception) => exception_to_err_result(tc_scope, exception, false),
+  }
+}
+
+/// Like `exception_to_err_result()`, for the rejection reason of `promise`.
+/// If promise creation stacks are captured, the `JsError` records where the
+/// promise was created.
+pub(crate) fn promise_exception_to_err_result<'s, T>(
+  scope: &mut v8::HandleScope<'s>,
+  exception: v8::Local<v8::Value>,
+  promise: v8::Local<v8::Promise>,
+) -> Result<T, Error> {
+  js_exception_to_err_result(scope, exception, true, Some(promise))
+}
+
+fn js_exception_to_err_result<'s, T>(
+  scope: &mut v8::HandleScope<'s>,
+  exception: v8::Local<v8::Value>,
+  in_promise: bool,
+  promise: Option<v8::Local<v8::Promise>>,
 ) -> Result<T, Error> {
   let is_terminating_exception = scope.is_execution_terminating();
   let mut exception = exception;
+  let mut termination_reason = None;
 
   if is_terminating_exception {
     // TerminateExecution was called. Cancel exception termination so that the
     // exception can be created..
     scope.cancel_terminate_execution();
 
-    // Maybe make a new exception object.
-    if exception.is_null_or_undefined() {
+    let state_rc = JsRuntime::state(scope);
+    termination_reason =
+      state_rc.borrow().termination_reason.lock().unwrap().take();
+    let termination_message = state_rc.borrow_mut().termination_message.take();
+    let termination_error =
+      state_rc.borrow().termination_error.lock().unwrap().take();
+    if let Some(err) = termination_error {
+      let class = state_rc.borrow().op_state.borrow().get_error_class(&err);
+      exception = error_to_exception(scope, class, &err.to_string());
+    } else if termination_reason == Some(TerminationReason::MemoryLimit) {
+      let message =
+        v8::String::new(scope, "isolate memory limit exceeded").unwrap();
+      exception = v8::Exception::range_error(scope, message);
+    } else if let Some(termination_message) = termination_message {
+      let message = v8::String::new(scope, &termination_message).unwrap();
+      exception = v8::Exception::error(scope, message);
+    } else if let Some(reason) = &termination_reason {
+      let message = format!("execution terminated: {}", reason);
+      let message = v8::String::new(scope, &message).unwrap();
+      exception = v8::Exception::error(scope, message);
+    } else if exception.is_null_or_undefined() {
+      // Maybe make a new exception object.
       let message = v8::String::new(scope, "execution terminated").unwrap();
       exception = v8::Exception::error(scope, message);
     }
@@ -924,10 +2825,23 @@ pub(crate) fn exception_to_err_result<'s, T>(
       js_error.message.trim_start_matches("Uncaught ")
     );
   }
+  if let Some(promise) = promise {
+    js_error.promise_creation_frames =
+      bindings::get_promise_creation_frames(scope, promise);
+  }
 
   let state_rc = JsRuntime::state(scope);
-  let state = state_rc.borrow();
+  let mut state = state_rc.borrow_mut();
+  state.last_exception = Some(v8::Global::new(scope, exception));
   let js_error = (state.js_error_create_fn)(js_error);
+  let js_error = match termination_reason {
+    Some(reason) => ExecutionTerminated {
+      reason,
+      error: js_error,
+    }
+    .into(),
+    None => js_error,
+  };
 
   if is_terminating_exception {
     // Re-enable exception termination.
@@ -937,6 +2851,47 @@ pub(crate) fn exception_to_err_result<'s, T>(
   Err(js_error)
 }
 
+/// Creates an exception of the JS error class `class`, using the built-in
+/// constructor if there is one and setting `name` otherwise.
+fn error_to_exception<'s>(
+  scope: &mut v8::HandleScope<'s>,
+  class: &str,
+  message: &str,
+) -> v8::Local<'s, v8::Value> {
+  let message = v8::String::new(scope, message).unwrap();
+  match class {
+    "TypeError" => v8::Exception::type_error(scope, message),
+    "RangeError" => v8::Exception::range_error(scope, message),
+    "ReferenceError" => v8::Exception::reference_error(scope, message),
+    "SyntaxError" => v8::Exception::syntax_error(scope, message),
+    _ => {
+      let exception = v8::Exception::error(scope, message);
+      if class != "Error" {
+        let object = v8::Local::<v8::Object>::try_from(exception).unwrap();
+        let key = v8::String::new(scope, "name").unwrap();
+        let name = v8::String::new(scope, class).unwrap();
+        object.set(scope, key.into(), name.into());
+      }
+      exception
+    }
+  }
+}
+
+/// Like `exception_to_err_result()`, for an exception thrown while compiling
+/// a script or module. The error is a `CompileError` carrying a
+/// `CompileDiagnostic`.
+pub(crate) fn compile_exception_to_err_result<'s, T>(
+  scope: &mut v8::HandleScope<'s>,
+  exception: v8::Local<v8::Value>,
+) -> Result<T, Error> {
+  if scope.is_execution_terminating() {
+    return exception_to_err_result(scope, exception, false);
+  }
+  let diagnostic = CompileDiagnostic::from_v8_exception(scope, exception);
+  exception_to_err_result(scope, exception, false)
+    .map_err(|error| CompileError { diagnostic, error }.into())
+}
+
 // Related to module loading
 impl JsRuntime {
   pub(crate) fn instantiate_module(
@@ -963,6 +2918,7 @@ impl JsRuntime {
     // IMPORTANT: No borrows to `ModuleMap` can be held at this point because
     // `module_resolve_callback` will be calling into `ModuleMap` from within
     // the isolate.
+    let instantiate_start = Instant::now();
     let instantiate_result =
       module.instantiate_module(tc_scope, bindings::module_resolve_callback);
 
@@ -973,6 +2929,10 @@ impl JsRuntime {
       return err;
     }
 
+    module_map_rc.borrow().emit(ModuleLoadEvent::Instantiate {
+      id,
+      duration: instantiate_start.elapsed(),
+    });
     Ok(())
   }
 
@@ -1011,6 +2971,9 @@ impl JsRuntime {
     // For more details see:
     // https://github.com/denoland/deno/issues/4908
     // https://v8.dev/features/top-level-await#module-execution-order
+    module_map_rc
+      .borrow()
+      .emit(ModuleLoadEvent::Evaluate { id });
     let scope = &mut self.handle_scope();
     let tc_scope = &mut v8::TryCatch::new(scope);
     let module = v8::Local::new(tc_scope, &module_handle);
@@ -1102,6 +3065,9 @@ impl JsRuntime {
     // For more details see:
     // https://github.com/denoland/deno/issues/4908
     // https://v8.dev/features/top-level-await#module-execution-order
+    module_map_rc
+      .borrow()
+      .emit(ModuleLoadEvent::Evaluate { id });
     let maybe_value = module.evaluate(tc_scope);
 
     // Update status after evaluating.
@@ -1116,7 +3082,9 @@ impl JsRuntime {
         .expect("Expected to get promise as module evaluation result");
       let promise_global = v8::Global::new(tc_scope, promise);
       let mut state = state_rc.borrow_mut();
-      state.pending_promise_exceptions.remove(&promise_global);
+      state
+        .pending_promise_exceptions
+        .shift_remove(&promise_global);
       let promise_global = v8::Global::new(tc_scope, promise);
       assert!(
         state.pending_mod_evaluate.is_none(),
@@ -1124,6 +3092,7 @@ impl JsRuntime {
       );
 
       state.pending_mod_evaluate = Some(ModEvaluate {
+        module_id: id,
         promise: promise_global,
         sender,
       });
@@ -1140,6 +3109,7 @@ impl JsRuntime {
   }
 
   fn dynamic_import_reject(&mut self, id: ModuleLoadId, err: Error) {
+    let state_rc = Self::state(self.v8_isolate());
     let module_map_rc = Self::module_map(self.v8_isolate());
     let scope = &mut self.handle_scope();
 
@@ -1148,15 +3118,38 @@ impl JsRuntime {
       .dynamic_import_map
       .remove(&id)
       .expect("Invalid dynamic import id");
+    module_map_rc
+      .borrow_mut()
+      .dynamic_import_specifiers
+      .remove(&id);
     let resolver = resolver_handle.open(scope);
 
     let exception = err
       .downcast_ref::<ErrWithV8Handle>()
       .map(|err| err.get_handle(scope))
       .unwrap_or_else(|| {
-        let message = err.to_string();
-        let message = v8::String::new(scope, &message).unwrap();
-        v8::Exception::type_error(scope, message)
+        let op_state = state_rc.borrow().op_state.clone();
+        let class_name = op_state.borrow().get_mapped_error_class(&err);
+        // Include the whole context chain, eg. which module failed to load
+        // while loading which importer.
+        let message = format!("{:#}", err);
+        match class_name {
+          Some(class_name) => {
+            let build_cb =
+              state_rc.borrow().js_build_custom_error_cb.clone().unwrap();
+            let build_cb = build_cb.open(scope);
+            let this = v8::undefined(scope).into();
+            let class_name = v8::String::new(scope, class_name).unwrap();
+            let message = v8::String::new(scope, &message).unwrap();
+            build_cb
+              .call(scope, this, &[class_name.into(), message.into()])
+              .unwrap()
+          }
+          None => {
+            let message = v8::String::new(scope, &message).unwrap();
+            v8::Exception::type_error(scope, message)
+          }
+        }
       });
 
     // IMPORTANT: No borrows to `ModuleMap` can be held at this point because
@@ -1177,6 +3170,10 @@ impl JsRuntime {
       .dynamic_import_map
       .remove(&id)
       .expect("Invalid dynamic import id");
+    module_map_rc
+      .borrow_mut()
+      .dynamic_import_specifiers
+      .remove(&id);
     let resolver = resolver_handle.open(scope);
 
     let module = {
@@ -1403,6 +3400,27 @@ impl JsRuntime {
     state_rc.borrow_mut().pending_dyn_mod_evaluate = still_pending;
   }
 
+  /// Compiles a module from a source held in UTF-8, UTF-16 or one-byte
+  /// (Latin-1) encoding, without loading its dependencies. Pass the same
+  /// specifier and no code to `load_main_module()` or `load_side_module()`
+  /// afterwards to load the dependencies and instantiate it. Only UTF-8
+  /// sources are transpiled and use `RuntimeOptions::code_cache_store`.
+  pub fn new_module_from_source(
+    &mut self,
+    specifier: &ModuleSpecifier,
+    main: bool,
+    source: ScriptSource,
+  ) -> Result<ModuleId, Error> {
+    let module_map_rc = Self::module_map(self.v8_isolate());
+    let result = module_map_rc.borrow_mut().new_module_from_source(
+      &mut self.handle_scope(),
+      main,
+      specifier.as_str(),
+      source,
+    );
+    result
+  }
+
   /// Asynchronously load specified module and all of its dependencies.
   ///
   /// The module will be marked as "main", and because of that
@@ -1477,65 +3495,192 @@ impl JsRuntime {
     Ok(root_id)
   }
 
+  /// Executes `source_code` as an ES module, so that unlike with
+  /// `execute_script()` it can use top-level `await` and import other
+  /// modules. Resolves with the module's id once its evaluation has
+  /// completed, running the event loop meanwhile.
+  ///
+  /// `name` is the module's URL, or a path resolved against the current
+  /// directory, see `resolve_url_or_path()`.
+  pub async fn execute_as_module(
+    &mut self,
+    name: &str,
+    source_code: &str,
+  ) -> Result<ModuleId, Error> {
+    let specifier = resolve_url_or_path(name)?;
+    let id = self
+      .load_side_module(&specifier, Some(source_code.to_string()))
+      .await?;
+    let receiver = self.mod_evaluate(id);
+    self.wait_for_mod_evaluation(receiver).await?;
+    Ok(id)
+  }
+
+  /// Runs the event loop until the evaluation started by `mod_evaluate()`
+  /// has completed.
+  pub(crate) async fn wait_for_mod_evaluation(
+    &mut self,
+    mut receiver: oneshot::Receiver<Result<(), Error>>,
+  ) -> Result<(), Error> {
+    poll_fn(|cx| {
+      let event_loop = self.poll_event_loop(cx, false);
+      if let Poll::Ready(result) = receiver.poll_unpin(cx) {
+        let result =
+          result.unwrap_or_else(|_| Err(generic_error("Evaluation canceled")));
+        return Poll::Ready(result);
+      }
+      match event_loop {
+        Poll::Ready(Ok(())) => Poll::Ready(Err(generic_error(
+          "Module evaluation is still pending but the event loop has already resolved.",
+        ))),
+        Poll::Ready(Err(err)) => Poll::Ready(Err(err)),
+        Poll::Pending => Poll::Pending,
+      }
+    })
+    .await
+  }
+
+  /// Surfaces all promises rejected without a handler, in the order they were
+  /// rejected. A single rejection is returned as is; several are combined
+  /// into an `UnhandledRejections` error.
   fn check_promise_exceptions(&mut self) -> Result<(), Error> {
     let state_rc = Self::state(self.v8_isolate());
-    let mut state = state_rc.borrow_mut();
+    let mut errors = Vec::new();
 
-    if state.pending_promise_exceptions.is_empty() {
-      return Ok(());
-    }
+    loop {
+      let mut state = state_rc.borrow_mut();
+      let (key, handle) =
+        match state.pending_promise_exceptions.shift_remove_index(0) {
+          Some(entry) => entry,
+          None => break,
+        };
+      let js_unhandled_rejection_cb = state.js_unhandled_rejection_cb.clone();
+      let unhandled_rejection_fn = state.unhandled_rejection_fn.clone();
+      drop(state);
 
-    let key = {
-      state
-        .pending_promise_exceptions
-        .keys()
-        .next()
-        .unwrap()
-        .clone()
-    };
-    let handle = state.pending_promise_exceptions.remove(&key).unwrap();
-    drop(state);
+      let scope = &mut self.handle_scope();
+      let promise = v8::Local::new(scope, key);
+      let exception = v8::Local::new(scope, handle);
+
+      if let Some(js_unhandled_rejection_cb) = js_unhandled_rejection_cb {
+        let tc_scope = &mut v8::TryCatch::new(scope);
+        let this = v8::undefined(tc_scope).into();
+        let handled = js_unhandled_rejection_cb.open(tc_scope).call(
+          tc_scope,
+          this,
+          &[promise.into(), exception],
+        );
+        // An exception thrown by the handler replaces the rejection.
+        if let Some(exception) = tc_scope.exception() {
+          let err = exception_to_err_result::<()>(tc_scope, exception, false)
+            .unwrap_err();
+          if tc_scope.is_execution_terminating() {
+            return Err(err);
+          }
+          errors.push(err);
+          continue;
+        }
+        if handled.map(|value| value.is_true()).unwrap_or(false) {
+          continue;
+        }
+      }
 
-    let scope = &mut self.handle_scope();
-    let exception = v8::Local::new(scope, handle);
-    exception_to_err_result(scope, exception, true)
+      if let Some(unhandled_rejection_fn) = unhandled_rejection_fn {
+        let js_error = JsError::from_v8_exception(scope, exception);
+        if unhandled_rejection_fn(&js_error) {
+          continue;
+        }
+      }
+
+      let has_uncaught_exception_cb =
+        state_rc.borrow().js_uncaught_exception_cb.is_some();
+      if has_uncaught_exception_cb {
+        let tc_scope = &mut v8::TryCatch::new(scope);
+        if let Err(err) = uncaught_exception_to_err_result(tc_scope, exception)
+        {
+          errors.push(err);
+        }
+        continue;
+      }
+
+      errors.push(
+        promise_exception_to_err_result::<()>(scope, exception, promise)
+          .unwrap_err(),
+      );
+    }
+
+    match errors.len() {
+      0 => Ok(()),
+      1 => Err(errors.pop().unwrap()),
+      _ => Err(UnhandledRejections(errors).into()),
+    }
   }
 
   // Send finished responses to JS
   fn resolve_async_ops(&mut self, cx: &mut Context) -> Result<(), Error> {
     let state_rc = Self::state(self.v8_isolate());
 
-    let js_recv_cb_handle = state_rc.borrow().js_recv_cb.clone().unwrap();
-    let scope = &mut self.handle_scope();
-
-    // We return async responses to JS in unbounded batches (may change),
-    // each batch is a flat vector of tuples:
-    // `[promise_id1, op_result1, promise_id2, op_result2, ...]`
-    // promise_id is a simple integer, op_result is an ops::OpResult
-    // which contains a value OR an error, encoded as a tuple.
-    // This batch is received in JS via the special `arguments` variable
-    // and then each tuple is used to resolve or reject promises
-    let mut args: Vec<v8::Local<v8::Value>> = vec![];
-
-    // Now handle actual ops.
-    {
+    // Poll the op futures under a single borrow of the state, and don't call
+    // into V8 while it's held.
+    let (completed, js_recv_cb_handle, op_state) = {
       let mut state = state_rc.borrow_mut();
       state.have_unpolled_ops = false;
 
-      let op_state = state.op_state.clone();
-
+      let mut completed = vec![];
       while let Poll::Ready(Some(item)) = state.pending_ops.poll_next_unpin(cx)
       {
-        let (promise_id, op_id, resp) = item;
-        op_state.borrow().tracker.track_async_completed(op_id);
+        let promise_id = item.0;
         state.unrefed_ops.remove(&promise_id);
-        args.push(v8::Integer::new(scope, promise_id as i32).into());
-        args.push(resp.to_v8(scope).unwrap());
+        state.pending_op_ids.remove(&promise_id);
+        state.op_cancel_handles.remove(&promise_id);
+        completed.push(item);
+        if state.check_poll_budget() {
+          break;
+        }
+      }
+
+      // Start polling queued ops now that some pending ops may have completed.
+      while !state.queued_ops.is_empty()
+        && state
+          .max_pending_ops
+          .map_or(true, |max| state.pending_ops.len() < max)
+      {
+        let fut = state.queued_ops.pop_front().unwrap();
+        state.pending_ops.push(fut);
+        state.have_unpolled_ops = true;
+      }
+
+      if completed.is_empty() {
+        return Ok(());
+      }
+      let js_recv_cb_handle = state.js_recv_cb.clone().unwrap();
+      (completed, js_recv_cb_handle, state.op_state.clone())
+    };
+
+    {
+      let mut op_state = op_state.borrow_mut();
+      for (promise_id, op_id, _) in &completed {
+        trace_event!(promise_id, op_id, "async op completed");
+        op_state.tracker.track_async_completed(*op_id);
+        if let Some(sanitizer) = op_state.sanitizer.as_mut() {
+          sanitizer.complete_op(*promise_id);
+        }
       }
     }
 
-    if args.is_empty() {
-      return Ok(());
+    // We return async responses to JS in unbounded batches (may change),
+    // each batch is a flat vector of tuples:
+    // `[promise_id1, op_result1, promise_id2, op_result2, ...]`
+    // promise_id is a simple integer, op_result is an ops::OpResult
+    // which contains a value OR an error, encoded as a tuple.
+    // This batch is received in JS via the special `arguments` variable
+    // and then each tuple is used to resolve or reject promises
+    let scope = &mut self.handle_scope();
+    let mut args: Vec<v8::Local<v8::Value>> =
+      Vec::with_capacity(completed.len() * 2);
+    for (promise_id, _, resp) in completed {
+      args.push(v8::Integer::new(scope, promise_id as i32).into());
+      args.push(resp.to_v8(scope).unwrap());
     }
 
     let tc_scope = &mut v8::TryCatch::new(scope);
@@ -1545,18 +3690,71 @@ impl JsRuntime {
 
     match tc_scope.exception() {
       None => Ok(()),
-      Some(exception) => exception_to_err_result(tc_scope, exception, false),
+      Some(exception) => uncaught_exception_to_err_result(tc_scope, exception),
+    }
+  }
+
+  fn resolve_promise_futures(&mut self, cx: &mut Context) -> Result<(), Error> {
+    let state_rc = Self::state(self.v8_isolate());
+    let scope = &mut self.handle_scope();
+
+    let mut settled = false;
+    loop {
+      let (resolver, result) = {
+        let mut state = state_rc.borrow_mut();
+        match state.pending_promise_futures.poll_next_unpin(cx) {
+          Poll::Ready(Some(item)) => item,
+          _ => break,
+        }
+      };
+      let resolver = resolver.open(scope);
+      match result {
+        OpResult::Ok(value) => {
+          let value = value.to_v8(scope)?;
+          resolver.resolve(scope, value).unwrap();
+        }
+        result @ (OpResult::Scalar(_) | OpResult::Scratch) => {
+          let value = result.to_v8(scope)?;
+          resolver.resolve(scope, value).unwrap();
+        }
+        OpResult::Err(err) => {
+          let build_cb =
+            state_rc.borrow().js_build_custom_error_cb.clone().unwrap();
+          let build_cb = build_cb.open(scope);
+          let this = v8::undefined(scope).into();
+          let class_name = v8::String::new(scope, err.class_name).unwrap();
+          let message = v8::String::new(scope, &err.message).unwrap();
+          let code = match err.code {
+            Some(code) => v8::String::new(scope, code).unwrap().into(),
+            None => v8::undefined(scope).into(),
+          };
+          let properties = serde_v8::to_v8(scope, &err.properties)?;
+          let exception = build_cb
+            .call(
+              scope,
+              this,
+              &[class_name.into(), message.into(), code, properties],
+            )
+            .unwrap();
+          resolver.reject(scope, exception).unwrap();
+        }
+      }
+      settled = true;
+    }
+
+    if settled {
+      scope.perform_microtask_checkpoint();
     }
+    Ok(())
   }
 
   fn drain_macrotasks(&mut self) -> Result<(), Error> {
     let state = Self::state(self.v8_isolate());
-
-    if state.borrow().js_macrotask_cbs.is_empty() {
+    let js_macrotask_cb_handles = state.borrow().js_macrotask_cbs.clone();
+    if js_macrotask_cb_handles.is_empty() {
       return Ok(());
     }
 
-    let js_macrotask_cb_handles = state.borrow().js_macrotask_cbs.clone();
     let scope = &mut self.handle_scope();
 
     for js_macrotask_cb_handle in js_macrotask_cb_handles {
@@ -1571,7 +3769,9 @@ impl JsRuntime {
         let is_done = js_macrotask_cb.call(tc_scope, this, &[]);
 
         if let Some(exception) = tc_scope.exception() {
-          return exception_to_err_result(tc_scope, exception, false);
+          uncaught_exception_to_err_result(tc_scope, exception)?;
+          // The exception was handled; move on to the next callback.
+          break;
         }
 
         if tc_scope.has_terminated() || tc_scope.is_execution_terminating() {
@@ -1582,12 +3782,32 @@ impl JsRuntime {
         if is_done.is_true() {
           break;
         }
+
+        if state.borrow_mut().check_poll_budget() {
+          return Ok(());
+        }
       }
     }
 
     Ok(())
   }
 
+  fn run_queued_macrotasks(&mut self) -> Result<(), Error> {
+    let state_rc = Self::state(self.v8_isolate());
+    let mut count = state_rc.borrow().macrotasks.len();
+    while count > 0 {
+      if state_rc.borrow().poll_budget_exhausted {
+        break;
+      }
+      let macrotask = state_rc.borrow_mut().macrotasks.pop_front().unwrap();
+      count -= 1;
+      macrotask(self)?;
+      self.handle_scope().perform_microtask_checkpoint();
+      state_rc.borrow_mut().check_poll_budget();
+    }
+    Ok(())
+  }
+
   fn drain_nexttick(&mut self) -> Result<(), Error> {
     let state = Self::state(self.v8_isolate());
 
@@ -1616,7 +3836,7 @@ impl JsRuntime {
       js_nexttick_cb.call(tc_scope, this, &[]);
 
       if let Some(exception) = tc_scope.exception() {
-        return exception_to_err_result(tc_scope, exception, false);
+        uncaught_exception_to_err_result(tc_scope, exception)?;
       }
     }
 
@@ -1632,21 +3852,16 @@ pub mod tests {
   use crate::modules::ModuleSourceFuture;
   use crate::op_async;
   use crate::op_sync;
+  use crate::test_util::run_in_task;
+  use crate::OpMetadata;
+  use crate::SlowOp;
   use crate::ZeroCopyBuf;
-  use futures::future::lazy;
   use std::ops::FnOnce;
   use std::pin::Pin;
   use std::rc::Rc;
-  use std::sync::atomic::{AtomicUsize, Ordering};
+  use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
   use std::sync::Arc;
 
-  pub fn run_in_task<F>(f: F)
-  where
-    F: FnOnce(&mut Context) + Send + 'static,
-  {
-    futures::executor::block_on(lazy(move |cx| f(cx)));
-  }
-
   enum Mode {
     Async,
     AsyncZeroCopy(bool),
@@ -1798,8 +4013,558 @@ pub mod tests {
     }
   }
 
-  #[test]
-  fn test_dispatch_no_zero_copy_buf() {
+  fn setup_with_max_pending_ops(
+    overflow: PendingOpsOverflow,
+  ) -> (JsRuntime, Arc<AtomicUsize>) {
+    let dispatch_count = Arc::new(AtomicUsize::new(0));
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      max_pending_ops: Some(1),
+      pending_ops_overflow: overflow,
+      ..Default::default()
+    });
+    runtime.op_state().borrow_mut().put(TestState {
+      mode: Mode::Async,
+      dispatch_count: dispatch_count.clone(),
+    });
+    runtime.register_op("op_test", dispatch);
+    runtime.sync_ops_cache();
+    (runtime, dispatch_count)
+  }
+
+  #[test]
+  fn test_max_pending_ops_throw() {
+    let (mut runtime, dispatch_count) =
+      setup_with_max_pending_ops(PendingOpsOverflow::Throw);
+    runtime
+      .execute_script(
+        "filename.js",
+        r#"
+        Deno.core.opAsync("op_test", 42);
+        let thrown;
+        try {
+          Deno.core.opAsync("op_test", 42);
+        } catch (e) {
+          thrown = e;
+        }
+        if (!(thrown instanceof RangeError)) {
+          throw new Error("expected RangeError");
+        }
+        "#,
+      )
+      .unwrap();
+    assert_eq!(dispatch_count.load(Ordering::Relaxed), 1);
+  }
+
+  #[test]
+  fn test_max_pending_ops_queue() {
+    run_in_task(|cx| {
+      let (mut runtime, dispatch_count) =
+        setup_with_max_pending_ops(PendingOpsOverflow::Queue);
+      runtime
+        .execute_script(
+          "filename.js",
+          r#"
+          Deno.core.opAsync("op_test", 42);
+          Deno.core.opAsync("op_test", 42);
+          "#,
+        )
+        .unwrap();
+      assert_eq!(dispatch_count.load(Ordering::Relaxed), 2);
+      {
+        let state_rc = JsRuntime::state(runtime.v8_isolate());
+        let state = state_rc.borrow();
+        assert_eq!(state.pending_ops.len(), 1);
+        assert_eq!(state.queued_ops.len(), 1);
+      }
+      while runtime.poll_event_loop(cx, false).is_pending() {}
+      let state_rc = JsRuntime::state(runtime.v8_isolate());
+      let state = state_rc.borrow();
+      assert_eq!(state.pending_ops.len(), 0);
+      assert_eq!(state.queued_ops.len(), 0);
+    });
+  }
+
+  #[test]
+  fn test_stats() {
+    let (mut runtime, _dispatch_count) =
+      setup_with_max_pending_ops(PendingOpsOverflow::Queue);
+    let stats = runtime.stats();
+    assert_eq!(stats.pending_refed_ops, 0);
+    assert_eq!(stats.module_count, 0);
+    assert!(stats.heap_used > 0);
+    assert!(stats.heap_limit >= stats.heap_used);
+    runtime
+      .execute_script(
+        "filename.js",
+        r#"
+        Deno.core.opAsync("op_test", 42);
+        const p = Deno.core.opAsync("op_test", 42);
+        Deno.core.unrefOp(p[Symbol.for("Deno.core.internalPromiseId")]);
+        "#,
+      )
+      .unwrap();
+    let stats = runtime.stats();
+    assert_eq!(stats.pending_refed_ops, 1);
+    assert_eq!(stats.pending_unrefed_ops, 1);
+    assert_eq!(stats.queued_ops, 1);
+    assert_eq!(stats.max_pending_ops, Some(1));
+    assert_eq!(stats.pending_dynamic_imports, 0);
+  }
+
+  #[test]
+  fn test_dump_pending_state() {
+    let (mut runtime, _dispatch_count) =
+      setup_with_max_pending_ops(PendingOpsOverflow::Queue);
+    runtime
+      .execute_script(
+        "filename.js",
+        r#"
+        Deno.core.opAsync("op_test", 42);
+        const p = Deno.core.opAsync("op_test", 42);
+        Deno.core.unrefOp(p[Symbol.for("Deno.core.internalPromiseId")]);
+        "#,
+      )
+      .unwrap();
+    let dump = runtime.dump_pending_state();
+    assert_eq!(dump.ops.len(), 2);
+    assert!(dump.ops.iter().all(|op| op.op_name == "op_test"));
+    assert!(dump.ops[0].refed);
+    assert!(!dump.ops[1].refed);
+    assert!(dump.dynamic_imports.is_empty());
+    assert!(dump.module_evaluations.is_empty());
+    assert!(dump.to_string().starts_with("Pending ops: 2\n"));
+  }
+
+  #[test]
+  fn test_sanitizer() {
+    struct LeakedResource;
+    impl crate::Resource for LeakedResource {
+      fn name(&self) -> std::borrow::Cow<str> {
+        "leakedResource".into()
+      }
+    }
+
+    run_in_task(|cx| {
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        sanitizer: true,
+        ..Default::default()
+      });
+      runtime.register_op(
+        "op_open",
+        op_sync(|state, _: (), _: ()| {
+          Ok(state.resource_table.add(LeakedResource))
+        }),
+      );
+      runtime.register_op(
+        "op_never",
+        op_async(|_state, _: (), _: ()| {
+          futures::future::pending::<Result<(), Error>>()
+        }),
+      );
+      runtime.sync_ops_cache();
+      runtime
+        .execute_script(
+          "sanitizer.js",
+          r#"
+          function openResource() {
+            return Deno.core.opSync("op_open");
+          }
+          function startOp() {
+            const p = Deno.core.opAsync("op_never");
+            Deno.core.unrefOp(p[Symbol.for("Deno.core.internalPromiseId")]);
+          }
+          const rid = openResource();
+          startOp();
+          "#,
+        )
+        .unwrap();
+      let err = match runtime.poll_event_loop(cx, false) {
+        Poll::Ready(Err(err)) => err.to_string(),
+        _ => panic!("expected the sanitizer to fail the event loop"),
+      };
+      assert!(err.contains("Leaking async ops:\n  - op_never"));
+      assert!(err.contains("at startOp (sanitizer.js:"));
+      assert!(err.contains("Leaking resources:\n  - \"leakedResource\""));
+      assert!(err.contains("at openResource (sanitizer.js:"));
+
+      // Resources closed before the event loop completes aren't reported.
+      runtime
+        .execute_script("close.js", "Deno.core.close(rid);")
+        .unwrap();
+      let err = match runtime.poll_event_loop(cx, false) {
+        Poll::Ready(Err(err)) => err.to_string(),
+        _ => panic!("expected the sanitizer to fail the event loop"),
+      };
+      assert!(!err.contains("Leaking resources"));
+    });
+  }
+
+  #[test]
+  fn test_slow_op_detector() {
+    run_in_task(|cx| {
+      let slow_ops = Rc::new(RefCell::new(Vec::new()));
+      let slow_ops_ = slow_ops.clone();
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        slow_op_detector: Some(SlowOpDetector {
+          sync_threshold: Duration::from_millis(2),
+          async_threshold: Duration::from_millis(2),
+          callback: Rc::new(move |op: &SlowOp| {
+            assert!(op.duration >= Duration::from_millis(5));
+            slow_ops_
+              .borrow_mut()
+              .push((op.op_name.to_string(), op.is_async));
+          }),
+        }),
+        ..Default::default()
+      });
+      runtime.register_op("op_fast", op_sync(|_, _: (), _: ()| Ok(())));
+      runtime.register_op(
+        "op_slow_sync",
+        op_sync(|_, _: (), _: ()| {
+          std::thread::sleep(Duration::from_millis(5));
+          Ok(())
+        }),
+      );
+      runtime.register_op(
+        "op_slow_async",
+        op_async(|_, _: (), _: ()| async {
+          std::thread::sleep(Duration::from_millis(5));
+          Ok(())
+        }),
+      );
+      runtime.sync_ops_cache();
+      runtime
+        .execute_script(
+          "slow_ops.js",
+          r#"
+          Deno.core.opSync("op_fast");
+          Deno.core.opSync("op_slow_sync");
+          Deno.core.opAsync("op_slow_async");
+          "#,
+        )
+        .unwrap();
+      assert!(matches!(
+        runtime.poll_event_loop(cx, false),
+        Poll::Ready(Ok(()))
+      ));
+      assert_eq!(
+        *slow_ops.borrow(),
+        vec![
+          ("op_slow_sync".to_string(), false),
+          ("op_slow_async".to_string(), true)
+        ]
+      );
+    });
+  }
+
+  #[test]
+  fn test_eval_context_origin() {
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "eval_context.js",
+        r#"
+        const [result, noError] = Deno.core.evalContext("1 + 1", {
+          filename: "file:///repl.js",
+        });
+        if (result !== 2 || noError !== null) {
+          throw new Error("unexpected result");
+        }
+        const [noResult, errInfo] = Deno.core.evalContext(
+          "\nthrow new Error('boom')",
+          { filename: "file:///repl.js", lineOffset: 10, columnOffset: 4 },
+        );
+        if (noResult !== null || errInfo.isCompileError) {
+          throw new Error("expected a runtime error");
+        }
+        if (!errInfo.thrown.stack.includes("file:///repl.js:12:7")) {
+          throw new Error(errInfo.thrown.stack);
+        }
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[tokio::test]
+  async fn test_close_on_error() {
+    struct TestResource;
+    impl crate::Resource for TestResource {}
+
+    let mut runtime = JsRuntime::new(Default::default());
+    let (rid1, rid2) = {
+      let op_state = runtime.op_state();
+      let resource_table = &mut op_state.borrow_mut().resource_table;
+      (
+        resource_table.add(TestResource),
+        resource_table.add(TestResource),
+      )
+    };
+    runtime
+      .execute_script(
+        "close_on_error.js",
+        &format!(
+          r#"
+          try {{
+            Deno.core.closeOnError({rid1}, () => {{
+              throw new Error("sync");
+            }});
+          }} catch {{}}
+          // Reading fails because the resource doesn't implement read().
+          Deno.core.closeOnError({rid2}, (rid) => {{
+            return Deno.core.read(rid, new Uint8Array(1));
+          }}).catch(() => {{}});
+          "#,
+          rid1 = rid1,
+          rid2 = rid2
+        ),
+      )
+      .unwrap();
+    runtime.run_event_loop(false).await.unwrap();
+    let op_state = runtime.op_state();
+    let resource_table = &op_state.borrow().resource_table;
+    assert!(!resource_table.has(rid1));
+    assert!(!resource_table.has(rid2));
+  }
+
+  #[test]
+  fn test_op_metadata() {
+    let ext = Extension::builder()
+      .ops(vec![("op_test", op_sync(|_, _: (), _: ()| Ok(())))])
+      .op_metadata(vec![(
+        "op_test",
+        OpMetadata {
+          is_async: false,
+          args: vec!["path: string"],
+          permission: Some("read"),
+        },
+      )])
+      .build();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      extensions: vec![ext],
+      ..Default::default()
+    });
+    let op_id = runtime
+      .op_state()
+      .borrow()
+      .op_table
+      .op_infos()
+      .into_iter()
+      .find(|info| info.name == "op_test")
+      .unwrap()
+      .id;
+    assert_eq!(
+      runtime
+        .op_state()
+        .borrow()
+        .op_table
+        .op_metadata(op_id)
+        .unwrap()
+        .permission,
+      Some("read")
+    );
+    runtime
+      .execute_script(
+        "op_metadata.js",
+        r#"
+        const infos = Deno.core.opMetadata();
+        const test = infos.find((info) => info.name === "op_test");
+        if (
+          test.metadata.isAsync !== false ||
+          test.metadata.args[0] !== "path: string" ||
+          test.metadata.permission !== "read"
+        ) {
+          throw new Error(JSON.stringify(test));
+        }
+        const read = infos.find((info) => info.name === "op_read");
+        if (!read.metadata.isAsync || read.id !== Deno.core.ops().op_read) {
+          throw new Error(JSON.stringify(read));
+        }
+        if (infos[0].metadata !== null) {
+          throw new Error("expected no metadata for the catalog op");
+        }
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn test_eval_expression() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let mut eval = |source| runtime.eval_expression(source).unwrap();
+    assert_eq!(eval("1 + 1"), "2");
+    assert_eq!(eval("_ * 3"), "6");
+    assert_eq!(eval("{ a: 1, 'b-c': 'x' }"), r#"{ a: 1, "b-c": "x" }"#);
+    assert_eq!(eval("{ let x = 1; x + 1 }"), "2");
+    assert_eq!(
+      eval("[1, [2, [3]], new Map([[1, -0]]), new Set(['a'])]"),
+      r#"[ 1, [ 2, [ 3 ] ], Map(1) { 1 => -0 }, Set(1) { "a" } ]"#
+    );
+    assert_eq!(
+      eval("const o = { n: 10n }; o.self = o; o"),
+      "{ n: 10n, self: [Circular] }"
+    );
+    assert_eq!(
+      eval("[Symbol('s'), function foo() {}, () => {}, undefined, null]"),
+      "[ Symbol(s), [Function: foo], [Function (anonymous)], undefined, null ]"
+    );
+    assert_eq!(
+      eval("class Point { x = 1 }; [new Point(), new Uint8Array([1, 2])]"),
+      "[ Point { x: 1 }, Uint8Array(2) [ 1, 2 ] ]"
+    );
+    assert_eq!(eval("Promise.resolve({})"), "Promise { {} }");
+    assert_eq!(
+      eval("({ a: { b: { c: { d: { e: 1 } } } } })"),
+      "{ a: { b: { c: { d: [Object] } } } }"
+    );
+
+    let err = runtime
+      .eval_expression("throw new Error('boom')")
+      .unwrap_err();
+    assert!(err.to_string().contains("boom"));
+    assert_eq!(
+      runtime.eval_expression("_error.message").unwrap(),
+      r#""boom""#
+    );
+    assert!(runtime.eval_expression("1 +").is_err());
+  }
+
+  #[test]
+  fn test_completions() {
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "completions.js",
+        r#"
+        globalThis.myObject = {
+          first: 1,
+          fn() {},
+          get lazy() { throw new Error("getter called"); },
+        };
+        "#,
+      )
+      .unwrap();
+    let names = |completions: Vec<Completion>| {
+      completions
+        .into_iter()
+        .map(|c| (c.name, c.kind))
+        .collect::<Vec<_>>()
+    };
+    let kind = |name: &str, kind: &str| (name.to_string(), kind.to_string());
+
+    assert_eq!(
+      names(runtime.completions("myObj")),
+      vec![kind("myObject", "object")]
+    );
+    assert_eq!(
+      names(runtime.completions("foo(myObject.f")),
+      vec![kind("first", "number"), kind("fn", "function")]
+    );
+    assert!(runtime.completions("myObject.").contains(&Completion {
+      name: "lazy".to_string(),
+      kind: "accessor".to_string(),
+    }));
+    // Inherited and non-enumerable properties are included.
+    assert!(names(runtime.completions("myObject.hasOwn"))
+      .contains(&kind("hasOwnProperty", "function")));
+    assert!(names(runtime.completions("Math.fl"))
+      .contains(&kind("floor", "function")));
+    assert!(runtime.completions("missing.a").is_empty());
+    assert!(runtime.completions("f().a").is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_finalizers() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let finalized = Rc::new(RefCell::new(Vec::new()));
+    let (collected_id, alive_id, removed_id) = {
+      let op_state = runtime.op_state();
+      let mut op_state = op_state.borrow_mut();
+      let mut add = |name: &'static str| {
+        let finalized = finalized.clone();
+        op_state.add_finalizer(move |_| finalized.borrow_mut().push(name))
+      };
+      (add("collected"), add("alive"), add("removed"))
+    };
+    runtime
+      .execute_script(
+        "finalizers.js",
+        &format!(
+          r#"
+          Deno.core.registerFinalizer({{}}, {collected_id});
+          globalThis.alive = {{}};
+          Deno.core.registerFinalizer(globalThis.alive, {alive_id});
+          Deno.core.registerFinalizer({{}}, {removed_id});
+          "#,
+          collected_id = collected_id,
+          alive_id = alive_id,
+          removed_id = removed_id
+        ),
+      )
+      .unwrap();
+    assert!(runtime.op_state().borrow_mut().remove_finalizer(removed_id));
+    runtime.v8_isolate().low_memory_notification();
+    runtime.run_event_loop(false).await.unwrap();
+    assert_eq!(*finalized.borrow(), vec!["collected"]);
+  }
+
+  #[test]
+  fn test_memory_usage() {
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "memory_usage.js",
+        r#"
+        const before = Deno.core.memoryUsage();
+        if (!(before.heapUsed > 0 && before.heapTotal >= before.heapUsed)) {
+          throw new Error(JSON.stringify(before));
+        }
+        globalThis.buffer = new ArrayBuffer(16 * 1024 * 1024);
+        const after = Deno.core.memoryUsage();
+        if (after.external < before.external + 16 * 1024 * 1024) {
+          throw new Error(JSON.stringify(after));
+        }
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn test_core_resources() {
+    struct TestResource;
+    impl crate::Resource for TestResource {
+      fn name(&self) -> std::borrow::Cow<str> {
+        "testResource".into()
+      }
+    }
+
+    let mut runtime = JsRuntime::new(Default::default());
+    let rid = runtime
+      .op_state()
+      .borrow_mut()
+      .resource_table
+      .add(TestResource);
+    runtime
+      .execute_script(
+        "resources.js",
+        &format!(
+          r#"
+          const resources = Deno.core.resources();
+          if (resources["{rid}"] !== "testResource") {{
+            throw new Error(JSON.stringify(resources));
+          }}
+          Deno.core.close({rid});
+          if ("{rid}" in Deno.core.resources()) {{
+            throw new Error("resource was not closed");
+          }}
+          "#,
+          rid = rid
+        ),
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn test_dispatch_no_zero_copy_buf() {
     let (mut runtime, dispatch_count) = setup(Mode::AsyncZeroCopy(false));
     runtime
       .execute_script(
@@ -1897,6 +4662,21 @@ pub mod tests {
     );
   }
 
+  #[tokio::test]
+  async fn test_resolve_value_settled_rejection() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let value_global = runtime
+      .execute_script("a.js", "Promise.reject(new Error('fail'))")
+      .unwrap();
+    let err = runtime.resolve_value(value_global).await.unwrap_err();
+    assert_eq!(
+      "Uncaught Error: fail",
+      err.downcast::<JsError>().unwrap().message
+    );
+    // The rejection was handled by `resolve_value()`.
+    runtime.run_event_loop(false).await.unwrap();
+  }
+
   #[test]
   fn terminate_execution() {
     let (mut isolate, _dispatch_count) = setup(Mode::Async);
@@ -1978,15 +4758,176 @@ pub mod tests {
     let src = "hocuspocus(";
     let r = runtime.execute_script("i.js", src);
     let e = r.unwrap_err();
-    let js_error = e.downcast::<JsError>().unwrap();
+    let compile_error = e.downcast::<CompileError>().unwrap();
+    assert_eq!(compile_error.diagnostic.end_column, Some(11));
+    assert_eq!(
+      compile_error.diagnostic.source_line.as_deref(),
+      Some("hocuspocus(")
+    );
+    let snippet = compile_error.diagnostic.snippet().unwrap();
+    assert!(snippet.starts_with("hocuspocus(\n"));
+    assert!(snippet.ends_with('^'));
+    let js_error = compile_error.error.downcast::<JsError>().unwrap();
     assert_eq!(js_error.end_column, Some(11));
   }
 
   #[test]
-  fn test_encode_decode() {
-    run_in_task(|cx| {
-      let (mut runtime, _dispatch_count) = setup(Mode::Async);
-      runtime
+  fn disallow_code_generation_from_strings() {
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      disallow_code_generation_from_strings: true,
+      ..Default::default()
+    });
+    let err = runtime.execute_script("a.js", "eval('1 + 2')").unwrap_err();
+    let js_error = err.downcast::<JsError>().unwrap();
+    assert!(js_error.message.starts_with("Uncaught EvalError"));
+    runtime
+      .execute_script("b.js", "new Function('return 1')")
+      .unwrap_err();
+
+    // Sub-contexts can't be used to escape the restriction.
+    let err = runtime
+      .execute_script(
+        "c.js",
+        "Deno.core.runInContext(\"eval('1 + 2')\", Deno.core.createContext())",
+      )
+      .unwrap_err();
+    let js_error = err.downcast::<JsError>().unwrap();
+    assert!(js_error.message.starts_with("Uncaught EvalError"));
+
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime.execute_script("d.js", "eval('1 + 2')").unwrap();
+    runtime
+      .execute_script(
+        "e.js",
+        "Deno.core.runInContext(\"eval('1 + 2')\", Deno.core.createContext())",
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn disallow_code_generation_from_strings_concurrently() {
+    // The flag is process-wide, so runtimes created concurrently must not
+    // pick up each other's setting.
+    let threads: Vec<_> = (0..8)
+      .map(|i| {
+        std::thread::spawn(move || {
+          let disallow = i % 2 == 0;
+          for _ in 0..20 {
+            let mut runtime = JsRuntime::new(RuntimeOptions {
+              disallow_code_generation_from_strings: disallow,
+              ..Default::default()
+            });
+            let result = runtime.execute_script("a.js", "eval('1 + 2')");
+            assert_eq!(result.is_err(), disallow);
+          }
+        })
+      })
+      .collect();
+    for thread in threads {
+      thread.join().unwrap();
+    }
+  }
+
+  #[test]
+  fn test_freeze_intrinsics() {
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      freeze_intrinsics: true,
+      ..Default::default()
+    });
+    runtime
+      .execute_script(
+        "freeze_intrinsics.js",
+        r#"
+        "use strict";
+        function assert(cond, msg) {
+          if (!cond) {
+            throw new Error(msg);
+          }
+        }
+        assert(Object.isFrozen(Array.prototype), "Array.prototype");
+        assert(Object.isFrozen(Promise), "Promise");
+        assert(
+          Object.isFrozen(Object.getPrototypeOf(Uint8Array)),
+          "%TypedArray%",
+        );
+        assert(!Object.isFrozen(globalThis), "globalThis");
+        let thrown = false;
+        try {
+          Array.prototype.push = () => {};
+        } catch {
+          thrown = true;
+        }
+        assert(thrown, "assignment should throw");
+        class CustomError extends Error {
+          constructor(msg) {
+            super(msg);
+            this.name = "CustomError";
+          }
+        }
+        assert(new CustomError("a").name === "CustomError", "override");
+        assert(Deno.core.opSync("op_resources") !== undefined, "ops");
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn test_lock_core() {
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "bootstrap.js",
+        "const print = Deno.core.print; globalThis.log = (s) => print(s);",
+      )
+      .unwrap();
+    runtime.lock_core(CoreLockMode::Freeze).unwrap();
+    runtime
+      .execute_script(
+        "frozen.js",
+        r#"
+        "use strict";
+        if (!Object.isFrozen(Deno.core)) {
+          throw new Error("Deno.core should be frozen");
+        }
+        let thrown = false;
+        try {
+          Deno.core = {};
+        } catch {
+          thrown = true;
+        }
+        if (!thrown) {
+          throw new Error("Deno.core should not be replaceable");
+        }
+        "#,
+      )
+      .unwrap();
+
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "bootstrap.js",
+        "const print = Deno.core.print; globalThis.log = (s) => print(s);",
+      )
+      .unwrap();
+    runtime.lock_core(CoreLockMode::Remove).unwrap();
+    runtime
+      .execute_script(
+        "removed.js",
+        r#"
+        if (Deno.core !== undefined || __bootstrap.core !== undefined) {
+          throw new Error("Deno.core should be removed");
+        }
+        log("");
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn test_encode_decode() {
+    run_in_task(|cx| {
+      let (mut runtime, _dispatch_count) = setup(Mode::Async);
+      runtime
         .execute_script(
           "encode_decode_test.js",
           include_str!("encode_decode_test.js"),
@@ -2043,6 +4984,378 @@ pub mod tests {
     });
   }
 
+  #[test]
+  fn test_permissions_handler() {
+    struct DenyOpHandler;
+
+    impl PermissionsHandler for DenyOpHandler {
+      fn check_op(
+        &self,
+        _state: &mut OpState,
+        call: &OpCallInfo,
+      ) -> Result<(), Error> {
+        if call.op_name == "op_denied" {
+          assert!(call.promise_id.is_none());
+          return Err(custom_error("PermissionDenied", "op_denied is denied"));
+        }
+        Ok(())
+      }
+    }
+
+    fn op_denied(_: &mut OpState, _: (), _: ()) -> Result<(), Error> {
+      unreachable!()
+    }
+
+    fn op_allowed(_: &mut OpState, _: (), _: ()) -> Result<u32, Error> {
+      Ok(42)
+    }
+
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      permissions_handler: Some(Rc::new(DenyOpHandler)),
+      ..Default::default()
+    });
+    runtime.register_op("op_denied", op_sync(op_denied));
+    runtime.register_op("op_allowed", op_sync(op_allowed));
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script(
+        "permissions_handler.js",
+        r#"
+        if (Deno.core.opSync("op_allowed") !== 42) {
+          throw new Error("expected op_allowed to return 42");
+        }
+        let thrown;
+        try {
+          Deno.core.opSync("op_denied");
+        } catch (e) {
+          thrown = e;
+        }
+        if (!thrown || !thrown.message.includes("op_denied is denied")) {
+          throw new Error("expected op_denied to be denied");
+        }
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn test_to_v8_from_v8() {
+    #[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+    struct Point {
+      x: i32,
+      y: Option<i32>,
+      label: String,
+    }
+
+    let mut runtime = JsRuntime::new(Default::default());
+    let point = Point {
+      x: 1,
+      y: None,
+      label: "origin".to_string(),
+    };
+    let value = runtime.to_v8(&point).unwrap();
+    assert_eq!(runtime.from_v8::<Point>(&value).unwrap(), point);
+
+    let value = runtime
+      .execute_script("point.js", "({ x: 3, y: 4, label: 'p' })")
+      .unwrap();
+    let point: Point = runtime.from_v8(&value).unwrap();
+    assert_eq!(point.y, Some(4));
+    assert!(runtime.from_v8::<Vec<u32>>(&value).is_err());
+  }
+
+  #[test]
+  fn test_json_helpers() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let json = serde_json::json!({ "a": [1, 2.5, "three"], "b": null });
+    let value = runtime.to_v8(&json).unwrap();
+    assert_eq!(runtime.from_v8::<serde_json::Value>(&value).unwrap(), json);
+
+    let value = runtime
+      .json_parse(r#"{"a":[1,2.5,"three"],"b":null}"#)
+      .unwrap();
+    assert_eq!(runtime.from_v8::<serde_json::Value>(&value).unwrap(), json);
+    assert_eq!(
+      runtime.json_stringify(&value).unwrap(),
+      r#"{"a":[1,2.5,"three"],"b":null}"#
+    );
+
+    let err = runtime.json_parse("{").unwrap_err();
+    assert!(err.to_string().contains("SyntaxError"));
+    let cyclic = runtime
+      .execute_script("cyclic.js", "const o = {}; o.o = o; o")
+      .unwrap();
+    let err = runtime.json_stringify(&cyclic).unwrap_err();
+    assert!(err.to_string().contains("TypeError"));
+  }
+
+  #[test]
+  fn test_serialize_value() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let value = runtime
+      .execute_script(
+        "state.js",
+        r#"({
+          count: 42n,
+          tags: new Set(["a", "b"]),
+          seen: new Map([[1, new Date(0)]]),
+          bytes: new Uint8Array([1, 2, 3]),
+        })"#,
+      )
+      .unwrap();
+    let bytes = runtime.serialize_value(&value).unwrap();
+
+    // Restore into a fresh runtime.
+    let mut runtime = JsRuntime::new(Default::default());
+    let value = runtime.deserialize_value(&bytes).unwrap();
+    {
+      let scope = &mut runtime.handle_scope();
+      let context = scope.get_current_context();
+      let global = context.global(scope);
+      let name = v8::String::new(scope, "state").unwrap();
+      let value = v8::Local::new(scope, value);
+      global.set(scope, name.into(), value).unwrap();
+    }
+    let ok = runtime
+      .execute_script(
+        "check.js",
+        r#"state.count === 42n && state.tags.has("b") &&
+          state.seen.get(1).getTime() === 0 && state.bytes[2] === 3"#,
+      )
+      .unwrap();
+    assert!(runtime.from_v8::<bool>(&ok).unwrap());
+
+    let func = runtime.execute_script("func.js", "() => {}").unwrap();
+    assert!(runtime.serialize_value(&func).is_err());
+    let sab = runtime
+      .execute_script("sab.js", "new SharedArrayBuffer(8)")
+      .unwrap();
+    let err = runtime.serialize_value(&sab).unwrap_err();
+    assert!(err.to_string().contains("can't be serialized persistently"));
+    assert!(runtime.deserialize_value(&[1, 2, 3]).is_err());
+  }
+
+  #[test]
+  fn test_js_callable() {
+    struct Listener(JsCallable);
+
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime.register_op(
+      "op_listen",
+      op_sync(|state, callable: JsCallable, _: ()| {
+        state.put(Listener(callable));
+        Ok(())
+      }),
+    );
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script(
+        "listen.js",
+        r#"
+        globalThis.events = [];
+        Deno.core.opSync(
+          "op_listen",
+          Deno.core.createCallable((name, count) => {
+            if (name === "boom") throw new Error("listener failed");
+            events.push(`${name}:${count}`);
+            return events.length;
+          }),
+        );
+        "#,
+      )
+      .unwrap();
+
+    let callable = runtime.op_state().borrow().borrow::<Listener>().0;
+    let name = runtime.to_v8("ready").unwrap();
+    let count = runtime.to_v8(2).unwrap();
+    let ret = runtime.call_callable(callable, &[name, count]).unwrap();
+    assert_eq!(runtime.from_v8::<u32>(&ret).unwrap(), 1);
+    let events = runtime.execute_script("events.js", "events").unwrap();
+    assert_eq!(
+      runtime.from_v8::<Vec<String>>(&events).unwrap(),
+      vec!["ready:2"]
+    );
+
+    let boom = runtime.to_v8("boom").unwrap();
+    let err = runtime.call_callable(callable, &[boom]).unwrap_err();
+    assert!(err.to_string().contains("listener failed"));
+
+    assert!(runtime.release_callable(callable));
+    assert!(!runtime.release_callable(callable));
+    assert!(runtime.call_callable(callable, &[]).is_err());
+  }
+
+  #[test]
+  fn test_resolve_promise_with() {
+    run_in_task(|cx| {
+      let mut runtime = JsRuntime::new(Default::default());
+      let (tx, rx) = oneshot::channel::<u32>();
+      let resolved = runtime.resolve_promise_with(async move {
+        let value = rx.await?;
+        Ok(value * 2)
+      });
+      let rejected = runtime
+        .resolve_promise_with(async { Err::<(), _>(type_error("not today")) });
+      {
+        let scope = &mut runtime.handle_scope();
+        let context = scope.get_current_context();
+        let global = context.global(scope);
+        for (name, promise) in [("resolved", resolved), ("rejected", rejected)]
+        {
+          let name = v8::String::new(scope, name).unwrap();
+          let promise = v8::Local::new(scope, promise);
+          global.set(scope, name.into(), promise.into()).unwrap();
+        }
+      }
+      runtime
+        .execute_script(
+          "promises.js",
+          r#"
+          globalThis.results = [];
+          resolved.then((value) => results.push(value));
+          rejected.catch((e) => results.push(e instanceof TypeError, e.message));
+          "#,
+        )
+        .unwrap();
+
+      // The event loop waits for the pending future.
+      assert!(matches!(runtime.poll_event_loop(cx, false), Poll::Pending));
+      tx.send(21).unwrap();
+      assert!(matches!(
+        runtime.poll_event_loop(cx, false),
+        Poll::Ready(Ok(()))
+      ));
+      let results = runtime.execute_script("results.js", "results").unwrap();
+      assert_eq!(
+        runtime.from_v8::<serde_json::Value>(&results).unwrap(),
+        serde_json::json!([true, "not today", 42])
+      );
+    });
+  }
+
+  #[test]
+  fn test_js_error_exception_value() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let err = runtime
+      .execute_script(
+        "throw_payload.js",
+        r#"throw Object.assign(new Error("boom"), {
+          code: 42,
+          retry: false,
+          detail: { nested: true },
+        });"#,
+      )
+      .unwrap_err();
+    let js_error = err.downcast::<JsError>().unwrap();
+    let properties = js_error.properties.unwrap();
+    assert_eq!(properties["code"], 42);
+    assert_eq!(properties["retry"], false);
+    assert!(!properties.contains_key("detail"));
+    assert!(!properties.contains_key("message"));
+
+    let exception = runtime.take_last_exception().unwrap();
+    let scope = &mut runtime.handle_scope();
+    let exception = v8::Local::new(scope, exception);
+    let exception = v8::Local::<v8::Object>::try_from(exception).unwrap();
+    let key = v8::String::new(scope, "detail").unwrap();
+    assert!(exception.get(scope, key.into()).unwrap().is_object());
+  }
+
+  #[test]
+  fn test_op_error_properties() {
+    fn op_fail(_: &mut OpState, _: (), _: ()) -> Result<(), Error> {
+      Err(crate::error::with_properties(
+        generic_error("no such file"),
+        serde_json::json!({ "errno": -2, "path": "/missing" }),
+      ))
+    }
+
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime.register_op("op_fail", op_sync(op_fail));
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script(
+        "op_error_properties.js",
+        r#"
+        let thrown;
+        try {
+          Deno.core.opSync("op_fail");
+        } catch (e) {
+          thrown = e;
+        }
+        if (thrown.message !== "no such file") {
+          throw new Error("unexpected message: " + thrown.message);
+        }
+        if (thrown.errno !== -2 || thrown.path !== "/missing") {
+          throw new Error("expected errno and path properties");
+        }
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn test_register_error_class() {
+    #[derive(Debug)]
+    struct NotFound;
+
+    impl std::fmt::Display for NotFound {
+      fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("thing not found")
+      }
+    }
+
+    impl std::error::Error for NotFound {}
+
+    fn op_not_found(_: &mut OpState, _: (), _: ()) -> Result<(), Error> {
+      Err(NotFound.into())
+    }
+
+    let mut runtime = JsRuntime::new(Default::default());
+    let constructor = runtime
+      .execute_script(
+        "not_found.js",
+        "globalThis.NotFound = class NotFound extends Error {}; NotFound",
+      )
+      .unwrap();
+    let constructor = {
+      let scope = &mut runtime.handle_scope();
+      let constructor = v8::Local::new(scope, constructor);
+      let constructor =
+        v8::Local::<v8::Function>::try_from(constructor).unwrap();
+      v8::Global::new(scope, constructor)
+    };
+    runtime
+      .register_error_class("NotFound", constructor.clone())
+      .unwrap();
+    // Registering the same class twice is an error.
+    assert!(runtime
+      .register_error_class("NotFound", constructor)
+      .is_err());
+    runtime.map_error_class::<NotFound>("NotFound");
+    runtime.register_op("op_not_found", op_sync(op_not_found));
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script(
+        "register_error_class.js",
+        r#"
+        let thrown;
+        try {
+          Deno.core.opSync("op_not_found");
+        } catch (e) {
+          thrown = e;
+        }
+        if (!(thrown instanceof NotFound)) {
+          throw new Error("expected a NotFound error");
+        }
+        if (thrown.message !== "thing not found") {
+          throw new Error("unexpected message: " + thrown.message);
+        }
+        "#,
+      )
+      .unwrap();
+  }
+
   #[test]
   fn will_snapshot() {
     let snapshot = {
@@ -2120,28 +5433,114 @@ pub mod tests {
   }
 
   #[test]
-  fn test_heap_limit_cb_remove() {
-    let mut runtime = JsRuntime::new(Default::default());
-
-    runtime.add_near_heap_limit_callback(|current_limit, _initial_limit| {
-      current_limit * 2
+  fn test_memory_limit() {
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      memory_limit: Some(MemoryLimit {
+        max_heap_size: 3 * 1024 * 1024,
+      }),
+      ..Default::default()
     });
-    runtime.remove_near_heap_limit_callback(3 * 1024 * 1024);
-    assert!(runtime.allocations.near_heap_limit_callback_data.is_none());
+    let err = runtime
+      .execute_script(
+        "script name",
+        r#"let s = ""; while(true) { s += "Hello"; }"#,
+      )
+      .expect_err("script should fail");
+    let err = err.downcast::<ExecutionTerminated>().unwrap();
+    assert_eq!(err.reason, TerminationReason::MemoryLimit);
+    assert_eq!(
+      "Uncaught RangeError: isolate memory limit exceeded",
+      err.error.downcast::<JsError>().unwrap().message
+    );
   }
 
   #[test]
-  fn test_heap_limit_cb_multiple() {
-    let create_params =
-      v8::Isolate::create_params().heap_limits(0, 3 * 1024 * 1024);
+  fn test_watchdog() {
     let mut runtime = JsRuntime::new(RuntimeOptions {
-      create_params: Some(create_params),
+      watchdog_timeout: Some(Duration::from_millis(100)),
       ..Default::default()
     });
-    let cb_handle = runtime.v8_isolate().thread_safe_handle();
-
-    let callback_invoke_count_first = Rc::new(AtomicUsize::default());
-    let inner_invoke_count_first = Rc::clone(&callback_invoke_count_first);
+    let err = runtime
+      .execute_script("infinite_loop.js", "while (true) {}")
+      .expect_err("script should fail");
+    let err = err.downcast::<ExecutionTerminated>().unwrap();
+    assert_eq!(err.reason, TerminationReason::Timeout);
+    let message = err.error.downcast::<JsError>().unwrap().message;
+    assert!(message.starts_with(
+      "Uncaught Error: execution terminated: time budget exceeded while executing infinite_loop.js:1:"
+    ));
+    let watchdog = runtime.watchdog().unwrap();
+    assert!(watchdog.has_terminated());
+    assert!(watchdog
+      .terminated_at()
+      .unwrap()
+      .starts_with("infinite_loop.js:1:"));
+  }
+
+  #[test]
+  fn test_watchdog_catastrophic_regexp() {
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      watchdog_timeout: Some(Duration::from_millis(100)),
+      ..Default::default()
+    });
+    let err = runtime
+      .execute_script(
+        "regexp.js",
+        r#"
+        const input = "a".repeat(40) + "!";
+        /^(a+)+$/.test(input);
+        "#,
+      )
+      .expect_err("script should fail");
+    assert!(err
+      .to_string()
+      .contains("time budget exceeded while executing regexp.js:3"));
+  }
+
+  #[test]
+  fn test_termination_handle() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let termination_handle = runtime.termination_handle();
+    let thread = std::thread::spawn(move || {
+      std::thread::sleep(Duration::from_millis(100));
+      termination_handle.terminate(TerminationReason::AdminKill);
+    });
+    let err = runtime
+      .execute_script("infinite_loop.js", "while (true) {}")
+      .expect_err("script should fail");
+    thread.join().unwrap();
+    assert_eq!(
+      crate::error::get_termination_reason(&err),
+      Some(&TerminationReason::AdminKill)
+    );
+    assert!(err
+      .to_string()
+      .contains("execution terminated: killed by administrator"));
+  }
+
+  #[test]
+  fn test_heap_limit_cb_remove() {
+    let mut runtime = JsRuntime::new(Default::default());
+
+    runtime.add_near_heap_limit_callback(|current_limit, _initial_limit| {
+      current_limit * 2
+    });
+    runtime.remove_near_heap_limit_callback(3 * 1024 * 1024);
+    assert!(runtime.allocations.near_heap_limit_callback_data.is_none());
+  }
+
+  #[test]
+  fn test_heap_limit_cb_multiple() {
+    let create_params =
+      v8::Isolate::create_params().heap_limits(0, 3 * 1024 * 1024);
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      create_params: Some(create_params),
+      ..Default::default()
+    });
+    let cb_handle = runtime.v8_isolate().thread_safe_handle();
+
+    let callback_invoke_count_first = Rc::new(AtomicUsize::default());
+    let inner_invoke_count_first = Rc::clone(&callback_invoke_count_first);
     runtime.add_near_heap_limit_callback(
       move |current_limit, _initial_limit| {
         inner_invoke_count_first.fetch_add(1, Ordering::SeqCst);
@@ -2378,6 +5777,10 @@ assertEquals(1, notify_return_value);
     };
     let mut runtime = JsRuntime::new(options);
     runtime.execute_script("<none>", "").unwrap();
+
+    // V8 is already initialized, so the platform is rejected.
+    let platform = v8::new_single_threaded_default_platform(false);
+    assert!(!init_v8_platform(platform.make_shared()));
   }
 
   #[test]
@@ -2534,8 +5937,6 @@ assertEquals(1, notify_return_value);
 
   #[test]
   fn test_has_tick_scheduled() {
-    use futures::task::ArcWake;
-
     let macrotask = Arc::new(AtomicUsize::default());
     let macrotask_ = Arc::clone(&macrotask);
 
@@ -2642,6 +6043,7 @@ assertEquals(1, notify_return_value);
             code: "console.log('hello world');".to_string(),
             module_url_specified: "file:///main.js".to_string(),
             module_url_found: "file:///main.js".to_string(),
+            redirects: vec![],
           })
         }
         .boxed_local()
@@ -2672,6 +6074,45 @@ assertEquals(1, notify_return_value);
       .contains("JavaScript execution has been terminated"));
   }
 
+  #[tokio::test]
+  async fn test_promise_reject_events() {
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "promise_reject_events.js",
+        r#"
+        const { promiseRejectEvents } = Deno.core;
+        const unhandled = new Set();
+        globalThis.events = [];
+        Deno.core.setPromiseRejectCallback((type, promise, reason) => {
+          if (type === promiseRejectEvents.rejectWithNoHandler) {
+            unhandled.add(promise);
+            globalThis.events.push("unhandledrejection " + reason.message);
+          } else if (type === promiseRejectEvents.handlerAddedAfterReject) {
+            if (unhandled.delete(promise)) {
+              globalThis.events.push("rejectionhandled");
+            }
+          }
+        });
+        globalThis.p = Promise.reject(new Error("late"));
+        "#,
+      )
+      .unwrap();
+    runtime.run_event_loop(false).await.unwrap();
+    runtime
+      .execute_script(
+        "handle.js",
+        r#"
+        globalThis.p.catch(() => {});
+        const events = globalThis.events.join();
+        if (events !== "unhandledrejection late,rejectionhandled") {
+          throw new Error(events);
+        }
+        "#,
+      )
+      .unwrap();
+  }
+
   #[tokio::test]
   async fn test_set_promise_reject_callback() {
     let promise_reject = Arc::new(AtomicUsize::default());
@@ -2761,4 +6202,1340 @@ assertEquals(1, notify_return_value);
     assert_eq!(2, promise_reject.load(Ordering::Relaxed));
     assert_eq!(2, uncaught_exception.load(Ordering::Relaxed));
   }
+
+  #[tokio::test]
+  async fn test_uncaught_exception_callback_handles_rejections() {
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "uncaught_exception_callback.js",
+        r#"
+        globalThis.uncaught = [];
+        Deno.core.setUncaughtExceptionCallback((err) => {
+          globalThis.uncaught.push(err.message);
+        });
+        Promise.reject(new Error("handled"));
+        "#,
+      )
+      .unwrap();
+    runtime.run_event_loop(false).await.unwrap();
+    runtime
+      .execute_script(
+        "check.js",
+        r#"
+        if (globalThis.uncaught.join() !== "handled") {
+          throw new Error(globalThis.uncaught.join());
+        }
+        Deno.core.setUncaughtExceptionCallback((err) => {
+          throw new Error("rethrown: " + err.message);
+        });
+        Promise.reject(new Error("unhandled"));
+        "#,
+      )
+      .unwrap();
+    let err = runtime.run_event_loop(false).await.unwrap_err();
+    assert!(err.to_string().contains("rethrown: unhandled"));
+  }
+
+  #[tokio::test]
+  async fn test_all_unhandled_rejections_reported() {
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "unhandled_rejections.js",
+        r#"
+        for (let i = 0; i < 5; i++) {
+          Promise.reject(Error("rejection " + i));
+        }
+        "#,
+      )
+      .unwrap();
+    let err = runtime.run_event_loop(false).await.unwrap_err();
+    let rejections = err.downcast_ref::<UnhandledRejections>().unwrap();
+    let messages: Vec<String> =
+      rejections.0.iter().map(|err| err.to_string()).collect();
+    assert_eq!(messages.len(), 5);
+    for (i, message) in messages.iter().enumerate() {
+      assert!(message.contains(&format!("rejection {}", i)));
+    }
+  }
+
+  #[tokio::test]
+  async fn test_promise_creation_stacks() {
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      capture_promise_creation_stacks: true,
+      ..Default::default()
+    });
+    runtime
+      .execute_script(
+        "promise_creation.js",
+        r#"
+        function createRejected() {
+          return new Promise((_, reject) => reject(new Error("rejected")));
+        }
+        createRejected();
+        "#,
+      )
+      .unwrap();
+    let err = runtime.run_event_loop(false).await.unwrap_err();
+    let js_error = err.downcast::<JsError>().unwrap();
+    let frame = js_error
+      .promise_creation_frames
+      .iter()
+      .find(|frame| frame.function_name.as_deref() == Some("createRejected"))
+      .unwrap();
+    assert_eq!(frame.file_name.as_deref(), Some("promise_creation.js"));
+    assert_eq!(frame.line_number, Some(3));
+    assert!(js_error.to_string().contains("Promise created at:"));
+  }
+
+  #[tokio::test]
+  async fn test_unhandled_rejection_handler() {
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    let seen_ = seen.clone();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      unhandled_rejection_fn: Some(Rc::new(move |err: &JsError| {
+        seen_.borrow_mut().push(err.message.clone());
+        err.message.contains("swallow in rust")
+      })),
+      ..Default::default()
+    });
+
+    runtime
+      .execute_script(
+        "unhandled_rejection_handler.js",
+        r#"
+        Deno.core.setUnhandledRejectionHandler((promise, reason) => {
+          if (!(promise instanceof Promise)) {
+            throw Error("expected a promise");
+          }
+          return reason.message === "swallow in js";
+        });
+        Promise.reject(Error("swallow in js"));
+        Promise.reject(Error("swallow in rust"));
+        "#,
+      )
+      .unwrap();
+    runtime.run_event_loop(false).await.unwrap();
+    assert_eq!(
+      seen.borrow().as_slice(),
+      ["Uncaught Error: swallow in rust".to_string()]
+    );
+
+    runtime
+      .execute_script(
+        "unhandled_rejection_handler.js",
+        "Promise.reject(Error('fatal'));",
+      )
+      .unwrap();
+    let err = runtime.run_event_loop(false).await.unwrap_err();
+    assert!(err.to_string().contains("fatal"));
+  }
+
+  #[tokio::test]
+  async fn test_shutdown_cancels_pending_ops() {
+    use crate::CancelFuture;
+    use std::cell::Cell;
+
+    let op_canceled = Rc::new(Cell::new(false));
+    let mut runtime = JsRuntime::new(Default::default());
+    let canceled = op_canceled.clone();
+    runtime.register_op(
+      "op_wait",
+      op_async(move |state, _: (), _: ()| {
+        let cancel_handle = state.borrow().cancel_handle();
+        let canceled = canceled.clone();
+        async move {
+          let result = futures::future::pending::<()>()
+            .or_cancel(cancel_handle)
+            .await;
+          canceled.set(result.is_err());
+          Ok(())
+        }
+      }),
+    );
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script("a.js", "Deno.core.opAsync('op_wait')")
+      .unwrap();
+    runtime.shutdown(None).await.unwrap();
+    assert!(op_canceled.get());
+  }
+
+  #[test]
+  fn test_shutdown_timeout() {
+    let clock = crate::ManualClock::new();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      clock: Some(Arc::new(clock.clone())),
+      ..Default::default()
+    });
+    runtime.register_op(
+      "op_wait",
+      op_async(|_, _: (), _: ()| {
+        futures::future::pending::<Result<(), Error>>()
+      }),
+    );
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script("a.js", "Deno.core.opAsync('op_wait')")
+      .unwrap();
+    let mut shutdown = runtime
+      .shutdown(Some(Duration::from_secs(60)))
+      .boxed_local();
+    let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+    assert!(shutdown.poll_unpin(&mut cx).is_pending());
+    clock.advance(Duration::from_secs(59));
+    assert!(shutdown.poll_unpin(&mut cx).is_pending());
+    clock.advance(Duration::from_secs(1));
+    assert!(matches!(shutdown.poll_unpin(&mut cx), Poll::Ready(Ok(()))));
+  }
+
+  #[tokio::test]
+  async fn test_shutdown() {
+    let ext = Extension::builder()
+      .ops(vec![
+        ("op_test_sync", op_sync(|_, _: (), _: ()| Ok(()))),
+        (
+          "op_test_async",
+          op_async(|_, _: (), _: ()| async { Ok(()) }),
+        ),
+      ])
+      .build();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      extensions: vec![ext],
+      ..Default::default()
+    });
+    runtime
+      .execute_script(
+        "shutdown.js",
+        r#"
+        Deno.core.opAsync("op_test_async").then(() => {
+          globalThis.done = true;
+        });
+        Deno.core.addBeforeExitCallback(() => {
+          try {
+            Deno.core.opSync("op_test_sync");
+          } catch (e) {
+            throw new Error(`beforeExit ${globalThis.done}: ${e.message}`);
+          }
+        });
+        "#,
+      )
+      .unwrap();
+    let err = runtime
+      .shutdown(Some(Duration::from_secs(10)))
+      .await
+      .unwrap_err();
+    assert!(
+      err.to_string().contains(
+        "beforeExit true: Can not dispatch ops, the runtime is shutting down"
+      ),
+      "{}",
+      err
+    );
+  }
+
+  #[test]
+  fn test_from_isolate() {
+    DENO_INIT.call_once(|| v8_init(None));
+    let isolate = v8::Isolate::new(Default::default());
+    let mut runtime =
+      unsafe { JsRuntime::from_isolate(isolate, RuntimeOptions::default()) };
+    let value = runtime
+      .execute_script("from_isolate.js", "typeof Deno.core.opSync")
+      .unwrap();
+    assert_eq!(runtime.from_v8::<String>(&value).unwrap(), "function");
+  }
+
+  #[test]
+  fn test_recycle_context() {
+    let snapshot = {
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        will_snapshot: true,
+        ..Default::default()
+      });
+      runtime.execute_script("a.js", "a = 1 + 2").unwrap();
+      let err = runtime.recycle_context().unwrap_err();
+      assert!(err.to_string().contains("snapshotted"));
+      runtime.snapshot()
+    };
+
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      startup_snapshot: Some(Snapshot::JustCreated(snapshot)),
+      ..Default::default()
+    });
+    runtime
+      .execute_script(
+        "request1.js",
+        r#"
+        globalThis.leak = true;
+        Deno.core.setMacrotaskCallback(() => true);
+        "#,
+      )
+      .unwrap();
+    runtime.recycle_context().unwrap();
+    assert!(JsRuntime::state(runtime.v8_isolate())
+      .borrow()
+      .js_macrotask_cbs
+      .is_empty());
+    runtime
+      .execute_script(
+        "request2.js",
+        r#"
+        if (a !== 3) throw new Error("snapshot not restored");
+        if (globalThis.leak) throw new Error("global leaked");
+        Deno.core.opSync("op_resources");
+        "#,
+      )
+      .unwrap();
+
+    // Contexts are also recycled without a snapshot.
+    let mut runtime = JsRuntime::new(RuntimeOptions::default());
+    runtime.execute_script("a.js", "a = 1").unwrap();
+    runtime.recycle_context().unwrap();
+    runtime
+      .execute_script(
+        "b.js",
+        r#"
+        if (typeof a !== "undefined") throw new Error("global leaked");
+        Deno.core.opSync("op_resources");
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn test_run_in_context() {
+    let mut runtime = JsRuntime::new(RuntimeOptions::default());
+    runtime
+      .execute_script(
+        "run_in_context.js",
+        r#"
+        const ctx = Deno.core.createContext({ x: 2 });
+        if (Deno.core.runInContext("globalThis.y = x * 21; y", ctx) !== 42) {
+          throw new Error("unexpected completion value");
+        }
+        if (Deno.core.runInContext("y + typeof Deno", ctx) !== "42undefined") {
+          throw new Error("context state not kept");
+        }
+        if (typeof y !== "undefined") {
+          throw new Error("global leaked out of the context");
+        }
+        let err;
+        try {
+          Deno.core.runInContext("throw new TypeError('boom')", ctx, "user.js");
+        } catch (e) {
+          err = e;
+        }
+        if (err.message !== "boom" || err instanceof TypeError) {
+          throw new Error("expected an error from the other context");
+        }
+        if (!Deno.core.disposeContext(ctx)) {
+          throw new Error("context not disposed");
+        }
+        try {
+          Deno.core.runInContext("1", ctx);
+          throw new Error("unreachable");
+        } catch (e) {
+          if (!(e instanceof TypeError)) throw e;
+        }
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  fn test_execute_script_with_origin() {
+    let mut runtime = JsRuntime::new(RuntimeOptions::default());
+    let origin = ScriptOriginOptions {
+      line_offset: 10,
+      column_offset: 4,
+      source_map_url: Some("page.html.map".to_string()),
+    };
+    let err = runtime
+      .execute_script_with_origin(
+        "file:///page.html",
+        "throw new Error('boom');",
+        &origin,
+      )
+      .unwrap_err();
+    let js_error = err.downcast::<JsError>().unwrap();
+    let frame = &js_error.frames[0];
+    assert_eq!(frame.file_name.as_deref(), Some("file:///page.html"));
+    assert_eq!(frame.line_number, Some(11));
+
+    let value = runtime
+      .execute_script(
+        "eval_context.js",
+        r#"
+        const [, err] = Deno.core.evalContext("throw new Error()", {
+          filename: "file:///page.html",
+          lineOffset: 2,
+          sourceMapUrl: "page.html.map",
+        });
+        err.thrown.stack
+        "#,
+      )
+      .unwrap();
+    let stack: String = runtime.from_v8(&value).unwrap();
+    assert!(stack.contains("file:///page.html:3:"), "{}", stack);
+  }
+
+  #[test]
+  fn test_execute_script_with_cached_data() {
+    let source = "function add(a, b) { return a + b; } add(1, 2)";
+    let code_cache = {
+      let mut runtime = JsRuntime::new(RuntimeOptions::default());
+      let (result, code_cache) =
+        runtime.execute_script_with_cached_data("bootstrap.js", source, None);
+      let value = result.unwrap();
+      assert_eq!(runtime.from_v8::<u32>(&value).unwrap(), 3);
+      code_cache.unwrap()
+    };
+    assert!(!code_cache.is_empty());
+
+    let mut runtime = JsRuntime::new(RuntimeOptions::default());
+    let (result, new_cache) = runtime.execute_script_with_cached_data(
+      "bootstrap.js",
+      source,
+      Some(&code_cache),
+    );
+    let value = result.unwrap();
+    assert_eq!(runtime.from_v8::<u32>(&value).unwrap(), 3);
+    assert!(new_cache.is_none());
+
+    // Rejected data falls back to compiling the source.
+    let (result, _) = runtime.execute_script_with_cached_data(
+      "bootstrap.js",
+      source,
+      Some(b"garbage"),
+    );
+    let value = result.unwrap();
+    assert_eq!(runtime.from_v8::<u32>(&value).unwrap(), 3);
+  }
+
+  #[test]
+  fn test_script_source_encodings() {
+    let mut runtime = JsRuntime::new(RuntimeOptions::default());
+    let utf16: Vec<u16> = "'h\u{e9}' + '\u{1F600}'".encode_utf16().collect();
+    let value = runtime
+      .execute_script_source("utf16.js", ScriptSource::TwoByte(&utf16))
+      .unwrap();
+    assert_eq!(
+      runtime.from_v8::<String>(&value).unwrap(),
+      "h\u{e9}\u{1F600}"
+    );
+    // "'caf\xe9'" in Latin-1.
+    let latin1 = b"'caf\xe9'";
+    let value = runtime
+      .execute_script_source("latin1.js", ScriptSource::OneByte(latin1))
+      .unwrap();
+    assert_eq!(runtime.from_v8::<String>(&value).unwrap(), "caf\u{e9}");
+
+    let specifier = crate::resolve_url("file:///main.js").unwrap();
+    let source: Vec<u16> =
+      "globalThis.fromModule = '\u{e9}';".encode_utf16().collect();
+    runtime
+      .new_module_from_source(&specifier, true, ScriptSource::TwoByte(&source))
+      .unwrap();
+    let id =
+      futures::executor::block_on(runtime.load_main_module(&specifier, None))
+        .unwrap();
+    let _ = runtime.mod_evaluate(id);
+    futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+    let value = runtime.execute_script("check.js", "fromModule").unwrap();
+    assert_eq!(runtime.from_v8::<String>(&value).unwrap(), "\u{e9}");
+  }
+
+  #[test]
+  fn test_run_microtasks() {
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      explicit_microtasks: true,
+      ..Default::default()
+    });
+    runtime
+      .execute_script(
+        "a.js",
+        r#"
+        globalThis.log = [];
+        globalThis.later = () => Promise.resolve().then(() => log.push("then"));
+        "#,
+      )
+      .unwrap();
+
+    // Simulate an embedder calling into JS outside of the event loop.
+    {
+      let scope = &mut runtime.handle_scope();
+      let global = scope.get_current_context().global(scope);
+      let key = v8::String::new(scope, "later").unwrap();
+      let later = global.get(scope, key.into()).unwrap();
+      let later = v8::Local::<v8::Function>::try_from(later).unwrap();
+      let this = v8::undefined(scope).into();
+      later.call(scope, this, &[]).unwrap();
+    }
+    let value = runtime.execute_script("b.js", "log.length").unwrap();
+    assert_eq!(runtime.from_v8::<u32>(&value).unwrap(), 0);
+    runtime.run_microtasks().unwrap();
+    let value = runtime.execute_script("c.js", "log.length").unwrap();
+    assert_eq!(runtime.from_v8::<u32>(&value).unwrap(), 1);
+
+    runtime
+      .execute_script(
+        "d.js",
+        "Promise.resolve().then(() => { throw new Error('boom'); });",
+      )
+      .unwrap();
+    let err = runtime.run_microtasks().unwrap_err();
+    assert!(err.to_string().contains("boom"), "{}", err);
+  }
+
+  #[test]
+  fn test_terminate_with_error() {
+    fn get_error_class_name(_: &Error) -> &'static str {
+      "QuotaExceeded"
+    }
+
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      get_error_class_fn: Some(&get_error_class_name),
+      ..Default::default()
+    });
+    let handle = runtime.termination_handle();
+    let terminator = std::thread::spawn(move || {
+      std::thread::sleep(Duration::from_millis(100));
+      assert!(handle.terminate_with_error(generic_error("quota exceeded")));
+    });
+    let err = runtime
+      .execute_script("loop.js", "while (true) {}")
+      .unwrap_err();
+    terminator.join().unwrap();
+    assert_eq!(
+      crate::error::get_termination_reason(&err),
+      Some(&TerminationReason::Other("quota exceeded".to_string()))
+    );
+    let js_error = err
+      .downcast_ref::<crate::error::ExecutionTerminated>()
+      .unwrap()
+      .error
+      .downcast_ref::<JsError>()
+      .unwrap();
+    assert_eq!(js_error.message, "Uncaught QuotaExceeded: quota exceeded");
+
+    // The runtime is usable again once the termination has been handled.
+    runtime.v8_isolate().cancel_terminate_execution();
+    runtime.execute_script("ok.js", "1").unwrap();
+  }
+
+  #[test]
+  fn test_cancel_pending_ops_on_drop() {
+    use crate::Resource;
+    use std::cell::Cell;
+
+    struct TestResource(Rc<Cell<bool>>);
+
+    impl Resource for TestResource {
+      fn close(self: Rc<Self>) {
+        self.0.set(true);
+      }
+    }
+
+    /// Records whether the cancel handle was canceled when the op's future
+    /// was dropped.
+    struct DropGuard(Rc<CancelHandle>, Rc<Cell<bool>>);
+
+    impl Drop for DropGuard {
+      fn drop(&mut self) {
+        self.1.set(self.0.is_canceled());
+      }
+    }
+
+    let op_canceled = Rc::new(Cell::new(false));
+    let resource_closed = Rc::new(Cell::new(false));
+    let mut runtime = JsRuntime::new(Default::default());
+    let canceled = op_canceled.clone();
+    runtime.register_op(
+      "op_wait",
+      op_async(move |state, _: (), _: ()| {
+        let guard = DropGuard(state.borrow().cancel_handle(), canceled.clone());
+        async move {
+          let _guard = &guard;
+          futures::future::pending::<()>().await;
+          Ok(())
+        }
+      }),
+    );
+    runtime.sync_ops_cache();
+    runtime
+      .op_state()
+      .borrow_mut()
+      .resource_table
+      .add(TestResource(resource_closed.clone()));
+    runtime
+      .execute_script("a.js", "Deno.core.opAsync('op_wait')")
+      .unwrap();
+    let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+    assert!(matches!(
+      runtime.poll_event_loop(&mut cx, false),
+      Poll::Pending
+    ));
+    drop(runtime);
+    assert!(op_canceled.get());
+    assert!(resource_closed.get());
+  }
+
+  #[test]
+  fn test_reset() {
+    use crate::CancelFuture;
+    use crate::Resource;
+
+    struct TestResource;
+
+    impl Resource for TestResource {}
+
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime.register_op(
+      "op_wait",
+      op_async(|state, _: (), _: ()| {
+        let cancel_handle = state.borrow().cancel_handle();
+        async move {
+          futures::future::pending::<()>()
+            .or_cancel(cancel_handle)
+            .await?;
+          Ok(())
+        }
+      }),
+    );
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script(
+        "a.js",
+        "globalThis.secret = 42; Deno.core.opAsync('op_wait');",
+      )
+      .unwrap();
+    let rid = runtime
+      .op_state()
+      .borrow_mut()
+      .resource_table
+      .add(TestResource);
+    let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+    assert!(matches!(
+      runtime.poll_event_loop(&mut cx, false),
+      Poll::Pending
+    ));
+
+    runtime.reset().unwrap();
+    let value = runtime
+      .execute_script("b.js", "typeof globalThis.secret")
+      .unwrap();
+    assert_eq!(runtime.from_v8::<String>(&value).unwrap(), "undefined");
+    assert!(!runtime.op_state().borrow().resource_table.has(rid));
+    assert!(!runtime.op_state().borrow().cancel_handle().is_canceled());
+    assert!(matches!(
+      runtime.poll_event_loop(&mut cx, false),
+      Poll::Ready(Ok(()))
+    ));
+    let value = runtime.execute_script("c.js", "Deno.core.ops()").unwrap();
+    assert!(runtime
+      .from_v8::<HashMap<String, usize>>(&value)
+      .unwrap()
+      .contains_key("op_wait"));
+  }
+
+  #[test]
+  fn test_broadcast_bus() {
+    let bus = BroadcastBus::new();
+    let mut subscription = bus.subscribe("test");
+    let mut runtimes: Vec<_> = (0..2)
+      .map(|_| {
+        JsRuntime::new(RuntimeOptions {
+          broadcast_bus: Some(bus.clone()),
+          ..Default::default()
+        })
+      })
+      .collect();
+    runtimes[0]
+      .execute_script(
+        "a.js",
+        r#"
+        const sub = Deno.core.broadcastSubscribe("test");
+        sub.recv().then((data) => {
+          globalThis.received = Deno.core.decode(data);
+          return sub.recv();
+        }).then((data) => {
+          globalThis.closed = data === null;
+        });
+        "#,
+      )
+      .unwrap();
+    runtimes[1]
+      .execute_script(
+        "b.js",
+        r#"
+        const sub = Deno.core.broadcastSubscribe("test");
+        if (sub.publish(Deno.core.encode("hello")) !== 2) throw new Error();
+        sub.close();
+        "#,
+      )
+      .unwrap();
+    assert_eq!(&*subscription.try_recv().unwrap(), b"hello");
+
+    let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+    assert!(runtimes[0].poll_event_loop(&mut cx, false).is_pending());
+    runtimes[0]
+      .execute_script(
+        "check.js",
+        "if (received !== 'hello') throw 1; sub.close();",
+      )
+      .unwrap();
+    assert!(matches!(
+      runtimes[0].poll_event_loop(&mut cx, false),
+      Poll::Ready(Ok(()))
+    ));
+    runtimes[0]
+      .execute_script("check.js", "if (!closed) throw 1")
+      .unwrap();
+
+    let mut runtime = JsRuntime::new(Default::default());
+    let err = runtime
+      .execute_script("c.js", "Deno.core.broadcastSubscribe('test')")
+      .unwrap_err();
+    assert!(
+      err.to_string().contains("The broadcast bus is not enabled"),
+      "{}",
+      err
+    );
+  }
+
+  #[test]
+  fn test_heap_limit_cb_with_diagnostics() {
+    let create_params =
+      v8::Isolate::create_params().heap_limits(0, 3 * 1024 * 1024);
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      create_params: Some(create_params),
+      ..Default::default()
+    });
+    let termination_handle = runtime.termination_handle();
+    let heap_snapshot = Rc::new(RefCell::new(Vec::new()));
+    let heap_used = Rc::new(std::cell::Cell::new(0));
+    let inner_heap_snapshot = heap_snapshot.clone();
+    let inner_heap_used = heap_used.clone();
+    runtime.add_near_heap_limit_callback_with_diagnostics(move |diagnostics| {
+      inner_heap_used.set(diagnostics.heap_statistics().used_heap_size());
+      diagnostics
+        .write_heap_snapshot(&mut *inner_heap_snapshot.borrow_mut())
+        .unwrap();
+      termination_handle.terminate(TerminationReason::MemoryLimit);
+      diagnostics.current_heap_limit() * 2
+    });
+    let err = runtime
+      .execute_script("a.js", r#"let s = ""; while(true) { s += "Hello"; }"#)
+      .unwrap_err();
+    assert_eq!(
+      crate::error::get_termination_reason(&err),
+      Some(&TerminationReason::MemoryLimit)
+    );
+    assert!(heap_used.get() > 0);
+    assert!(heap_snapshot.borrow().starts_with(b"{\"snapshot\":"));
+  }
+
+  #[test]
+  fn test_execute_as_module() {
+    let mut runtime = JsRuntime::new(Default::default());
+    futures::executor::block_on(async {
+      runtime
+        .execute_as_module(
+          "file:///tla.js",
+          r#"
+          await Deno.core.opAsync("op_void_async");
+          globalThis.value = await Promise.resolve(21).then((n) => n * 2);
+          "#,
+        )
+        .await
+        .unwrap();
+      runtime
+        .execute_script("check.js", "if (value !== 42) throw 1")
+        .unwrap();
+      let err = runtime
+        .execute_as_module("throws.js", "await null; throw new Error('boom');")
+        .await
+        .unwrap_err();
+      assert!(err.to_string().contains("boom"), "{}", err);
+    });
+  }
+
+  #[test]
+  fn test_cancel_op_with_signal() {
+    async fn op_never(
+      _: Rc<RefCell<OpState>>,
+      _: (),
+      _: (),
+    ) -> Result<(), Error> {
+      futures::future::pending().await
+    }
+
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime.register_op("op_never", op_async(op_never));
+    runtime.sync_ops_cache();
+    runtime
+      .execute_script(
+        "a.js",
+        r#"
+        // A minimal signal, as ext/web's AbortSignal isn't available.
+        class Signal {
+          aborted = false;
+          listeners = [];
+          addEventListener(_type, listener) {
+            this.listeners.push(listener);
+          }
+          removeEventListener(_type, listener) {
+            this.listeners = this.listeners.filter((l) => l !== listener);
+          }
+          abort() {
+            this.aborted = true;
+            this.listeners.forEach((listener) => listener());
+          }
+        }
+        globalThis.signal = new Signal();
+        globalThis.results = [];
+        const record = (p) => p.then(
+          () => results.push("resolved"),
+          (err) => results.push(err.message),
+        );
+        record(Deno.core.opAsync("op_never", null, null, { signal }));
+        record(Deno.core.opAsync("op_void_async", null, null, { signal }));
+        const aborted = new Signal();
+        aborted.abort();
+        record(Deno.core.opAsync("op_never", null, null, { signal: aborted }));
+        "#,
+      )
+      .unwrap();
+
+    let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+    assert!(runtime.poll_event_loop(&mut cx, false).is_pending());
+    runtime
+      .execute_script(
+        "b.js",
+        r#"
+        if (signal.listeners.length !== 1) throw new Error();
+        signal.abort();
+        "#,
+      )
+      .unwrap();
+    assert!(matches!(
+      runtime.poll_event_loop(&mut cx, false),
+      Poll::Ready(Ok(()))
+    ));
+    runtime
+      .execute_script(
+        "check.js",
+        r#"
+        const expected = ["operation canceled", "resolved", "operation canceled"];
+        if (results.sort().join() !== expected.sort().join()) {
+          throw new Error(results.join());
+        }
+        if (signal.listeners.length !== 0) throw new Error();
+        "#,
+      )
+      .unwrap();
+    let state_rc = JsRuntime::state(runtime.v8_isolate());
+    assert!(state_rc.borrow().op_cancel_handles.is_empty());
+  }
+
+  #[test]
+  fn test_tick_hooks() {
+    run_in_task(|cx| {
+      let mut runtime = JsRuntime::new(Default::default());
+      let ticks = Rc::new(RefCell::new(Vec::new()));
+      let ticks_ = ticks.clone();
+      runtime.set_before_tick_callback(move |runtime| {
+        ticks_.borrow_mut().push("before");
+        runtime
+          .execute_script("frame.js", "globalThis.frames = (frames ?? 0) + 1")
+          .unwrap();
+      });
+      let ticks_ = ticks.clone();
+      runtime.set_after_tick_callback(move |_, duration| {
+        assert!(duration < Duration::from_secs(10));
+        ticks_.borrow_mut().push("after");
+      });
+
+      runtime
+        .execute_script("a.js", "globalThis.frames = undefined")
+        .unwrap();
+      assert!(matches!(
+        runtime.poll_event_loop(cx, false),
+        Poll::Ready(Ok(()))
+      ));
+      assert!(matches!(
+        runtime.poll_event_loop(cx, false),
+        Poll::Ready(Ok(()))
+      ));
+      assert_eq!(*ticks.borrow(), vec!["before", "after", "before", "after"]);
+      runtime
+        .execute_script("b.js", "if (frames !== 2) throw new Error()")
+        .unwrap();
+    });
+  }
+
+  #[test]
+  fn test_interrupt_handle() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let handle = runtime.interrupt_handle();
+    let (tx, rx) = std::sync::mpsc::channel();
+
+    let interrupter_thread = std::thread::spawn(move || {
+      let queued = handle.interrupt(move |scope| {
+        let global = scope.get_current_context().global(scope);
+        let key = v8::String::new(scope, "iterations").unwrap();
+        let iterations = global.get(scope, key.into()).unwrap();
+        tx.send(iterations.is_number()).unwrap();
+        let key = v8::String::new(scope, "interrupted").unwrap();
+        let value = v8::Boolean::new(scope, true);
+        global.set(scope, key.into(), value.into()).unwrap();
+      });
+      assert!(queued);
+      handle
+    });
+
+    // Loops until the interrupt sets `interrupted`.
+    runtime
+      .execute_script(
+        "loop.js",
+        r#"
+        globalThis.iterations = 0;
+        while (!globalThis.interrupted) iterations++;
+        "#,
+      )
+      .unwrap();
+    assert!(rx.recv().unwrap());
+
+    let handle = interrupter_thread.join().unwrap();
+    drop(runtime);
+    assert!(!handle.interrupt(|_| unreachable!()));
+  }
+
+  #[test]
+  fn test_interrupt_handle_state_borrowed() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let handle = runtime.interrupt_handle();
+    let ran = Arc::new(AtomicBool::new(false));
+    let ran_ = ran.clone();
+    assert!(handle.interrupt(move |_| ran_.store(true, Ordering::SeqCst)));
+
+    // Runs JavaScript while the state is borrowed, as a Rust callback that
+    // holds it would.
+    {
+      let state_rc = JsRuntime::state(runtime.v8_isolate());
+      let scope = &mut runtime.handle_scope();
+      let _state = state_rc.borrow_mut();
+      let source =
+        v8::String::new(scope, "for (let i = 0; i < 1e3; i++) {}").unwrap();
+      let script = v8::Script::compile(scope, source, None).unwrap();
+      script.run(scope).unwrap();
+    }
+    assert!(!ran.load(Ordering::SeqCst));
+
+    runtime
+      .execute_script("b.js", "for (let i = 0; i < 1e3; i++) {}")
+      .unwrap();
+    assert!(ran.load(Ordering::SeqCst));
+  }
+
+  #[test]
+  fn test_poll_time_budget() {
+    run_in_task(|cx| {
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        poll_time_budget: Some(Duration::ZERO),
+        ..Default::default()
+      });
+      runtime
+        .execute_script(
+          "a.js",
+          r#"
+          globalThis.resolved = 0;
+          for (let i = 0; i < 3; i++) {
+            Deno.core.opAsync("op_void_async").then(() => resolved++);
+          }
+          "#,
+        )
+        .unwrap();
+
+      // A single op is resolved per tick.
+      let mut ticks = 0;
+      while runtime.poll_event_loop(cx, false).is_pending() {
+        ticks += 1;
+        assert!(ticks <= 3);
+      }
+      assert_eq!(ticks, 3);
+      runtime
+        .execute_script("b.js", "if (resolved !== 3) throw new Error()")
+        .unwrap();
+    });
+  }
+
+  #[test]
+  fn test_waker() {
+    struct ArcWakeImpl(Arc<AtomicUsize>);
+    impl ArcWake for ArcWakeImpl {
+      fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.fetch_add(1, Ordering::Relaxed);
+      }
+    }
+
+    let mut runtime = JsRuntime::new(Default::default());
+    let awoken_times = Arc::new(AtomicUsize::new(0));
+    let waker =
+      futures::task::waker(Arc::new(ArcWakeImpl(awoken_times.clone())));
+    let cx = &mut Context::from_waker(&waker);
+    assert!(matches!(
+      runtime.poll_event_loop(cx, false),
+      Poll::Ready(Ok(()))
+    ));
+    assert_eq!(awoken_times.load(Ordering::Relaxed), 0);
+
+    let runtime_waker = runtime.waker();
+    std::thread::spawn(move || runtime_waker.wake())
+      .join()
+      .unwrap();
+    assert_eq!(awoken_times.load(Ordering::Relaxed), 1);
+  }
+
+  #[test]
+  fn test_completion_policy() {
+    async fn op_wait(
+      _: Rc<RefCell<OpState>>,
+      ms: u64,
+      _: (),
+    ) -> Result<(), Error> {
+      let (tx, rx) = oneshot::channel();
+      std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(ms));
+        let _ = tx.send(());
+      });
+      rx.await?;
+      Ok(())
+    }
+
+    fn new_runtime(completion_policy: CompletionPolicy) -> JsRuntime {
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        completion_policy,
+        ..Default::default()
+      });
+      runtime.register_op("op_wait", op_async(op_wait));
+      runtime.sync_ops_cache();
+      runtime
+        .execute_script(
+          "a.js",
+          r#"
+          globalThis.done = false;
+          const promise = Deno.core.opAsync("op_wait", 10);
+          Deno.core.unrefOp(promise[Symbol.for("Deno.core.internalPromiseId")]);
+          promise.then(() => done = true);
+          "#,
+        )
+        .unwrap();
+      runtime
+    }
+    fn is_done(runtime: &mut JsRuntime) -> bool {
+      let value = runtime.execute_script("b.js", "done").unwrap();
+      runtime.from_v8::<bool>(&value).unwrap()
+    }
+
+    run_in_task(|cx| {
+      let mut runtime = new_runtime(CompletionPolicy::RefedOpsDone);
+      assert!(matches!(
+        runtime.poll_event_loop(cx, false),
+        Poll::Ready(Ok(()))
+      ));
+      assert!(!is_done(&mut runtime));
+    });
+
+    let mut runtime = new_runtime(CompletionPolicy::AllOpsDone);
+    futures::executor::block_on(runtime.run_event_loop(false)).unwrap();
+    assert!(is_done(&mut runtime));
+
+    run_in_task(|cx| {
+      let mut runtime = new_runtime(CompletionPolicy::Never);
+      assert!(runtime.poll_event_loop(cx, false).is_pending());
+      runtime.set_completion_policy(CompletionPolicy::RefedOpsDone);
+      assert!(matches!(
+        runtime.poll_event_loop(cx, false),
+        Poll::Ready(Ok(()))
+      ));
+    });
+  }
+
+  #[test]
+  fn test_manual_clock() {
+    async fn op_sleep(
+      state: Rc<RefCell<OpState>>,
+      ms: u64,
+      _: (),
+    ) -> Result<(), Error> {
+      let clock = state.borrow().clock.clone();
+      let deadline = clock.now() + Duration::from_millis(ms);
+      clock.sleep_until(deadline).await;
+      Ok(())
+    }
+
+    run_in_task(|cx| {
+      let clock = crate::ManualClock::new();
+      let mut runtime = JsRuntime::new(RuntimeOptions {
+        clock: Some(Arc::new(clock.clone())),
+        ..Default::default()
+      });
+      runtime.register_op("op_sleep", op_async(op_sleep));
+      runtime.sync_ops_cache();
+      runtime
+        .execute_script(
+          "a.js",
+          r#"
+          globalThis.slept = false;
+          Deno.core.opAsync("op_sleep", 60 * 60 * 1000).then(() => slept = true);
+          "#,
+        )
+        .unwrap();
+      assert!(runtime.poll_event_loop(cx, false).is_pending());
+      clock.advance(Duration::from_secs(60 * 60));
+      assert!(matches!(
+        runtime.poll_event_loop(cx, false),
+        Poll::Ready(Ok(()))
+      ));
+      runtime
+        .execute_script("b.js", "if (!slept) throw new Error()")
+        .unwrap();
+    });
+  }
+
+  #[test]
+  fn test_queue_macrotask() {
+    run_in_task(|cx| {
+      let mut runtime = JsRuntime::new(Default::default());
+      runtime
+        .execute_script(
+          "a.js",
+          r#"
+          globalThis.log = [];
+          Deno.core.setMacrotaskCallback(() => {
+            log.push("js");
+            Promise.resolve().then(() => log.push("js microtask"));
+            return true;
+          });
+          "#,
+        )
+        .unwrap();
+      runtime.queue_macrotask(|runtime| {
+        runtime.execute_script(
+          "b.js",
+          r#"
+          log.push("rust");
+          Promise.resolve().then(() => log.push("rust microtask"));
+          "#,
+        )?;
+        runtime.queue_macrotask(|runtime| {
+          runtime.execute_script("c.js", "log.push('rust again')")?;
+          Ok(())
+        });
+        Ok(())
+      });
+
+      assert!(runtime.poll_event_loop(cx, false).is_pending());
+      assert!(matches!(
+        runtime.poll_event_loop(cx, false),
+        Poll::Ready(Ok(()))
+      ));
+      let log = runtime.execute_script("d.js", "log.join()").unwrap();
+      assert_eq!(
+        runtime.from_v8::<String>(&log).unwrap(),
+        "js,js microtask,rust,rust microtask,js,js microtask,rust again"
+      );
+
+      runtime.queue_macrotask(|_| Err(generic_error("macrotask failed")));
+      match runtime.poll_event_loop(cx, false) {
+        Poll::Ready(Err(err)) => {
+          assert_eq!(err.to_string(), "macrotask failed")
+        }
+        _ => panic!("expected the macrotask error"),
+      }
+    });
+  }
+
+  #[test]
+  fn test_performance_now() {
+    let clock = crate::ManualClock::new();
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      clock: Some(Arc::new(clock.clone())),
+      now_precision: Some(Duration::from_millis(100)),
+      ..Default::default()
+    });
+    let now = |runtime: &mut JsRuntime| {
+      let value = runtime
+        .execute_script("now.js", "performance.now()")
+        .unwrap();
+      runtime.from_v8::<f64>(&value).unwrap()
+    };
+    assert_eq!(now(&mut runtime), 0.0);
+    clock.advance(Duration::from_micros(1_234_567));
+    assert_eq!(now(&mut runtime), 1200.0);
+    clock.advance(Duration::from_millis(66));
+    assert_eq!(now(&mut runtime), 1300.0);
+
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "a.js",
+        r#"
+        const start = Deno.core.now();
+        while (performance.now() === start);
+        if (start < 0 || performance.now() <= start) throw new Error();
+        "#,
+      )
+      .unwrap();
+  }
+
+  #[test]
+  #[cfg(not(feature = "tracing"))]
+  fn test_op_log() {
+    lazy_static::lazy_static! {
+      static ref RECORDS: Mutex<Vec<(log::Level, String, String)>> =
+        Mutex::new(Vec::new());
+    }
+    struct TestLogger;
+    impl log::Log for TestLogger {
+      fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.target().starts_with("js::test_op_log")
+      }
+      fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+          RECORDS.lock().unwrap().push((
+            record.level(),
+            record.target().to_string(),
+            record.args().to_string(),
+          ));
+        }
+      }
+      fn flush(&self) {}
+    }
+    let _ = log::set_logger(&TestLogger);
+    log::set_max_level(log::LevelFilter::Debug);
+
+    let mut runtime = JsRuntime::new(Default::default());
+    runtime
+      .execute_script(
+        "log.js",
+        r#"
+        Deno.core.log("warn", "test_op_log", "plain");
+        const logger = Deno.core.createLogger("test_op_log::app");
+        logger.info("request", { method: "GET", status: 200, tags: ["a"] });
+        logger.trace("filtered out by the max level");
+        Deno.core.log("info", null, "filtered out by the logger");
+        "#,
+      )
+      .unwrap();
+    let err = runtime
+      .execute_script("log.js", "Deno.core.log('verbose', null, '')")
+      .unwrap_err();
+    assert!(err.to_string().contains("Invalid log level: verbose"));
+
+    assert_eq!(
+      *RECORDS.lock().unwrap(),
+      vec![
+        (
+          log::Level::Warn,
+          "js::test_op_log".to_string(),
+          "plain".to_string()
+        ),
+        (
+          log::Level::Info,
+          "js::test_op_log::app".to_string(),
+          r#"request method="GET" status=200 tags=["a"]"#.to_string()
+        ),
+      ]
+    );
+  }
+  #[test]
+  #[cfg(feature = "tracing")]
+  fn test_op_log_tracing() {
+    use std::collections::BTreeMap;
+    use tracing::field::Field;
+    use tracing::field::Visit;
+    use tracing::span;
+
+    type Events = Arc<Mutex<Vec<(tracing::Level, BTreeMap<String, String>)>>>;
+
+    /// Records the fields of the events with the target "js".
+    struct TestSubscriber(Events);
+
+    struct FieldVisitor(BTreeMap<String, String>);
+
+    impl Visit for FieldVisitor {
+      fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_string(), value.to_string());
+      }
+
+      fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self
+          .0
+          .insert(field.name().to_string(), format!("{:?}", value));
+      }
+    }
+
+    impl tracing::Subscriber for TestSubscriber {
+      fn enabled(&self, metadata: &tracing::Metadata) -> bool {
+        metadata.target() == "js"
+      }
+      fn new_span(&self, _: &span::Attributes) -> span::Id {
+        span::Id::from_u64(1)
+      }
+      fn record(&self, _: &span::Id, _: &span::Record) {}
+      fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+      fn event(&self, event: &tracing::Event) {
+        let mut visitor = FieldVisitor(BTreeMap::new());
+        event.record(&mut visitor);
+        let level = *event.metadata().level();
+        self.0.lock().unwrap().push((level, visitor.0));
+      }
+      fn enter(&self, _: &span::Id) {}
+      fn exit(&self, _: &span::Id) {}
+    }
+
+    let events = Events::default();
+    let subscriber = TestSubscriber(events.clone());
+    tracing::subscriber::with_default(subscriber, || {
+      let mut runtime = JsRuntime::new(Default::default());
+      runtime
+        .execute_script(
+          "log.js",
+          r#"
+          Deno.core.log("warn", null, "plain");
+          const logger = Deno.core.createLogger("app");
+          logger.info("request", { method: "GET", status: 200 });
+          "#,
+        )
+        .unwrap();
+    });
+
+    let fields = |fields: &[(&str, &str)]| {
+      fields
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect::<BTreeMap<_, _>>()
+    };
+    assert_eq!(
+      *events.lock().unwrap(),
+      vec![
+        (
+          tracing::Level::WARN,
+          fields(&[("js.target", "js"), ("message", "plain")])
+        ),
+        (
+          tracing::Level::INFO,
+          fields(&[
+            ("fields", r#"{"method":"GET","status":200}"#),
+            ("js.target", "js::app"),
+            ("message", "request"),
+          ])
+        ),
+      ]
+    );
+  }
 }
@@ -0,0 +1,367 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::generic_error;
+use crate::error::type_error;
+use crate::error::TerminationReason;
+use crate::modules::ModuleId;
+use crate::runtime::exception_to_err_result;
+use crate::JsRuntime;
+use crate::ModuleSpecifier;
+use crate::TerminationHandle;
+use anyhow::Error;
+use futures::channel::mpsc;
+use futures::channel::oneshot;
+use futures::future::poll_fn;
+use futures::future::FutureExt;
+use futures::future::LocalBoxFuture;
+use futures::stream::StreamExt;
+use serde_json::Value;
+use std::task::Poll;
+use std::thread;
+
+type CommandFn =
+  dyn for<'a> FnOnce(&'a mut JsRuntime) -> LocalBoxFuture<'a, ()> + Send;
+
+enum Command {
+  Run(Box<CommandFn>),
+  RunEventLoop(oneshot::Sender<Result<(), Error>>),
+}
+
+/// Owns a `JsRuntime` on a dedicated thread and drives its event loop, so
+/// that it can be used from multi-threaded code. The handle is `Send + Sync`
+/// and cheap to clone; each method sends a command to the runtime's thread
+/// and resolves with its result. Commands run one at a time, in order, and
+/// the event loop is polled whenever no command is running.
+///
+/// The thread exits once all handles have been dropped and the command it
+/// is running, if any, has completed.
+///
+/// The runtime is driven with `futures::executor::block_on()`, so ops that
+/// need a Tokio reactor must provide one themselves.
+#[derive(Clone)]
+pub struct JsRuntimeHandle {
+  sender: mpsc::UnboundedSender<Command>,
+  termination_handle: TerminationHandle,
+}
+
+impl JsRuntimeHandle {
+  /// Spawns a thread, creates a runtime on it with `create` and returns a
+  /// handle to it.
+  ///
+  /// # Panics
+  ///
+  /// Panics if `create` panics.
+  pub fn spawn<F>(create: F) -> Self
+  where
+    F: FnOnce() -> JsRuntime + Send + 'static,
+  {
+    let (sender, receiver) = mpsc::unbounded();
+    let (handle_sender, handle_receiver) = std::sync::mpsc::channel();
+    thread::Builder::new()
+      .name("JsRuntime".to_string())
+      .spawn(move || {
+        let mut runtime = create();
+        handle_sender.send(runtime.termination_handle()).unwrap();
+        futures::executor::block_on(run(&mut runtime, receiver));
+      })
+      .unwrap();
+    let termination_handle =
+      handle_receiver.recv().expect("JsRuntime creation panicked");
+    Self {
+      sender,
+      termination_handle,
+    }
+  }
+
+  /// Runs `f` with the runtime on its thread and resolves with its return
+  /// value, eg. to register ops or read from `OpState`.
+  pub async fn with_runtime<F, R>(&self, f: F) -> Result<R, Error>
+  where
+    F: FnOnce(&mut JsRuntime) -> R + Send + 'static,
+    R: Send + 'static,
+  {
+    self
+      .send(move |runtime| async move { Ok(f(runtime)) }.boxed_local())
+      .await
+  }
+
+  /// Executes traditional JavaScript code, see `JsRuntime::execute_script()`.
+  /// Resolves with the completion value converted using serde_v8.
+  pub async fn execute_script(
+    &self,
+    name: impl Into<String>,
+    source_code: impl Into<String>,
+  ) -> Result<Value, Error> {
+    let name = name.into();
+    let source_code = source_code.into();
+    self
+      .send(move |runtime| {
+        async move {
+          let value = runtime.execute_script(&name, &source_code)?;
+          runtime.from_v8(&value)
+        }
+        .boxed_local()
+      })
+      .await
+  }
+
+  /// Calls the global function at `path`, eg. `"handlers.onRequest"`, with
+  /// `args` converted using serde_v8. If it returns a promise, the event loop
+  /// is run until the promise settles. Resolves with the function's result.
+  pub async fn call(
+    &self,
+    path: impl Into<String>,
+    args: Vec<Value>,
+  ) -> Result<Value, Error> {
+    let path = path.into();
+    self
+      .send(move |runtime| {
+        async move {
+          let value = call_global(runtime, &path, &args)?;
+          let value = runtime.resolve_value(value).await?;
+          runtime.from_v8(&value)
+        }
+        .boxed_local()
+      })
+      .await
+  }
+
+  /// Loads a module and its dependencies as the main module, see
+  /// `JsRuntime::load_main_module()`.
+  pub async fn load_main_module(
+    &self,
+    specifier: ModuleSpecifier,
+    code: Option<String>,
+  ) -> Result<ModuleId, Error> {
+    self
+      .send(move |runtime| {
+        async move { runtime.load_main_module(&specifier, code).await }
+          .boxed_local()
+      })
+      .await
+  }
+
+  /// Loads a module and its dependencies, see
+  /// `JsRuntime::load_side_module()`.
+  pub async fn load_side_module(
+    &self,
+    specifier: ModuleSpecifier,
+    code: Option<String>,
+  ) -> Result<ModuleId, Error> {
+    self
+      .send(move |runtime| {
+        async move { runtime.load_side_module(&specifier, code).await }
+          .boxed_local()
+      })
+      .await
+  }
+
+  /// Evaluates a loaded module, running the event loop until its evaluation
+  /// (including top-level await) has completed.
+  pub async fn mod_evaluate(&self, id: ModuleId) -> Result<(), Error> {
+    self
+      .send(move |runtime| {
+        async move {
+          let receiver = runtime.mod_evaluate(id);
+          runtime.wait_for_mod_evaluation(receiver).await
+        }
+        .boxed_local()
+      })
+      .await
+  }
+
+  /// Resolves when the event loop has completed, see
+  /// `JsRuntime::run_event_loop()`. Also resolves with errors the event loop
+  /// returned while it was polled between commands.
+  pub async fn run_event_loop(&self) -> Result<(), Error> {
+    let (sender, receiver) = oneshot::channel();
+    self
+      .sender
+      .unbounded_send(Command::RunEventLoop(sender))
+      .map_err(|_| thread_exited())?;
+    receiver.await.map_err(|_| thread_exited())?
+  }
+
+  /// Terminates the JavaScript execution in progress, if any, from any
+  /// thread. Returns false if the runtime has been dropped.
+  pub fn terminate(&self, reason: TerminationReason) -> bool {
+    self.termination_handle.terminate(reason)
+  }
+
+  /// Terminates the JavaScript execution in progress with an error, see
+  /// `TerminationHandle::terminate_with_error()`.
+  pub fn terminate_with_error(&self, err: Error) -> bool {
+    self.termination_handle.terminate_with_error(err)
+  }
+
+  async fn send<F, R>(&self, f: F) -> Result<R, Error>
+  where
+    F: for<'a> FnOnce(&'a mut JsRuntime) -> LocalBoxFuture<'a, Result<R, Error>>
+      + Send
+      + 'static,
+    R: Send + 'static,
+  {
+    let (sender, receiver) = oneshot::channel();
+    let command: Box<CommandFn> = Box::new(move |runtime| {
+      let future = f(runtime);
+      async move {
+        let _ = sender.send(future.await);
+      }
+      .boxed_local()
+    });
+    self
+      .sender
+      .unbounded_send(Command::Run(command))
+      .map_err(|_| thread_exited())?;
+    receiver.await.map_err(|_| thread_exited())?
+  }
+}
+
+fn thread_exited() -> Error {
+  generic_error("JsRuntime thread has exited")
+}
+
+/// Runs commands from `receiver` until it's closed, polling the event loop
+/// while waiting for the next one.
+async fn run(
+  runtime: &mut JsRuntime,
+  mut receiver: mpsc::UnboundedReceiver<Command>,
+) {
+  let mut event_loop_error = None;
+  loop {
+    let command = poll_fn(|cx| {
+      if let Poll::Ready(command) = receiver.poll_next_unpin(cx) {
+        return Poll::Ready(command);
+      }
+      if event_loop_error.is_none() {
+        if let Poll::Ready(Err(err)) = runtime.poll_event_loop(cx, false) {
+          event_loop_error = Some(err);
+        }
+      }
+      Poll::Pending
+    })
+    .await;
+    match command {
+      None => break,
+      Some(Command::Run(f)) => f(runtime).await,
+      Some(Command::RunEventLoop(sender)) => {
+        let result = match event_loop_error.take() {
+          Some(err) => Err(err),
+          None => runtime.run_event_loop(false).await,
+        };
+        let _ = sender.send(result);
+      }
+    }
+  }
+}
+
+fn call_global(
+  runtime: &mut JsRuntime,
+  path: &str,
+  args: &[Value],
+) -> Result<v8::Global<v8::Value>, Error> {
+  let scope = &mut runtime.handle_scope();
+  let global = scope.get_current_context().global(scope);
+  let mut this: v8::Local<v8::Value> = v8::undefined(scope).into();
+  let mut value: v8::Local<v8::Value> = global.into();
+  for key in path.split('.') {
+    let object = v8::Local::<v8::Object>::try_from(value)
+      .map_err(|_| type_error(format!("{} is not a function", path)))?;
+    let key = v8::String::new(scope, key).unwrap();
+    this = object.into();
+    value = object.get(scope, key.into()).unwrap();
+  }
+  let function = v8::Local::<v8::Function>::try_from(value)
+    .map_err(|_| type_error(format!("{} is not a function", path)))?;
+  let args = args
+    .iter()
+    .map(|arg| serde_v8::to_v8(scope, arg))
+    .collect::<Result<Vec<_>, _>>()?;
+
+  let tc_scope = &mut v8::TryCatch::new(scope);
+  match function.call(tc_scope, this, &args) {
+    Some(value) => Ok(v8::Global::new(tc_scope, value)),
+    None => {
+      let exception = tc_scope.exception().unwrap();
+      exception_to_err_result(tc_scope, exception, false)
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::error::get_termination_reason;
+  use serde_json::json;
+
+  #[test]
+  fn test_runtime_handle() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<JsRuntimeHandle>();
+
+    let handle = JsRuntimeHandle::spawn(|| JsRuntime::new(Default::default()));
+    futures::executor::block_on(async {
+      let value = handle.execute_script("a.js", "1 + 2").await.unwrap();
+      assert_eq!(value, json!(3));
+      handle
+        .execute_script(
+          "b.js",
+          r#"
+          globalThis.handlers = {
+            prefix: "hello ",
+            greet(name) { return this.prefix + name; },
+            async later(n) { await null; return n * 2; },
+          };
+          "#,
+        )
+        .await
+        .unwrap();
+      let value = handle
+        .call("handlers.greet", vec![json!("world")])
+        .await
+        .unwrap();
+      assert_eq!(value, json!("hello world"));
+      let value = handle
+        .call("handlers.later", vec![json!(21)])
+        .await
+        .unwrap();
+      assert_eq!(value, json!(42));
+      let err = handle.call("handlers.nope", vec![]).await.unwrap_err();
+      assert_eq!(err.to_string(), "handlers.nope is not a function");
+
+      let specifier = crate::resolve_url("file:///main.js").unwrap();
+      let code = "globalThis.loaded = await Promise.resolve(true);".to_string();
+      let id = handle
+        .load_main_module(specifier, Some(code))
+        .await
+        .unwrap();
+      handle.mod_evaluate(id).await.unwrap();
+      let value = handle.execute_script("c.js", "loaded").await.unwrap();
+      assert_eq!(value, json!(true));
+      handle.run_event_loop().await.unwrap();
+
+      let op_count = handle
+        .with_runtime(|runtime| {
+          runtime.op_state().borrow().op_table.op_infos().len()
+        })
+        .await
+        .unwrap();
+      assert!(op_count > 0);
+    });
+
+    let handle2 = handle.clone();
+    let terminator = thread::spawn(move || {
+      thread::sleep(std::time::Duration::from_millis(100));
+      assert!(handle2.terminate(TerminationReason::AdminKill));
+    });
+    let err = futures::executor::block_on(
+      handle.execute_script("loop.js", "while (true) {}"),
+    )
+    .unwrap_err();
+    assert_eq!(
+      get_termination_reason(&err),
+      Some(&TerminationReason::AdminKill)
+    );
+    terminator.join().unwrap();
+  }
+}
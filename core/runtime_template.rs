@@ -0,0 +1,150 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::generic_error;
+use crate::JsRuntime;
+use crate::RuntimeOptions;
+use crate::Snapshot;
+use anyhow::Error;
+use std::sync::Arc;
+
+type OptionsFn = dyn Fn() -> RuntimeOptions + Send + Sync;
+
+/// Creates many identical runtimes cheaply, eg. for a pool of sandboxes.
+///
+/// The template builds one runtime from the options returned by `options_fn`,
+/// runs `setup` on it (eg. to execute a bundle of scripts) and snapshots it
+/// in memory. `spawn()` then deserializes new runtimes from that snapshot
+/// instead of initializing them from scratch, with their ops, module loader
+/// and other options wired up from a fresh call to `options_fn`, since
+/// `RuntimeOptions` can't be shared between runtimes.
+///
+/// Extension JS runs once, when the template is built. Modules loaded by
+/// `setup` are not part of the snapshot.
+///
+/// The template is cheap to clone and can be sent to other threads, so that
+/// each worker thread can spawn its own runtimes.
+#[derive(Clone)]
+pub struct RuntimeTemplate {
+  snapshot: Arc<[u8]>,
+  options_fn: Arc<OptionsFn>,
+}
+
+impl RuntimeTemplate {
+  /// Builds the template. Fails if `options_fn` returns options with a
+  /// `startup_snapshot`, which can't be used when snapshotting, or if `setup`
+  /// fails.
+  pub fn new<F, S>(options_fn: F, setup: S) -> Result<Self, Error>
+  where
+    F: Fn() -> RuntimeOptions + Send + Sync + 'static,
+    S: FnOnce(&mut JsRuntime) -> Result<(), Error>,
+  {
+    let mut options = options_fn();
+    if options.startup_snapshot.is_some() {
+      return Err(generic_error(
+        "A runtime template can not be built from a startup snapshot",
+      ));
+    }
+    options.will_snapshot = true;
+    let mut runtime = JsRuntime::new(options);
+    setup(&mut runtime)?;
+    let snapshot = runtime.snapshot();
+    Ok(Self {
+      snapshot: Arc::from(&*snapshot),
+      options_fn: Arc::new(options_fn),
+    })
+  }
+
+  /// Creates a runtime from the template's snapshot.
+  pub fn spawn(&self) -> JsRuntime {
+    let mut options = (self.options_fn)();
+    options.will_snapshot = false;
+    options.startup_snapshot =
+      Some(Snapshot::Boxed(self.snapshot.to_vec().into_boxed_slice()));
+    JsRuntime::new(options)
+  }
+
+  /// Returns the template's snapshot, eg. to store it and pass it as
+  /// `RuntimeOptions::startup_snapshot` in another process.
+  pub fn snapshot(&self) -> &[u8] {
+    &self.snapshot
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::op_sync;
+  use crate::Extension;
+  use crate::JsRuntimeHandle;
+  use serde_json::json;
+
+  #[test]
+  fn test_runtime_template() {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<RuntimeTemplate>();
+
+    let template = RuntimeTemplate::new(
+      || RuntimeOptions {
+        extensions: vec![Extension::builder()
+          .js(vec![(
+            "ext.js",
+            Box::new(|| {
+              Ok(
+                "globalThis.extInits = (globalThis.extInits ?? 0) + 1;"
+                  .to_string(),
+              )
+            }),
+          )])
+          .ops(vec![("op_double", op_sync(|_, n: u32, _: ()| Ok(n * 2)))])
+          .build()],
+        ..Default::default()
+      },
+      |runtime| {
+        runtime.execute_script("bundle.js", "globalThis.counter = 20;")?;
+        Ok(())
+      },
+    )
+    .unwrap();
+    assert!(!template.snapshot().is_empty());
+
+    let mut runtime1 = template.spawn();
+    let mut runtime2 = template.spawn();
+    runtime1
+      .execute_script(
+        "a.js",
+        r#"
+        if (extInits !== 1) throw new Error("extension JS ran again");
+        counter += Deno.core.opSync("op_double", 1);
+        if (counter !== 22) throw new Error(String(counter));
+        "#,
+      )
+      .unwrap();
+    // Runtimes spawned from the template don't share state.
+    runtime2
+      .execute_script("b.js", "if (counter !== 20) throw new Error()")
+      .unwrap();
+    drop(runtime1);
+    drop(runtime2);
+
+    let handle = JsRuntimeHandle::spawn(move || template.spawn());
+    let value = futures::executor::block_on(
+      handle.execute_script("c.js", "Deno.core.opSync('op_double', counter)"),
+    )
+    .unwrap();
+    assert_eq!(value, json!(40));
+
+    let err = RuntimeTemplate::new(
+      || RuntimeOptions {
+        startup_snapshot: Some(Snapshot::Static(&[])),
+        ..Default::default()
+      },
+      |_| Ok(()),
+    )
+    .err()
+    .unwrap();
+    assert_eq!(
+      err.to_string(),
+      "A runtime template can not be built from a startup snapshot"
+    );
+  }
+}
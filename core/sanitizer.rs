@@ -0,0 +1,184 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::generic_error;
+use crate::resources::ResourceId;
+use crate::resources::ResourceTable;
+use crate::OpState;
+use crate::PromiseId;
+use anyhow::Error;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::Context;
+use std::task::Poll;
+
+/// Maximum number of frames recorded for a creation stack.
+const STACK_FRAMES: usize = 10;
+
+/// Records where async ops were started and resources were opened, so the
+/// ones that are still pending or open when the event loop completes can be
+/// reported. Enabled with `RuntimeOptions::sanitizer`.
+///
+/// Only resources opened from within an op are tracked; resources added to
+/// the `ResourceTable` directly by the embedder are never reported.
+#[derive(Default)]
+pub(crate) struct Sanitizer {
+  op_stacks: HashMap<PromiseId, Rc<str>>,
+  resource_stacks: HashMap<ResourceId, Rc<str>>,
+}
+
+impl Sanitizer {
+  pub fn track_op(&mut self, promise_id: PromiseId, stack: Rc<str>) {
+    self.op_stacks.insert(promise_id, stack);
+  }
+
+  pub fn complete_op(&mut self, promise_id: PromiseId) {
+    self.op_stacks.remove(&promise_id);
+  }
+
+  /// Attributes the resources with ids in `first_rid..end_rid`, which were
+  /// opened while running an op, to the stack that started the op.
+  pub fn track_resources(
+    &mut self,
+    first_rid: ResourceId,
+    end_rid: ResourceId,
+    stack: &Rc<str>,
+  ) {
+    for rid in first_rid..end_rid {
+      self.resource_stacks.insert(rid, stack.clone());
+    }
+  }
+
+  /// Returns an error listing the given pending ops, as `(promise id, op
+  /// name)`, and the tracked resources that are still open.
+  pub fn check(
+    &mut self,
+    resource_table: &ResourceTable,
+    pending_ops: &[(PromiseId, String)],
+  ) -> Result<(), Error> {
+    self
+      .resource_stacks
+      .retain(|rid, _| resource_table.has(*rid));
+    let mut leaked_resources: Vec<_> = resource_table
+      .names()
+      .filter_map(|(rid, name)| {
+        let stack = self.resource_stacks.get(&rid)?;
+        Some((rid, name, stack))
+      })
+      .collect();
+    leaked_resources.sort_by_key(|(rid, _, _)| *rid);
+    if pending_ops.is_empty() && leaked_resources.is_empty() {
+      return Ok(());
+    }
+
+    let mut message = String::new();
+    if !pending_ops.is_empty() {
+      writeln!(message, "Leaking async ops:").unwrap();
+      for (promise_id, op_name) in pending_ops {
+        writeln!(
+          message,
+          "  - {} (promise {}), started at:",
+          op_name, promise_id
+        )
+        .unwrap();
+        if let Some(stack) = self.op_stacks.get(promise_id) {
+          message.push_str(stack);
+        }
+      }
+    }
+    if !leaked_resources.is_empty() {
+      writeln!(message, "Leaking resources:").unwrap();
+      for (rid, name, stack) in leaked_resources {
+        writeln!(message, "  - \"{}\" (rid {}), opened at:", name, rid)
+          .unwrap();
+        message.push_str(stack);
+      }
+    }
+    Err(generic_error(message.trim_end().to_string()))
+  }
+}
+
+/// Called before running an op. If the sanitizer is enabled, returns the
+/// current stack and the id the next resource will get, to be passed to
+/// `end_op()`.
+pub(crate) fn start_op(
+  scope: &mut v8::HandleScope,
+  op_state: &RefCell<OpState>,
+) -> Option<(Rc<str>, ResourceId)> {
+  op_state.borrow().sanitizer.as_ref()?;
+  let stack = current_stack(scope);
+  Some((stack, op_state.borrow().resource_table.next_rid()))
+}
+
+/// Called after running an op, attributing the resources it opened to the
+/// stack returned by `start_op()`.
+pub(crate) fn end_op(
+  op_state: &RefCell<OpState>,
+  (stack, first_rid): &(Rc<str>, ResourceId),
+) {
+  let mut op_state = op_state.borrow_mut();
+  let end_rid = op_state.resource_table.next_rid();
+  if let Some(sanitizer) = op_state.sanitizer.as_mut() {
+    sanitizer.track_resources(*first_rid, end_rid, stack);
+  }
+}
+
+/// Formats the current JavaScript stack, one indented frame per line.
+pub(crate) fn current_stack(scope: &mut v8::HandleScope) -> Rc<str> {
+  let mut stack = String::new();
+  // rusty_v8 doesn't expose `StackTrace::CurrentStackTrace()`, but the stack
+  // captured for a new exception is the current one.
+  let message = v8::String::empty(scope);
+  let exception = v8::Exception::error(scope, message);
+  if let Some(stack_trace) = v8::Exception::get_stack_trace(scope, exception) {
+    for i in 0..stack_trace.get_frame_count().min(STACK_FRAMES) {
+      let frame = stack_trace.get_frame(scope, i).unwrap();
+      let function_name = frame
+        .get_function_name(scope)
+        .map(|name| name.to_rust_string_lossy(scope))
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "<anonymous>".to_string());
+      let script_name = frame
+        .get_script_name_or_source_url(scope)
+        .map(|name| name.to_rust_string_lossy(scope))
+        .unwrap_or_else(|| "<unknown>".to_string());
+      writeln!(
+        stack,
+        "      at {} ({}:{}:{})",
+        function_name,
+        script_name,
+        frame.get_line_number(),
+        frame.get_column()
+      )
+      .unwrap();
+    }
+  }
+  stack.into()
+}
+
+/// Wraps the future of an async op, attributing resources opened while it is
+/// polled to the stack that started the op.
+pub(crate) struct TrackResources<F> {
+  pub inner: Pin<Box<F>>,
+  pub op_state: Rc<RefCell<OpState>>,
+  pub stack: Rc<str>,
+}
+
+impl<F: Future> Future for TrackResources<F> {
+  type Output = F::Output;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+    let this = self.get_mut();
+    let first_rid = this.op_state.borrow().resource_table.next_rid();
+    let poll = this.inner.as_mut().poll(cx);
+    let mut op_state = this.op_state.borrow_mut();
+    let end_rid = op_state.resource_table.next_rid();
+    if let Some(sanitizer) = op_state.sanitizer.as_mut() {
+      sanitizer.track_resources(first_rid, end_rid, &this.stack);
+    }
+    poll
+  }
+}
@@ -0,0 +1,83 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::OpId;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+use std::time::Instant;
+
+/// An op call that took longer than the threshold configured with
+/// `SlowOpDetector`.
+#[derive(Debug, Clone, Copy)]
+pub struct SlowOp<'a> {
+  pub op_id: OpId,
+  pub op_name: &'a str,
+  /// For a sync op, how long it blocked the isolate. For an async op, the
+  /// time from its dispatch until its future completed.
+  pub duration: Duration,
+  pub is_async: bool,
+}
+
+pub type SlowOpFn = dyn Fn(&SlowOp);
+
+/// Calls `callback` for every op that takes longer than a threshold, eg. to
+/// find what is stalling the event loop in production.
+/// See `RuntimeOptions::slow_op_detector`.
+#[derive(Clone)]
+pub struct SlowOpDetector {
+  /// Sync ops blocking for longer than this are reported.
+  pub sync_threshold: Duration,
+  /// Async ops taking longer than this to complete are reported.
+  pub async_threshold: Duration,
+  pub callback: Rc<SlowOpFn>,
+}
+
+impl SlowOpDetector {
+  pub(crate) fn check_sync(
+    &self,
+    op_id: OpId,
+    op_name: &str,
+    duration: Duration,
+  ) {
+    if duration > self.sync_threshold {
+      (self.callback)(&SlowOp {
+        op_id,
+        op_name,
+        duration,
+        is_async: false,
+      });
+    }
+  }
+}
+
+/// Wraps the future of an async op, reporting it to a `SlowOpDetector` if it
+/// completes after the async threshold.
+pub(crate) struct DetectSlowOp<F> {
+  pub inner: Pin<Box<F>>,
+  pub detector: SlowOpDetector,
+  pub op_id: OpId,
+  pub op_name: String,
+  pub started: Instant,
+}
+
+impl<F: Future> Future for DetectSlowOp<F> {
+  type Output = F::Output;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<F::Output> {
+    let this = self.get_mut();
+    let output = futures::ready!(this.inner.as_mut().poll(cx));
+    let duration = this.started.elapsed();
+    if duration > this.detector.async_threshold {
+      (this.detector.callback)(&SlowOp {
+        op_id: this.op_id,
+        op_name: &this.op_name,
+        duration,
+        is_async: true,
+      });
+    }
+    Poll::Ready(output)
+  }
+}
@@ -0,0 +1,192 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+//! Helpers for testing ops, module loaders and other code built on top of
+//! `JsRuntime`.
+
+use crate::serialize_op_result;
+use crate::JsRuntime;
+use crate::Op;
+use crate::OpCall;
+use crate::OpId;
+use crate::ZeroCopyBuf;
+use anyhow::Error;
+use futures::future::lazy;
+use serde_json::Value;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+
+/// Runs `f` to completion on the current thread with a `Context` whose waker
+/// unparks the thread, eg. to poll a `JsRuntime` by hand.
+pub fn run_in_task<F>(f: F)
+where
+  F: FnOnce(&mut Context) + Send + 'static,
+{
+  futures::executor::block_on(lazy(move |cx| f(cx)));
+}
+
+/// Polls the event loop of `runtime` until it completes, at most
+/// `max_poll_count` times, without waiting for wakeups in between.
+///
+/// # Panics
+///
+/// Panics if the event loop is still pending after `max_poll_count` polls.
+pub fn poll_until_ready(
+  runtime: &mut JsRuntime,
+  max_poll_count: usize,
+) -> Result<(), Error> {
+  let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+  for _ in 0..max_poll_count {
+    match runtime.poll_event_loop(&mut cx, false) {
+      Poll::Pending => continue,
+      Poll::Ready(result) => return result,
+    }
+  }
+  panic!(
+    "JsRuntime still not ready after polling {} times.",
+    max_poll_count
+  )
+}
+
+/// How an op registered with `MockOp::register()` completes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MockOpMode {
+  /// Calls return their response synchronously. Call the op with
+  /// `Deno.core.opSync()`.
+  Sync,
+  /// Calls resolve with their response on the next event loop turn. Call the
+  /// op with `Deno.core.opAsync()`.
+  Async,
+}
+
+/// A call received by an op registered with `MockOp::register()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockOpCall {
+  pub arg: Value,
+  /// The length of the buffer passed as the second argument, if any.
+  pub buf_len: Option<usize>,
+}
+
+/// An op that responds to every call with the same value and records the
+/// calls it receives, for testing JS code that calls ops.
+///
+/// ```ignore
+/// let op = MockOp::new(MockOpMode::Async, json!(43));
+/// let calls = op.calls();
+/// op.register(&mut runtime, "op_test");
+/// runtime.execute_script("test.js", "Deno.core.opAsync('op_test', 42)")?;
+/// assert_eq!(calls.borrow()[0].arg, json!(42));
+/// ```
+pub struct MockOp {
+  mode: MockOpMode,
+  response: Value,
+  dispatch_count: Arc<AtomicUsize>,
+  calls: Rc<RefCell<Vec<MockOpCall>>>,
+}
+
+impl MockOp {
+  pub fn new(mode: MockOpMode, response: Value) -> Self {
+    Self {
+      mode,
+      response,
+      dispatch_count: Default::default(),
+      calls: Default::default(),
+    }
+  }
+
+  /// The number of times the op was dispatched, including calls whose
+  /// arguments failed to deserialize.
+  pub fn dispatch_count(&self) -> Arc<AtomicUsize> {
+    self.dispatch_count.clone()
+  }
+
+  /// The calls the op received, in order.
+  pub fn calls(&self) -> Rc<RefCell<Vec<MockOpCall>>> {
+    self.calls.clone()
+  }
+
+  /// Registers the op on `runtime` and makes it callable from JS.
+  pub fn register(self, runtime: &mut JsRuntime, name: &str) -> OpId {
+    let op_id = runtime.register_op(name, move |state, payload| {
+      self.dispatch_count.fetch_add(1, Ordering::Relaxed);
+      let (promise_id, op_id) = (payload.promise_id, payload.op_id);
+      let args = payload.deserialize::<Value, Option<ZeroCopyBuf>>();
+      let result = args.map(|(arg, buf)| {
+        self.calls.borrow_mut().push(MockOpCall {
+          arg,
+          buf_len: buf.map(|buf| buf.len()),
+        });
+        self.response.clone()
+      });
+      let result = serialize_op_result(result, state);
+      match self.mode {
+        MockOpMode::Sync => Op::Sync(result),
+        MockOpMode::Async => {
+          Op::Async(OpCall::ready((promise_id, op_id, result)))
+        }
+      }
+    });
+    runtime.sync_ops_cache();
+    op_id
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn test_mock_op() {
+    let mut runtime = JsRuntime::new(Default::default());
+    let sync_op = MockOp::new(MockOpMode::Sync, json!("sync"));
+    let sync_calls = sync_op.calls();
+    sync_op.register(&mut runtime, "op_mock_sync");
+    let async_op = MockOp::new(MockOpMode::Async, json!({ "a": 1 }));
+    let async_calls = async_op.calls();
+    let async_dispatch_count = async_op.dispatch_count();
+    async_op.register(&mut runtime, "op_mock_async");
+
+    runtime
+      .execute_script(
+        "mock_op.js",
+        r#"
+        if (Deno.core.opSync("op_mock_sync", 42) !== "sync") {
+          throw new Error("unexpected sync response");
+        }
+        Deno.core.opAsync("op_mock_async", null, new Uint8Array(3))
+          .then((res) => {
+            if (res.a !== 1) {
+              throw new Error("unexpected async response");
+            }
+            globalThis.resolved = true;
+          });
+        "#,
+      )
+      .unwrap();
+    poll_until_ready(&mut runtime, 10).unwrap();
+    runtime
+      .execute_script("check.js", "if (!resolved) throw 1")
+      .unwrap();
+
+    assert_eq!(
+      *sync_calls.borrow(),
+      vec![MockOpCall {
+        arg: json!(42),
+        buf_len: None
+      }]
+    );
+    assert_eq!(
+      *async_calls.borrow(),
+      vec![MockOpCall {
+        arg: Value::Null,
+        buf_len: Some(3)
+      }]
+    );
+    assert_eq!(async_dispatch_count.load(Ordering::Relaxed), 1);
+  }
+}
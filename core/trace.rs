@@ -0,0 +1,44 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+//! Instrumentation through the `tracing` crate, enabled with the "tracing"
+//! feature. Without the feature the macros expand to (almost) nothing and
+//! their arguments are not evaluated, so instrumented code paths pay no cost.
+//!
+//! All spans and events are emitted at the `TRACE` level, with a target under
+//! `deno_core`, so subscribers can filter them independently of the
+//! embedder's own instrumentation.
+
+/// Enters a span that is exited when the returned guard is dropped.
+#[cfg(feature = "tracing")]
+macro_rules! trace_span {
+  ($($arg:tt)*) => {
+    tracing::trace_span!($($arg)*).entered()
+  };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_span {
+  ($($arg:tt)*) => {
+    $crate::trace::NoSpan
+  };
+}
+
+/// Emits an event in the current span.
+#[cfg(feature = "tracing")]
+macro_rules! trace_event {
+  ($($arg:tt)*) => {
+    tracing::trace!($($arg)*)
+  };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! trace_event {
+  ($($arg:tt)*) => {{}};
+}
+
+pub(crate) use trace_event;
+pub(crate) use trace_span;
+
+/// Stand-in for an entered span when the "tracing" feature is disabled.
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct NoSpan;
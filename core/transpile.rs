@@ -0,0 +1,157 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::disk_cache::hash_parts;
+use crate::disk_cache::write_atomically;
+use crate::ModuleSpecifier;
+use anyhow::Error;
+use std::fs;
+use std::path::PathBuf;
+
+/// The kind of source a module is written in, as detected from the extension
+/// of its URL.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MediaType {
+  JavaScript,
+  Jsx,
+  TypeScript,
+  /// A TypeScript declaration file (`.d.ts`).
+  Dts,
+  Tsx,
+  Json,
+  Unknown,
+}
+
+impl MediaType {
+  pub fn from_specifier(specifier: &ModuleSpecifier) -> Self {
+    let path = specifier.path().to_lowercase();
+    if path.ends_with(".d.ts") || path.ends_with(".d.mts") {
+      return Self::Dts;
+    }
+    match path.rsplit_once('.').map(|(_, ext)| ext) {
+      Some("js" | "mjs" | "cjs") => Self::JavaScript,
+      Some("jsx") => Self::Jsx,
+      Some("ts" | "mts" | "cts") => Self::TypeScript,
+      Some("tsx") => Self::Tsx,
+      Some("json") => Self::Json,
+      _ => Self::Unknown,
+    }
+  }
+
+  /// Whether modules of this type have to be transpiled to JavaScript before
+  /// V8 can compile them.
+  pub fn needs_transpile(self) -> bool {
+    matches!(self, Self::Jsx | Self::TypeScript | Self::Dts | Self::Tsx)
+  }
+}
+
+/// Transpiles modules written in TypeScript or JSX to JavaScript. The runtime
+/// consults it for every module whose `MediaType` needs transpiling, just
+/// before compiling it. See `RuntimeOptions::media_transpiler`.
+pub trait MediaTranspiler {
+  /// Returns the JavaScript for `code`, preferably with an inline source map
+  /// so that stack traces point into the original source.
+  fn transpile(
+    &self,
+    specifier: &ModuleSpecifier,
+    media_type: MediaType,
+    code: &str,
+  ) -> Result<String, Error>;
+
+  /// Identifies the transpiler and its configuration, so that a
+  /// `TranspileCache` doesn't serve code transpiled differently. Its output is
+  /// only cached if this returns `Some`.
+  fn version(&self) -> Option<String> {
+    None
+  }
+}
+
+/// Stores the output of a `MediaTranspiler` across runs, so that unchanged
+/// modules aren't transpiled again. See `RuntimeOptions::transpile_cache`.
+pub trait TranspileCache {
+  /// Returns the code stored for `key`, if any.
+  fn get(&self, key: &str) -> Option<String>;
+
+  /// Stores `code` for `key`. Errors are logged and otherwise ignored by the
+  /// runtime.
+  fn set(&self, key: &str, code: &str) -> Result<(), Error>;
+}
+
+/// Returns the key the transpiled code of a module is cached under: a hash of
+/// the transpiler version, the module's URL and its source.
+pub fn transpile_cache_key(
+  version: &str,
+  specifier: &ModuleSpecifier,
+  source: &str,
+) -> String {
+  hash_parts(&[version, specifier.as_str(), source])
+}
+
+/// A `TranspileCache` storing each entry as a file in a directory.
+pub struct DiskTranspileCache {
+  dir: PathBuf,
+}
+
+impl DiskTranspileCache {
+  /// The directory is created when the first entry is stored.
+  pub fn new(dir: impl Into<PathBuf>) -> Self {
+    Self { dir: dir.into() }
+  }
+
+  fn path(&self, key: &str) -> PathBuf {
+    self.dir.join(format!("{}.js", key))
+  }
+}
+
+impl TranspileCache for DiskTranspileCache {
+  fn get(&self, key: &str) -> Option<String> {
+    fs::read_to_string(self.path(key)).ok()
+  }
+
+  fn set(&self, key: &str, code: &str) -> Result<(), Error> {
+    fs::create_dir_all(&self.dir)?;
+    write_atomically(&self.path(key), code.as_bytes())?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::resolve_url;
+
+  #[test]
+  fn media_type_from_specifier() {
+    let cases = [
+      ("file:///a.js", MediaType::JavaScript),
+      ("https://example.com/a.MJS?x=.ts", MediaType::JavaScript),
+      ("file:///a.jsx", MediaType::Jsx),
+      ("file:///a.ts", MediaType::TypeScript),
+      ("file:///a.d.ts", MediaType::Dts),
+      ("file:///a.tsx", MediaType::Tsx),
+      ("file:///a.json", MediaType::Json),
+      ("file:///a", MediaType::Unknown),
+    ];
+    for (specifier, media_type) in cases {
+      let specifier = resolve_url(specifier).unwrap();
+      assert_eq!(MediaType::from_specifier(&specifier), media_type);
+    }
+    assert!(MediaType::Tsx.needs_transpile());
+    assert!(!MediaType::JavaScript.needs_transpile());
+  }
+
+  #[test]
+  fn disk_transpile_cache() {
+    let dir = std::env::temp_dir()
+      .join(format!("deno_core_transpile_cache_{}", std::process::id()));
+    let cache = DiskTranspileCache::new(&dir);
+    let specifier = resolve_url("file:///a.ts").unwrap();
+    let key = transpile_cache_key("1", &specifier, "let a: number;");
+    assert_ne!(key, transpile_cache_key("2", &specifier, "let a: number;"));
+    assert_ne!(key, transpile_cache_key("1", &specifier, "let a: string;"));
+
+    assert_eq!(cache.get(&key), None);
+    cache.set(&key, "let a;").unwrap();
+    assert_eq!(cache.get(&key), Some("let a;".to_string()));
+    fs::remove_dir_all(dir).unwrap();
+  }
+}
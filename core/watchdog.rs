@@ -0,0 +1,213 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::TerminationReason;
+use crate::runtime::JsRuntimeState;
+use std::cell::RefCell;
+use std::ffi::c_void;
+use std::rc::Rc;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+struct WatchdogState {
+  last_heartbeat: Instant,
+  /// The watchdog only fires while the isolate is busy, so an event loop that
+  /// is idle waiting on I/O is not mistaken for a stalled isolate.
+  busy: bool,
+  shutdown: bool,
+}
+
+struct WatchdogShared {
+  state: Mutex<WatchdogState>,
+  condvar: Condvar,
+  terminated: AtomicBool,
+  /// Script location that was executing when the watchdog fired.
+  location: Mutex<Option<String>>,
+}
+
+/// Terminates execution of an isolate whose heartbeat stops for longer than a
+/// given timeout. This catches infinite loops, catastrophic regular expression
+/// backtracking and pathological GC stalls.
+///
+/// Execution is interrupted rather than terminated from the watchdog thread,
+/// so the script location that was executing can be recorded first. For a
+/// `JsRuntime`, it is included in the message of the resulting error.
+///
+/// The watchdog owns a thread that is stopped when the watchdog is dropped.
+/// `JsRuntime` sends heartbeats automatically when created with
+/// `RuntimeOptions::watchdog_timeout`; a standalone watchdog must be driven
+/// with `heartbeat()` and `idle()`.
+pub struct Watchdog {
+  shared: Arc<WatchdogShared>,
+  thread: Option<thread::JoinHandle<()>>,
+}
+
+/// Keeps a `Watchdog` busy until dropped, see `Watchdog::busy()`.
+pub(crate) struct WatchdogGuard {
+  shared: Arc<WatchdogShared>,
+  was_busy: bool,
+}
+
+impl Drop for WatchdogGuard {
+  fn drop(&mut self) {
+    if !self.was_busy {
+      self.shared.state.lock().unwrap().busy = false;
+    }
+  }
+}
+
+impl Watchdog {
+  pub fn new(isolate_handle: v8::IsolateHandle, timeout: Duration) -> Self {
+    let shared = Arc::new(WatchdogShared {
+      state: Mutex::new(WatchdogState {
+        last_heartbeat: Instant::now(),
+        busy: false,
+        shutdown: false,
+      }),
+      condvar: Condvar::new(),
+      terminated: AtomicBool::new(false),
+      location: Mutex::new(None),
+    });
+    let thread_shared = shared.clone();
+    let thread = thread::spawn(move || {
+      let shared = thread_shared;
+      let mut state = shared.state.lock().unwrap();
+      loop {
+        if state.shutdown {
+          return;
+        }
+        if !state.busy {
+          state = shared.condvar.wait(state).unwrap();
+          continue;
+        }
+        let elapsed = state.last_heartbeat.elapsed();
+        if elapsed >= timeout {
+          state.busy = false;
+          // Ownership of this reference is passed to the interrupt callback.
+          let data = Arc::into_raw(shared.clone()) as *mut c_void;
+          if !isolate_handle.request_interrupt(interrupt_callback, data) {
+            // The isolate has been disposed, so the callback will never run.
+            drop(unsafe { Arc::from_raw(data as *const WatchdogShared) });
+          }
+          continue;
+        }
+        state = shared
+          .condvar
+          .wait_timeout(state, timeout - elapsed)
+          .unwrap()
+          .0;
+      }
+    });
+    Self {
+      shared,
+      thread: Some(thread),
+    }
+  }
+
+  /// Signals that the isolate is alive and busy running code. The isolate is
+  /// terminated if no further heartbeat or `idle()` call follows within the
+  /// timeout.
+  pub fn heartbeat(&self) {
+    self.beat();
+  }
+
+  /// Sends a heartbeat, and calls `idle()` when the returned guard is
+  /// dropped unless the watchdog was already busy, eg. for nested calls.
+  pub(crate) fn busy(&self) -> WatchdogGuard {
+    WatchdogGuard {
+      shared: self.shared.clone(),
+      was_busy: self.beat(),
+    }
+  }
+
+  /// Returns whether the watchdog was busy before the heartbeat.
+  fn beat(&self) -> bool {
+    let mut state = self.shared.state.lock().unwrap();
+    state.last_heartbeat = Instant::now();
+    let was_busy = state.busy;
+    if !was_busy {
+      state.busy = true;
+      self.shared.condvar.notify_one();
+    }
+    was_busy
+  }
+
+  /// Signals that the isolate is no longer running code, eg. because the
+  /// event loop is waiting on I/O. Pauses the watchdog until the next
+  /// heartbeat.
+  pub fn idle(&self) {
+    self.shared.state.lock().unwrap().busy = false;
+  }
+
+  /// Returns true if the watchdog has terminated execution of the isolate.
+  pub fn has_terminated(&self) -> bool {
+    self.shared.terminated.load(Ordering::SeqCst)
+  }
+
+  /// Returns the script location (`file:line:column`) that was executing when
+  /// the watchdog terminated execution, if it could be determined.
+  pub fn terminated_at(&self) -> Option<String> {
+    self.shared.location.lock().unwrap().clone()
+  }
+}
+
+extern "C" fn interrupt_callback(isolate: &mut v8::Isolate, data: *mut c_void) {
+  let shared = unsafe { Arc::from_raw(data as *const WatchdogShared) };
+  let location = current_location(isolate);
+  if let Some(state_rc) = isolate.get_slot::<Rc<RefCell<JsRuntimeState>>>() {
+    if let Ok(mut state) = state_rc.try_borrow_mut() {
+      *state.termination_reason.lock().unwrap() =
+        Some(TerminationReason::Timeout);
+      state.termination_message = Some(match &location {
+        Some(location) => format!(
+          "execution terminated: time budget exceeded while executing {}",
+          location
+        ),
+        None => "execution terminated: time budget exceeded".to_string(),
+      });
+    }
+  }
+  *shared.location.lock().unwrap() = location;
+  shared.terminated.store(true, Ordering::SeqCst);
+  isolate.terminate_execution();
+}
+
+/// Returns the location of the innermost JavaScript frame that is executing
+/// in a `JsRuntime`'s isolate.
+fn current_location(isolate: &mut v8::Isolate) -> Option<String> {
+  let state_rc = isolate.get_slot::<Rc<RefCell<JsRuntimeState>>>()?.clone();
+  let context = state_rc.try_borrow().ok()?.global_context.clone()?;
+  let scope = &mut unsafe { v8::CallbackScope::new(isolate) };
+  let scope = &mut v8::HandleScope::new(scope);
+  let context = v8::Local::new(scope, context);
+  let scope = &mut v8::ContextScope::new(scope, context);
+  let message = v8::String::empty(scope);
+  let exception = v8::Exception::error(scope, message);
+  let stack_trace = v8::Exception::get_stack_trace(scope, exception)?;
+  let frame = stack_trace.get_frame(scope, 0)?;
+  let script_name = frame
+    .get_script_name_or_source_url(scope)
+    .map(|name| name.to_rust_string_lossy(scope))
+    .unwrap_or_else(|| "<unknown>".to_string());
+  Some(format!(
+    "{}:{}:{}",
+    script_name,
+    frame.get_line_number(),
+    frame.get_column()
+  ))
+}
+
+impl Drop for Watchdog {
+  fn drop(&mut self) {
+    self.shared.state.lock().unwrap().shutdown = true;
+    self.shared.condvar.notify_one();
+    if let Some(thread) = self.thread.take() {
+      let _ = thread.join();
+    }
+  }
+}
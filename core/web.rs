@@ -0,0 +1,392 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::error::custom_error;
+use crate::error::range_error;
+use crate::error::type_error;
+use crate::include_js_files;
+use crate::op_sync;
+use crate::Extension;
+use crate::OpState;
+use crate::ZeroCopyBuf;
+use anyhow::Error;
+use serde::Deserialize;
+use serde::Serialize;
+use url::form_urlencoded;
+use url::quirks;
+use url::Url;
+
+/// An extension providing `URL`, `URLSearchParams`, `TextEncoder`,
+/// `TextDecoder`, `atob()` and `btoa()` globals, for embedders that want a
+/// basic web-like environment without the full Deno runtime. `TextDecoder`
+/// supports UTF-8 and UTF-16LE, without streaming.
+///
+/// Requires the `web` feature.
+pub fn init_web() -> Extension {
+  Extension::builder()
+    .js(include_js_files!(
+      prefix "deno:core",
+      "04_web.js",
+    ))
+    .ops(vec![
+      ("op_url_parse", op_sync(op_url_parse)),
+      ("op_url_set", op_sync(op_url_set)),
+      (
+        "op_url_parse_search_params",
+        op_sync(op_url_parse_search_params),
+      ),
+      (
+        "op_url_stringify_search_params",
+        op_sync(op_url_stringify_search_params),
+      ),
+      ("op_text_decode", op_sync(op_text_decode)),
+      ("op_base64_encode", op_sync(op_base64_encode)),
+      ("op_base64_decode", op_sync(op_base64_decode)),
+    ])
+    .build()
+}
+
+/// The components of a URL, as returned by the getters of `URL`.
+#[derive(Serialize)]
+struct UrlParts {
+  href: String,
+  origin: String,
+  protocol: String,
+  username: String,
+  password: String,
+  host: String,
+  hostname: String,
+  port: String,
+  pathname: String,
+  search: String,
+  hash: String,
+}
+
+impl From<&Url> for UrlParts {
+  fn from(url: &Url) -> Self {
+    Self {
+      href: quirks::href(url).to_string(),
+      origin: quirks::origin(url),
+      protocol: quirks::protocol(url).to_string(),
+      username: quirks::username(url).to_string(),
+      password: quirks::password(url).to_string(),
+      host: quirks::host(url).to_string(),
+      hostname: quirks::hostname(url).to_string(),
+      port: quirks::port(url).to_string(),
+      pathname: quirks::pathname(url).to_string(),
+      search: quirks::search(url).to_string(),
+      hash: quirks::hash(url).to_string(),
+    }
+  }
+}
+
+fn op_url_parse(
+  _state: &mut OpState,
+  href: String,
+  base: Option<String>,
+) -> Result<UrlParts, Error> {
+  let base = match base {
+    Some(base) => {
+      Some(Url::parse(&base).map_err(|_| type_error("Invalid base URL"))?)
+    }
+    None => None,
+  };
+  let url = Url::options()
+    .base_url(base.as_ref())
+    .parse(&href)
+    .map_err(|_| type_error("Invalid URL"))?;
+  Ok(UrlParts::from(&url))
+}
+
+/// Applies the setter of `URL` named `setter` to `href`. Like in browsers,
+/// invalid values are ignored, except for `href`.
+fn op_url_set(
+  _state: &mut OpState,
+  href: String,
+  (setter, value): (String, String),
+) -> Result<UrlParts, Error> {
+  let mut url = Url::parse(&href).map_err(|_| type_error("Invalid URL"))?;
+  match setter.as_str() {
+    "href" => quirks::set_href(&mut url, &value)
+      .map_err(|_| type_error("Invalid URL"))?,
+    "protocol" => quirks::set_protocol(&mut url, &value).unwrap_or_default(),
+    "username" => quirks::set_username(&mut url, &value).unwrap_or_default(),
+    "password" => quirks::set_password(&mut url, &value).unwrap_or_default(),
+    "host" => quirks::set_host(&mut url, &value).unwrap_or_default(),
+    "hostname" => quirks::set_hostname(&mut url, &value).unwrap_or_default(),
+    "port" => quirks::set_port(&mut url, &value).unwrap_or_default(),
+    "pathname" => quirks::set_pathname(&mut url, &value),
+    "search" => quirks::set_search(&mut url, &value),
+    "hash" => quirks::set_hash(&mut url, &value),
+    _ => return Err(type_error(format!("Invalid URL setter: {}", setter))),
+  }
+  Ok(UrlParts::from(&url))
+}
+
+fn op_url_parse_search_params(
+  _state: &mut OpState,
+  query: String,
+  _: (),
+) -> Result<Vec<(String, String)>, Error> {
+  Ok(
+    form_urlencoded::parse(query.as_bytes())
+      .into_owned()
+      .collect(),
+  )
+}
+
+fn op_url_stringify_search_params(
+  _state: &mut OpState,
+  pairs: Vec<(String, String)>,
+  _: (),
+) -> Result<String, Error> {
+  Ok(
+    form_urlencoded::Serializer::new(String::new())
+      .extend_pairs(pairs)
+      .finish(),
+  )
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TextDecodeOptions {
+  /// Either "utf-8" or "utf-16le"; labels are resolved in JS.
+  encoding: String,
+  fatal: bool,
+  ignore_bom: bool,
+}
+
+fn op_text_decode(
+  _state: &mut OpState,
+  buf: ZeroCopyBuf,
+  options: TextDecodeOptions,
+) -> Result<String, Error> {
+  let invalid = || type_error("The encoded data is not valid");
+  match options.encoding.as_str() {
+    "utf-8" => {
+      let mut bytes = &buf[..];
+      if !options.ignore_bom && bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        bytes = &bytes[3..];
+      }
+      if options.fatal {
+        std::str::from_utf8(bytes)
+          .map(str::to_string)
+          .map_err(|_| invalid())
+      } else {
+        Ok(String::from_utf8_lossy(bytes).into_owned())
+      }
+    }
+    "utf-16le" => {
+      let mut bytes = &buf[..];
+      if !options.ignore_bom && bytes.starts_with(&[0xff, 0xfe]) {
+        bytes = &bytes[2..];
+      }
+      let chunks = bytes.chunks_exact(2);
+      let trailing_byte = !chunks.remainder().is_empty();
+      let units = chunks.map(|unit| u16::from_le_bytes([unit[0], unit[1]]));
+      let mut text = String::with_capacity(bytes.len() / 2);
+      for c in char::decode_utf16(units) {
+        match c {
+          Ok(c) => text.push(c),
+          Err(_) if options.fatal => return Err(invalid()),
+          Err(_) => text.push(char::REPLACEMENT_CHARACTER),
+        }
+      }
+      if trailing_byte {
+        if options.fatal {
+          return Err(invalid());
+        }
+        text.push(char::REPLACEMENT_CHARACTER);
+      }
+      Ok(text)
+    }
+    encoding => Err(range_error(format!("Unsupported encoding: {}", encoding))),
+  }
+}
+
+const BASE64_ALPHABET: &[u8; 64] =
+  b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// `btoa()`: encodes a string of code points up to U+00FF, each standing for
+/// a byte, as base64.
+fn op_base64_encode(
+  _state: &mut OpState,
+  data: String,
+  _: (),
+) -> Result<String, Error> {
+  let bytes = data
+    .chars()
+    .map(|c| u8::try_from(u32::from(c)))
+    .collect::<Result<Vec<u8>, _>>()
+    .map_err(|_| {
+      custom_error(
+        "InvalidCharacterError",
+        "The string to be encoded contains characters outside of the Latin1 range",
+      )
+    })?;
+  let mut out = String::with_capacity((bytes.len() + 2) / 3 * 4);
+  for chunk in bytes.chunks(3) {
+    let n = chunk
+      .iter()
+      .enumerate()
+      .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+    for i in 0..4 {
+      if i <= chunk.len() {
+        out.push(BASE64_ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+      } else {
+        out.push('=');
+      }
+    }
+  }
+  Ok(out)
+}
+
+/// `atob()`: decodes base64 into a string with a code point per byte, with
+/// the "forgiving-base64 decode" algorithm of the HTML standard.
+fn op_base64_decode(
+  _state: &mut OpState,
+  data: String,
+  _: (),
+) -> Result<String, Error> {
+  let invalid = || {
+    custom_error(
+      "InvalidCharacterError",
+      "The string to be decoded is not correctly encoded",
+    )
+  };
+  let mut input: Vec<u8> = data
+    .bytes()
+    .filter(|b| !matches!(b, b'\t' | b'\n' | b'\x0c' | b'\r' | b' '))
+    .collect();
+  if input.len() % 4 == 0 {
+    for _ in 0..2 {
+      if input.last() == Some(&b'=') {
+        input.pop();
+      }
+    }
+  }
+  if input.len() % 4 == 1 {
+    return Err(invalid());
+  }
+
+  let mut out = String::with_capacity(input.len() / 4 * 3);
+  let mut n = 0u32;
+  let mut bits = 0;
+  for b in input {
+    let value = match b {
+      b'A'..=b'Z' => b - b'A',
+      b'a'..=b'z' => b - b'a' + 26,
+      b'0'..=b'9' => b - b'0' + 52,
+      b'+' => 62,
+      b'/' => 63,
+      _ => return Err(invalid()),
+    };
+    n = n << 6 | value as u32;
+    bits += 6;
+    if bits >= 8 {
+      bits -= 8;
+      out.push(char::from((n >> bits) as u8));
+    }
+  }
+  Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::JsRuntime;
+  use crate::RuntimeOptions;
+
+  #[test]
+  fn test_base64() {
+    let encode =
+      |data: &str| op_base64_encode(&mut OpState::new(), data.to_string(), ());
+    assert_eq!(encode("").unwrap(), "");
+    assert_eq!(encode("f").unwrap(), "Zg==");
+    assert_eq!(encode("fo").unwrap(), "Zm8=");
+    assert_eq!(encode("foo").unwrap(), "Zm9v");
+    assert_eq!(encode("\u{ff}\u{0}").unwrap(), "/wA=");
+    assert!(encode("\u{100}").is_err());
+
+    let decode =
+      |data: &str| op_base64_decode(&mut OpState::new(), data.to_string(), ());
+    assert_eq!(decode("Zm9v").unwrap(), "foo");
+    assert_eq!(decode("Zm8=").unwrap(), "fo");
+    assert_eq!(decode(" Zg\n").unwrap(), "f");
+    assert_eq!(decode("/wA=").unwrap(), "\u{ff}\u{0}");
+    assert!(decode("Zm9vY").is_err());
+    assert!(decode("Zm9v!").is_err());
+    assert!(decode("Zg=").is_err());
+  }
+
+  #[test]
+  fn test_web_extension() {
+    let mut runtime = JsRuntime::new(RuntimeOptions {
+      extensions: vec![init_web()],
+      ..Default::default()
+    });
+    runtime
+      .execute_script(
+        "web_test.js",
+        r#"
+        function assertEquals(actual, expected) {
+          if (actual !== expected) {
+            throw new Error(`expected ${expected}, got ${actual}`);
+          }
+        }
+
+        const url = new URL("../b?x=1#h", "https://user@example.com:8080/a/");
+        assertEquals(url.href, "https://user@example.com:8080/b?x=1#h");
+        assertEquals(url.origin, "https://example.com:8080");
+        assertEquals(url.pathname, "/b");
+        url.port = "not a port";
+        assertEquals(url.port, "8080");
+        url.searchParams.append("y", "a b");
+        assertEquals(url.search, "?x=1&y=a+b");
+        url.search = "?z=2";
+        assertEquals(url.searchParams.get("z"), "2");
+        assertEquals(url.searchParams.has("x"), false);
+        assertEquals(String(url), "https://user@example.com:8080/b?z=2#h");
+        let threw = false;
+        try {
+          new URL("not a url");
+        } catch (err) {
+          threw = err instanceof TypeError;
+        }
+        assertEquals(threw, true);
+
+        const params = new URLSearchParams({ b: "2", a: "1" });
+        params.sort();
+        assertEquals(params.toString(), "a=1&b=2");
+        assertEquals([...params.keys()].join(), "a,b");
+
+        const bytes = new TextEncoder().encode("hé");
+        assertEquals(bytes.length, 3);
+        assertEquals(new TextDecoder().decode(bytes), "hé");
+        const dest = new Uint8Array(2);
+        const { read, written } = new TextEncoder().encodeInto("hé", dest);
+        assertEquals(read, 1);
+        assertEquals(written, 1);
+        assertEquals(
+          new TextDecoder("utf-16le").decode(new Uint8Array([0x68, 0, 0xe9, 0])),
+          "hé",
+        );
+        threw = false;
+        try {
+          new TextDecoder("utf-8", { fatal: true }).decode(new Uint8Array([0xff]));
+        } catch (err) {
+          threw = err instanceof TypeError;
+        }
+        assertEquals(threw, true);
+
+        assertEquals(btoa("hello"), "aGVsbG8=");
+        assertEquals(atob("aGVsbG8="), "hello");
+        try {
+          atob("!");
+        } catch (err) {
+          assertEquals(err.name, "InvalidCharacterError");
+        }
+        "#,
+      )
+      .unwrap();
+  }
+}
@@ -0,0 +1,21 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+"use strict";
+
+((window) => {
+  const core = window.Deno.core;
+  const { pathFromURL } = window.__bootstrap.util;
+
+  function openPlugin(filename) {
+    if (!("op_open_plugin" in core.ops())) {
+      throw new Error("Plugins are not supported by this build of Deno");
+    }
+    const opNames = core.opSync("op_open_plugin", pathFromURL(filename));
+    // Make the plugin's ops callable with `Deno.core.opSync()`
+    core.syncOpsCache();
+    return opNames;
+  }
+
+  window.__bootstrap.plugins = {
+    openPlugin,
+  };
+})(this);
@@ -6,6 +6,8 @@ pub mod http;
 pub mod io;
 pub mod os;
 pub mod permissions;
+#[cfg(feature = "plugins")]
+pub mod plugin;
 pub mod process;
 pub mod runtime;
 pub mod signal;
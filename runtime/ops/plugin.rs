@@ -0,0 +1,71 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use crate::permissions::Permissions;
+use deno_core::error::type_error;
+use deno_core::error::AnyError;
+use deno_core::op_sync;
+use deno_core::plugin_api;
+use deno_core::Extension;
+use deno_core::OpFn;
+use deno_core::OpId;
+use deno_core::OpState;
+use dlopen::symbor::Library;
+use log::debug;
+use std::path::PathBuf;
+
+pub fn init() -> Extension {
+  Extension::builder()
+    .ops(vec![("op_open_plugin", op_sync(op_open_plugin))])
+    .build()
+}
+
+/// Loads a plugin and registers its ops. Returns the names of the ops, which
+/// JS can call after syncing its ops cache.
+///
+/// Plugin libraries are never unloaded: their ops stay registered for the
+/// lifetime of the runtime, and the code of op functions lives in the library.
+pub fn op_open_plugin(
+  state: &mut OpState,
+  filename: String,
+  _: (),
+) -> Result<Vec<String>, AnyError> {
+  let filename = PathBuf::from(&filename);
+
+  super::check_unstable(state, "Deno.openPlugin");
+  state.borrow_mut::<Permissions>().ffi.check(&filename)?;
+
+  debug!("Loading Plugin: {:#?}", filename);
+  let plugin_lib = Library::open(&filename)?;
+  let init_fn = *unsafe {
+    plugin_lib.symbol::<plugin_api::InitFn>(plugin_api::INIT_FN_NAME)
+  }?;
+  // Keep the library loaded, see above.
+  std::mem::forget(plugin_lib);
+
+  let mut interface = PluginInterface {
+    state,
+    op_names: Vec::new(),
+  };
+  init_fn(&mut interface);
+  Ok(interface.op_names)
+}
+
+struct PluginInterface<'a> {
+  state: &'a mut OpState,
+  op_names: Vec<String>,
+}
+
+impl plugin_api::Interface for PluginInterface<'_> {
+  fn register_op(
+    &mut self,
+    name: &str,
+    op_fn: Box<OpFn>,
+  ) -> Result<OpId, AnyError> {
+    if self.state.op_table.has_op(name) {
+      return Err(type_error(format!("Op \"{}\" is already registered", name)));
+    }
+    let op_id = self.state.op_table.register_op(name, op_fn);
+    self.op_names.push(name.to_string());
+    Ok(op_id)
+  }
+}
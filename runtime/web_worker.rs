@@ -8,6 +8,7 @@ use crate::tokio_util::run_basic;
 use crate::BootstrapOptions;
 use deno_broadcast_channel::InMemoryBroadcastChannel;
 use deno_core::error::AnyError;
+use deno_core::error::CompileError;
 use deno_core::error::JsError;
 use deno_core::futures::channel::mpsc;
 use deno_core::futures::future::poll_fn;
@@ -90,7 +91,13 @@ impl Serialize for WorkerControlEvent {
     match self {
       WorkerControlEvent::TerminalError(error)
       | WorkerControlEvent::Error(error) => {
-        let value = match error.downcast_ref::<JsError>() {
+        // Compile errors wrap the JsError together with a diagnostic.
+        let js_error = error.downcast_ref::<JsError>().or_else(|| {
+          error
+            .downcast_ref::<CompileError>()
+            .and_then(|e| e.error.downcast_ref::<JsError>())
+        });
+        let value = match js_error {
           Some(js_error) => json!({
             "message": js_error.message,
             "fileName": js_error.script_resource_name,
@@ -428,6 +435,10 @@ impl WebWorker {
     // Append exts
     extensions.extend(runtime_exts);
     extensions.extend(deno_ns_exts); // May be empty
+    #[cfg(feature = "plugins")]
+    if options.use_deno_namespace {
+      extensions.push(ops::plugin::init());
+    }
     extensions.extend(std::mem::take(&mut options.extensions));
 
     let mut js_runtime = JsRuntime::new(RuntimeOptions {
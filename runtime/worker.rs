@@ -147,6 +147,8 @@ impl MainWorker {
       // Permissions ext (worker specific state)
       perm_ext,
     ];
+    #[cfg(feature = "plugins")]
+    extensions.push(ops::plugin::init());
     extensions.extend(std::mem::take(&mut options.extensions));
 
     let mut js_runtime = JsRuntime::new(RuntimeOptions {
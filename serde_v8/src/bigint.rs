@@ -0,0 +1,30 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+//! Serializes integers as JS BigInts instead of Numbers, for values like ids
+//! and file offsets that may not fit in a f64. For use with
+//! `#[serde(with = "serde_v8::bigint")]`.
+//!
+//! `i128` and `u128` are always serialized as BigInts. Deserialization accepts
+//! both Numbers and BigInts, and fails if the value doesn't fit.
+
+use serde::de::Error;
+use serde::Deserialize;
+use serde::Deserializer;
+use serde::Serializer;
+
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+  T: Copy + Into<i128>,
+  S: Serializer,
+{
+  serializer.serialize_i128((*value).into())
+}
+
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+  T: TryFrom<i128>,
+  D: Deserializer<'de>,
+{
+  let value = i128::deserialize(deserializer)?;
+  T::try_from(value).map_err(|_| D::Error::custom("integer out of range"))
+}
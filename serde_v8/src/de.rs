@@ -55,31 +55,18 @@ where
   Ok(t)
 }
 
-macro_rules! wip {
-  ($method:ident) => {
-    fn $method<V>(self, _v: V) -> Result<V::Value>
-    where
-      V: Visitor<'de>,
-    {
-      unimplemented!()
-    }
-  };
-}
-
-// TODO: maybe check for BigInt truncation ?
-// (i.e: values larger than i64/u64 can hold)
+// Numbers are truncated to fit the integer type, BigInts that don't fit are
+// rejected with Error::IntegerOutOfRange
 macro_rules! deserialize_signed {
   ($dmethod:ident, $vmethod:ident, $t:tt) => {
     fn $dmethod<V>(self, visitor: V) -> Result<V::Value>
     where
       V: Visitor<'de>,
     {
-      let value: $t = match self.input.is_big_int() {
-        true => {
-          let bigint = v8::Local::<v8::BigInt>::try_from(self.input);
-          bigint.unwrap().i64_value().0 as $t
-        }
-        false => self.input.integer_value(&mut self.scope).unwrap() as $t,
+      let value: $t = match v8::Local::<v8::BigInt>::try_from(self.input) {
+        Ok(bigint) => $t::try_from(bigint_to_i128(bigint)?)
+          .map_err(|_| Error::IntegerOutOfRange)?,
+        Err(_) => self.input.integer_value(&mut self.scope).unwrap() as $t,
       };
       visitor.$vmethod(value)
     }
@@ -92,18 +79,46 @@ macro_rules! deserialize_unsigned {
     where
       V: Visitor<'de>,
     {
-      let value: $t = match self.input.is_big_int() {
-        true => {
-          let bigint = v8::Local::<v8::BigInt>::try_from(self.input);
-          bigint.unwrap().u64_value().0 as $t
-        }
-        false => self.input.integer_value(&mut self.scope).unwrap() as $t,
+      let value: $t = match v8::Local::<v8::BigInt>::try_from(self.input) {
+        Ok(bigint) => $t::try_from(bigint_to_u128(bigint)?)
+          .map_err(|_| Error::IntegerOutOfRange)?,
+        Err(_) => self.input.integer_value(&mut self.scope).unwrap() as $t,
       };
       visitor.$vmethod(value)
     }
   };
 }
 
+// Returns the sign and magnitude of a BigInt that fits in 128 bits
+fn bigint_words(bigint: v8::Local<v8::BigInt>) -> Result<(bool, u128)> {
+  if bigint.word_count() > 2 {
+    return Err(Error::IntegerOutOfRange);
+  }
+  let mut words = [0u64; 2];
+  let (sign_bit, _) = bigint.to_words_array(&mut words);
+  Ok((sign_bit, words[0] as u128 | ((words[1] as u128) << 64)))
+}
+
+fn bigint_to_i128(bigint: v8::Local<v8::BigInt>) -> Result<i128> {
+  match bigint_words(bigint)? {
+    (false, magnitude) => {
+      i128::try_from(magnitude).map_err(|_| Error::IntegerOutOfRange)
+    }
+    (true, magnitude) if magnitude <= i128::MIN.unsigned_abs() => {
+      Ok((magnitude as i128).wrapping_neg())
+    }
+    _ => Err(Error::IntegerOutOfRange),
+  }
+}
+
+fn bigint_to_u128(bigint: v8::Local<v8::BigInt>) -> Result<u128> {
+  match bigint_words(bigint)? {
+    (false, magnitude) => Ok(magnitude),
+    (true, 0) => Ok(0),
+    _ => Err(Error::IntegerOutOfRange),
+  }
+}
+
 impl<'de, 'a, 'b, 's, 'x> de::Deserializer<'de>
   for &'x mut Deserializer<'a, 'b, 's>
 {
@@ -126,6 +141,20 @@ impl<'de, 'a, 'b, 's, 'x> de::Deserializer<'de>
           self.deserialize_f64(visitor)
         }
       }
+      // Visit the smallest integer type that holds the BigInt
+      ValueType::BigInt => {
+        let bigint = v8::Local::<v8::BigInt>::try_from(self.input).unwrap();
+        match bigint.i64_value() {
+          (v, true) => visitor.visit_i64(v),
+          _ => match bigint.u64_value() {
+            (v, true) => visitor.visit_u64(v),
+            _ => match bigint_to_i128(bigint) {
+              Ok(v) => visitor.visit_i128(v),
+              Err(_) => visitor.visit_u128(bigint_to_u128(bigint)?),
+            },
+          },
+        }
+      }
       ValueType::String => self.deserialize_string(visitor),
       ValueType::Array => self.deserialize_seq(visitor),
       ValueType::Object => self.deserialize_map(visitor),
@@ -155,11 +184,13 @@ impl<'de, 'a, 'b, 's, 'x> de::Deserializer<'de>
   deserialize_signed!(deserialize_i16, visit_i16, i16);
   deserialize_signed!(deserialize_i32, visit_i32, i32);
   deserialize_signed!(deserialize_i64, visit_i64, i64);
+  deserialize_signed!(deserialize_i128, visit_i128, i128);
   // unsigned
   deserialize_unsigned!(deserialize_u8, visit_u8, u8);
   deserialize_unsigned!(deserialize_u16, visit_u16, u16);
   deserialize_unsigned!(deserialize_u32, visit_u32, u32);
   deserialize_unsigned!(deserialize_u64, visit_u64, u64);
+  deserialize_unsigned!(deserialize_u128, visit_u128, u128);
 
   fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value>
   where
@@ -175,7 +206,23 @@ impl<'de, 'a, 'b, 's, 'x> de::Deserializer<'de>
     visitor.visit_f64(self.input.number_value(self.scope).unwrap())
   }
 
-  wip!(deserialize_char);
+  fn deserialize_char<V>(self, visitor: V) -> Result<V::Value>
+  where
+    V: Visitor<'de>,
+  {
+    // A char is a string holding exactly one code point
+    if self.input.is_string() {
+      let v8_string = v8::Local::<v8::String>::try_from(self.input).unwrap();
+      let string = v8_string.to_rust_string_lossy(self.scope);
+      let mut chars = string.chars();
+      match (chars.next(), chars.next()) {
+        (Some(c), None) => visitor.visit_char(c),
+        _ => Err(Error::ExpectedString),
+      }
+    } else {
+      Err(Error::ExpectedString)
+    }
+  }
 
   fn deserialize_str<V>(self, visitor: V) -> Result<V::Value>
   where
@@ -197,8 +244,25 @@ impl<'de, 'a, 'b, 's, 'x> de::Deserializer<'de>
     }
   }
 
-  wip!(deserialize_bytes);
-  wip!(deserialize_byte_buf);
+  fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value>
+  where
+    V: Visitor<'de>,
+  {
+    self.deserialize_byte_buf(visitor)
+  }
+
+  // Copies the contents of an ArrayBufferView (e.g: Uint8Array)
+  fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value>
+  where
+    V: Visitor<'de>,
+  {
+    v8::Local::<v8::ArrayBufferView>::try_from(self.input)
+      .and_then(|view| {
+        magic::zero_copy_buf::ZeroCopyBuf::try_new(self.scope, view)
+      })
+      .map_err(|_| Error::ExpectedArray)
+      .and_then(|zb| visitor.visit_byte_buf(Vec::from(&*zb)))
+  }
 
   fn deserialize_option<V>(self, visitor: V) -> Result<V::Value>
   where
@@ -349,6 +413,15 @@ impl<'de, 'a, 'b, 's, 'x> de::Deserializer<'de>
       return visitor.visit_bytes(&data);
     }
 
+    // Magic BufferSource
+    if name == magic::buffer_source::NAME {
+      let source =
+        magic::buffer_source::BufferSource::from_v8(self.scope, self.input)
+          .ok_or(Error::ExpectedBuffer)?;
+      let source = Box::into_raw(Box::new(source));
+      return visitor.visit_u64(source as usize as u64);
+    }
+
     // Magic ByteString
     if name == magic::bytestring::NAME {
       if let Some(v8_string) = self.input.to_string(self.scope) {
@@ -372,6 +445,18 @@ impl<'de, 'a, 'b, 's, 'x> de::Deserializer<'de>
       }
     }
 
+    // Magic Date
+    if name == magic::date::NAME {
+      let millis = match v8::Local::<v8::Date>::try_from(self.input) {
+        Ok(date) => date.value_of(),
+        Err(_) if self.input.is_number() => {
+          self.input.number_value(self.scope).unwrap()
+        }
+        Err(_) => return Err(Error::ExpectedDate),
+      };
+      return visitor.visit_f64(millis);
+    }
+
     // Regular struct
     let obj = v8::Local::<v8::Object>::try_from(self.input)
       .map_err(|_| Error::ExpectedObject)?;
@@ -11,11 +11,14 @@ pub enum Error {
 
   ExpectedBoolean,
   ExpectedInteger,
+  IntegerOutOfRange,
   ExpectedString,
   ExpectedArray,
+  ExpectedBuffer,
   ExpectedMap,
   ExpectedEnum,
   ExpectedObject,
+  ExpectedDate,
 
   ExpectedUtf8,
 
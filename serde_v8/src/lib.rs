@@ -1,4 +1,5 @@
 // Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+pub mod bigint;
 mod de;
 mod error;
 mod keys;
@@ -12,7 +13,9 @@ pub use de::{from_v8, from_v8_cached, Deserializer};
 pub use error::{Error, Result};
 pub use keys::KeyCache;
 pub use magic::buffer::MagicBuffer as Buffer;
+pub use magic::buffer_source::BufferSource;
 pub use magic::bytestring::ByteString;
+pub use magic::date::Date;
 pub use magic::string_or_buffer::StringOrBuffer;
 pub use magic::Value;
 pub use ser::{to_v8, Serializer};
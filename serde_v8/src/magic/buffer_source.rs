@@ -0,0 +1,80 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use std::fmt;
+
+pub const NAME: &str = "$__v8_magic_BufferSource";
+
+/// The backing store and byte range of a WebIDL `BufferSource`, that is an
+/// `ArrayBuffer` or an `ArrayBufferView` (a typed array or a `DataView`).
+///
+/// Unlike `Buffer`, it also accepts plain and shared `ArrayBuffer`s, and
+/// leaves how the bytes are accessed to its user, eg. `deno_core::BufferView`.
+/// The backing store is kept alive while it exists.
+pub struct BufferSource {
+  pub backing_store: v8::SharedRef<v8::BackingStore>,
+  pub byte_offset: usize,
+  pub byte_length: usize,
+}
+
+impl BufferSource {
+  /// Returns None if `value` isn't an `ArrayBuffer` or an `ArrayBufferView`.
+  pub fn from_v8(
+    scope: &mut v8::HandleScope,
+    value: v8::Local<v8::Value>,
+  ) -> Option<Self> {
+    let (backing_store, byte_offset, byte_length) =
+      if let Ok(buffer) = v8::Local::<v8::ArrayBuffer>::try_from(value) {
+        (buffer.get_backing_store(), 0, buffer.byte_length())
+      } else if let Ok(buffer) =
+        v8::Local::<v8::SharedArrayBuffer>::try_from(value)
+      {
+        (buffer.get_backing_store(), 0, buffer.byte_length())
+      } else {
+        let view = v8::Local::<v8::ArrayBufferView>::try_from(value).ok()?;
+        let buffer = view.buffer(scope)?;
+        (
+          buffer.get_backing_store(),
+          view.byte_offset(),
+          view.byte_length(),
+        )
+      };
+    // A detached buffer reports a length of 0.
+    let byte_length =
+      byte_length.min(backing_store.byte_length().saturating_sub(byte_offset));
+    Some(Self {
+      backing_store,
+      byte_offset,
+      byte_length,
+    })
+  }
+}
+
+impl<'de> serde::Deserialize<'de> for BufferSource {
+  fn deserialize<D>(deserializer: D) -> Result<BufferSource, D::Error>
+  where
+    D: serde::Deserializer<'de>,
+  {
+    struct ValueVisitor {}
+
+    impl<'de> serde::de::Visitor<'de> for ValueVisitor {
+      type Value = BufferSource;
+
+      fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+        formatter.write_str("a serde_v8::BufferSource")
+      }
+
+      fn visit_u64<E>(self, v: u64) -> Result<Self::Value, E>
+      where
+        E: serde::de::Error,
+      {
+        // The deserializer passes ownership of a boxed `BufferSource`.
+        let source = unsafe { Box::from_raw(v as usize as *mut BufferSource) };
+        Ok(*source)
+      }
+    }
+
+    static FIELDS: [&str; 0] = [];
+    let visitor = ValueVisitor {};
+    deserializer.deserialize_struct(NAME, &FIELDS, visitor)
+  }
+}
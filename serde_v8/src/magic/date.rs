@@ -0,0 +1,136 @@
+// Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
+
+use std::time::Duration;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use serde::{de::Visitor, Deserialize, Deserializer, Serialize, Serializer};
+
+pub const NAME: &str = "$__v8_magic_date";
+pub const FIELD: &str = "$__v8_magic_date_millis";
+
+/// A point in time that is serialized to a JS `Date`. Deserializes from a
+/// `Date` or a number of milliseconds since the epoch.
+///
+/// JS dates have millisecond precision, so sub-millisecond parts of the time
+/// are truncated when it's converted to a `Date`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date(pub SystemTime);
+
+impl Date {
+  pub fn now() -> Self {
+    Self(SystemTime::now())
+  }
+
+  /// Returns None for non-finite or out of range values, eg. the time value
+  /// of an invalid `Date`.
+  pub fn from_millis(millis: f64) -> Option<Self> {
+    let whole = millis.abs().trunc();
+    if !millis.is_finite() || whole >= u64::MAX as f64 {
+      return None;
+    }
+    // Split off the fraction so whole milliseconds convert exactly
+    let nanos = ((millis.abs() - whole) * 1e6).round() as u64;
+    let offset =
+      Duration::from_millis(whole as u64) + Duration::from_nanos(nanos);
+    let time = if millis < 0.0 {
+      UNIX_EPOCH.checked_sub(offset)
+    } else {
+      UNIX_EPOCH.checked_add(offset)
+    };
+    time.map(Self)
+  }
+
+  pub fn to_millis(&self) -> f64 {
+    fn millis(offset: Duration) -> f64 {
+      offset.as_millis() as f64
+        + (offset.subsec_nanos() % 1_000_000) as f64 / 1e6
+    }
+    match self.0.duration_since(UNIX_EPOCH) {
+      Ok(offset) => millis(offset),
+      Err(err) => -millis(err.duration()),
+    }
+  }
+
+  /// Returns None if the time is outside the range of a JS `Date`.
+  pub fn to_v8<'s>(
+    &self,
+    scope: &mut v8::HandleScope<'s>,
+  ) -> Option<v8::Local<'s, v8::Date>> {
+    v8::Date::new(scope, self.to_millis())
+      .filter(|date| !date.value_of().is_nan())
+  }
+
+  /// Returns None for an invalid `Date`.
+  pub fn from_v8(date: v8::Local<v8::Date>) -> Option<Self> {
+    Self::from_millis(date.value_of())
+  }
+}
+
+impl From<SystemTime> for Date {
+  fn from(time: SystemTime) -> Self {
+    Self(time)
+  }
+}
+
+impl From<Date> for SystemTime {
+  fn from(date: Date) -> Self {
+    date.0
+  }
+}
+
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> From<chrono::DateTime<Tz>> for Date {
+  fn from(time: chrono::DateTime<Tz>) -> Self {
+    Self(time.into())
+  }
+}
+
+#[cfg(feature = "chrono")]
+impl From<Date> for chrono::DateTime<chrono::Utc> {
+  fn from(date: Date) -> Self {
+    date.0.into()
+  }
+}
+
+impl Serialize for Date {
+  fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+  where
+    S: Serializer,
+  {
+    use serde::ser::SerializeStruct;
+
+    let mut s = serializer.serialize_struct(NAME, 1)?;
+    s.serialize_field(FIELD, &self.to_millis().to_bits())?;
+    s.end()
+  }
+}
+
+impl<'de> Deserialize<'de> for Date {
+  fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+  where
+    D: Deserializer<'de>,
+  {
+    struct ValueVisitor {}
+
+    impl<'de> Visitor<'de> for ValueVisitor {
+      type Value = Date;
+
+      fn expecting(
+        &self,
+        formatter: &mut std::fmt::Formatter,
+      ) -> std::fmt::Result {
+        formatter.write_str("a serde_v8::Date")
+      }
+
+      fn visit_f64<E>(self, v: f64) -> Result<Self::Value, E>
+      where
+        E: serde::de::Error,
+      {
+        Date::from_millis(v).ok_or_else(|| E::custom("Invalid Date"))
+      }
+    }
+
+    deserializer.deserialize_struct(NAME, &[], ValueVisitor {})
+  }
+}
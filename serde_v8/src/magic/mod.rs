@@ -1,6 +1,8 @@
 // Copyright 2018-2021 the Deno authors. All rights reserved. MIT license.
 pub mod buffer;
+pub mod buffer_source;
 pub mod bytestring;
+pub mod date;
 mod field;
 pub mod string_or_buffer;
 mod value;
@@ -9,6 +9,7 @@ pub enum ValueType {
   Null,
   Bool,
   Number,
+  BigInt,
   String,
   Array,
   ArrayBufferView,
@@ -21,6 +22,8 @@ impl ValueType {
       return Self::Bool;
     } else if v.is_number() {
       return Self::Number;
+    } else if v.is_big_int() {
+      return Self::BigInt;
     } else if v.is_string() {
       return Self::String;
     } else if v.is_array() {
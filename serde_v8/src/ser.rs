@@ -339,11 +339,52 @@ impl<'a, 'b, 'c> ser::SerializeStruct
   }
 }
 
+pub struct MagicDateSerializer<'a, 'b, 'c> {
+  scope: ScopePtr<'a, 'b, 'c>,
+  millis: f64,
+}
+
+impl<'a, 'b, 'c> MagicDateSerializer<'a, 'b, 'c> {
+  pub fn new(scope: ScopePtr<'a, 'b, 'c>) -> Self {
+    Self {
+      scope,
+      millis: f64::NAN,
+    }
+  }
+}
+
+impl<'a, 'b, 'c> ser::SerializeStruct for MagicDateSerializer<'a, 'b, 'c> {
+  type Ok = JsValue<'a>;
+  type Error = Error;
+
+  fn serialize_field<T: ?Sized + Serialize>(
+    &mut self,
+    key: &'static str,
+    value: &T,
+  ) -> Result<()> {
+    if key != magic::date::FIELD {
+      unreachable!();
+    }
+    // The time value is transmitted as the bits of a f64
+    let transmuted: u64 = value.serialize(magic::FieldSerializer {})?;
+    self.millis = f64::from_bits(transmuted);
+    Ok(())
+  }
+
+  fn end(self) -> JsResult<'a> {
+    let scope = &mut *self.scope.borrow_mut();
+    v8::Date::new(scope, self.millis)
+      .map(|v| v.into())
+      .ok_or(Error::ExpectedDate)
+  }
+}
+
 // Dispatches between magic and regular struct serializers
 pub enum StructSerializers<'a, 'b, 'c> {
   Magic(MagicSerializer<'a>),
   MagicBuffer(MagicBufferSerializer<'a, 'b, 'c>),
   MagicByteString(MagicByteStringSerializer<'a, 'b, 'c>),
+  MagicDate(MagicDateSerializer<'a, 'b, 'c>),
   Regular(ObjectSerializer<'a, 'b, 'c>),
 }
 
@@ -360,6 +401,7 @@ impl<'a, 'b, 'c> ser::SerializeStruct for StructSerializers<'a, 'b, 'c> {
       StructSerializers::Magic(s) => s.serialize_field(key, value),
       StructSerializers::MagicBuffer(s) => s.serialize_field(key, value),
       StructSerializers::MagicByteString(s) => s.serialize_field(key, value),
+      StructSerializers::MagicDate(s) => s.serialize_field(key, value),
       StructSerializers::Regular(s) => s.serialize_field(key, value),
     }
   }
@@ -369,6 +411,7 @@ impl<'a, 'b, 'c> ser::SerializeStruct for StructSerializers<'a, 'b, 'c> {
       StructSerializers::Magic(s) => s.end(),
       StructSerializers::MagicBuffer(s) => s.end(),
       StructSerializers::MagicByteString(s) => s.end(),
+      StructSerializers::MagicDate(s) => s.end(),
       StructSerializers::Regular(s) => s.end(),
     }
   }
@@ -480,6 +523,15 @@ impl<'a, 'b, 'c> ser::Serializer for Serializer<'a, 'b, 'c> {
     Ok(v8::Integer::new_from_unsigned(&mut self.scope.borrow_mut(), v).into())
   }
 
+  // 128-bit integers don't fit in a Number, so they become BigInts
+  fn serialize_i128(self, v: i128) -> JsResult<'a> {
+    bigint_from_words(&mut self.scope.borrow_mut(), v < 0, v.unsigned_abs())
+  }
+
+  fn serialize_u128(self, v: u128) -> JsResult<'a> {
+    bigint_from_words(&mut self.scope.borrow_mut(), false, v)
+  }
+
   fn serialize_f64(self, v: f64) -> JsResult<'a> {
     Ok(v8::Number::new(&mut self.scope.borrow_mut(), v).into())
   }
@@ -488,8 +540,8 @@ impl<'a, 'b, 'c> ser::Serializer for Serializer<'a, 'b, 'c> {
     Ok(v8::Boolean::new(&mut self.scope.borrow_mut(), v).into())
   }
 
-  fn serialize_char(self, _v: char) -> JsResult<'a> {
-    unimplemented!();
+  fn serialize_char(self, v: char) -> JsResult<'a> {
+    self.serialize_str(v.encode_utf8(&mut [0; 4]))
   }
 
   fn serialize_str(self, v: &str) -> JsResult<'a> {
@@ -498,9 +550,10 @@ impl<'a, 'b, 'c> ser::Serializer for Serializer<'a, 'b, 'c> {
       .ok_or(Error::ExpectedString)
   }
 
-  fn serialize_bytes(self, _v: &[u8]) -> JsResult<'a> {
-    // TODO: investigate using Uint8Arrays
-    unimplemented!()
+  // Copies the bytes into a new Uint8Array
+  fn serialize_bytes(self, v: &[u8]) -> JsResult<'a> {
+    let scope = &mut *self.scope.borrow_mut();
+    Ok(boxed_slice_to_uint8array(scope, v.into()).into())
   }
 
   fn serialize_none(self) -> JsResult<'a> {
@@ -607,6 +660,10 @@ impl<'a, 'b, 'c> ser::Serializer for Serializer<'a, 'b, 'c> {
         let m = MagicByteStringSerializer::new(self.scope);
         Ok(StructSerializers::MagicByteString(m))
       }
+      magic::date::NAME => {
+        let m = MagicDateSerializer::new(self.scope);
+        Ok(StructSerializers::MagicDate(m))
+      }
       _ => {
         let o = ObjectSerializer::new(self.scope, len);
         Ok(StructSerializers::Regular(o))
@@ -644,3 +701,14 @@ pub fn boxed_slice_to_uint8array<'a>(
   v8::Uint8Array::new(scope, ab, 0, buf_len)
     .expect("Failed to create UintArray8")
 }
+
+fn bigint_from_words<'a>(
+  scope: &mut v8::HandleScope<'a>,
+  sign_bit: bool,
+  magnitude: u128,
+) -> JsResult<'a> {
+  let words = [magnitude as u64, (magnitude >> 64) as u64];
+  v8::BigInt::new_from_words(scope, sign_bit, &words)
+    .map(|v| v.into())
+    .ok_or(Error::IntegerOutOfRange)
+}
@@ -3,6 +3,39 @@ use serde::Deserialize;
 
 use serde_v8::utils::{js_exec, v8_do};
 use serde_v8::Error;
+use std::time::{Duration, UNIX_EPOCH};
+
+// Deserializes via `deserialize_byte_buf()`, like `serde_bytes::ByteBuf`
+#[derive(Debug, PartialEq)]
+struct ByteBuf(Vec<u8>);
+
+impl<'de> Deserialize<'de> for ByteBuf {
+  fn deserialize<D: serde::Deserializer<'de>>(
+    deserializer: D,
+  ) -> Result<Self, D::Error> {
+    struct ByteBufVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for ByteBufVisitor {
+      type Value = ByteBuf;
+
+      fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a byte buffer")
+      }
+
+      fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<ByteBuf, E> {
+        Ok(ByteBuf(v))
+      }
+    }
+
+    deserializer.deserialize_byte_buf(ByteBufVisitor)
+  }
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+struct Snowflake {
+  #[serde(with = "serde_v8::bigint")]
+  id: u64,
+}
 
 #[derive(Debug, Deserialize, PartialEq)]
 struct MathOp {
@@ -266,8 +299,60 @@ detest!(
     .collect()
   )
 );
+detest!(de_char, char, "'🦕'", '🦕');
+detest!(
+  de_byte_buf,
+  ByteBuf,
+  "new Uint8Array([1, 2, 3])",
+  ByteBuf(vec![1, 2, 3])
+);
 detest!(de_bigint_u64, u64, "BigInt(2**59)", 1 << 59);
 detest!(de_bigint_i64, i64, "BigInt(-(2**59))", -(1 << 59));
+detest!(
+  de_date,
+  serde_v8::Date,
+  "new Date(1600000000123)",
+  serde_v8::Date(UNIX_EPOCH + Duration::from_millis(1_600_000_000_123))
+);
+detest!(
+  de_date_millis,
+  serde_v8::Date,
+  "-1000",
+  serde_v8::Date(UNIX_EPOCH - Duration::from_secs(1))
+);
+detest!(de_bigint_i128, i128, "-(2n ** 127n)", i128::MIN);
+detest!(de_bigint_u128, u128, "2n ** 128n - 1n", u128::MAX);
+detest!(de_number_i128, i128, "-12345", -12345);
+detest!(
+  de_bigint_field,
+  Snowflake,
+  "({ id: 2n ** 64n - 1n })",
+  Snowflake { id: u64::MAX }
+);
+detest!(
+  de_bigint_json,
+  serde_json::Value,
+  "2n ** 63n",
+  serde_json::json!(1u64 << 63)
+);
 
 defail!(defail_struct, MathOp, "123", |e| e
   == Err(Error::ExpectedObject));
+
+defail!(defail_char, char, "'ab'", |e| e
+  == Err(Error::ExpectedString));
+
+defail!(defail_date, serde_v8::Date, "'2021-01-01'", |e| e
+  == Err(Error::ExpectedDate));
+
+defail!(defail_invalid_date, serde_v8::Date, "new Date(NaN)", |e| e
+  == Err(Error::Message("Invalid Date".to_string())));
+
+defail!(defail_bigint_u64, u64, "2n ** 64n", |e| e
+  == Err(Error::IntegerOutOfRange));
+
+defail!(defail_bigint_negative_u64, u64, "-1n", |e| e
+  == Err(Error::IntegerOutOfRange));
+
+defail!(defail_bigint_i128, i128, "2n ** 127n", |e| e
+  == Err(Error::IntegerOutOfRange));
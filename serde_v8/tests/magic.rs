@@ -130,6 +130,35 @@ fn magic_buffer() {
   })
 }
 
+#[test]
+fn magic_buffer_source() {
+  v8_do(|| {
+    let isolate = &mut v8::Isolate::new(v8::CreateParams::default());
+    let handle_scope = &mut v8::HandleScope::new(isolate);
+    let context = v8::Context::new(handle_scope);
+    let scope = &mut v8::ContextScope::new(handle_scope, context);
+
+    let v8_values = js_exec(
+      scope,
+      "const b = new ArrayBuffer(8); [b, new DataView(b, 2, 4), new SharedArrayBuffer(4)]",
+    );
+    let (buffer, view, shared): (
+      serde_v8::BufferSource,
+      serde_v8::BufferSource,
+      serde_v8::BufferSource,
+    ) = serde_v8::from_v8(scope, v8_values).unwrap();
+    assert_eq!((buffer.byte_offset, buffer.byte_length), (0, 8));
+    assert_eq!((view.byte_offset, view.byte_length), (2, 4));
+    assert_eq!(shared.byte_length, 4);
+    assert!(shared.backing_store.is_shared());
+
+    let v8_value = js_exec(scope, "[1, 2, 3]");
+    let source: Result<serde_v8::BufferSource> =
+      serde_v8::from_v8(scope, v8_value);
+    assert!(source.is_err());
+  })
+}
+
 #[test]
 fn magic_byte_string() {
   v8_do(|| {
@@ -2,6 +2,26 @@
 use serde::Serialize;
 use serde_json::json;
 use serde_v8::utils::{js_exec, v8_do};
+use std::time::{Duration, UNIX_EPOCH};
+
+// Serializes via `serialize_bytes()`, like `serde_bytes::Bytes`
+#[derive(Debug)]
+struct Bytes(&'static [u8]);
+
+impl Serialize for Bytes {
+  fn serialize<S: serde::Serializer>(
+    &self,
+    serializer: S,
+  ) -> Result<S::Ok, S::Error> {
+    serializer.serialize_bytes(self.0)
+  }
+}
+
+#[derive(Debug, Serialize)]
+struct Snowflake {
+  #[serde(with = "serde_v8::bigint")]
+  id: u64,
+}
 
 #[derive(Debug, Serialize, PartialEq)]
 struct MathOp {
@@ -108,8 +128,31 @@ sertest!(ser_option_some, Some(true), "x === true");
 sertest!(ser_option_null, None as Option<bool>, "x === null");
 sertest!(ser_unit_null, (), "x === null");
 sertest!(ser_bool, true, "x === true");
+sertest!(ser_char, '🦕', "x === '🦕'");
+sertest!(
+  ser_bytes,
+  Bytes(&[1, 2, 3]),
+  "x instanceof Uint8Array && arrEqual(x, [1, 2, 3])"
+);
 sertest!(ser_u64, 32, "x === 32");
 sertest!(ser_f64, 12345.0, "x === 12345.0");
+sertest!(
+  ser_date,
+  serde_v8::Date(UNIX_EPOCH + Duration::from_millis(1_600_000_000_123)),
+  "x instanceof Date && x.getTime() === 1600000000123"
+);
+sertest!(
+  ser_date_before_epoch,
+  serde_v8::Date(UNIX_EPOCH - Duration::from_millis(86_400_000)),
+  "x instanceof Date && x.toISOString() === '1969-12-31T00:00:00.000Z'"
+);
+sertest!(ser_i128, -(1i128 << 100), "x === -(2n ** 100n)");
+sertest!(ser_u128, u128::MAX, "x === 2n ** 128n - 1n");
+sertest!(
+  ser_bigint_field,
+  Snowflake { id: u64::MAX },
+  "x.id === 2n ** 64n - 1n"
+);
 sertest!(ser_string, "Hello".to_owned(), "x === 'Hello'");
 sertest!(ser_vec_u64, vec![1, 2, 3, 4, 5], "arrEqual(x, [1,2,3,4,5])");
 sertest!(